@@ -0,0 +1,151 @@
+//! The boot info passed to the root task. Based on libsel4's include/sel4/bootinfo_types.h.
+//!
+//! The kernel maps a BootInfo frame into the root task's address space, and passes its address
+//! in the cap register (RDI) when the root task starts. It describes the caps the kernel placed in
+//! the root task's CNode.
+
+use crate::constants::PAGE_BITS;
+use crate::ipc_buffer::IpcBuffer;
+use crate::types::{CPtr, Word};
+
+// The caps the kernel places in the root task's CNode. This is the anonymous enum in
+// bootinfo_types.h. Some slots are only filled on other platforms, but the numbering is fixed.
+pub const CAP_NULL: CPtr = 0;
+pub const CAP_INIT_THREAD_TCB: CPtr = 1;
+pub const CAP_INIT_THREAD_CNODE: CPtr = 2;
+pub const CAP_INIT_THREAD_VSPACE: CPtr = 3;
+pub const CAP_IRQ_CONTROL: CPtr = 4;
+pub const CAP_ASID_CONTROL: CPtr = 5;
+pub const CAP_INIT_THREAD_ASID_POOL: CPtr = 6;
+pub const CAP_IO_PORT_CONTROL: CPtr = 7;
+pub const CAP_IO_SPACE: CPtr = 8;
+pub const CAP_BOOT_INFO_FRAME: CPtr = 9;
+pub const CAP_INIT_THREAD_IPC_BUFFER: CPtr = 10;
+pub const CAP_DOMAIN: CPtr = 11;
+pub const NUM_INITIAL_CAPS: CPtr = 16;
+
+/// CONFIG_MAX_NUM_BOOTINFO_UNTYPED_CAPS in SeL4.
+pub const MAX_BOOTINFO_UNTYPED_CAPS: usize = 230;
+
+/// A range of slots in the root task's CNode, [start, end). This is seL4_SlotRegion.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub struct SlotRegion {
+    pub start: CPtr,
+    pub end: CPtr,
+}
+
+impl SlotRegion {
+    pub const fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+}
+
+/// Describes one untyped cap given to the root task. This is seL4_UntypedDesc.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub struct UntypedDesc {
+    pub paddr: Word,
+    pub size_bits: u8,
+    /// Device untypeds cover memory mapped devices. They can only be retyped into frames.
+    pub is_device: u8,
+    pub padding: [u8; size_of::<Word>() - 2],
+}
+
+/// seL4_BootInfo.
+#[repr(C)]
+pub struct BootInfo {
+    /// The length in bytes of the extra boot info, which follows this page.
+    pub extra_len: Word,
+    pub node_id: Word,
+    pub num_nodes: Word,
+    pub num_io_pt_levels: Word,
+    pub ipc_buffer: *mut IpcBuffer,
+    /// Empty slots in the root task's CNode.
+    pub empty: SlotRegion,
+    pub shared_frames: SlotRegion,
+    /// Frames holding the root task's image.
+    pub user_image_frames: SlotRegion,
+    /// Paging structures for the root task's image.
+    pub user_image_paging: SlotRegion,
+    pub io_space_caps: SlotRegion,
+    /// Frames holding the extra boot info.
+    pub extra_bi_pages: SlotRegion,
+    pub init_thread_cnode_size_bits: Word,
+    pub init_thread_domain: Word,
+    /// The untyped caps. untyped_list describes each one, in order.
+    pub untyped: SlotRegion,
+    pub untyped_list: [UntypedDesc; MAX_BOOTINFO_UNTYPED_CAPS],
+}
+
+const _: () = assert!(size_of::<BootInfo>() <= 1 << PAGE_BITS);
+
+/// The IDs of extra boot info chunks. This is seL4_BootInfoID.
+pub const BOOTINFO_ID_PADDING: Word = 0;
+pub const BOOTINFO_ID_X86_VBE: Word = 1;
+pub const BOOTINFO_ID_X86_MBMMAP: Word = 2;
+pub const BOOTINFO_ID_X86_ACPI_RSDP: Word = 3;
+pub const BOOTINFO_ID_X86_FRAMEBUFFER: Word = 4;
+pub const BOOTINFO_ID_X86_TSC_FREQ: Word = 5;
+pub const BOOTINFO_ID_FDT: Word = 6;
+
+/// The header of each extra boot info chunk. `len` includes the header. (seL4_BootInfoHeader)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub struct BootInfoHeader {
+    pub id: Word,
+    pub len: Word,
+}
+
+impl BootInfo {
+    /// Iterate over the untyped caps, with their descriptions.
+    pub fn untypeds(&self) -> impl Iterator<Item = (CPtr, &UntypedDesc)> {
+        (self.untyped.start..self.untyped.end).zip(self.untyped_list.iter())
+    }
+
+    /// Iterate over the chunks of extra boot info, as (id, chunk data). The data doesn't include
+    /// the header.
+    pub fn extra(&self) -> ExtraBootInfo<'_> {
+        // The extra boot info starts on the page after the boot info.
+        let start = (self as *const Self as usize + (1 << PAGE_BITS)) as *const u8;
+        // SAFETY: The kernel maps extra_len bytes of extra boot info after the boot info frame.
+        let bytes = unsafe { core::slice::from_raw_parts(start, self.extra_len) };
+        ExtraBootInfo { bytes }
+    }
+}
+
+pub struct ExtraBootInfo<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for ExtraBootInfo<'a> {
+    type Item = (Word, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        const HEADER_SIZE: usize = size_of::<BootInfoHeader>();
+
+        loop {
+            if self.bytes.len() < HEADER_SIZE {
+                return None;
+            }
+            let word = |i: usize| Word::from_ne_bytes(self.bytes[i..i + 8].try_into().unwrap());
+            let header = BootInfoHeader { id: word(0), len: word(8) };
+
+            if header.len < HEADER_SIZE || header.len > self.bytes.len() {
+                // Corrupt. Stop here rather than reading garbage.
+                self.bytes = &[];
+                return None;
+            }
+
+            let (chunk, rest) = self.bytes.split_at(header.len);
+            self.bytes = rest;
+            if header.id != BOOTINFO_ID_PADDING {
+                return Some((header.id, &chunk[HEADER_SIZE..]));
+            }
+        }
+    }
+}
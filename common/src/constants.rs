@@ -0,0 +1,135 @@
+//! Object sizes and other constants. These are from libsel4's constants.h, for x86_64.
+//!
+//! All the `*_BITS` constants are log2 sizes.
+
+pub const WORD_BITS: u32 = 64;
+pub const WORD_SIZE_BITS: u32 = 3;
+
+pub const PAGE_BITS: u32 = 12;
+/// For x86-64, large pages are 2 MiB.
+pub const LARGE_PAGE_BITS: u32 = 21;
+/// For x86-64, huge pages are 1 GiB.
+pub const HUGE_PAGE_BITS: u32 = 30;
+
+pub const SLOT_BITS: u32 = 5;
+/// This depends on CONFIG_XSAVE_SIZE in SeL4. It would be 12 with an XSAVE area of 832 bytes or
+/// more.
+pub const TCB_BITS: u32 = 11;
+pub const ENDPOINT_BITS: u32 = 4;
+pub const NOTIFICATION_BITS: u32 = 5;
+
+pub const PAGE_TABLE_BITS: u32 = 12;
+pub const PAGE_TABLE_ENTRY_BITS: u32 = 3;
+pub const PAGE_TABLE_INDEX_BITS: u32 = 9;
+
+pub const PAGE_DIR_BITS: u32 = 12;
+pub const PAGE_DIR_ENTRY_BITS: u32 = 3;
+pub const PAGE_DIR_INDEX_BITS: u32 = 9;
+
+pub const PDPT_BITS: u32 = 12;
+pub const PDPT_ENTRY_BITS: u32 = 3;
+pub const PDPT_INDEX_BITS: u32 = 9;
+
+pub const PML4_BITS: u32 = 12;
+pub const PML4_ENTRY_BITS: u32 = 3;
+pub const PML4_INDEX_BITS: u32 = 9;
+pub const VSPACE_BITS: u32 = PML4_BITS;
+
+pub const IO_PAGE_TABLE_BITS: u32 = 12;
+pub const NUM_ASID_POOLS_BITS: u32 = 3;
+pub const ASID_POOL_BITS: u32 = 12;
+pub const ASID_POOL_INDEX_BITS: u32 = 9;
+
+// Untyped size limits
+pub const MIN_UNTYPED_BITS: u32 = 4;
+pub const MAX_UNTYPED_BITS: u32 = 47;
+
+// Each table is made of 2^INDEX_BITS entries, each 2^ENTRY_BITS big.
+const _: () = assert!(PAGE_TABLE_ENTRY_BITS + PAGE_TABLE_INDEX_BITS == PAGE_TABLE_BITS);
+const _: () = assert!(PAGE_DIR_ENTRY_BITS + PAGE_DIR_INDEX_BITS == PAGE_DIR_BITS);
+const _: () = assert!(PDPT_ENTRY_BITS + PDPT_INDEX_BITS == PDPT_BITS);
+const _: () = assert!(PML4_ENTRY_BITS + PML4_INDEX_BITS == PML4_BITS);
+const _: () = assert!(WORD_SIZE_BITS + ASID_POOL_INDEX_BITS == ASID_POOL_BITS);
+
+/// The number of message words passed in registers, rather than through the IPC buffer.
+pub const FAST_MESSAGE_REGISTERS: usize = 4;
+
+pub const MSG_LENGTH_BITS: u32 = 7;
+pub const MSG_EXTRA_CAP_BITS: u32 = 2;
+/// The maximum number of message words in an IPC.
+pub const MSG_MAX_LENGTH: usize = 120;
+/// The maximum number of caps which can be sent with an IPC.
+pub const MSG_MAX_EXTRA_CAPS: usize = (1 << MSG_EXTRA_CAP_BITS) - 1;
+
+/// The IPC buffer is 1024 bytes.
+pub const IPC_BUFFER_SIZE_BITS: u32 = 10;
+
+pub const GUARD_SIZE_BITS: u32 = 6;
+pub const GUARD_BITS: u32 = 58;
+pub const BADGE_BITS: u32 = 64;
+
+/// First address in the virtual address space that is not accessible to user level.
+pub const USER_TOP: usize = 0x00007ffffffff000;
+
+pub const TLS_GDT_ENTRY: usize = 7;
+pub const TLS_GDT_SELECTOR: usize = (TLS_GDT_ENTRY << 3) | 3;
+pub const IPCBUF_GDT_ENTRY: usize = 8;
+pub const IPCBUF_GDT_SELECTOR: usize = (IPCBUF_GDT_ENTRY << 3) | 3;
+
+// Values for vm_fault::PREFETCH_FAULT.
+pub const DATA_FAULT: usize = 0;
+pub const INSTRUCTION_FAULT: usize = 1;
+
+/// The types of fault delivered to a thread's fault handler. This is seL4_FaultType.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(usize)]
+pub enum FaultType {
+    NullFault = 0,
+    CapFault = 1,
+    UnknownSyscall = 2,
+    UserException = 3,
+    VMFault = 5,
+}
+
+/// Message register layout for VM faults.
+pub mod vm_fault {
+    pub const IP: usize = 0;
+    pub const ADDR: usize = 1;
+    pub const PREFETCH_FAULT: usize = 2;
+    pub const FSR: usize = 3;
+    pub const LENGTH: usize = 4;
+}
+
+/// Message register layout for unknown syscall faults.
+pub mod unknown_syscall {
+    pub const RAX: usize = 0;
+    pub const RBX: usize = 1;
+    pub const RCX: usize = 2;
+    pub const RDX: usize = 3;
+    pub const RSI: usize = 4;
+    pub const RDI: usize = 5;
+    pub const RBP: usize = 6;
+    pub const R8: usize = 7;
+    pub const R9: usize = 8;
+    pub const R10: usize = 9;
+    pub const R11: usize = 10;
+    pub const R12: usize = 11;
+    pub const R13: usize = 12;
+    pub const R14: usize = 13;
+    pub const R15: usize = 14;
+    pub const FAULT_IP: usize = 15;
+    pub const SP: usize = 16;
+    pub const FLAGS: usize = 17;
+    pub const SYSCALL: usize = 18;
+    pub const LENGTH: usize = 19;
+}
+
+/// Message register layout for user exceptions.
+pub mod user_exception {
+    pub const FAULT_IP: usize = 0;
+    pub const SP: usize = 1;
+    pub const FLAGS: usize = 2;
+    pub const NUMBER: usize = 3;
+    pub const CODE: usize = 4;
+    pub const LENGTH: usize = 5;
+}
@@ -0,0 +1,110 @@
+//! Just enough ELF parsing to load a statically linked x86_64 executable. This is shared by the
+//! supervisor example, the sysdesc tool and the runtime's TLS setup.
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_TLS: u32 = 7;
+pub const PF_W: u32 = 2;
+const EM_X86_64: u16 = 62;
+
+/// The size of the ELF header.
+pub const EHDR_SIZE: usize = 64;
+
+/// The fields we use from a program header.
+#[derive(Copy, Clone, Debug)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: usize,
+    pub vaddr: usize,
+    pub file_size: usize,
+    pub mem_size: usize,
+    pub align: usize,
+}
+
+/// A loadable segment.
+pub struct Segment<'a> {
+    pub vaddr: usize,
+    pub mem_size: usize,
+    /// The initialised part of the segment. The rest is zeroed.
+    pub data: &'a [u8],
+    pub writable: bool,
+}
+
+pub struct Elf<'a> {
+    bytes: &'a [u8],
+    pub entry: usize,
+    phoff: usize,
+    phentsize: usize,
+    phnum: usize,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> usize {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize
+}
+
+/// The number of bytes from the start of the file to the end of the program headers. This is for
+/// images which are already loaded, where only the headers can be read.
+pub fn headers_len(ehdr: &[u8; EHDR_SIZE]) -> usize {
+    let phoff = read_u64(ehdr, 32);
+    let phentsize = read_u16(ehdr, 54) as usize;
+    let phnum = read_u16(ehdr, 56) as usize;
+    EHDR_SIZE.max(phoff.saturating_add(phentsize * phnum))
+}
+
+impl<'a> Elf<'a> {
+    /// Returns None if this isn't a 64 bit little endian x86_64 ELF file.
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < EHDR_SIZE || &bytes[0..4] != b"\x7fELF" || bytes[4] != 2 || bytes[5] != 1
+            || read_u16(bytes, 18) != EM_X86_64 {
+            return None;
+        }
+
+        let elf = Self {
+            bytes,
+            entry: read_u64(bytes, 24),
+            phoff: read_u64(bytes, 32),
+            phentsize: read_u16(bytes, 54) as usize,
+            phnum: read_u16(bytes, 56) as usize,
+        };
+
+        let headers_end = elf.phoff.checked_add(elf.phentsize.checked_mul(elf.phnum)?)?;
+        (elf.phentsize >= 56 && headers_end <= bytes.len()).then_some(elf)
+    }
+
+    /// Iterate over every program header.
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        (0..self.phnum)
+            .map(|i| &self.bytes[self.phoff + i * self.phentsize..])
+            .map(|phdr| ProgramHeader {
+                kind: read_u32(phdr, 0),
+                flags: read_u32(phdr, 4),
+                offset: read_u64(phdr, 8),
+                vaddr: read_u64(phdr, 16),
+                file_size: read_u64(phdr, 32),
+                mem_size: read_u64(phdr, 40),
+                align: read_u64(phdr, 48),
+            })
+    }
+
+    /// Iterate over the PT_LOAD segments. Returns None for segments which fall outside the file.
+    pub fn segments(&self) -> impl Iterator<Item = Option<Segment<'a>>> + '_ {
+        self.program_headers()
+            .filter(|phdr| phdr.kind == PT_LOAD)
+            .map(|phdr| {
+                Some(Segment {
+                    vaddr: phdr.vaddr,
+                    mem_size: phdr.mem_size,
+                    data: self.bytes.get(phdr.offset..phdr.offset.checked_add(phdr.file_size)?)?,
+                    writable: phdr.flags & PF_W != 0,
+                })
+            })
+    }
+}
@@ -0,0 +1,40 @@
+//! Based on libsel4's include/sel4/errors.h.
+
+/// An error returned by an invocation. This is seL4_Error, minus seL4_NoError, which is
+/// represented by Ok(()).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(usize)]
+pub enum Error {
+    InvalidArgument = 1,
+    InvalidCapability = 2,
+    IllegalOperation = 3,
+    RangeError = 4,
+    AlignmentError = 5,
+    FailedLookup = 6,
+    TruncatedMessage = 7,
+    DeleteFirst = 8,
+    RevokeFirst = 9,
+    NotEnoughMemory = 10,
+}
+
+impl Error {
+    /// Convert an error code returned by the kernel. Returns Ok(()) for seL4_NoError (0).
+    ///
+    /// Unknown error codes are reported as IllegalOperation.
+    pub fn result_from_word(w: usize) -> Result<(), Error> {
+        Err(match w {
+            0 => return Ok(()),
+            1 => Error::InvalidArgument,
+            2 => Error::InvalidCapability,
+            3 => Error::IllegalOperation,
+            4 => Error::RangeError,
+            5 => Error::AlignmentError,
+            6 => Error::FailedLookup,
+            7 => Error::TruncatedMessage,
+            8 => Error::DeleteFirst,
+            9 => Error::RevokeFirst,
+            10 => Error::NotEnoughMemory,
+            _ => Error::IllegalOperation,
+        })
+    }
+}
@@ -0,0 +1,175 @@
+//! Invocation labels and object types.
+//!
+//! In SeL4, these are generated from the libsel4 interface XML files (object-api.xml and friends).
+//! Like syscall numbers, entries which are configured out of the kernel don't get a number - so
+//! the values depend on the kernel configuration. These match a default x86_64 SeL4 build, which
+//! is non-MCS, single core, with no hardware debug API, VT-x or IOMMU.
+
+use crate::constants::{ENDPOINT_BITS, HUGE_PAGE_BITS, LARGE_PAGE_BITS, NOTIFICATION_BITS, PAGE_BITS, PAGE_DIR_BITS,
+                       PAGE_TABLE_BITS, PDPT_BITS, PML4_BITS, SLOT_BITS, TCB_BITS};
+
+/// The label of an invocation message.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(usize)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum InvocationLabel {
+    InvalidInvocation = 0,
+    UntypedRetype = 1,
+    TCBReadRegisters = 2,
+    TCBWriteRegisters = 3,
+    TCBCopyRegisters = 4,
+    TCBConfigure = 5,
+    TCBSetPriority = 6,
+    TCBSetMCPriority = 7,
+    TCBSetSchedParams = 8,
+    TCBSetIPCBuffer = 9,
+    TCBSetSpace = 10,
+    TCBSuspend = 11,
+    TCBResume = 12,
+    TCBBindNotification = 13,
+    TCBUnbindNotification = 14,
+    TCBSetTLSBase = 15,
+    CNodeRevoke = 16,
+    CNodeDelete = 17,
+    CNodeCancelBadgedSends = 18,
+    CNodeCopy = 19,
+    CNodeMint = 20,
+    CNodeMove = 21,
+    CNodeMutate = 22,
+    CNodeRotate = 23,
+    CNodeSaveCaller = 24,
+    IRQIssueIRQHandler = 25,
+    IRQAckIRQ = 26,
+    IRQSetIRQHandler = 27,
+    IRQClearIRQHandler = 28,
+    DomainSetSet = 29,
+
+    // x86_64 specific (sel4_arch) invocations.
+    X86PDPTMap = 30,
+    X86PDPTUnmap = 31,
+
+    // x86 specific (arch) invocations.
+    X86PageDirectoryMap = 32,
+    X86PageDirectoryUnmap = 33,
+    X86PageTableMap = 34,
+    X86PageTableUnmap = 35,
+    X86IOPageTableMap = 36,
+    X86IOPageTableUnmap = 37,
+    X86PageMap = 38,
+    X86PageUnmap = 39,
+    X86PageMapIO = 40,
+    X86PageGetAddress = 41,
+    X86ASIDControlMakePool = 42,
+    X86ASIDPoolAssign = 43,
+    X86IOPortControlIssue = 44,
+    X86IOPortIn8 = 45,
+    X86IOPortIn16 = 46,
+    X86IOPortIn32 = 47,
+    X86IOPortOut8 = 48,
+    X86IOPortOut16 = 49,
+    X86IOPortOut32 = 50,
+    X86IRQIssueIRQHandlerIOAPIC = 51,
+    X86IRQIssueIRQHandlerMSI = 52,
+}
+
+impl InvocationLabel {
+    pub const LAST: InvocationLabel = InvocationLabel::X86IRQIssueIRQHandlerMSI;
+
+    pub fn from_word(w: usize) -> Option<Self> {
+        if w > Self::LAST as usize {
+            return None;
+        }
+        // SAFETY: The enum is repr(usize), with every value from 0 to LAST.
+        Some(unsafe { core::mem::transmute::<usize, InvocationLabel>(w) })
+    }
+}
+
+/// The kinds of object which can be created with Untyped_Retype. This is seL4_ObjectType.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(usize)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum ObjectType {
+    Untyped = 0,
+    TCB = 1,
+    Endpoint = 2,
+    Notification = 3,
+    CapTable = 4,
+
+    // x86_64 specific.
+    X86PDPT = 5,
+    X64PML4 = 6,
+    X64HugePage = 7,
+
+    // x86 specific.
+    X86SmallPage = 8,
+    X86LargePage = 9,
+    X86PageTable = 10,
+    X86PageDirectory = 11,
+}
+
+impl ObjectType {
+    pub fn from_word(w: usize) -> Option<Self> {
+        Some(match w {
+            0 => ObjectType::Untyped,
+            1 => ObjectType::TCB,
+            2 => ObjectType::Endpoint,
+            3 => ObjectType::Notification,
+            4 => ObjectType::CapTable,
+            5 => ObjectType::X86PDPT,
+            6 => ObjectType::X64PML4,
+            7 => ObjectType::X64HugePage,
+            8 => ObjectType::X86SmallPage,
+            9 => ObjectType::X86LargePage,
+            10 => ObjectType::X86PageTable,
+            11 => ObjectType::X86PageDirectory,
+            _ => return None,
+        })
+    }
+
+    /// The log2 size of an object of this type. `user_size_bits` is only used by objects with a
+    /// variable size - untypeds (their size) and CNodes (the log2 number of slots). This is
+    /// getObjectSize in SeL4.
+    pub const fn size_bits(self, user_size_bits: u32) -> u32 {
+        match self {
+            ObjectType::Untyped => user_size_bits,
+            ObjectType::TCB => TCB_BITS,
+            ObjectType::Endpoint => ENDPOINT_BITS,
+            ObjectType::Notification => NOTIFICATION_BITS,
+            ObjectType::CapTable => user_size_bits + SLOT_BITS,
+            ObjectType::X86PDPT => PDPT_BITS,
+            ObjectType::X64PML4 => PML4_BITS,
+            ObjectType::X64HugePage => HUGE_PAGE_BITS,
+            ObjectType::X86SmallPage => PAGE_BITS,
+            ObjectType::X86LargePage => LARGE_PAGE_BITS,
+            ObjectType::X86PageTable => PAGE_TABLE_BITS,
+            ObjectType::X86PageDirectory => PAGE_DIR_BITS,
+        }
+    }
+}
+
+/// Caching attributes for page mappings. This is seL4_X86_VMAttributes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(usize)]
+pub enum VmAttributes {
+    WriteBack = 0,
+    WriteThrough = 1,
+    CacheDisabled = 2,
+    Uncacheable = 3,
+    WriteCombining = 4,
+}
+
+impl VmAttributes {
+    /// seL4_X86_Default_VMAttributes.
+    pub const DEFAULT: VmAttributes = VmAttributes::WriteBack;
+
+    pub fn from_word(w: usize) -> Option<Self> {
+        Some(match w {
+            0 => VmAttributes::WriteBack,
+            1 => VmAttributes::WriteThrough,
+            2 => VmAttributes::CacheDisabled,
+            3 => VmAttributes::Uncacheable,
+            4 => VmAttributes::WriteCombining,
+            _ => return None,
+        })
+    }
+}
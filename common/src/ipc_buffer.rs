@@ -0,0 +1,99 @@
+//! The IPC buffer. Based on libsel4's include/sel4/functions.h.
+//!
+//! Every thread has an IPC buffer, mapped into its address space. Message words which don't fit in
+//! registers, and any caps being transferred, are passed through it.
+
+use core::sync::atomic::{AtomicPtr, Ordering};
+use crate::constants::{IPC_BUFFER_SIZE_BITS, MSG_MAX_EXTRA_CAPS, MSG_MAX_LENGTH};
+use crate::types::{CPtr, MessageInfo, Word};
+
+/// The layout of the IPC buffer. This is seL4_IPCBuffer.
+#[repr(C, align(1024))]
+pub struct IpcBuffer {
+    pub tag: MessageInfo,
+    pub msg: [Word; MSG_MAX_LENGTH],
+    pub user_data: Word,
+    /// Caps to send, or the badges of caps which were received unwrapped.
+    pub caps_or_badges: [Word; MSG_MAX_EXTRA_CAPS],
+    /// Where to put a received cap.
+    pub receive_cnode: CPtr,
+    pub receive_index: CPtr,
+    pub receive_depth: Word,
+}
+
+const _: () = assert!(size_of::<IpcBuffer>() == 1 << IPC_BUFFER_SIZE_BITS);
+
+/// The current thread's IPC buffer.
+///
+/// DEPARTURE: libsel4 keeps this in a thread local variable (__sel4_ipc_buffer). We don't have
+/// thread locals yet, so this is shared by every thread in the address space. Programs with more
+/// than one thread must switch it themselves.
+static IPC_BUFFER: AtomicPtr<IpcBuffer> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Set the IPC buffer used by the syscall stubs. This is normally done by the runtime, using the
+/// address in BootInfo. (seL4_SetIPCBuffer)
+///
+/// # Safety
+///
+/// `buffer` must point to this thread's IPC buffer, and stay mapped.
+pub unsafe fn set_ipc_buffer(buffer: *mut IpcBuffer) {
+    IPC_BUFFER.store(buffer, Ordering::Relaxed);
+}
+
+/// Returns the IPC buffer set with [set_ipc_buffer]. (seL4_GetIPCBuffer)
+pub fn ipc_buffer() -> *mut IpcBuffer {
+    IPC_BUFFER.load(Ordering::Relaxed)
+}
+
+/// Call `f` with a reference to the IPC buffer.
+///
+/// Panics if the IPC buffer hasn't been set.
+pub fn with_ipc_buffer<R>(f: impl FnOnce(&mut IpcBuffer) -> R) -> R {
+    let buffer = ipc_buffer();
+    assert!(!buffer.is_null(), "IPC buffer not set");
+    // SAFETY: set_ipc_buffer requires the pointer to be valid. The buffer is only used by this
+    // thread, and we don't hand out references which outlive this call.
+    f(unsafe { &mut *buffer })
+}
+
+/// Get message register `i`. (seL4_GetMR)
+pub fn get_mr(i: usize) -> Word {
+    with_ipc_buffer(|buf| buf.msg[i])
+}
+
+/// Set message register `i`. (seL4_SetMR)
+pub fn set_mr(i: usize, value: Word) {
+    with_ipc_buffer(|buf| buf.msg[i] = value);
+}
+
+pub fn get_user_data() -> Word {
+    with_ipc_buffer(|buf| buf.user_data)
+}
+
+pub fn set_user_data(data: Word) {
+    with_ipc_buffer(|buf| buf.user_data = data);
+}
+
+/// Get the badge of the `i`th cap received, if it was unwrapped. (seL4_GetBadge)
+pub fn get_badge(i: usize) -> Word {
+    with_ipc_buffer(|buf| buf.caps_or_badges[i])
+}
+
+/// Get the `i`th extra cap to send. (seL4_GetCap)
+pub fn get_cap(i: usize) -> CPtr {
+    with_ipc_buffer(|buf| buf.caps_or_badges[i])
+}
+
+/// Set the `i`th extra cap to send. (seL4_SetCap)
+pub fn set_cap(i: usize, cap: CPtr) {
+    with_ipc_buffer(|buf| buf.caps_or_badges[i] = cap);
+}
+
+/// Set the slot received caps are placed in. (seL4_SetCapReceivePath)
+pub fn set_cap_receive_path(receive_cnode: CPtr, receive_index: CPtr, receive_depth: Word) {
+    with_ipc_buffer(|buf| {
+        buf.receive_cnode = receive_cnode;
+        buf.receive_index = receive_index;
+        buf.receive_depth = receive_depth;
+    });
+}
@@ -0,0 +1,125 @@
+//! The kernel's embedded symbol table, used to symbolize backtraces.
+//!
+//! `cargo xtask build` generates the table from the kernel ELF's function symbols, and links it
+//! into the kernel's .ksyms section (see kernel/build.rs). This module describes the format, and
+//! looks addresses up in it.
+//!
+//! The table is a [KsymsHeader], followed by `count` entries sorted by address, followed by the
+//! symbol names. Everything is little endian. Each entry is three u32s:
+//!
+//!  - The symbol's physical address.
+//!  - Its size in bytes. 0 if unknown, in which case it runs up to the next symbol. The generator
+//!    fills in sizes it can work out, so this is rare, and the last symbol always has one.
+//!  - The offset of its name (NUL terminated, demangled) from the start of the names.
+//!
+//! Addresses are physical, so code linked at physical addresses (.phys.text) and code running from
+//! its physical alias (like .boot.text early in boot) resolve the same way as everything else.
+//! Virtual addresses are converted by subtracting `virt_offset` first.
+
+use core::ffi::CStr;
+
+/// "KSYMS001", read as a little endian u64.
+pub const KSYMS_MAGIC: u64 = u64::from_le_bytes(*b"KSYMS001");
+
+pub const KSYMS_HEADER_SIZE: usize = 24;
+pub const KSYMS_ENTRY_SIZE: usize = 12;
+
+/// The fields of the table's header.
+#[derive(Copy, Clone, Debug)]
+pub struct KsymsHeader {
+    /// The number of entries.
+    pub count: u32,
+    /// The size of the names, in bytes.
+    pub names_len: u32,
+    /// The offset from physical to virtual addresses in the kernel image.
+    pub virt_offset: u64,
+}
+
+impl KsymsHeader {
+    pub fn to_bytes(&self) -> [u8; KSYMS_HEADER_SIZE] {
+        let mut out = [0; KSYMS_HEADER_SIZE];
+        out[0..8].copy_from_slice(&KSYMS_MAGIC.to_le_bytes());
+        out[8..12].copy_from_slice(&self.count.to_le_bytes());
+        out[12..16].copy_from_slice(&self.names_len.to_le_bytes());
+        out[16..24].copy_from_slice(&self.virt_offset.to_le_bytes());
+        out
+    }
+
+    /// Returns None if the magic number is wrong.
+    pub fn from_bytes(bytes: &[u8; KSYMS_HEADER_SIZE]) -> Option<Self> {
+        if u64::from_le_bytes(bytes[0..8].try_into().unwrap()) != KSYMS_MAGIC {
+            return None;
+        }
+        Some(Self {
+            count: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            names_len: u32::from_le_bytes(bytes[12..16].try_into().unwrap()),
+            virt_offset: u64::from_le_bytes(bytes[16..24].try_into().unwrap()),
+        })
+    }
+}
+
+/// A symbol table, borrowed from its raw bytes.
+pub struct Ksyms<'a> {
+    header: KsymsHeader,
+    entries: &'a [u8],
+    names: &'a [u8],
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+impl<'a> Ksyms<'a> {
+    /// Returns None if the table is empty, or isn't a valid symbol table.
+    pub fn from_bytes(bytes: &'a [u8]) -> Option<Self> {
+        let header = KsymsHeader::from_bytes(bytes.first_chunk()?)?;
+        let entries_len = header.count as usize * KSYMS_ENTRY_SIZE;
+        let entries = bytes.get(KSYMS_HEADER_SIZE..KSYMS_HEADER_SIZE + entries_len)?;
+        let names = bytes.get(KSYMS_HEADER_SIZE + entries_len..)?.get(..header.names_len as usize)?;
+        Some(Self { header, entries, names })
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The (address, size, name offset) of entry i.
+    fn entry(&self, i: usize) -> (u64, u64, usize) {
+        let offset = i * KSYMS_ENTRY_SIZE;
+        (read_u32(self.entries, offset) as u64,
+         read_u32(self.entries, offset + 4) as u64,
+         read_u32(self.entries, offset + 8) as usize)
+    }
+
+    /// Find the symbol containing addr, which may be virtual or physical. Returns the symbol's
+    /// name and addr's offset into it.
+    pub fn lookup(&self, addr: u64) -> Option<(&'a str, u64)> {
+        let paddr = if addr >= self.header.virt_offset { addr - self.header.virt_offset } else { addr };
+
+        // Binary search for the last symbol starting at or before paddr.
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.entry(mid).0 <= paddr {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let index = low.checked_sub(1)?;
+        let (start, size, name) = self.entry(index);
+        let offset = paddr - start;
+        // Symbols without a size run up to the next symbol. If there isn't one, there's nothing to
+        // bound it, so it doesn't match.
+        if (size != 0 && offset >= size) || (size == 0 && index + 1 == self.len()) {
+            return None;
+        }
+
+        let name = CStr::from_bytes_until_nul(self.names.get(name..)?).ok()?;
+        Some((name.to_str().ok()?, offset))
+    }
+}
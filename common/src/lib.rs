@@ -1,50 +1,29 @@
-#![no_std]
-
-
-
-// /* for x86-64, the large page size is 2 MiB and huge page size is 1 GiB */
-// #define seL4_WordBits           64
-// #define seL4_WordSizeBits       3
-// #define seL4_PageBits           12
-// #define seL4_SlotBits           5
-// #if CONFIG_XSAVE_SIZE >= 832
-// #define seL4_TCBBits            12
-// #else
-// #define seL4_TCBBits            11
-// #endif
-// #define seL4_EndpointBits       4
-// #ifdef CONFIG_KERNEL_MCS
-// #define seL4_NotificationBits   6
-// #define seL4_ReplyBits          5
-// #else
-// #define seL4_NotificationBits   5
-// #endif
-
-//
-// #define seL4_PageTableBits      12
-// #define seL4_PageTableEntryBits 3
-// #define seL4_PageTableIndexBits 9
-//
-// #define seL4_PageDirBits        12
-// #define seL4_PageDirEntryBits   3
-// #define seL4_PageDirIndexBits   9
-//
-// #define seL4_PDPTBits           12
-// #define seL4_PDPTEntryBits      3
-// #define seL4_PDPTIndexBits      9
-//
-// #define seL4_PML4Bits           12
-// #define seL4_PML4EntryBits      3
-// #define seL4_PML4IndexBits      9
-// #define seL4_VSpaceBits seL4_PML4Bits
-//
-// #define seL4_IOPageTableBits    12
-// #define seL4_LargePageBits      21
-// #define seL4_HugePageBits       30
-// #define seL4_NumASIDPoolsBits    3
-// #define seL4_ASIDPoolBits       12
-// #define seL4_ASIDPoolIndexBits 9
+//! A Rust port of libsel4, for userland programs running on this kernel.
+//!
+//! This crate also contains everything shared between the kernel and userland - constants, the
+//! syscall ABI, invocation labels, the message info bitfield and so on. The kernel uses this crate
+//! directly, so the two sides can't drift apart.
+//!
+//! SeL4 officially supports both 32 and 64 bit modes of operation. But I don't really care about
+//! 32 bit support, because all modern x86 chips support running in 64 bit mode anyway. So this is
+//! x86_64 only, and I've simplified a lot of libsel4 as a result.
+//!
+//! Unlike the kernel, this crate is distributed under the ISC license.
 
+#![no_std]
 
-pub const PML4_INDEX_BITS: usize = 9;
+pub mod constants;
+pub mod errors;
+pub mod types;
+pub mod syscalls;
+pub mod ipc_buffer;
+pub mod invocation;
+pub mod objects;
+pub mod bootinfo;
+pub mod sysdesc;
+pub mod ksyms;
+pub mod elf;
 
+pub use bootinfo::BootInfo;
+pub use errors::Error;
+pub use types::{CPtr, CapRights, MessageInfo, Word};
@@ -0,0 +1,475 @@
+//! Typed wrappers for invoking kernel objects. These correspond to the seL4_<Object>_<Method>
+//! functions generated from libsel4's interface XML files.
+//!
+//! Each object type is a newtype around the CPtr of a cap to it. Invocations return
+//! Err(Error) if the kernel rejected them. Where SeL4 returns extra values in the reply message,
+//! they're returned in the Ok variant.
+
+use crate::errors::Error;
+use crate::invocation::{InvocationLabel, ObjectType, VmAttributes};
+use crate::ipc_buffer::{get_mr, set_cap, set_mr};
+use crate::syscalls::call;
+use crate::types::{CPtr, CapRights, MessageInfo, Word};
+
+/// Make an invocation. The arguments are placed in the message registers, and the caps in the IPC
+/// buffer.
+fn invoke(service: CPtr, label: InvocationLabel, caps: &[CPtr], args: &[Word]) -> Result<MessageInfo, Error> {
+    for (i, cap) in caps.iter().enumerate() {
+        set_cap(i, *cap);
+    }
+    for (i, arg) in args.iter().enumerate() {
+        set_mr(i, *arg);
+    }
+
+    let info = MessageInfo::new(label as Word, 0, caps.len(), args.len());
+    let reply = call(service, info);
+    Error::result_from_word(reply.label())?;
+    Ok(reply)
+}
+
+/// The registers read and written by TCB_ReadRegisters and TCB_WriteRegisters. This is
+/// seL4_UserContext.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[repr(C)]
+pub struct UserContext {
+    pub rip: Word,
+    pub rsp: Word,
+    pub rflags: Word,
+    pub rax: Word,
+    pub rbx: Word,
+    pub rcx: Word,
+    pub rdx: Word,
+    pub rsi: Word,
+    pub rdi: Word,
+    pub rbp: Word,
+    pub r8: Word,
+    pub r9: Word,
+    pub r10: Word,
+    pub r11: Word,
+    pub r12: Word,
+    pub r13: Word,
+    pub r14: Word,
+    pub r15: Word,
+    pub fs_base: Word,
+    pub gs_base: Word,
+}
+
+impl UserContext {
+    pub const COUNT: usize = size_of::<UserContext>() / size_of::<Word>();
+
+    fn as_words(&self) -> &[Word; Self::COUNT] {
+        // SAFETY: UserContext is repr(C) and made entirely of words.
+        unsafe { &*(self as *const Self as *const [Word; Self::COUNT]) }
+    }
+
+    fn as_words_mut(&mut self) -> &mut [Word; Self::COUNT] {
+        // SAFETY: As above.
+        unsafe { &mut *(self as *mut Self as *mut [Word; Self::COUNT]) }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Untyped(pub CPtr);
+
+impl Untyped {
+    /// Create `num_objects` new objects, placing caps to them in consecutive slots starting at
+    /// `node_offset` in the CNode at (`root`, `node_index`, `node_depth`).
+    #[allow(clippy::too_many_arguments)]
+    pub fn retype(self, object_type: ObjectType, size_bits: Word, root: CNode, node_index: Word,
+                  node_depth: Word, node_offset: Word, num_objects: Word) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::UntypedRetype, &[root.0],
+               &[object_type as Word, size_bits, node_index, node_depth, node_offset, num_objects])?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Tcb(pub CPtr);
+
+impl Tcb {
+    /// Read the first `count` registers of the thread (in [UserContext] order).
+    pub fn read_registers(self, suspend_source: bool, arch_flags: u8, count: usize) -> Result<UserContext, Error> {
+        let count = count.min(UserContext::COUNT);
+        invoke(self.0, InvocationLabel::TCBReadRegisters, &[],
+               &[suspend_source as Word | (arch_flags as Word) << 8, count])?;
+
+        let mut regs = UserContext::default();
+        for (i, reg) in regs.as_words_mut().iter_mut().enumerate().take(count) {
+            *reg = get_mr(i);
+        }
+        Ok(regs)
+    }
+
+    /// Write the first `count` registers of the thread (in [UserContext] order).
+    pub fn write_registers(self, resume_target: bool, arch_flags: u8, count: usize,
+                           regs: &UserContext) -> Result<(), Error> {
+        let count = count.min(UserContext::COUNT);
+        set_mr(0, resume_target as Word | (arch_flags as Word) << 8);
+        set_mr(1, count);
+        for (i, reg) in regs.as_words().iter().enumerate().take(count) {
+            set_mr(i + 2, *reg);
+        }
+
+        let info = MessageInfo::new(InvocationLabel::TCBWriteRegisters as Word, 0, 0, count + 2);
+        Error::result_from_word(call(self.0, info).label())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn copy_registers(self, source: Tcb, suspend_source: bool, resume_target: bool,
+                          transfer_frame: bool, transfer_integer: bool, arch_flags: u8) -> Result<(), Error> {
+        let flags = suspend_source as Word
+            | (resume_target as Word) << 1
+            | (transfer_frame as Word) << 2
+            | (transfer_integer as Word) << 3
+            | (arch_flags as Word) << 8;
+        invoke(self.0, InvocationLabel::TCBCopyRegisters, &[source.0], &[flags])?;
+        Ok(())
+    }
+
+    /// Set the thread's fault endpoint, address spaces and IPC buffer all at once.
+    ///
+    /// `fault_ep` is a CPtr in the thread's own CSpace, so it isn't a cap argument.
+    #[allow(clippy::too_many_arguments)]
+    pub fn configure(self, fault_ep: CPtr, cspace_root: CNode, cspace_root_data: Word,
+                     vspace_root: Pml4, vspace_root_data: Word, buffer: Word,
+                     buffer_frame: Page) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::TCBConfigure, &[cspace_root.0, vspace_root.0, buffer_frame.0],
+               &[fault_ep, cspace_root_data, vspace_root_data, buffer])?;
+        Ok(())
+    }
+
+    pub fn set_priority(self, authority: Tcb, priority: Word) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::TCBSetPriority, &[authority.0], &[priority])?;
+        Ok(())
+    }
+
+    pub fn set_mc_priority(self, authority: Tcb, mcp: Word) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::TCBSetMCPriority, &[authority.0], &[mcp])?;
+        Ok(())
+    }
+
+    pub fn set_sched_params(self, authority: Tcb, mcp: Word, priority: Word) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::TCBSetSchedParams, &[authority.0], &[mcp, priority])?;
+        Ok(())
+    }
+
+    pub fn set_ipc_buffer(self, buffer: Word, buffer_frame: Page) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::TCBSetIPCBuffer, &[buffer_frame.0], &[buffer])?;
+        Ok(())
+    }
+
+    pub fn set_space(self, fault_ep: CPtr, cspace_root: CNode, cspace_root_data: Word,
+                     vspace_root: Pml4, vspace_root_data: Word) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::TCBSetSpace, &[cspace_root.0, vspace_root.0],
+               &[fault_ep, cspace_root_data, vspace_root_data])?;
+        Ok(())
+    }
+
+    pub fn suspend(self) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::TCBSuspend, &[], &[])?;
+        Ok(())
+    }
+
+    pub fn resume(self) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::TCBResume, &[], &[])?;
+        Ok(())
+    }
+
+    pub fn bind_notification(self, notification: Notification) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::TCBBindNotification, &[notification.0], &[])?;
+        Ok(())
+    }
+
+    pub fn unbind_notification(self) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::TCBUnbindNotification, &[], &[])?;
+        Ok(())
+    }
+
+    pub fn set_tls_base(self, tls_base: Word) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::TCBSetTLSBase, &[], &[tls_base])?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Endpoint(pub CPtr);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Notification(pub CPtr);
+
+impl Notification {
+    pub fn signal(self) {
+        crate::syscalls::signal(self.0);
+    }
+
+    pub fn wait(self) -> Word {
+        crate::syscalls::wait(self.0)
+    }
+
+    pub fn poll(self) -> Word {
+        crate::syscalls::poll(self.0)
+    }
+}
+
+/// A CNode. Slots are addressed relative to this CNode by (index, depth) pairs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct CNode(pub CPtr);
+
+impl CNode {
+    pub fn revoke(self, index: Word, depth: u8) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::CNodeRevoke, &[], &[index, depth as Word])?;
+        Ok(())
+    }
+
+    pub fn delete(self, index: Word, depth: u8) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::CNodeDelete, &[], &[index, depth as Word])?;
+        Ok(())
+    }
+
+    pub fn cancel_badged_sends(self, index: Word, depth: u8) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::CNodeCancelBadgedSends, &[], &[index, depth as Word])?;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn copy(self, dest_index: Word, dest_depth: u8, src_root: CNode, src_index: Word,
+                src_depth: u8, rights: CapRights) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::CNodeCopy, &[src_root.0],
+               &[dest_index, dest_depth as Word, src_index, src_depth as Word, rights.0])?;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn mint(self, dest_index: Word, dest_depth: u8, src_root: CNode, src_index: Word,
+                src_depth: u8, rights: CapRights, badge: Word) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::CNodeMint, &[src_root.0],
+               &[dest_index, dest_depth as Word, src_index, src_depth as Word, rights.0, badge])?;
+        Ok(())
+    }
+
+    pub fn move_(self, dest_index: Word, dest_depth: u8, src_root: CNode, src_index: Word,
+                 src_depth: u8) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::CNodeMove, &[src_root.0],
+               &[dest_index, dest_depth as Word, src_index, src_depth as Word])?;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn mutate(self, dest_index: Word, dest_depth: u8, src_root: CNode, src_index: Word,
+                  src_depth: u8, badge: Word) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::CNodeMutate, &[src_root.0],
+               &[dest_index, dest_depth as Word, src_index, src_depth as Word, badge])?;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn rotate(self, dest_index: Word, dest_depth: u8, dest_badge: Word, pivot_root: CNode,
+                  pivot_index: Word, pivot_depth: u8, pivot_badge: Word, src_root: CNode,
+                  src_index: Word, src_depth: u8, src_badge: Word) -> Result<(), Error> {
+        // dest_badge is applied to the cap moved from the pivot slot into the destination slot.
+        invoke(self.0, InvocationLabel::CNodeRotate, &[pivot_root.0, src_root.0],
+               &[dest_index, dest_depth as Word, dest_badge, pivot_index, pivot_depth as Word,
+                 pivot_badge, src_index, src_depth as Word, src_badge])?;
+        Ok(())
+    }
+
+    /// Move the current reply cap into the given slot.
+    pub fn save_caller(self, index: Word, depth: u8) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::CNodeSaveCaller, &[], &[index, depth as Word])?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct IrqControl(pub CPtr);
+
+impl IrqControl {
+    /// Create an IRQ handler cap for the given (legacy) IRQ.
+    pub fn get(self, irq: Word, root: CNode, index: Word, depth: u8) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::IRQIssueIRQHandler, &[root.0], &[irq, index, depth as Word])?;
+        Ok(())
+    }
+
+    /// Create an IRQ handler cap for an IOAPIC pin, delivered on `vector`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_ioapic(self, root: CNode, index: Word, depth: u8, ioapic: Word, pin: Word,
+                      level: Word, polarity: Word, vector: Word) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::X86IRQIssueIRQHandlerIOAPIC, &[root.0],
+               &[index, depth as Word, ioapic, pin, level, polarity, vector])?;
+        Ok(())
+    }
+
+    /// Create an IRQ handler cap for an MSI, delivered on `vector`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_msi(self, root: CNode, index: Word, depth: u8, pci_bus: Word, pci_dev: Word,
+                   pci_func: Word, handle: Word, vector: Word) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::X86IRQIssueIRQHandlerMSI, &[root.0],
+               &[index, depth as Word, pci_bus, pci_dev, pci_func, handle, vector])?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct IrqHandler(pub CPtr);
+
+impl IrqHandler {
+    pub fn ack(self) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::IRQAckIRQ, &[], &[])?;
+        Ok(())
+    }
+
+    /// Signal `notification` when the IRQ fires.
+    pub fn set_notification(self, notification: Notification) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::IRQSetIRQHandler, &[notification.0], &[])?;
+        Ok(())
+    }
+
+    pub fn clear(self) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::IRQClearIRQHandler, &[], &[])?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct DomainSet(pub CPtr);
+
+impl DomainSet {
+    pub fn set(self, domain: u8, thread: Tcb) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::DomainSetSet, &[thread.0], &[domain as Word])?;
+        Ok(())
+    }
+}
+
+/// The top level paging structure, which is the root of a VSpace.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Pml4(pub CPtr);
+
+macro_rules! paging_structure {
+    ($name:ident, $map:ident, $unmap:ident) => {
+        #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+        #[repr(transparent)]
+        pub struct $name(pub CPtr);
+
+        impl $name {
+            /// Map this paging structure into `vspace` at `vaddr`.
+            pub fn map(self, vspace: Pml4, vaddr: Word, attr: VmAttributes) -> Result<(), Error> {
+                invoke(self.0, InvocationLabel::$map, &[vspace.0], &[vaddr, attr as Word])?;
+                Ok(())
+            }
+
+            pub fn unmap(self) -> Result<(), Error> {
+                invoke(self.0, InvocationLabel::$unmap, &[], &[])?;
+                Ok(())
+            }
+        }
+    };
+}
+
+paging_structure!(Pdpt, X86PDPTMap, X86PDPTUnmap);
+paging_structure!(PageDirectory, X86PageDirectoryMap, X86PageDirectoryUnmap);
+paging_structure!(PageTable, X86PageTableMap, X86PageTableUnmap);
+
+/// A frame of memory of any size.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Page(pub CPtr);
+
+impl Page {
+    pub fn map(self, vspace: Pml4, vaddr: Word, rights: CapRights, attr: VmAttributes) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::X86PageMap, &[vspace.0], &[vaddr, rights.0, attr as Word])?;
+        Ok(())
+    }
+
+    pub fn unmap(self) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::X86PageUnmap, &[], &[])?;
+        Ok(())
+    }
+
+    /// Returns the physical address of the frame.
+    pub fn get_address(self) -> Result<Word, Error> {
+        invoke(self.0, InvocationLabel::X86PageGetAddress, &[], &[])?;
+        Ok(get_mr(0))
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct AsidControl(pub CPtr);
+
+impl AsidControl {
+    /// Turn `untyped` (which must be exactly 4k) into a new ASID pool.
+    pub fn make_pool(self, untyped: Untyped, root: CNode, index: Word, depth: u8) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::X86ASIDControlMakePool, &[untyped.0, root.0],
+               &[index, depth as Word])?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct AsidPool(pub CPtr);
+
+impl AsidPool {
+    pub fn assign(self, vspace: Pml4) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::X86ASIDPoolAssign, &[vspace.0], &[])?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct IoPortControl(pub CPtr);
+
+impl IoPortControl {
+    /// Create an IOPort cap for the ports in [first_port, last_port].
+    pub fn issue(self, first_port: u16, last_port: u16, root: CNode, index: Word, depth: u8) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::X86IOPortControlIssue, &[root.0],
+               &[first_port as Word, last_port as Word, index, depth as Word])?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct IoPort(pub CPtr);
+
+impl IoPort {
+    pub fn in8(self, port: u16) -> Result<u8, Error> {
+        invoke(self.0, InvocationLabel::X86IOPortIn8, &[], &[port as Word])?;
+        Ok(get_mr(0) as u8)
+    }
+
+    pub fn in16(self, port: u16) -> Result<u16, Error> {
+        invoke(self.0, InvocationLabel::X86IOPortIn16, &[], &[port as Word])?;
+        Ok(get_mr(0) as u16)
+    }
+
+    pub fn in32(self, port: u16) -> Result<u32, Error> {
+        invoke(self.0, InvocationLabel::X86IOPortIn32, &[], &[port as Word])?;
+        Ok(get_mr(0) as u32)
+    }
+
+    pub fn out8(self, port: u16, data: u8) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::X86IOPortOut8, &[], &[port as Word, data as Word])?;
+        Ok(())
+    }
+
+    pub fn out16(self, port: u16, data: u16) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::X86IOPortOut16, &[], &[port as Word, data as Word])?;
+        Ok(())
+    }
+
+    pub fn out32(self, port: u16, data: u32) -> Result<(), Error> {
+        invoke(self.0, InvocationLabel::X86IOPortOut32, &[], &[port as Word, data as Word])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+//! Syscall numbers, and the userland syscall stubs from libsel4's sel4_arch/syscalls.h.
+//!
+//! ## ABI
+//!
+//! Syscalls are made with the SYSCALL instruction:
+//!
+//! - RDX holds the syscall number
+//! - RDI holds the cap being invoked (or the badge, on return)
+//! - RSI holds the [MessageInfo]
+//! - R10, R8, R9 and R15 hold the first [FAST_MESSAGE_REGISTERS] message words. The rest are
+//!   passed in the IPC buffer.
+//!
+//! RCX and R11 are clobbered by SYSCALL / SYSRET. Every other register is preserved.
+//!
+//! Like libsel4, the stubs here copy the message registers to and from the IPC buffer, so callers
+//! can always use [get_mr] and [set_mr] regardless of message length.
+
+use crate::constants::FAST_MESSAGE_REGISTERS;
+use crate::ipc_buffer::{get_mr, set_mr};
+use crate::types::{CPtr, MessageInfo, Word};
+
+// Syscall numbers. In SeL4 these are generated from libsel4/include/api/syscall.xml. Each syscall
+// is numbered in order from -1, including syscalls which are configured out of the kernel.
+pub const SYS_CALL: isize = -1;
+pub const SYS_REPLY_RECV: isize = -2;
+pub const SYS_SEND: isize = -3;
+pub const SYS_NB_SEND: isize = -4;
+pub const SYS_RECV: isize = -5;
+pub const SYS_REPLY: isize = -6;
+pub const SYS_YIELD: isize = -7;
+pub const SYS_NB_RECV: isize = -8;
+
+// Only available in kernels built with the `debug` feature.
+pub const SYS_DEBUG_PUT_CHAR: isize = -9;
+pub const SYS_DEBUG_DUMP_SCHEDULER: isize = -10;
+pub const SYS_DEBUG_HALT: isize = -11;
+pub const SYS_DEBUG_CAP_IDENTIFY: isize = -12;
+pub const SYS_DEBUG_SNAPSHOT: isize = -13;
+pub const SYS_DEBUG_NAME_THREAD: isize = -14;
+
+// Only available in kernels built with the `benchmark` feature.
+pub const SYS_BENCHMARK_RESET_LOG: isize = -18;
+pub const SYS_BENCHMARK_FINALIZE_LOG: isize = -19;
+pub const SYS_BENCHMARK_SET_LOG_BUFFER: isize = -20;
+pub const SYS_BENCHMARK_GET_THREAD_UTILISATION: isize = -22;
+pub const SYS_BENCHMARK_RESET_THREAD_UTILISATION: isize = -23;
+
+/// The register state passed to and returned from a syscall.
+struct Regs {
+    /// RDI. The destination cap on entry and the badge on return.
+    cap: Word,
+    /// RSI.
+    info: Word,
+    mrs: [Word; FAST_MESSAGE_REGISTERS],
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+fn x64_syscall(sys: isize, mut regs: Regs) -> Regs {
+    // SAFETY: The kernel only modifies the registers listed here, and doesn't touch our stack.
+    // Memory may be modified through the IPC buffer.
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            inout("rdx") sys => _,
+            inout("rdi") regs.cap,
+            inout("rsi") regs.info,
+            inout("r10") regs.mrs[0],
+            inout("r8") regs.mrs[1],
+            inout("r9") regs.mrs[2],
+            inout("r15") regs.mrs[3],
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack),
+        );
+    }
+    regs
+}
+
+/// Load the fast message registers from the IPC buffer.
+fn load_mrs(length: Word) -> [Word; FAST_MESSAGE_REGISTERS] {
+    let mut mrs = [0; FAST_MESSAGE_REGISTERS];
+    for (i, mr) in mrs.iter_mut().enumerate().take(length) {
+        *mr = get_mr(i);
+    }
+    mrs
+}
+
+/// Store the fast message registers into the IPC buffer.
+fn store_mrs(regs: &Regs) {
+    let length = MessageInfo(regs.info).length();
+    for (i, mr) in regs.mrs.iter().enumerate().take(length) {
+        set_mr(i, *mr);
+    }
+}
+
+fn send_recv(sys: isize, cap: CPtr, info: MessageInfo) -> (MessageInfo, Word) {
+    let regs = x64_syscall(sys, Regs { cap, info: info.0, mrs: load_mrs(info.length()) });
+    store_mrs(&regs);
+    (MessageInfo(regs.info), regs.cap)
+}
+
+fn send_only(sys: isize, cap: CPtr, info: MessageInfo) {
+    x64_syscall(sys, Regs { cap, info: info.0, mrs: load_mrs(info.length()) });
+}
+
+fn recv_only(sys: isize, cap: CPtr) -> (MessageInfo, Word) {
+    let regs = x64_syscall(sys, Regs { cap, info: 0, mrs: [0; _] });
+    store_mrs(&regs);
+    (MessageInfo(regs.info), regs.cap)
+}
+
+/// Send a message through an endpoint, blocking until it's delivered. (seL4_Send)
+pub fn send(dest: CPtr, info: MessageInfo) {
+    send_only(SYS_SEND, dest, info);
+}
+
+/// Send a message if there's a receiver waiting. Otherwise the message is dropped. (seL4_NBSend)
+pub fn nb_send(dest: CPtr, info: MessageInfo) {
+    send_only(SYS_NB_SEND, dest, info);
+}
+
+/// Block until a message arrives. Returns the message info and the sender's badge. (seL4_Recv)
+pub fn recv(src: CPtr) -> (MessageInfo, Word) {
+    recv_only(SYS_RECV, src)
+}
+
+/// Receive a message if one is waiting. (seL4_NBRecv)
+pub fn nb_recv(src: CPtr) -> (MessageInfo, Word) {
+    recv_only(SYS_NB_RECV, src)
+}
+
+/// Send a message and wait for the reply. This is also used to invoke kernel objects.
+/// (seL4_Call)
+pub fn call(dest: CPtr, info: MessageInfo) -> MessageInfo {
+    send_recv(SYS_CALL, dest, info).0
+}
+
+/// Reply to the last thread which called us. (seL4_Reply)
+pub fn reply(info: MessageInfo) {
+    send_only(SYS_REPLY, 0, info);
+}
+
+/// Reply to the last caller, then wait for the next message. This is the usual way a server
+/// loops. (seL4_ReplyRecv)
+pub fn reply_recv(src: CPtr, info: MessageInfo) -> (MessageInfo, Word) {
+    send_recv(SYS_REPLY_RECV, src, info)
+}
+
+/// Give up the rest of our timeslice. (seL4_Yield)
+pub fn yield_now() {
+    x64_syscall(SYS_YIELD, Regs { cap: 0, info: 0, mrs: [0; _] });
+}
+
+/// Signal a notification. (seL4_Signal)
+pub fn signal(dest: CPtr) {
+    send_only(SYS_SEND, dest, MessageInfo::new(0, 0, 0, 0));
+}
+
+/// Block until a notification is signalled. Returns the notification word. (seL4_Wait)
+pub fn wait(src: CPtr) -> Word {
+    recv(src).1
+}
+
+/// Poll a notification without blocking. Returns the notification word. (seL4_Poll)
+pub fn poll(src: CPtr) -> Word {
+    nb_recv(src).1
+}
+
+/// Print a character to the kernel's debug console. (seL4_DebugPutChar)
+pub fn debug_put_char(c: u8) {
+    x64_syscall(SYS_DEBUG_PUT_CHAR, Regs { cap: c as Word, info: 0, mrs: [0; _] });
+}
+
+/// Print a string to the kernel's debug console, one syscall per character.
+pub fn debug_put_str(s: &str) {
+    for c in s.bytes() {
+        debug_put_char(c);
+    }
+}
+
+/// Dump the scheduler's state to the debug console. (seL4_DebugDumpScheduler)
+pub fn debug_dump_scheduler() {
+    x64_syscall(SYS_DEBUG_DUMP_SCHEDULER, Regs { cap: 0, info: 0, mrs: [0; _] });
+}
+
+/// Halt the system. (seL4_DebugHalt)
+pub fn debug_halt() -> ! {
+    x64_syscall(SYS_DEBUG_HALT, Regs { cap: 0, info: 0, mrs: [0; _] });
+    unreachable!("DebugHalt returned");
+}
+
+/// Return the type of the cap at `cap`, or 0 for an empty slot. (seL4_DebugCapIdentify)
+pub fn debug_cap_identify(cap: CPtr) -> Word {
+    x64_syscall(SYS_DEBUG_CAP_IDENTIFY, Regs { cap, info: 0, mrs: [0; _] }).cap
+}
+
+/// Ask the kernel to take a snapshot of the system. (seL4_DebugSnapshot)
+pub fn debug_snapshot() {
+    x64_syscall(SYS_DEBUG_SNAPSHOT, Regs { cap: 0, info: 0, mrs: [0; _] });
+}
+
+/// Name a thread, for debugging. The name is passed through the IPC buffer, and is truncated if
+/// it doesn't fit. (seL4_DebugNameThread)
+pub fn debug_name_thread(tcb: CPtr, name: &str) {
+    crate::ipc_buffer::with_ipc_buffer(|buf| {
+        // View the message registers as bytes, leaving room for a nul terminator.
+        let dest = unsafe {
+            core::slice::from_raw_parts_mut(buf.msg.as_mut_ptr() as *mut u8, size_of_val(&buf.msg))
+        };
+        let len = name.len().min(dest.len() - 1);
+        dest[..len].copy_from_slice(&name.as_bytes()[..len]);
+        dest[len] = 0;
+    });
+    x64_syscall(SYS_DEBUG_NAME_THREAD, Regs { cap: tcb, info: 0, mrs: [0; _] });
+}
+
+/// Reset the kernel's benchmark log. (seL4_BenchmarkResetLog)
+pub fn benchmark_reset_log() -> Result<(), crate::Error> {
+    let regs = x64_syscall(SYS_BENCHMARK_RESET_LOG, Regs { cap: 0, info: 0, mrs: [0; _] });
+    crate::Error::result_from_word(regs.cap)
+}
+
+/// Stop logging. Returns the index of the last log entry. (seL4_BenchmarkFinalizeLog)
+pub fn benchmark_finalize_log() -> Word {
+    x64_syscall(SYS_BENCHMARK_FINALIZE_LOG, Regs { cap: 0, info: 0, mrs: [0; _] }).cap
+}
+
+/// Use the given large frame as the kernel's benchmark log buffer. (seL4_BenchmarkSetLogBuffer)
+pub fn benchmark_set_log_buffer(frame_cap: CPtr) -> Result<(), crate::Error> {
+    let regs = x64_syscall(SYS_BENCHMARK_SET_LOG_BUFFER, Regs { cap: frame_cap, info: 0, mrs: [0; _] });
+    crate::Error::result_from_word(regs.cap)
+}
+
+/// Write a thread's utilisation counters into the IPC buffer. (seL4_BenchmarkGetThreadUtilisation)
+pub fn benchmark_get_thread_utilisation(tcb: CPtr) {
+    x64_syscall(SYS_BENCHMARK_GET_THREAD_UTILISATION, Regs { cap: tcb, info: 0, mrs: [0; _] });
+}
+
+/// Reset a thread's utilisation counters. (seL4_BenchmarkResetThreadUtilisation)
+pub fn benchmark_reset_thread_utilisation(tcb: CPtr) {
+    x64_syscall(SYS_BENCHMARK_RESET_THREAD_UTILISATION, Regs { cap: tcb, info: 0, mrs: [0; _] });
+}
@@ -0,0 +1,310 @@
+//! The compiled form of a static system description.
+//!
+//! Systems are described declaratively: a set of named kernel objects, the caps in each CNode, the
+//! frames and paging structures mapped into each VSpace, IRQ handlers, and how each TCB is
+//! configured. The `sysdesc` host tool (tools/sysdesc) compiles a TOML description into this format.
+//! The `sysdesc-loader` root task reads it and creates the system at boot.
+//!
+//! DEPARTURE: This borrows its model of objects and caps from SeL4's capDL, but it isn't capDL.
+//! There's no parser for capDL's spec language, so capDL specs have to be rewritten in TOML.
+//!
+//! The format is a header, followed by tables of fixed size records, followed by the data used to
+//! fill frames. Everything is made of little endian u64 words. Objects are referred to by their
+//! index in the object table.
+//!
+//! The loader processes the tables in order, so the compiler is responsible for ordering them
+//! sensibly. In particular, paging structures must be mapped before anything beneath them.
+
+use crate::invocation::ObjectType;
+
+/// "SYSDESC1", read as a little endian u64.
+pub const SPEC_MAGIC: u64 = u64::from_le_bytes(*b"SYSDESC1");
+
+/// Used for optional object references and addresses.
+pub const NONE: u64 = u64::MAX;
+
+/// Object kinds, other than the ObjectType values which can be created by retyping.
+pub const KIND_IRQ: u64 = 0x100;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SpecError {
+    BadMagic,
+    Truncated,
+    /// A record refers to an object which doesn't exist, or has a bad value.
+    BadRecord,
+}
+
+/// A fixed size record in one of the spec's tables.
+pub trait Record: Sized {
+    const WORDS: usize;
+    fn to_words(&self, out: &mut [u64]);
+    fn from_words(words: &[u64]) -> Self;
+}
+
+macro_rules! record {
+    ($(#[$attr:meta])* $name:ident { $($(#[$fattr:meta])* $field:ident),* $(,)? }) => {
+        $(#[$attr])*
+        #[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+        pub struct $name {
+            $($(#[$fattr])* pub $field: u64,)*
+        }
+
+        impl Record for $name {
+            const WORDS: usize = [$(stringify!($field)),*].len();
+
+            fn to_words(&self, out: &mut [u64]) {
+                let words = [$(self.$field),*];
+                out[..Self::WORDS].copy_from_slice(&words);
+            }
+
+            fn from_words(words: &[u64]) -> Self {
+                let mut words = words.iter().copied();
+                Self { $($field: words.next().unwrap(),)* }
+            }
+        }
+    };
+}
+
+record!(
+    /// An object to create.
+    ObjectRecord {
+        /// An ObjectType, or KIND_IRQ.
+        kind,
+        /// The size_bits passed to Untyped_Retype. For IRQs, the IRQ number.
+        size_bits,
+        /// For frames, the physical address to create the frame at (which must be in a device
+        /// untyped). Otherwise NONE.
+        paddr,
+        /// For frames, the range of the data section to copy into the frame.
+        fill_start,
+        fill_len,
+    }
+);
+
+record!(
+    /// A cap to place in a CNode.
+    CapRecord {
+        cnode,
+        slot,
+        object,
+        /// A CapRights value.
+        rights,
+        /// The badge for endpoints and notifications, or the guard (as CNode cap data) for CNodes.
+        badge,
+    }
+);
+
+record!(
+    /// A paging structure or frame to map into a VSpace.
+    MappingRecord {
+        vspace,
+        vaddr,
+        object,
+        /// CapRights. Only used for frames.
+        rights,
+        /// A VmAttributes value.
+        attr,
+    }
+);
+
+record!(
+    /// Bind an IRQ to a notification.
+    IrqRecord {
+        irq,
+        notification,
+        badge,
+    }
+);
+
+record!(
+    /// The configuration of a TCB.
+    TcbRecord {
+        tcb,
+        cspace,
+        /// The guard of the CSpace root, as CNode cap data.
+        cspace_data,
+        vspace,
+        /// A frame, or NONE.
+        ipc_buffer_frame,
+        ipc_buffer_addr,
+        /// A CPtr in the TCB's own CSpace, or 0 for none.
+        fault_ep,
+        priority,
+        max_priority,
+        domain,
+        ip,
+        sp,
+        /// Passed in RDI, RSI, RDX and RCX.
+        arg0,
+        arg1,
+        arg2,
+        arg3,
+        /// If non zero, the thread is started once the system is loaded.
+        resume,
+    }
+);
+
+/// The counts of each table, at the start of the spec.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SpecHeader {
+    pub num_objects: usize,
+    pub num_caps: usize,
+    pub num_mappings: usize,
+    pub num_irqs: usize,
+    pub num_tcbs: usize,
+    /// The length of the data section, in bytes.
+    pub data_len: usize,
+}
+
+impl SpecHeader {
+    pub const WORDS: usize = 7;
+
+    pub fn to_words(&self) -> [u64; Self::WORDS] {
+        [SPEC_MAGIC, self.num_objects as u64, self.num_caps as u64, self.num_mappings as u64,
+         self.num_irqs as u64, self.num_tcbs as u64, self.data_len as u64]
+    }
+
+    /// The total size of the tables, in words.
+    fn table_words(&self) -> Option<usize> {
+        let mut words = 0usize;
+        for (count, size) in [
+            (self.num_objects, ObjectRecord::WORDS),
+            (self.num_caps, CapRecord::WORDS),
+            (self.num_mappings, MappingRecord::WORDS),
+            (self.num_irqs, IrqRecord::WORDS),
+            (self.num_tcbs, TcbRecord::WORDS),
+        ] {
+            words = words.checked_add(count.checked_mul(size)?)?;
+        }
+        Some(words)
+    }
+}
+
+/// A table of records in a spec.
+#[derive(Copy, Clone)]
+pub struct Table<'a, R> {
+    bytes: &'a [u8],
+    _record: core::marker::PhantomData<R>,
+}
+
+impl<'a, R: Record> Table<'a, R> {
+    pub fn len(&self) -> usize {
+        self.bytes.len() / (R::WORDS * 8)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> R {
+        // The largest record is a TcbRecord.
+        let mut words = [0u64; TcbRecord::WORDS];
+        let start = index * R::WORDS * 8;
+        for (i, word) in words.iter_mut().enumerate().take(R::WORDS) {
+            *word = read_u64(self.bytes, start + i * 8);
+        }
+        R::from_words(&words)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = R> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// A compiled spec, borrowed from its raw bytes.
+pub struct Spec<'a> {
+    pub objects: Table<'a, ObjectRecord>,
+    pub caps: Table<'a, CapRecord>,
+    pub mappings: Table<'a, MappingRecord>,
+    pub irqs: Table<'a, IrqRecord>,
+    pub tcbs: Table<'a, TcbRecord>,
+    pub data: &'a [u8],
+}
+
+impl<'a> Spec<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, SpecError> {
+        if bytes.len() < SpecHeader::WORDS * 8 {
+            return Err(SpecError::Truncated);
+        }
+        if read_u64(bytes, 0) != SPEC_MAGIC {
+            return Err(SpecError::BadMagic);
+        }
+
+        let count = |i: usize| read_u64(bytes, i * 8) as usize;
+        let header = SpecHeader {
+            num_objects: count(1),
+            num_caps: count(2),
+            num_mappings: count(3),
+            num_irqs: count(4),
+            num_tcbs: count(5),
+            data_len: count(6),
+        };
+
+        let total = header.table_words()
+            .and_then(|words| words.checked_mul(8))
+            .and_then(|len| len.checked_add(SpecHeader::WORDS * 8))
+            .and_then(|len| len.checked_add(header.data_len))
+            .ok_or(SpecError::Truncated)?;
+        if bytes.len() < total {
+            return Err(SpecError::Truncated);
+        }
+
+        let mut rest = &bytes[SpecHeader::WORDS * 8..];
+        let mut take = |len: usize| {
+            let (table, tail) = rest.split_at(len);
+            rest = tail;
+            table
+        };
+
+        let spec = Self {
+            objects: Table { bytes: take(header.num_objects * ObjectRecord::WORDS * 8), _record: Default::default() },
+            caps: Table { bytes: take(header.num_caps * CapRecord::WORDS * 8), _record: Default::default() },
+            mappings: Table { bytes: take(header.num_mappings * MappingRecord::WORDS * 8), _record: Default::default() },
+            irqs: Table { bytes: take(header.num_irqs * IrqRecord::WORDS * 8), _record: Default::default() },
+            tcbs: Table { bytes: take(header.num_tcbs * TcbRecord::WORDS * 8), _record: Default::default() },
+            data: take(header.data_len),
+        };
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    /// Check every object reference is in range, so the loader doesn't have to.
+    fn validate(&self) -> Result<(), SpecError> {
+        let n = self.objects.len() as u64;
+        let object = |i: u64| if i < n { Ok(()) } else { Err(SpecError::BadRecord) };
+        let optional = |i: u64| if i == NONE { Ok(()) } else { object(i) };
+
+        for o in self.objects.iter() {
+            if o.kind != KIND_IRQ && ObjectType::from_word(o.kind as usize).is_none() {
+                return Err(SpecError::BadRecord);
+            }
+            let fill_end = o.fill_start.checked_add(o.fill_len).ok_or(SpecError::BadRecord)?;
+            if fill_end > self.data.len() as u64 {
+                return Err(SpecError::BadRecord);
+            }
+        }
+        for c in self.caps.iter() {
+            object(c.cnode)?;
+            object(c.object)?;
+        }
+        for m in self.mappings.iter() {
+            object(m.vspace)?;
+            object(m.object)?;
+        }
+        for i in self.irqs.iter() {
+            object(i.irq)?;
+            object(i.notification)?;
+        }
+        for t in self.tcbs.iter() {
+            object(t.tcb)?;
+            object(t.cspace)?;
+            object(t.vspace)?;
+            optional(t.ipc_buffer_frame)?;
+        }
+        Ok(())
+    }
+}
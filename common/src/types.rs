@@ -0,0 +1,91 @@
+//! Basic types, and the bitfields in libsel4's shared_types.bf.
+
+use crate::constants::{GUARD_SIZE_BITS, MSG_EXTRA_CAP_BITS, MSG_LENGTH_BITS};
+
+/// seL4_Word.
+pub type Word = usize;
+/// A capability pointer. (seL4_CPtr)
+pub type CPtr = usize;
+
+/// The message info word passed with every IPC. This is seL4_MessageInfo_t, which is a bitfield
+/// laid out like this:
+///
+/// | label (52 bits) | caps unwrapped (3 bits) | extra caps (2 bits) | length (7 bits) |
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct MessageInfo(pub Word);
+
+const LENGTH_MASK: Word = (1 << MSG_LENGTH_BITS) - 1;
+const EXTRA_CAPS_MASK: Word = (1 << MSG_EXTRA_CAP_BITS) - 1;
+const CAPS_UNWRAPPED_SHIFT: u32 = MSG_LENGTH_BITS + MSG_EXTRA_CAP_BITS;
+const LABEL_SHIFT: u32 = CAPS_UNWRAPPED_SHIFT + 3;
+
+impl MessageInfo {
+    pub const fn new(label: Word, caps_unwrapped: Word, extra_caps: Word, length: Word) -> Self {
+        Self((label << LABEL_SHIFT)
+            | ((caps_unwrapped & 0x7) << CAPS_UNWRAPPED_SHIFT)
+            | ((extra_caps & EXTRA_CAPS_MASK) << MSG_LENGTH_BITS)
+            | (length & LENGTH_MASK))
+    }
+
+    pub const fn label(self) -> Word {
+        self.0 >> LABEL_SHIFT
+    }
+
+    pub const fn caps_unwrapped(self) -> Word {
+        (self.0 >> CAPS_UNWRAPPED_SHIFT) & 0x7
+    }
+
+    pub const fn extra_caps(self) -> Word {
+        (self.0 >> MSG_LENGTH_BITS) & EXTRA_CAPS_MASK
+    }
+
+    pub const fn length(self) -> Word {
+        self.0 & LENGTH_MASK
+    }
+}
+
+/// The access rights of a capability. This is seL4_CapRights_t.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct CapRights(pub Word);
+
+impl CapRights {
+    pub const ALL: CapRights = CapRights::new(true, true, true, true);
+    pub const NONE: CapRights = CapRights::new(false, false, false, false);
+    pub const READ_WRITE: CapRights = CapRights::new(false, false, true, true);
+    pub const READ: CapRights = CapRights::new(false, false, true, false);
+    pub const WRITE: CapRights = CapRights::new(false, false, false, true);
+
+    pub const fn new(grant_reply: bool, grant: bool, read: bool, write: bool) -> Self {
+        Self(((grant_reply as Word) << 3)
+            | ((grant as Word) << 2)
+            | ((read as Word) << 1)
+            | (write as Word))
+    }
+
+    pub const fn allow_grant_reply(self) -> bool {
+        self.0 & (1 << 3) != 0
+    }
+
+    pub const fn allow_grant(self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    pub const fn allow_read(self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+
+    pub const fn allow_write(self) -> bool {
+        self.0 & 1 != 0
+    }
+}
+
+/// The data word used to set a CNode cap's guard, when it's installed as a thread's CSpace root.
+/// This is seL4_CNode_CapData_new.
+///
+/// A CNode with 2^n slots used as the only level of a CSpace needs a guard of size 64 - n, so
+/// CPtrs resolve all 64 bits.
+pub const fn cnode_cap_data(guard: Word, guard_size: u32) -> Word {
+    (guard << GUARD_SIZE_BITS) | (guard_size as Word & ((1 << GUARD_SIZE_BITS) - 1))
+}
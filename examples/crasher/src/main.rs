@@ -0,0 +1,31 @@
+//! A child component for the supervisor example. It prints a message, then crashes by reading an
+//! unmapped address. The supervisor gets the fault and restarts it.
+//!
+//! Children aren't root tasks, so they don't use the runtime crate. The supervisor starts them
+//! with a stack already set up and the address of their IPC buffer in RDI.
+
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use common::ipc_buffer::{set_ipc_buffer, IpcBuffer};
+use common::syscalls::{debug_halt, debug_put_str};
+
+#[unsafe(no_mangle)]
+extern "C" fn _start(ipc_buffer: *mut IpcBuffer) -> ! {
+    unsafe { set_ipc_buffer(ipc_buffer) };
+
+    debug_put_str("crasher: Hello from a child component. Crashing now.\n");
+
+    // Nothing is mapped in the first page. (Reading address 0 would trip Rust's null pointer check
+    // and panic instead.)
+    // SAFETY: It isn't. That's the point.
+    unsafe { (0x10 as *const usize).read_volatile() };
+    unreachable!()
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    debug_put_str("crasher: panicked\n");
+    debug_halt()
+}
@@ -0,0 +1,21 @@
+//! Hello world, as a root task. run_debug.sh boots the kernel with this.
+
+#![no_std]
+#![no_main]
+
+use runtime::common::invocation::ObjectType;
+use runtime::alloc::ObjectAllocator;
+use runtime::{println, BootInfo};
+
+runtime::entry!(main);
+
+fn main(bootinfo: &'static BootInfo) {
+    println!("Hello, world!");
+    println!("{} empty slots, {} untypeds", bootinfo.empty.len(), bootinfo.untyped.len());
+
+    let mut allocator = ObjectAllocator::new(bootinfo);
+    match allocator.alloc(ObjectType::Endpoint, 0) {
+        Ok(slot) => println!("Created an endpoint in slot {}", slot),
+        Err(err) => println!("Could not create an endpoint: {:?}", err),
+    }
+}
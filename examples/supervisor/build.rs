@@ -0,0 +1,49 @@
+//! Embed the child components' ELF files, and pick the restart policy.
+//!
+//! Children are listed in the SUPERVISOR_CHILDREN environment variable, as comma separated
+//! name=path pairs. For example:
+//!
+//! ```sh
+//! cargo build -p crasher
+//! SUPERVISOR_CHILDREN=crasher=target/x86_64-unknown-none/debug/crasher cargo build -p supervisor
+//! ```
+//!
+//! Relative paths are relative to the workspace root.
+//!
+//! SUPERVISOR_POLICY sets the restart policy. It can be one_for_one (the default) or one_for_all.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=SUPERVISOR_CHILDREN");
+    println!("cargo:rerun-if-env-changed=SUPERVISOR_POLICY");
+
+    let workspace_root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("../..");
+    let children = env::var("SUPERVISOR_CHILDREN").unwrap_or_default();
+
+    let mut out = String::from("pub(crate) static CHILDREN: &[(&str, &[u8])] = &[\n");
+    for entry in children.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (name, path) = entry.split_once('=')
+            .unwrap_or_else(|| panic!("SUPERVISOR_CHILDREN entry {entry:?} should be name=path"));
+        let path = workspace_root.join(path);
+        let path = path.canonicalize()
+            .unwrap_or_else(|e| panic!("Child ELF {}: {e}", path.display()));
+
+        println!("cargo:rerun-if-changed={}", path.display());
+        writeln!(out, "    ({name:?}, include_bytes!({:?})),", path.display().to_string()).unwrap();
+    }
+    out.push_str("];\n");
+
+    let policy = match env::var("SUPERVISOR_POLICY").as_deref() {
+        Err(_) | Ok("one_for_one") => "OneForOne",
+        Ok("one_for_all") => "OneForAll",
+        Ok(other) => panic!("Unknown SUPERVISOR_POLICY {other:?}. Expected one_for_one or one_for_all"),
+    };
+    writeln!(out, "pub(crate) const POLICY: RestartPolicy = RestartPolicy::{policy};").unwrap();
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("config.rs");
+    fs::write(out_path, out).unwrap();
+}
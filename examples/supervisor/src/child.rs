@@ -0,0 +1,311 @@
+//! Child components. Each child runs in its own CSpace and VSpace, built from a dedicated untyped.
+//! Stopping a child revokes that untyped, which destroys every object the child was using.
+
+use runtime::alloc::{AllocError, ObjectAllocator};
+use runtime::common::bootinfo::{CAP_INIT_THREAD_ASID_POOL, CAP_INIT_THREAD_CNODE, CAP_INIT_THREAD_TCB,
+                                CAP_INIT_THREAD_VSPACE};
+use runtime::common::constants::{PAGE_BITS, WORD_BITS};
+use runtime::common::invocation::{ObjectType, VmAttributes};
+use runtime::common::objects::{AsidPool, CNode, Endpoint, Page, PageDirectory, PageTable, Pdpt, Pml4, Tcb,
+                               Untyped, UserContext};
+use runtime::common::types::cnode_cap_data;
+use runtime::common::elf::Elf;
+use runtime::common::{CPtr, CapRights, Error, Word};
+
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+
+/// Each child gets 4 MiB of memory, for its kernel objects and its image.
+const CHILD_UNTYPED_BITS: u32 = 22;
+/// The number of slots in the supervisor's CNode used to hold caps to each child's objects.
+const CHILD_SLOTS: usize = 256;
+/// Each child's CNode has 2^CNODE_BITS slots.
+const CNODE_BITS: u32 = 8;
+
+// The layout of a child's CSpace.
+const FAULT_EP_SLOT: CPtr = 1;
+
+// The layout of a child's VSpace. The image is loaded wherever the ELF file says, which is usually
+// around 2 MiB.
+const STACK_TOP: usize = 0x1000_0000;
+const STACK_PAGES: usize = 4;
+const IPC_BUFFER_VADDR: usize = 0x1000_1000;
+
+const CHILD_PRIORITY: Word = 100;
+
+/// Where the supervisor maps a child's frames while it fills them in.
+const SCRATCH_VADDR: usize = 0x80_0000_0000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum SpawnError {
+    BadElf,
+    Alloc(AllocError),
+    Kernel(Error),
+}
+
+impl From<AllocError> for SpawnError {
+    fn from(err: AllocError) -> Self {
+        SpawnError::Alloc(err)
+    }
+}
+
+impl From<Error> for SpawnError {
+    fn from(err: Error) -> Self {
+        SpawnError::Kernel(err)
+    }
+}
+
+/// Map paging structures in the supervisor's own VSpace, so frames can be mapped at
+/// SCRATCH_VADDR. This must be called once, before any children are started.
+pub(crate) fn init_scratch(allocator: &mut ObjectAllocator) -> Result<(), SpawnError> {
+    let vspace = Pml4(CAP_INIT_THREAD_VSPACE);
+    let attr = VmAttributes::DEFAULT;
+
+    // SCRATCH_VADDR is in the second 512 GiB (PML4 index 1), away from our own image, so the PDPT
+    // is normally new. But something else could have put one there, and we can't tell until we
+    // try, so in that case we waste a page.
+    let pdpt = Pdpt(allocator.alloc(ObjectType::X86PDPT, 0)?);
+    match pdpt.map(vspace, SCRATCH_VADDR, attr) {
+        Ok(()) | Err(Error::DeleteFirst) => {}
+        Err(err) => return Err(err.into()),
+    }
+    PageDirectory(allocator.alloc(ObjectType::X86PageDirectory, 0)?).map(vspace, SCRATCH_VADDR, attr)?;
+    PageTable(allocator.alloc(ObjectType::X86PageTable, 0)?).map(vspace, SCRATCH_VADDR, attr)?;
+    Ok(())
+}
+
+/// Allocates a child's objects from its untyped, with caps in its range of the supervisor's
+/// CNode.
+struct Pool {
+    untyped: CPtr,
+    used: usize,
+    first_slot: CPtr,
+    next_slot: CPtr,
+}
+
+impl Pool {
+    fn reset(&mut self) {
+        self.used = 0;
+        self.next_slot = self.first_slot;
+    }
+
+    fn alloc(&mut self, object_type: ObjectType, size_bits: u32) -> Result<CPtr, SpawnError> {
+        let object_size = 1usize << object_type.size_bits(size_bits);
+        let offset = self.used.next_multiple_of(object_size);
+        if offset + object_size > 1 << CHILD_UNTYPED_BITS {
+            return Err(AllocError::OutOfMemory.into());
+        }
+        if self.next_slot >= self.first_slot + CHILD_SLOTS {
+            return Err(AllocError::OutOfSlots.into());
+        }
+
+        let slot = self.next_slot;
+        Untyped(self.untyped).retype(object_type, size_bits as usize, CNode(CAP_INIT_THREAD_CNODE), 0, 0, slot, 1)?;
+        self.next_slot += 1;
+        self.used = offset + object_size;
+        Ok(slot)
+    }
+}
+
+/// The paging structures we've mapped into a child's VSpace, as (level shift, vaddr >> shift).
+struct MappedTables {
+    tables: [(u32, usize); 32],
+    len: usize,
+}
+
+impl MappedTables {
+    fn contains(&self, table: (u32, usize)) -> bool {
+        self.tables[..self.len].contains(&table)
+    }
+
+    fn push(&mut self, table: (u32, usize)) -> Result<(), SpawnError> {
+        if self.len == self.tables.len() {
+            return Err(AllocError::OutOfMemory.into());
+        }
+        self.tables[self.len] = table;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// The frames holding a child's image, as (vaddr, frame, writable). Segments can share a page, so
+/// every page is filled in before any are mapped into the child.
+struct ImagePages {
+    pages: [(usize, Page, bool); 128],
+    len: usize,
+}
+
+impl ImagePages {
+    fn find(&mut self, vaddr: usize) -> Option<&mut (usize, Page, bool)> {
+        self.pages[..self.len].iter_mut().find(|(v, _, _)| *v == vaddr)
+    }
+
+    fn push(&mut self, page: (usize, Page, bool)) -> Result<(), SpawnError> {
+        if self.len == self.pages.len() {
+            return Err(AllocError::OutOfMemory.into());
+        }
+        self.pages[self.len] = page;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+pub(crate) struct Child {
+    pub name: &'static str,
+    elf: &'static [u8],
+    /// Faults from this child arrive with this badge.
+    pub badge: Word,
+    pool: Pool,
+    pml4: Pml4,
+    tables: MappedTables,
+    image: ImagePages,
+}
+
+impl Child {
+    /// Set aside memory and slots for a child. It isn't started until [Child::start] is called.
+    pub fn new(allocator: &mut ObjectAllocator, name: &'static str, elf: &'static [u8],
+               badge: Word) -> Result<Self, SpawnError> {
+        let untyped = allocator.alloc(ObjectType::Untyped, CHILD_UNTYPED_BITS)?;
+        let first_slot = allocator.alloc_slots(CHILD_SLOTS)?;
+        Ok(Self {
+            name,
+            elf,
+            badge,
+            pool: Pool { untyped, used: 0, first_slot, next_slot: first_slot },
+            pml4: Pml4(0),
+            tables: MappedTables { tables: [(0, 0); _], len: 0 },
+            image: ImagePages { pages: [(0, Page(0), false); _], len: 0 },
+        })
+    }
+
+    /// Build the child's CSpace and VSpace, load its image and start its thread.
+    pub fn start(&mut self, fault_ep: Endpoint) -> Result<(), SpawnError> {
+        self.pool.reset();
+        self.tables.len = 0;
+        self.image.len = 0;
+
+        let elf = Elf::parse(self.elf).ok_or(SpawnError::BadElf)?;
+
+        let cnode = CNode(self.pool.alloc(ObjectType::CapTable, CNODE_BITS)?);
+        self.pml4 = Pml4(self.pool.alloc(ObjectType::X64PML4, 0)?);
+        AsidPool(CAP_INIT_THREAD_ASID_POOL).assign(self.pml4)?;
+
+        for segment in elf.segments() {
+            let segment = segment.ok_or(SpawnError::BadElf)?;
+            let start = segment.vaddr & !(PAGE_SIZE - 1);
+            let end = segment.vaddr + segment.mem_size;
+
+            for page_vaddr in (start..end).step_by(PAGE_SIZE) {
+                let frame = self.image_page(page_vaddr, segment.writable)?;
+                fill_frame(frame, |page| {
+                    // Copy the part of the segment's data which lands in this page.
+                    let data_start = segment.vaddr.max(page_vaddr);
+                    let data_end = (segment.vaddr + segment.data.len()).min(page_vaddr + PAGE_SIZE);
+                    if data_start < data_end {
+                        page[data_start - page_vaddr..data_end - page_vaddr]
+                            .copy_from_slice(&segment.data[data_start - segment.vaddr..data_end - segment.vaddr]);
+                    }
+                })?;
+            }
+        }
+
+        for i in 0..self.image.len {
+            let (vaddr, frame, writable) = self.image.pages[i];
+            let rights = if writable { CapRights::READ_WRITE } else { CapRights::READ };
+            self.map_frame(frame, vaddr, rights)?;
+        }
+
+        for i in 1..=STACK_PAGES {
+            self.map_page(STACK_TOP - i * PAGE_SIZE, CapRights::READ_WRITE)?;
+        }
+        let ipc_buffer = self.map_page(IPC_BUFFER_VADDR, CapRights::READ_WRITE)?;
+
+        // The child's fault endpoint is looked up in its own CSpace, so it needs a copy.
+        cnode.mint(FAULT_EP_SLOT, CNODE_BITS as u8, CNode(CAP_INIT_THREAD_CNODE), fault_ep.0,
+                   WORD_BITS as u8, CapRights::ALL, self.badge)?;
+
+        let tcb = Tcb(self.pool.alloc(ObjectType::TCB, 0)?);
+        tcb.configure(FAULT_EP_SLOT, cnode, cnode_cap_data(0, WORD_BITS - CNODE_BITS), self.pml4, 0,
+                      IPC_BUFFER_VADDR, ipc_buffer)?;
+        tcb.set_priority(Tcb(CAP_INIT_THREAD_TCB), CHILD_PRIORITY)?;
+
+        let regs = UserContext {
+            rip: elf.entry,
+            // _start is entered like a normal function, which expects a return address on the
+            // stack.
+            rsp: STACK_TOP - size_of::<usize>(),
+            rdi: IPC_BUFFER_VADDR,
+            ..Default::default()
+        };
+        tcb.write_registers(true, 0, UserContext::COUNT, &regs)?;
+        Ok(())
+    }
+
+    /// Destroy the child and all its objects. Its memory can be reused by calling
+    /// [Child::start] again.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        // Revoking the untyped deletes every cap derived from it, which destroys the child's TCB,
+        // CNode, VSpace and frames. The untyped's memory is reused from the start when we next
+        // retype it.
+        CNode(CAP_INIT_THREAD_CNODE).revoke(self.pool.untyped, WORD_BITS as u8)
+    }
+
+    /// The frame for the image page at `vaddr`, which is created the first time a segment covers
+    /// it. A page shared by a writable segment is mapped writable.
+    fn image_page(&mut self, vaddr: usize, writable: bool) -> Result<Page, SpawnError> {
+        if let Some(page) = self.image.find(vaddr) {
+            page.2 |= writable;
+            return Ok(page.1);
+        }
+        let frame = Page(self.pool.alloc(ObjectType::X86SmallPage, 0)?);
+        self.image.push((vaddr, frame, writable))?;
+        Ok(frame)
+    }
+
+    /// Create a zeroed frame, and map it into the child at `vaddr`.
+    fn map_page(&mut self, vaddr: usize, rights: CapRights) -> Result<Page, SpawnError> {
+        let frame = Page(self.pool.alloc(ObjectType::X86SmallPage, 0)?);
+        self.map_frame(frame, vaddr, rights)?;
+        Ok(frame)
+    }
+
+    fn map_frame(&mut self, frame: Page, vaddr: usize, rights: CapRights) -> Result<(), SpawnError> {
+        self.map_tables(vaddr)?;
+        frame.map(self.pml4, vaddr, rights, VmAttributes::DEFAULT)?;
+        Ok(())
+    }
+
+    /// Make sure the paging structures covering `vaddr` exist in the child's VSpace.
+    fn map_tables(&mut self, vaddr: usize) -> Result<(), SpawnError> {
+        const LEVELS: [(ObjectType, u32); 3] = [
+            (ObjectType::X86PDPT, 39),
+            (ObjectType::X86PageDirectory, 30),
+            (ObjectType::X86PageTable, 21),
+        ];
+
+        for (object_type, shift) in LEVELS {
+            let table = (shift, vaddr >> shift);
+            if self.tables.contains(table) {
+                continue;
+            }
+
+            let cap = self.pool.alloc(object_type, 0)?;
+            let attr = VmAttributes::DEFAULT;
+            match object_type {
+                ObjectType::X86PDPT => Pdpt(cap).map(self.pml4, vaddr, attr),
+                ObjectType::X86PageDirectory => PageDirectory(cap).map(self.pml4, vaddr, attr),
+                _ => PageTable(cap).map(self.pml4, vaddr, attr),
+            }?;
+            self.tables.push(table)?;
+        }
+        Ok(())
+    }
+}
+
+/// Map a frame at SCRATCH_VADDR in the supervisor's VSpace while `fill` writes to it.
+fn fill_frame(frame: Page, fill: impl FnOnce(&mut [u8; PAGE_SIZE])) -> Result<(), SpawnError> {
+    frame.map(Pml4(CAP_INIT_THREAD_VSPACE), SCRATCH_VADDR, CapRights::READ_WRITE, VmAttributes::DEFAULT)?;
+    // SAFETY: The frame is mapped at SCRATCH_VADDR, and nothing else uses that address.
+    fill(unsafe { &mut *(SCRATCH_VADDR as *mut [u8; PAGE_SIZE]) });
+    frame.unmap()?;
+    Ok(())
+}
@@ -0,0 +1,42 @@
+//! A root task which runs child components under a supervisor, restarting them when they fault.
+//!
+//! The children are ELF files embedded at build time, and the restart policy is also picked at
+//! build time. See build.rs.
+
+#![no_std]
+#![no_main]
+
+mod child;
+mod supervisor;
+
+use runtime::alloc::ObjectAllocator;
+use runtime::common::invocation::ObjectType;
+use runtime::common::objects::Endpoint;
+use runtime::{println, BootInfo};
+use crate::supervisor::{RestartPolicy, Supervisor};
+
+// CHILDREN and POLICY.
+include!(concat!(env!("OUT_DIR"), "/config.rs"));
+
+const MAX_RESTARTS: usize = 5;
+
+runtime::entry!(main);
+
+fn main(bootinfo: &'static BootInfo) {
+    if CHILDREN.is_empty() {
+        println!("supervisor: No children. Set SUPERVISOR_CHILDREN when building.");
+        return;
+    }
+
+    let mut allocator = ObjectAllocator::new(bootinfo);
+    let fault_ep = Endpoint(allocator.alloc(ObjectType::Endpoint, 0).expect("Could not create fault endpoint"));
+    child::init_scratch(&mut allocator).expect("Could not map scratch page");
+
+    let mut supervisor = Supervisor::new(POLICY, MAX_RESTARTS, fault_ep);
+    for (name, elf) in CHILDREN {
+        supervisor.add_child(&mut allocator, name, elf).expect("Could not allocate child");
+    }
+
+    supervisor.start_all();
+    supervisor.run();
+}
@@ -0,0 +1,142 @@
+//! An Erlang style supervisor.
+//!
+//! The supervisor is the fault handler for all of its children. When a child faults, it's
+//! restarted according to the restart policy. If children fault too often, the supervisor gives
+//! up: it stops every child and exits. (In Erlang the failure would be escalated to the
+//! supervisor's own supervisor. We're the root task, so there's nobody to escalate to.)
+
+use runtime::alloc::ObjectAllocator;
+use runtime::common::constants::{unknown_syscall, user_exception, vm_fault, FaultType};
+use runtime::common::ipc_buffer::get_mr;
+use runtime::common::objects::Endpoint;
+use runtime::common::syscalls::recv;
+use runtime::common::MessageInfo;
+use runtime::println;
+use crate::child::{Child, SpawnError};
+
+const MAX_CHILDREN: usize = 16;
+
+/// Picked at build time, so only one of these is ever constructed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[allow(dead_code)]
+pub(crate) enum RestartPolicy {
+    /// Only restart the child which faulted.
+    OneForOne,
+    /// Restart every child when any of them faults. This suits children which depend on each
+    /// other.
+    OneForAll,
+}
+
+pub(crate) struct Supervisor {
+    policy: RestartPolicy,
+    /// The total number of restarts allowed before we give up.
+    max_restarts: usize,
+    restarts: usize,
+    fault_ep: Endpoint,
+    children: [Option<Child>; MAX_CHILDREN],
+    num_children: usize,
+}
+
+impl Supervisor {
+    pub fn new(policy: RestartPolicy, max_restarts: usize, fault_ep: Endpoint) -> Self {
+        Self {
+            policy,
+            max_restarts,
+            restarts: 0,
+            fault_ep,
+            children: [const { None }; MAX_CHILDREN],
+            num_children: 0,
+        }
+    }
+
+    pub fn add_child(&mut self, allocator: &mut ObjectAllocator, name: &'static str,
+                     elf: &'static [u8]) -> Result<(), SpawnError> {
+        assert!(self.num_children < MAX_CHILDREN, "Too many children");
+        // Badge 0 means an unbadged message, so child i gets badge i + 1.
+        let badge = self.num_children + 1;
+        self.children[self.num_children] = Some(Child::new(allocator, name, elf, badge)?);
+        self.num_children += 1;
+        Ok(())
+    }
+
+    fn children(&mut self) -> impl Iterator<Item = &mut Child> {
+        self.children.iter_mut().flatten()
+    }
+
+    pub fn start_all(&mut self) {
+        let fault_ep = self.fault_ep;
+        for child in self.children() {
+            println!("supervisor: Starting {}", child.name);
+            if let Err(err) = child.start(fault_ep) {
+                println!("supervisor: Could not start {}: {:?}", child.name, err);
+            }
+        }
+    }
+
+    fn stop_all(&mut self) {
+        for child in self.children() {
+            if let Err(err) = child.stop() {
+                println!("supervisor: Could not stop {}: {:?}", child.name, err);
+            }
+        }
+    }
+
+    fn restart(child: &mut Child, fault_ep: Endpoint) {
+        println!("supervisor: Restarting {}", child.name);
+        let result = child.stop().map_err(SpawnError::Kernel).and_then(|()| child.start(fault_ep));
+        if let Err(err) = result {
+            println!("supervisor: Could not restart {}: {:?}", child.name, err);
+        }
+    }
+
+    /// Wait for faults and restart children. Returns if the restart limit is hit.
+    pub fn run(&mut self) {
+        loop {
+            let (info, badge) = recv(self.fault_ep.0);
+            let Some(index) = badge.checked_sub(1).filter(|&i| i < self.num_children) else {
+                println!("supervisor: Ignoring message with unknown badge {}", badge);
+                continue;
+            };
+
+            let fault_ep = self.fault_ep;
+            let faulted = self.children[index].as_mut().unwrap();
+            report_fault(faulted.name, info);
+
+            if self.restarts == self.max_restarts {
+                println!("supervisor: Too many restarts. Shutting down.");
+                self.stop_all();
+                return;
+            }
+            self.restarts += 1;
+
+            match self.policy {
+                RestartPolicy::OneForOne => Self::restart(faulted, fault_ep),
+                RestartPolicy::OneForAll => {
+                    for child in self.children() {
+                        Self::restart(child, fault_ep);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Print a fault message received from a child.
+fn report_fault(name: &str, info: MessageInfo) {
+    match info.label() {
+        l if l == FaultType::VMFault as usize => {
+            println!("supervisor: {} page faulted at {:#x} (ip {:#x})", name,
+                     get_mr(vm_fault::ADDR), get_mr(vm_fault::IP));
+        }
+        l if l == FaultType::UnknownSyscall as usize => {
+            println!("supervisor: {} made unknown syscall {} (ip {:#x})", name,
+                     get_mr(unknown_syscall::SYSCALL) as isize, get_mr(unknown_syscall::FAULT_IP));
+        }
+        l if l == FaultType::UserException as usize => {
+            println!("supervisor: {} raised exception {} (ip {:#x})", name,
+                     get_mr(user_exception::NUMBER), get_mr(user_exception::FAULT_IP));
+        }
+        l if l == FaultType::CapFault as usize => println!("supervisor: {} had a cap fault", name),
+        l => println!("supervisor: {} faulted (fault type {})", name, l),
+    }
+}
@@ -1,8 +1,214 @@
-  use std::path::PathBuf;
-
-  fn main() {
-      let script = PathBuf::from("linker.lds"); // adjust if in another dir
-      println!("cargo:rerun-if-changed={}", script.display());
-      // println!("cargo:rustc-link-arg=-T{}", script.display());
-      // println!("cargo:rustc-link-arg=-no-pie"); // if you need this too
-  }
+//! Kernel build configuration.
+//!
+//! The linker script is generated into OUT_DIR from linker.lds.in, with the memory layout constants
+//! taken from src/arch/x86_64/layout.rs. So the layout is only written down once.
+//!
+//! Setting ROOT_TASK_ELF embeds a root task in the kernel image, instead of loading it from the
+//! first multiboot module. This makes a single bootable file, which is handy for netboot and USB
+//! images. For example:
+//!
+//! ```sh
+//! cargo build -p hello
+//! ROOT_TASK_ELF=target/x86_64-unknown-none/debug/hello cargo build -p kernel
+//! ```
+//!
+//! Setting KERNEL_SYMBOLS embeds a symbol table (see common::ksyms) in the .ksyms section, so
+//! panics print symbolized backtraces. The table is generated from the kernel ELF itself, so
+//! `cargo xtask build` links the kernel twice: once to get the symbols, and again to embed them.
+//! The .ksyms section comes after all of the kernel's code, so embedding it doesn't move any
+//! functions.
+//!
+//! The kernel's configuration (src/config.rs) is read from kernel/kernel.toml, or the file named by
+//! KERNEL_CONFIG. Any option can be overridden with an environment variable named after its
+//! constant. For example:
+//!
+//! ```sh
+//! CONFIG_KERNEL_STACK_BITS=14 cargo build -p kernel
+//! ```
+//!
+//! Relative paths are relative to the workspace root.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The kernel's memory layout. This is shared with the kernel.
+#[path = "src/arch/x86_64/layout.rs"]
+#[allow(dead_code)]
+mod layout;
+
+/// The layout constants defined in the linker script, in the order they're written.
+const LINKER_CONSTANTS: &[(&str, usize)] = &[
+    ("USER_TOP", layout::USER_TOP),
+    ("PADDR_BASE", layout::PADDR_BASE),
+    ("PPTR_BASE", layout::PPTR_BASE),
+    ("PPTR_TOP", layout::PPTR_TOP),
+    ("KERNEL_ELF_PADDR_BASE", layout::KERNEL_ELF_PADDR_BASE),
+    ("KERNEL_ELF_BASE", layout::KERNEL_ELF_BASE),
+    ("KDEV_BASE", layout::KDEV_BASE),
+];
+
+/// The kernel configuration options. Each option is a key in kernel.toml, and CONFIG_<KEY> in the
+/// environment. The generated constants are checked further in src/config.rs.
+const CONFIG_OPTIONS: &[ConfigOption] = &[
+    ConfigOption { key: "max_num_nodes", ty: ConfigType::Int("usize"), default: max_num_nodes_default },
+    ConfigOption { key: "kernel_stack_bits", ty: ConfigType::Int("u32"), default: |_| "12" },
+    ConfigOption { key: "max_num_ioapic", ty: ConfigType::Int("usize"), default: |_| "1" },
+    ConfigOption { key: "max_num_drhu", ty: ConfigType::Int("usize"), default: |_| "8" },
+    ConfigOption { key: "max_num_freemem_reg", ty: ConfigType::Int("usize"), default: |_| "16" },
+    ConfigOption {
+        key: "multiboot_graphics_mode",
+        ty: ConfigType::Enum("ConfigGraphicsMode", &[("none", "None"), ("text", "Text"), ("linear", "Linear")]),
+        default: |_| "none",
+    },
+    ConfigOption { key: "timer_tick_ms", ty: ConfigType::Int("u64"), default: |_| "2" },
+    ConfigOption { key: "num_domains", ty: ConfigType::Int("usize"), default: |_| "1" },
+    ConfigOption { key: "xsave_feature_set", ty: ConfigType::Int("u64"), default: |_| "7" },
+    ConfigOption { key: "xsave_size", ty: ConfigType::Int("usize"), default: |_| "832" },
+    ConfigOption {
+        key: "fpu_switching",
+        ty: ConfigType::Enum("ConfigFpuSwitching", &[("eager", "Eager"), ("lazy", "Lazy")]),
+        default: |_| "eager",
+    },
+    ConfigOption { key: "fpu_max_restores_since_switch", ty: ConfigType::Int("u32"), default: |_| "64" },
+];
+
+struct ConfigOption {
+    key: &'static str,
+    ty: ConfigType,
+    /// The value used when neither kernel.toml nor the environment sets one.
+    default: fn(smp: bool) -> &'static str,
+}
+
+enum ConfigType {
+    /// An unsigned integer of the named type.
+    Int(&'static str),
+    /// A string, which selects a variant of the named enum. Each variant is (value, variant name).
+    Enum(&'static str, &'static [(&'static str, &'static str)]),
+}
+
+fn max_num_nodes_default(smp: bool) -> &'static str {
+    if smp { "32" } else { "1" }
+}
+
+fn main() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+
+    // The linker script is only for the kernel. Userland binaries in the workspace use the
+    // linker's default layout.
+    let script = write_linker_script(&manifest_dir);
+    println!("cargo:rustc-link-arg-bins=-T{}", script.display());
+
+    println!("cargo:rerun-if-env-changed=ROOT_TASK_ELF");
+    println!("cargo:rustc-check-cfg=cfg(embedded_root_task)");
+    if let Ok(path) = env::var("ROOT_TASK_ELF") && !path.is_empty() {
+        let path = manifest_dir.join("..").join(path);
+        let path = path.canonicalize()
+            .unwrap_or_else(|e| panic!("Root task ELF {}: {e}", path.display()));
+        println!("cargo:rerun-if-changed={}", path.display());
+        println!("cargo:rustc-cfg=embedded_root_task");
+
+        let out = format!("#[used]\n\
+                           #[unsafe(link_section = \".root_task\")]\n\
+                           static ROOT_TASK_IMAGE: RootTaskImage<[u8; {}]> = RootTaskImage(*include_bytes!({:?}));\n",
+                          fs::metadata(&path).unwrap().len(), path.display().to_string());
+        let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("root_task.rs");
+        fs::write(out_path, out).unwrap();
+    }
+
+    println!("cargo:rerun-if-env-changed=KERNEL_SYMBOLS");
+    println!("cargo:rustc-check-cfg=cfg(embedded_ksyms)");
+    if let Ok(path) = env::var("KERNEL_SYMBOLS") && !path.is_empty() {
+        let path = manifest_dir.join("..").join(path);
+        let path = path.canonicalize()
+            .unwrap_or_else(|e| panic!("Kernel symbol table {}: {e}", path.display()));
+        println!("cargo:rerun-if-changed={}", path.display());
+        println!("cargo:rustc-cfg=embedded_ksyms");
+
+        let out = format!("#[used]\n\
+                           #[unsafe(link_section = \".ksyms\")]\n\
+                           static KSYMS_TABLE: [u8; {}] = *include_bytes!({:?});\n",
+                          fs::metadata(&path).unwrap().len(), path.display().to_string());
+        let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("ksyms.rs");
+        fs::write(out_path, out).unwrap();
+    }
+
+    write_config(&manifest_dir);
+}
+
+/// Write the linker script to OUT_DIR/linker.lds: the layout constants, followed by linker.lds.in.
+fn write_linker_script(manifest_dir: &Path) -> PathBuf {
+    let layout = manifest_dir.join("src/arch/x86_64/layout.rs");
+    let template = manifest_dir.join("linker.lds.in");
+    println!("cargo:rerun-if-changed={}", layout.display());
+    println!("cargo:rerun-if-changed={}", template.display());
+
+    let mut out = String::from("/* Generated by kernel/build.rs from src/arch/x86_64/layout.rs. */\n");
+    for (name, value) in LINKER_CONSTANTS {
+        out.push_str(&format!("{name} = 0x{value:x};\n"));
+    }
+    out.push('\n');
+    out.push_str(&fs::read_to_string(&template)
+        .unwrap_or_else(|e| panic!("Linker script {}: {e}", template.display())));
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("linker.lds");
+    fs::write(&out_path, out).unwrap();
+    out_path
+}
+
+/// Resolve the kernel configuration, and write it to OUT_DIR/config.rs as constants for
+/// src/config.rs.
+fn write_config(manifest_dir: &Path) {
+    println!("cargo:rerun-if-env-changed=KERNEL_CONFIG");
+    let path = match env::var("KERNEL_CONFIG") {
+        Ok(path) if !path.is_empty() => manifest_dir.join("..").join(path),
+        _ => manifest_dir.join("kernel.toml"),
+    };
+    println!("cargo:rerun-if-changed={}", path.display());
+    let contents = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Kernel config {}: {e}", path.display()));
+    let mut table: toml::Table = contents.parse()
+        .unwrap_or_else(|e| panic!("Kernel config {}: {e}", path.display()));
+
+    let smp = env::var_os("CARGO_FEATURE_SMP").is_some();
+    let mut out = String::from("// Generated by build.rs from the kernel config. See src/config.rs.\n");
+    for option in CONFIG_OPTIONS {
+        let env_name = format!("CONFIG_{}", option.key.to_uppercase());
+        println!("cargo:rerun-if-env-changed={env_name}");
+
+        let from_file = table.remove(option.key);
+        let (value, source) = match (env::var(&env_name), &from_file) {
+            (Ok(value), _) => (value, env_name.clone()),
+            (Err(_), Some(toml::Value::Integer(n))) => (n.to_string(), path.display().to_string()),
+            (Err(_), Some(toml::Value::String(s))) => (s.clone(), path.display().to_string()),
+            (Err(_), Some(other)) => panic!("Kernel config {}: {} should be a number or string, not {other}",
+                                            path.display(), option.key),
+            (Err(_), None) => ((option.default)(smp).to_string(), "default".into()),
+        };
+
+        let name = option.key.to_uppercase();
+        let line = match option.ty {
+            ConfigType::Int(ty) => {
+                let n: u64 = value.trim().parse()
+                    .unwrap_or_else(|_| panic!("{} ({source}) should be a non-negative integer, not {value:?}", option.key));
+                format!("pub(super) const {name}: {ty} = {n};\n")
+            }
+            ConfigType::Enum(ty, variants) => {
+                let Some((_, variant)) = variants.iter().find(|(v, _)| *v == value.trim()) else {
+                    let allowed = variants.iter().map(|(v, _)| *v).collect::<Vec<_>>().join(", ");
+                    panic!("{} ({source}) should be one of {allowed}, not {value:?}", option.key);
+                };
+                format!("pub(super) const {name}: {ty} = {ty}::{variant};\n")
+            }
+        };
+        out.push_str(&line);
+    }
+
+    // Catch typos, rather than silently using the default.
+    if let Some(key) = table.keys().next() {
+        panic!("Kernel config {}: Unknown option {key}", path.display());
+    }
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("config.rs");
+    fs::write(out_path, out).unwrap();
+}
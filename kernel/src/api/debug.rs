@@ -0,0 +1,85 @@
+//! The debugging syscalls. These are only available when the kernel is built with the `debug`
+//! feature (CONFIG_DEBUG_BUILD and CONFIG_PRINTING in SeL4).
+//!
+//! Based on include/api/debug.h and handleUnknownSyscall in src/api/syscall.c.
+//!
+//! All output goes straight to the kernel's debug console. Root tasks use this to print before
+//! they have any drivers of their own.
+
+use crate::arch::registerset::{UserContext, CAP_REGISTER};
+use crate::console::DEBUG_PORT;
+use crate::cspace::{lookup_cap_type, CapTag};
+use crate::utils::halt;
+use crate::{kprintln, kwarnln};
+
+/// seL4_DebugPutChar. Write the low byte of the cap register to the debug console.
+pub(crate) fn handle_put_char(ctx: &UserContext) {
+    let port = unsafe { DEBUG_PORT.get_mut() };
+    port.put_char(ctx.get(CAP_REGISTER) as u8);
+}
+
+/// seL4_DebugDumpScheduler. Print out all the TCBs in the system.
+pub(crate) fn dump_scheduler() {
+    kprintln!("Dumping all tcbs!");
+    kprintln!("Name                                    \tState          \tIP                  \t Prio \t Core");
+    kprintln!("--------------------------------------------------------------------------------------");
+    // TODO: Walk ksDebugTCBs once we have TCBs.
+}
+
+/// seL4_DebugHalt.
+pub(crate) fn handle_halt() -> ! {
+    // TODO: SeL4 also prints the address and name of the calling thread.
+    kprintln!("Debug halt syscall from user thread");
+    halt();
+}
+
+/// seL4_DebugCapIdentify. Replace the cptr in the cap register with the type of the cap it
+/// points to.
+pub(crate) fn handle_cap_identify(ctx: &mut UserContext) {
+    let cap_type = lookup_cap_type(ctx.get(CAP_REGISTER));
+    ctx.set(CAP_REGISTER, cap_type as usize);
+}
+
+/// seL4_DebugSnapshot. In SeL4 this dumps the root task's CSpace in capDL format.
+pub(crate) fn handle_snapshot() {
+    kprintln!("Debug snapshot syscall from user thread");
+    // TODO: Port debug_capDL() once there's a CSpace to dump.
+    kwarnln!("Warning: capDL snapshots are not supported yet");
+}
+
+/// seL4_DebugNameThread. Set the debug name of the TCB in the cap register, using the
+/// null-terminated string at the start of the caller's IPC buffer.
+///
+/// This is a syscall meant to aid debugging, so if anything goes wrong we assume the system is
+/// completely misconfigured and halt. (This matches SeL4.)
+///
+/// TODO: There are no TCBs or IPC buffers yet, so this always halts. Once they exist, read the name
+/// out of the IPC buffer and store it in the TCB.
+pub(crate) fn handle_name_thread(ctx: &UserContext) -> ! {
+    let reason = name_thread_error(lookup_cap_type(ctx.get(CAP_REGISTER)));
+    kwarnln!("SysDebugNameThread: {}, halting", reason);
+    halt();
+}
+
+/// Why the thread behind a cap of type cap_type can't be named.
+fn name_thread_error(cap_type: CapTag) -> &'static str {
+    if cap_type != CapTag::Thread {
+        "cap is not a TCB"
+    } else {
+        "naming threads is not supported yet"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_thread() {
+        // With no CSpaces, every cap is the null cap, so nothing can be named.
+        assert!(lookup_cap_type(0) == CapTag::Null);
+        assert_eq!(name_thread_error(CapTag::Null), "cap is not a TCB");
+        assert_eq!(name_thread_error(CapTag::Endpoint), "cap is not a TCB");
+        assert_eq!(name_thread_error(CapTag::Thread), "naming threads is not supported yet");
+    }
+}
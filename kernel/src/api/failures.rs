@@ -0,0 +1,60 @@
+//! Based on include/api/failures.h and libsel4's include/sel4/errors.h.
+//!
+//! In SeL4, syscall errors are reported by setting the global current_syscall_error and returning
+//! EXCEPTION_SYSCALL_ERROR. Here, invocations just return a Result with one of these errors
+//! instead. The extra fields in syscall_error_t live in the enum variants that use them.
+
+use common::errors::Error;
+use ufmt::derive::uDebug;
+
+/// An error returned to userland from an invocation. This is syscall_error_t in SeL4.
+#[derive(uDebug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum SyscallError {
+    /// The argument at the given index (counting from 0) is invalid.
+    InvalidArgument { arg: usize },
+    /// The capability argument at the given index is invalid.
+    InvalidCapability { arg: usize },
+    IllegalOperation,
+    /// An argument fell outside the permitted range [min, max].
+    RangeError { min: usize, max: usize },
+    AlignmentError,
+    // TODO: SeL4 also reports the lookup failure here. Add it when we have a CSpace.
+    FailedLookup { source_is_dest: bool },
+    TruncatedMessage,
+    DeleteFirst,
+    RevokeFirst,
+    NotEnoughMemory { available: usize },
+}
+
+impl SyscallError {
+    /// The error reported to userland, without the extra details.
+    pub fn error(&self) -> Error {
+        match self {
+            SyscallError::InvalidArgument { .. } => Error::InvalidArgument,
+            SyscallError::InvalidCapability { .. } => Error::InvalidCapability,
+            SyscallError::IllegalOperation => Error::IllegalOperation,
+            SyscallError::RangeError { .. } => Error::RangeError,
+            SyscallError::AlignmentError => Error::AlignmentError,
+            SyscallError::FailedLookup { .. } => Error::FailedLookup,
+            SyscallError::TruncatedMessage => Error::TruncatedMessage,
+            SyscallError::DeleteFirst => Error::DeleteFirst,
+            SyscallError::RevokeFirst => Error::RevokeFirst,
+            SyscallError::NotEnoughMemory { .. } => Error::NotEnoughMemory,
+        }
+    }
+
+    /// The seL4_Error value passed back to userland in the message label.
+    pub fn code(&self) -> usize {
+        self.error() as usize
+    }
+}
+
+/// The result of handling a kernel entry. This is exception_t in SeL4, minus EXCEPTION_NONE which
+/// is represented by Ok(()).
+#[derive(uDebug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Exception {
+    Fault,
+    LookupFault,
+    SyscallError(SyscallError),
+    Preempted,
+}
@@ -0,0 +1,7 @@
+//! The kernel's user facing API. This roughly corresponds to include/api and src/api in SeL4.
+
+pub mod failures;
+pub mod syscall;
+
+#[cfg(feature = "debug")]
+pub mod debug;
@@ -0,0 +1,144 @@
+//! Based on include/api/syscall.h and src/api/syscall.c.
+//!
+//! TODO: Nothing calls into this yet. The SYSCALL entry path and TCBs don't exist.
+
+use common::syscalls;
+use crate::api::failures::Exception;
+use crate::arch::registerset::UserContext;
+
+/// System call numbers. The numbering is shared with userland, so it comes from the common crate.
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[repr(isize)]
+pub(crate) enum Syscall {
+    Call = syscalls::SYS_CALL,
+    ReplyRecv = syscalls::SYS_REPLY_RECV,
+    Send = syscalls::SYS_SEND,
+    NBSend = syscalls::SYS_NB_SEND,
+    Recv = syscalls::SYS_RECV,
+    Reply = syscalls::SYS_REPLY,
+    Yield = syscalls::SYS_YIELD,
+    NBRecv = syscalls::SYS_NB_RECV,
+
+    #[cfg(feature = "debug")]
+    DebugPutChar = syscalls::SYS_DEBUG_PUT_CHAR,
+    #[cfg(feature = "debug")]
+    DebugDumpScheduler = syscalls::SYS_DEBUG_DUMP_SCHEDULER,
+    #[cfg(feature = "debug")]
+    DebugHalt = syscalls::SYS_DEBUG_HALT,
+    #[cfg(feature = "debug")]
+    DebugCapIdentify = syscalls::SYS_DEBUG_CAP_IDENTIFY,
+    #[cfg(feature = "debug")]
+    DebugSnapshot = syscalls::SYS_DEBUG_SNAPSHOT,
+    #[cfg(feature = "debug")]
+    DebugNameThread = syscalls::SYS_DEBUG_NAME_THREAD,
+
+    #[cfg(feature = "benchmark")]
+    BenchmarkResetLog = syscalls::SYS_BENCHMARK_RESET_LOG,
+    #[cfg(feature = "benchmark")]
+    BenchmarkFinalizeLog = syscalls::SYS_BENCHMARK_FINALIZE_LOG,
+    #[cfg(feature = "benchmark")]
+    BenchmarkSetLogBuffer = syscalls::SYS_BENCHMARK_SET_LOG_BUFFER,
+    #[cfg(feature = "benchmark")]
+    BenchmarkGetThreadUtilisation = syscalls::SYS_BENCHMARK_GET_THREAD_UTILISATION,
+    #[cfg(feature = "benchmark")]
+    BenchmarkResetThreadUtilisation = syscalls::SYS_BENCHMARK_RESET_THREAD_UTILISATION,
+}
+
+impl Syscall {
+    pub fn from_word(w: isize) -> Option<Self> {
+        Some(match w {
+            syscalls::SYS_CALL => Syscall::Call,
+            syscalls::SYS_REPLY_RECV => Syscall::ReplyRecv,
+            syscalls::SYS_SEND => Syscall::Send,
+            syscalls::SYS_NB_SEND => Syscall::NBSend,
+            syscalls::SYS_RECV => Syscall::Recv,
+            syscalls::SYS_REPLY => Syscall::Reply,
+            syscalls::SYS_YIELD => Syscall::Yield,
+            syscalls::SYS_NB_RECV => Syscall::NBRecv,
+
+            #[cfg(feature = "debug")]
+            syscalls::SYS_DEBUG_PUT_CHAR => Syscall::DebugPutChar,
+            #[cfg(feature = "debug")]
+            syscalls::SYS_DEBUG_DUMP_SCHEDULER => Syscall::DebugDumpScheduler,
+            #[cfg(feature = "debug")]
+            syscalls::SYS_DEBUG_HALT => Syscall::DebugHalt,
+            #[cfg(feature = "debug")]
+            syscalls::SYS_DEBUG_CAP_IDENTIFY => Syscall::DebugCapIdentify,
+            #[cfg(feature = "debug")]
+            syscalls::SYS_DEBUG_SNAPSHOT => Syscall::DebugSnapshot,
+            #[cfg(feature = "debug")]
+            syscalls::SYS_DEBUG_NAME_THREAD => Syscall::DebugNameThread,
+
+            #[cfg(feature = "benchmark")]
+            syscalls::SYS_BENCHMARK_RESET_LOG => Syscall::BenchmarkResetLog,
+            #[cfg(feature = "benchmark")]
+            syscalls::SYS_BENCHMARK_FINALIZE_LOG => Syscall::BenchmarkFinalizeLog,
+            #[cfg(feature = "benchmark")]
+            syscalls::SYS_BENCHMARK_SET_LOG_BUFFER => Syscall::BenchmarkSetLogBuffer,
+            #[cfg(feature = "benchmark")]
+            syscalls::SYS_BENCHMARK_GET_THREAD_UTILISATION => Syscall::BenchmarkGetThreadUtilisation,
+            #[cfg(feature = "benchmark")]
+            syscalls::SYS_BENCHMARK_RESET_THREAD_UTILISATION => Syscall::BenchmarkResetThreadUtilisation,
+
+            _ => return None,
+        })
+    }
+}
+
+/// Handle a syscall. Unlike SeL4, this returns to the caller rather than restoring the user context
+/// itself.
+pub(crate) fn slowpath(syscall: isize, ctx: &mut UserContext) -> Result<(), Exception> {
+    if syscall < Syscall::NBRecv as isize || syscall > Syscall::Call as isize {
+        handle_unknown_syscall(syscall, ctx)
+    } else {
+        handle_syscall(syscall, ctx)
+    }
+}
+
+/// Handle one of the regular IPC syscalls. (handleSyscall in SeL4)
+///
+/// TODO: Invocations, IPC and the scheduler. There's no CSpace yet, so every cap lookup would fail,
+/// and SeL4 raises a cap fault when that happens. That's what we do for now.
+fn handle_syscall(_syscall: isize, _ctx: &mut UserContext) -> Result<(), Exception> {
+    Err(Exception::Fault)
+}
+
+/// Handle any syscall which isn't part of the regular IPC API. These are the debugging and
+/// benchmarking syscalls. Anything else is an unknown syscall, which faults the calling thread.
+pub(crate) fn handle_unknown_syscall(w: isize, ctx: &mut UserContext) -> Result<(), Exception> {
+    match Syscall::from_word(w) {
+        #[cfg(feature = "debug")]
+        Some(Syscall::DebugPutChar) => crate::api::debug::handle_put_char(ctx),
+        #[cfg(feature = "debug")]
+        Some(Syscall::DebugDumpScheduler) => crate::api::debug::dump_scheduler(),
+        #[cfg(feature = "debug")]
+        Some(Syscall::DebugHalt) => crate::api::debug::handle_halt(),
+        #[cfg(feature = "debug")]
+        Some(Syscall::DebugCapIdentify) => crate::api::debug::handle_cap_identify(ctx),
+        #[cfg(feature = "debug")]
+        Some(Syscall::DebugSnapshot) => crate::api::debug::handle_snapshot(),
+        #[cfg(feature = "debug")]
+        Some(Syscall::DebugNameThread) => crate::api::debug::handle_name_thread(ctx),
+
+        #[cfg(feature = "benchmark")]
+        Some(Syscall::BenchmarkResetLog) => return crate::benchmark::handle_reset_log(ctx),
+        #[cfg(feature = "benchmark")]
+        Some(Syscall::BenchmarkFinalizeLog) => crate::benchmark::handle_finalize_log(ctx),
+        #[cfg(feature = "benchmark")]
+        Some(Syscall::BenchmarkSetLogBuffer) => return crate::benchmark::handle_set_log_buffer(ctx),
+        #[cfg(feature = "benchmark")]
+        Some(Syscall::BenchmarkGetThreadUtilisation) => {
+            return crate::benchmark::utilisation::handle_get_thread_utilisation(ctx);
+        }
+        #[cfg(feature = "benchmark")]
+        Some(Syscall::BenchmarkResetThreadUtilisation) => {
+            return crate::benchmark::utilisation::handle_reset_thread_utilisation(ctx);
+        }
+
+        // TODO: This should raise an UnknownSyscall fault and send it to the thread's fault
+        // handler.
+        _ => return Err(Exception::Fault),
+    }
+
+    Ok(())
+}
@@ -272,14 +272,14 @@ impl AcpiRsdp {
             // RSDT.
             let rsdt_address_ptr = &raw const self.rsdt_address;
             let rsdt_address = unsafe { rsdt_address_ptr.read_unaligned() };
-            assert_ne!(rsdt_address, 0, "RSDT pointer is null");
+            assert!(rsdt_address != 0, "RSDT pointer is null");
             rsdt_address as usize
         } else {
             // XSDT.
             // DEPARTURE: SeL4 always uses RSDT, and never XSDT when its available.
             let xsdt_address_ptr = &raw const self.xsdt_address;
             let xsdt_address = unsafe { xsdt_address_ptr.read_unaligned() };
-            assert_ne!(xsdt_address, 0, "XSDT pointer is null");
+            assert!(xsdt_address != 0, "XSDT pointer is null");
             xsdt_address as usize
         };
 
@@ -342,7 +342,7 @@ impl AcpiRsdt {
     #[unsafe(link_section = ".boot.text")]
     pub(crate) fn print_table_entries(&self) {
         for (sig, ptr) in self.iter() {
-            let sig = core::str::from_utf8(sig.as_slice()).unwrap();
+            let sig = core::str::from_utf8(sig.as_slice()).unwrap_or("????");
             kprintln!("RSDT entry at 0x{:x} with signature {}", ptr.0, sig);
         }
     }
@@ -493,3 +493,22 @@ impl AcpiRsdt {
 //     }
 // }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum() {
+        let mut header = [0u8; 20];
+        header[..8].copy_from_slice(&RSDP_SIGNATURE);
+        header[10] = 0xf0;
+        assert!(!checksum_valid(&header));
+
+        // Fix up the checksum byte, like the firmware does.
+        header[8] = 0u8.wrapping_sub(acpi_calc_checksum(&header));
+        assert!(checksum_valid(&header));
+
+        assert_eq!(acpi_calc_checksum(&[0xffu8, 0x02]), 0x01);
+    }
+}
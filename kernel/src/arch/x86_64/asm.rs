@@ -71,3 +71,62 @@ pub unsafe fn in32(port: u16) -> u32 {
     }
     value
 }
+
+/// Read the CPU's timestamp counter. This is x86_rdtsc() in SeL4.
+#[inline(always)]
+pub fn rdtsc() -> u64 {
+    let (high, low): (u32, u32);
+    unsafe {
+        asm!("rdtsc",
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    ((high as u64) << 32) | (low as u64)
+}
+
+/// The address which caused the last page fault.
+#[inline(always)]
+pub fn read_cr2() -> usize {
+    let value: usize;
+    unsafe { asm!("mov {}, cr2", out(reg) value, options(nomem, nostack, preserves_flags)) };
+    value
+}
+
+#[inline(always)]
+pub fn read_cr3() -> usize {
+    let value: usize;
+    unsafe { asm!("mov {}, cr3", out(reg) value, options(nomem, nostack, preserves_flags)) };
+    value
+}
+
+/// Switch page tables. This also flushes all non-global TLB entries.
+#[inline(always)]
+pub unsafe fn write_cr3(value: usize) {
+    unsafe { asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags)) };
+}
+
+#[inline(always)]
+pub fn read_cr0() -> usize {
+    let value: usize;
+    unsafe { asm!("mov {}, cr0", out(reg) value, options(nomem, nostack, preserves_flags)) };
+    value
+}
+
+#[inline(always)]
+pub unsafe fn write_cr0(value: usize) {
+    unsafe { asm!("mov cr0, {}", in(reg) value, options(nostack, preserves_flags)) };
+}
+
+#[inline(always)]
+pub fn read_cr4() -> usize {
+    let value: usize;
+    unsafe { asm!("mov {}, cr4", out(reg) value, options(nomem, nostack, preserves_flags)) };
+    value
+}
+
+#[inline(always)]
+pub unsafe fn write_cr4(value: usize) {
+    unsafe { asm!("mov cr4, {}", in(reg) value, options(nostack, preserves_flags)) };
+}
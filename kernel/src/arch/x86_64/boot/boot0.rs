@@ -6,9 +6,14 @@
 //! layout is much simpler!
 
 use core::arch::naked_asm;
+use core::mem::offset_of;
 use super::boot1::boot_sys;
-use crate::stack::KERNEL_STACK;
-use crate::config::CONFIG_KERNEL_STACK_BITS;
+use super::cpu_features::{CpuFeature, REQUIRED_FEATURES};
+use crate::arch::x86_64::msr::{Efer, IA32_EFER};
+use crate::arch::hardware::{KERNEL_ELF_BASE, KERNEL_ELF_PADDR_BASE, PPTR_BASE, PPTR_TOP};
+use crate::const_assert;
+use crate::hardware::KERNEL_ELF_BASE_OFFSET;
+use crate::stack::{BOOT_STACK_TOP_OFFSET, KERNEL_STACKS};
 
 #[allow(dead_code)]
 unsafe extern "C" {
@@ -28,8 +33,20 @@ pub const PML3_INDEX_BITS: usize = 9;
 // I'm not sure why the boot page directory in sel4 has 2048 entries.
 pub const PML2_INDEX_BITS: usize = 11;
 
+/// The byte offset of the boot PML4 entry for the kernel's half of the address space. The
+/// physical memory window (PPTR_BASE) and the kernel image (KERNEL_ELF_BASE) share it.
+const KERNEL_PML4_OFFSET: usize = (PPTR_BASE >> 39) % (1 << PML4_INDEX_BITS) * 8;
+/// The byte offset of the boot PDPT entry for the 1GiB the kernel image is in (from PPTR_TOP).
+const KERNEL_PDPT_OFFSET: usize = (PPTR_TOP >> 30) % (1 << PML3_INDEX_BITS) * 8;
+
+const_assert!(PPTR_BASE >> 39 == KERNEL_ELF_BASE >> 39,
+    "The boot page tables map the kernel window and image with one PDPT");
+// The boot PDPT's first 4 entries map the first 4GiB, for both the identity mapping and the window.
+const_assert!((PPTR_BASE >> 30).is_multiple_of(1 << PML3_INDEX_BITS), "The kernel window must start a PDPT");
+const_assert!(KERNEL_ELF_PADDR_BASE < 1 << 30, "The boot page tables only map the kernel image's first 1GiB");
+
 #[repr(align(4096))]
-struct Align4k<T>(T);
+pub(super) struct Align4k<T>(pub T);
 
 // "PM level 4"
 #[unsafe(link_section = ".phys.bss")]
@@ -42,7 +59,7 @@ static mut BOOT_PML3: Align4k<[u64; 1 << PML3_INDEX_BITS]> = Align4k([0; _]);
 // "Page directory", which has 2048 entries to cover the whole 4gb of addressable memory in 32 bit
 // mode
 #[unsafe(link_section = ".phys.bss")]
-static mut BOOT_PML2: Align4k<[u64; 1 << PML2_INDEX_BITS]> = Align4k([0; _]);
+pub(super) static mut BOOT_PML2: Align4k<[u64; 1 << PML2_INDEX_BITS]> = Align4k([0; _]);
 
 
 const fn new_gdt(flags: u8, access_byte: u8) -> u64 {
@@ -97,6 +114,111 @@ extern "C" fn print_string64() {
     ")
 }
 
+#[unsafe(link_section = ".phys.data")]
+static MISSING_FEATURES_MSG: [u8; 38] = *b"This CPU is missing required features:";
+#[unsafe(link_section = ".phys.data")]
+static FEATURE_INDENT: [u8; 3] = *b"\n  ";
+#[unsafe(link_section = ".phys.data")]
+static HALTING_MSG: [u8; 10] = *b"\nHalting.\n";
+
+/// Check the CPU has everything in REQUIRED_FEATURES (cpu_features.rs), and if not, list what's
+/// missing and halt. This runs in 32 bit mode, since long mode is one of the features. The stack
+/// must be set up.
+#[unsafe(naked)]
+#[unsafe(link_section = ".phys.text")]
+extern "C" fn check_cpu_features() {
+    naked_asm!(r"
+    .code32
+        push ebx
+        push esi
+        push edi
+        push ebp
+
+        xor ebp, ebp // Number of missing features
+        mov esi, offset {features}
+        mov edi, {num_features}
+
+    .Lcheck_feature:
+        // Leaves past the maximum (cpuid 0 for basic leaves, 0x80000000 for extended ones) return
+        // junk, so those features count as missing.
+        mov eax, [esi + {leaf}]
+        and eax, 0x80000000
+        cpuid
+        cmp eax, [esi + {leaf}]
+        jb .Lmissing_feature
+
+        mov eax, [esi + {leaf}]
+        mov ecx, [esi + {subleaf}]
+        cpuid
+        // Pick out the register, in CpuidReg order.
+        push edx
+        push ecx
+        push ebx
+        push eax
+        mov ecx, [esi + {reg}]
+        mov eax, [esp + ecx * 4]
+        add esp, 16
+        mov ecx, [esi + {bit}]
+        bt eax, ecx
+        jc .Lnext_feature
+
+    .Lmissing_feature:
+        test ebp, ebp
+        jnz .Lprint_feature
+        mov ebx, offset {missing_msg}
+        mov ecx, {missing_len}
+        call {print_string}
+    .Lprint_feature:
+        inc ebp
+        mov ebx, offset {indent}
+        mov ecx, {indent_len}
+        call {print_string}
+        lea ebx, [esi + {name}]
+        mov ecx, [esi + {name_len}]
+        call {print_string}
+
+    .Lnext_feature:
+        add esi, {feature_size}
+        dec edi
+        jnz .Lcheck_feature
+
+        test ebp, ebp
+        jnz .Lrefuse_boot
+
+        pop ebp
+        pop edi
+        pop esi
+        pop ebx
+        ret
+
+    .Lrefuse_boot:
+        mov ebx, offset {halting_msg}
+        mov ecx, {halting_len}
+        call {print_string}
+    .Lhalt:
+        cli
+        hlt
+        jmp .Lhalt
+    ",
+        features = sym REQUIRED_FEATURES,
+        num_features = const REQUIRED_FEATURES.len(),
+        feature_size = const size_of::<CpuFeature>(),
+        leaf = const offset_of!(CpuFeature, leaf),
+        subleaf = const offset_of!(CpuFeature, subleaf),
+        reg = const offset_of!(CpuFeature, reg),
+        bit = const offset_of!(CpuFeature, bit),
+        name = const offset_of!(CpuFeature, name),
+        name_len = const offset_of!(CpuFeature, name_len),
+        missing_msg = sym MISSING_FEATURES_MSG,
+        missing_len = const MISSING_FEATURES_MSG.len(),
+        indent = sym FEATURE_INDENT,
+        indent_len = const FEATURE_INDENT.len(),
+        halting_msg = sym HALTING_MSG,
+        halting_len = const HALTING_MSG.len(),
+        print_string = sym print_string,
+    )
+}
+
 /*
  *          2^64 +-------------------+
  *               | Kernel Page PDPT  | --+
@@ -151,7 +273,7 @@ static PAGE_ENABLED_MSG: [u8; 16] = *b"Paging enabled!\n";
 extern "C" fn setup_pagetable() {
     naked_asm!(r"
         .code32
-            // TODO: check huge page support
+            // 2MiB pages always exist in long mode, which check_cpu_features has checked for.
 
             // Zero pml4 and pml3
             mov edi, offset {boot_pml4}
@@ -172,21 +294,21 @@ extern "C" fn setup_pagetable() {
             // Setup the level 4 page table with a single entry.
             mov edi, offset {boot_pml4}
             mov ecx, offset {boot_pml3}
-            or ecx, 0x7 // 0x7 = preset, writable, user accessable.
+            or ecx, 0x3 // 0x3 = present, writable.
+            // Not user accessible, since the kernel half is copied into user page tables.
             // (Other bits are zero because of alignment.)
 
-            // 3 copied mappings:
+            // 2 copied mappings:
             mov [edi], ecx // Lower (physical)
-            mov [edi+0x800], ecx // [256]. Upper half, for kernel's physical map. We jump here!
-            mov [edi+4088], ecx // [511]. Map the top page for sign-extended compatibility mode pointers.
+            mov [edi+{kernel_pml4_offset}], ecx // The kernel window and image. We jump here!
 
             // Setup the level 3 page table (aka PDPT)
             mov ecx, offset {boot_pml2}
-            or ecx, 0x7 // same bits - present, writable, user accessible
+            or ecx, 0x3 // same bits - present, writable
 
             mov edi, offset {boot_pml3}
             mov [edi], ecx // 0-1gb
-            mov [edi+4080], ecx // [510]. Mapped for sign extension compatibility.
+            mov [edi+{kernel_pdpt_offset}], ecx // The kernel image, at PPTR_TOP.
             add ecx, 0x1000
             mov [edi+8], ecx // 1-2gb
             add ecx, 0x1000
@@ -196,7 +318,7 @@ extern "C" fn setup_pagetable() {
 
             // Setup level 2 page tables using large pages (2mb)
             mov edi, offset {boot_pml2}
-            mov edx, 0x87 // Flags. Present, writable, user, and large mode.
+            mov edx, 0x83 // Flags. Present, writable, and large mode.
 
             // Loop through assigning L2PT entries (PD). 2048 entries * 2mb = Entire 4gb.
             mov ecx, 2048
@@ -218,7 +340,8 @@ extern "C" fn setup_pagetable() {
         boot_pml4 = sym BOOT_PML4,
         boot_pml3 = sym BOOT_PML3,
         boot_pml2 = sym BOOT_PML2,
-
+        kernel_pml4_offset = const KERNEL_PML4_OFFSET,
+        kernel_pdpt_offset = const KERNEL_PDPT_OFFSET,
 
         msg = sym PAGE_ENABLED_MSG,
         len = const PAGE_ENABLED_MSG.len(),
@@ -256,8 +379,6 @@ static GDT64_PTR: GdtPtr = GdtPtr {
 };
 
 
-const IA32_EFER_MSR: u32 = 0xC0000080;
-
 /// Enable x64 mode on the current CPU.
 #[unsafe(naked)]
 #[unsafe(no_mangle)]
@@ -275,10 +396,10 @@ extern "C" fn enable_x64_mode() {
             or  eax, 0x20
             mov cr4, eax
 
-            // Set Long Mode Extension (bit 8) in IA32_EFER (MSR 0xC000_0080).
-            mov ecx, {IA32_EFER_MSR}
+            // Set Long Mode Enable in IA32_EFER.
+            mov ecx, {IA32_EFER}
             rdmsr
-            or eax, 0x100
+            or eax, {EFER_LME}
             wrmsr
 
             // Enable paging (bit 31) in CR0. With LME set, this enters long mode.
@@ -298,7 +419,8 @@ extern "C" fn enable_x64_mode() {
             ret
         ",
         boot_pml4 = sym BOOT_PML4,
-        IA32_EFER_MSR = const IA32_EFER_MSR,
+        IA32_EFER = const IA32_EFER,
+        EFER_LME = const Efer::LME,
     )
 }
 
@@ -408,11 +530,9 @@ pub extern "C" fn _start() -> ! {
             push edi // multiboot_magic
 
 
-            // TODO: Check for required features:
-            //   - Large pages
-            //   - invpcid
-            //   - long mode
-            //   - syscall
+            // Refuse to boot on CPUs missing anything we need, before we try to use it.
+            call {check_cpu_features}
+
             // TODO: Check / clear CPU state. Make sure we're currently in 32 bit mode.
 
             call {common_init}
@@ -426,6 +546,7 @@ pub extern "C" fn _start() -> ! {
     ",
         boot_stack_top = sym boot_stack_top,
         common_init = sym common_init,
+        check_cpu_features = sym check_cpu_features,
         _start64 = sym start64,
     )
 }
@@ -455,7 +576,7 @@ extern "C" fn entry64() -> ! {
     naked_asm!(r"
         .code64
             // Update stack pointer
-            mov rax, 0xffffffff80000000
+            mov rax, {kernel_elf_base_offset}
             add rsp, rax
             add rbp, rax
 
@@ -463,17 +584,21 @@ extern "C" fn entry64() -> ! {
             pop rdi
             pop rsi
 
-            // Load the real kernel stack
-            lea rsp, [{kernel_stack} + 1 << {KERNEL_STACK_BITS}]
+            // Load the real kernel stack (the boot core's, in KERNEL_STACKS)
+            lea rsp, [{kernel_stacks} + {BOOT_STACK_TOP_OFFSET}]
 
             // Set restore_user_context() as return EIP, which will start the root task as soon as
             // boot_sys returns. (??)
             // push restore_user_context
 
+            // End the frame pointer chain here, so backtraces stop at boot_sys.
+            xor ebp, ebp
+
             jmp {boot_sys}
     ",
-        kernel_stack = sym KERNEL_STACK,
-        KERNEL_STACK_BITS = const CONFIG_KERNEL_STACK_BITS,
+        kernel_elf_base_offset = const KERNEL_ELF_BASE_OFFSET,
+        kernel_stacks = sym KERNEL_STACKS,
+        BOOT_STACK_TOP_OFFSET = const BOOT_STACK_TOP_OFFSET,
         boot_sys = sym boot_sys,
         // junk64 = sym junk64,
     )
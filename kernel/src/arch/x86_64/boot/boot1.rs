@@ -9,12 +9,13 @@
 use crate::arch::constants::PAGE_BITS;
 use crate::arch::x86_64::acpi::{AcpiRsdp};
 use crate::arch::x86_64::boot::bootinfo::{BootState, MemPRegs, MAX_NUM_FREEMEM_REG};
+use crate::arch::x86_64::boot::embedded::embedded_root_task;
 use crate::arch::x86_64::boot::multiboot::{MMapEntry, MMapType, MultibootBootInfo, MultibootInfoFlags, MULTIBOOT_BOOTLOADER_MAGIC};
-use crate::arch::x86_64::cpu::{ia32_arch_caps_msr_get_rdcl_no, read_ia32_arch_cap_msr, x86_cpuid_get_vendor, CpuVendor};
+use crate::arch::x86_64::boot::cmdline::{parse_cmdline, CmdLineOpts};
+use crate::arch::x86_64::cpu::init_cpu_info;
 use crate::arch::x86_64::U32Ptr;
 use crate::basic_types::{Paddr, PhysRegion};
 use crate::boot::get_p_reg_kernel_img;
-use crate::config::CONFIG_KERNEL_SKIM_WINDOW;
 use crate::console::init_serial;
 use crate::hardware::PADDR_TOP;
 use crate::utils::{halt, NumUtils};
@@ -22,6 +23,11 @@ use crate::{kpanic, kprintln, kwarnln};
 use crate::arch::devices::MAX_NUM_DRHU;
 use crate::arch::x86_64::machine::IRQ_INT_OFFSET;
 use crate::arch::x86_64::pic::{pic_disable, pic_remap_irqs};
+#[cfg(not(test))]
+use crate::arch::x86_64::skim::init_skim_window;
+use crate::arch::x86_64::fpu::init_fpu;
+use crate::arch::x86_64::speculation::init_mitigations;
+use crate::arch::x86_64::ioport::reserve_kernel_ioports;
 use crate::utils::fixedarr::FixedArr;
 
 const SEL4_MULTIBOOT_MAX_MMAP_ENTRIES: usize = 50;
@@ -132,10 +138,6 @@ unsafe fn parse_mem_map(mem_p_regs: &mut MemPRegs, bytelen: u32, base_addr: U32P
 /// like this.
 #[unsafe(link_section = ".boot.text")]
 fn try_boot_sys_mbi1(mbi: &MultibootBootInfo) -> Result<BootState, ()> {
-    // TODO: Boot command line. Not sure if I ever want to support this, but its certainly in sel4.
-    // (src/arch/x86/kernel/cmdline.c)
-    // Could be nice for configuring debugging port when booting on real hardware.
-
     // I could return a proper result, but we're going to halt immediately if any error happens.
     // In this case, its simpler to just print out the error we get here and return Err(()) to bail.
     if mbi.flags & (MultibootInfoFlags::Memory as u32) == 0 {
@@ -143,29 +145,52 @@ fn try_boot_sys_mbi1(mbi: &MultibootBootInfo) -> Result<BootState, ()> {
         return Err(());
     }
 
-    if mbi.flags & (MultibootInfoFlags::Mods as u32) == 0 {
-        kprintln!("Boot loader did not provide information about physical memory size");
-        return Err(());
-    }
+    let cmdline = if mbi.flags & (MultibootInfoFlags::CmdLine as u32) != 0 {
+        let cmdline = unsafe { mbi.cmdline.try_as_cstr(mbi) };
+        let cmdline = cmdline.map_or("", |s| s.to_str().unwrap_or(""));
+        kprintln!("Command line: {}", cmdline);
+        parse_cmdline(cmdline)
+    } else {
+        CmdLineOpts::default()
+    };
 
-    kprintln!("Detected {} boot module(s)", mbi.mods.len);
+    let embedded = embedded_root_task();
+
+    // Modules are optional when the root task is embedded in the kernel.
+    let has_modules = mbi.flags & (MultibootInfoFlags::Mods as u32) != 0;
 
     let mut mods_end_paddr = 0;
 
-    let modules = unsafe { mbi.mods.to_slice(mbi) };
+    let modules = if has_modules {
+        kprintln!("Detected {} boot module(s)", mbi.mods.len);
+        unsafe { mbi.mods.to_slice(mbi) }
+    } else {
+        &[]
+    };
     // kprintln!("modules: {:?}", modules);
 
-    let Some(first_module) = modules.first() else {
-        kprintln!("Expected at least 1 boot module (passed as initrd) for root process");
+    if modules.is_empty() && embedded.is_none() {
+        kprintln!("No root task module (passed as initrd) or embedded root task found");
         return Err(());
-    };
+    }
 
     // This is the entrypoint we jump to after initializing SeL4.
-    let boot_module_start = first_module.mod_start as usize;
+    let boot_module_start = match embedded {
+        Some(image) => {
+            kprintln!("Using root task embedded in kernel image at 0x{:x} size 0x{:x}",
+                image.start, image.end - image.start);
+            if !modules.is_empty() {
+                kwarnln!("Ignoring boot modules. The root task is embedded in the kernel image.");
+            }
+            image.start
+        }
+        // There's at least one module, from the check above.
+        None => modules[0].mod_start as usize,
+    };
 
     for m in modules {
         let name = unsafe { m.name.try_as_cstr(mbi) };
-        kprintln!("\tmod {}: {:?}", name.unwrap().to_str().unwrap(), m);
+        kprintln!("\tmod {}: {:?}", name.unwrap().to_str().unwrap_or("(not utf8)"), m);
 
         if m.mod_end < m.mod_start {
             kprintln!("Invalid boot module size!");
@@ -241,43 +266,25 @@ fn try_boot_sys_mbi1(mbi: &MultibootBootInfo) -> Result<BootState, ()> {
         mem_lower: mbi.mem_lower,
         cpus: Default::default(),
         mem_p_regs,
+        cmdline,
     })
 }
 
 #[unsafe(link_section = ".boot.text")]
 fn try_boot_sys(mut boot_state: BootState) -> Result<(), ()> {
     // kern_p_reg is set above.
-    let vendor = x86_cpuid_get_vendor();
-
-    // DEPARTURE: Not detecting and warning on microarch deviations.
-
-    // see if we can definitively say whether we need the skim window by
-    // checking whether the CPU is vulnerable to rogue data cache loads (rdcl)
-    if let Some(msr) = read_ia32_arch_cap_msr() {
-        let rdcl_no = ia32_arch_caps_msr_get_rdcl_no(msr);
-
-        if rdcl_no && CONFIG_KERNEL_SKIM_WINDOW {
-            kwarnln!("CPU reports not vulnerable to Rogue Data Cache Load (aka meltdown) \n\
-                yet SKIM window is enabled. Performance is being needlessly impacted, consider \n\
-                disabling.");
-        } else if !rdcl_no && !CONFIG_KERNEL_SKIM_WINDOW {
-            kwarnln!("CPU reports it is vulnerable to Rogue Data Cache Load (aka meltdown) \n\
-                yet SKIM window is DISABLED. Please rebuild with SKIM window enabled.");
-        }
-    } else {
-        // hardware doesn't tell us directly so guess based on CPU vendor
-        match (CONFIG_KERNEL_SKIM_WINDOW, vendor) {
-            (true, CpuVendor::Amd) => {
-                kwarnln!("SKIM window for mitigating Meltdown (https://www.meltdownattack.com) \
-                       not necessary for AMD and performance is being needlessly affected, \
-                       consider disabling");
-            },
-            (false, CpuVendor::Intel) => {
-                kwarnln!("***WARNING*** SKIM window not enabled, this machine is probably vulnerable \
-                   to Meltdown (https://www.meltdownattack.com), consider enabling\n");
-            },
-            _ => {}
-        }
+    let cpu = init_cpu_info(0);
+
+    // DEPARTURE: SeL4 only warns here, when the SKIM window setting doesn't suit the CPU.
+    let mitigations = init_mitigations(cpu, &boot_state.cmdline.mitigations);
+    if mitigations.skim {
+        #[cfg(not(test))]
+        unsafe { init_skim_window() };
+    }
+
+    if init_fpu(0, cpu).is_err() {
+        kprintln!("Failed to initialize FPU");
+        return Err(());
     }
 
     if cfg!(feature = "smp") {
@@ -305,6 +312,10 @@ fn try_boot_sys(mut boot_state: BootState) -> Result<(), ()> {
     // Disable the PIC. We need to do this before enabling APIC.
     unsafe { pic_disable() };
 
+    // Make sure userland can never be issued the IO ports used by the kernel's debug console (and
+    // GDB stub).
+    reserve_kernel_ioports();
+
     // DEPARTURE: SeL4 validates APIC again here, even though we already did that above.
 
     // DEPARTURE: Skip the FADT scan. We don't actually care about the FADT contents unless
@@ -321,6 +332,9 @@ fn try_boot_sys(mut boot_state: BootState) -> Result<(), ()> {
 
     kprintln!("{:?}", boot_state.cpus);
 
+    #[cfg(feature = "test")]
+    report_boot_state(&boot_state);
+
 
     // let vendor = VendorInfo::new().as_vendor();
     // kprintln!("vendor {:?}", vendor);
@@ -329,6 +343,18 @@ fn try_boot_sys(mut boot_state: BootState) -> Result<(), ()> {
     Ok(())
 }
 
+/// Report what we found during boot to the test harness. See crate::test.
+#[cfg(feature = "test")]
+#[unsafe(link_section = ".boot.text")]
+fn report_boot_state(boot_state: &BootState) {
+    let mem_bytes: usize = boot_state.mem_p_regs.iter().map(|r| r.end - r.start).sum();
+    kprintln!("KTEST mem_regions={}", boot_state.mem_p_regs.len());
+    kprintln!("KTEST mem_bytes={}", mem_bytes);
+    kprintln!("KTEST cpus={}", boot_state.cpus.len());
+    kprintln!("KTEST max_num_nodes={}", crate::config::CONFIG_MAX_NUM_NODES);
+    kprintln!("KTEST ioapics={}", boot_state.ioapic_paddr.len());
+}
+
 // pub fn vga_write_str(s: &str) {
 //     let vga = 0xb8000 as *mut u8;
 //     static mut COL: usize = 0;
@@ -358,15 +384,31 @@ pub extern "C" fn boot_sys(multiboot_magic: u32, mbi: u32) -> ! {
     // init_serial is called once at the start of the boot process before we use the serial console.
     // This is used for debug messages.
     unsafe { init_serial() };
+    #[cfg(feature = "gdb")]
+    unsafe { crate::gdb::init_gdb() };
+    crate::config::print_config();
+
+    // Unmap the stack guard pages, and give the CPU the stacks for #DF and NMI, so a kernel stack
+    // overflow is reported rather than silently corrupting memory.
+    // (The boot page tables are in boot0, which isn't in host unit tests.)
+    #[cfg(not(test))]
+    unsafe { super::guard::map_stack_guards() };
+    unsafe {
+        crate::arch::gdt::init_cpu_descriptors(0);
+        // The fault handlers load the kernel's page tables from here.
+        crate::arch::x86_64::skim::init_kernel_cr3();
+        crate::arch::idt::init_idt();
+        crate::arch::idt::load_idt();
+    }
 
     // In SeL4, the root process is compiled to an ELF module and passed to the kernel as a
     // multiboot module. This is very convenient during development, because you can compile it
     // directly to an elf file and just pass it through. And we should be able to set up debugging
     // the normal way too.
     //
-    // For this kernel I'd like to have some alternate ways of building the kernel with an embedded
-    // root process. But I want this implementation to be compatible, so for now I'll stick to
-    // sel4's behaviour here.
+    // This kernel can also be built with the root process embedded in the kernel image (see
+    // kernel/build.rs), which gives a single bootable file. Otherwise we stick to sel4's
+    // behaviour, so existing images keep working.
 
     // Multiboot 1 and 2 both pass modules to the kernel slightly differently.
 
@@ -392,5 +434,87 @@ pub extern "C" fn boot_sys(multiboot_magic: u32, mbi: u32) -> ! {
     }
 
     kprintln!("END OF LINE ------ BEEEEEEPPPP");
+    #[cfg(feature = "test")]
+    crate::test::test_exit(crate::test::TestExit::Success);
     halt();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::x86_64::alloc_low_memory;
+
+    /// Lay out a multiboot memory map in low memory. Each entry is (size, base, len, type). The
+    /// size field is normally 20, but entries can be padded out beyond that.
+    fn make_mem_map(entries: &[(u32, u64, u64, MMapType)]) -> (u32, U32Ptr<MMapEntry>) {
+        let mem = alloc_low_memory(4096);
+        let mut offset = 0;
+        for &(size, base_addr, len, mtype) in entries {
+            let entry = MMapEntry { size, base_addr, len, mtype: mtype as u32 };
+            unsafe { core::ptr::write_unaligned(mem[offset..].as_mut_ptr() as *mut MMapEntry, entry) };
+            offset += size as usize + size_of::<u32>();
+        }
+        (offset as u32, U32Ptr::new(mem.as_ptr() as u32))
+    }
+
+    fn regions(mem_p_regs: &MemPRegs) -> Vec<(usize, usize)> {
+        mem_p_regs.iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    #[test]
+    fn mem_map() {
+        let (bytelen, addr) = make_mem_map(&[
+            // Low memory is skipped, even when its usable.
+            (20, 0, 0x9fc00, MMapType::Usable),
+            (20, 0xf0000, 0x10000, MMapType::Reserved),
+            // Usable regions are shrunk to page boundaries.
+            (20, 0x100800, 0x7fe_f000, MMapType::Usable),
+            // Regions smaller than a page are skipped.
+            (20, 0x9000_0000, 0x800, MMapType::Usable),
+            // Padded entries are walked using their size field.
+            (28, 0xfffc_0000, 0x40000, MMapType::Reserved),
+            (20, 0x1_0000_0000, 0x4000_0000, MMapType::Usable),
+        ]);
+
+        let mut mem_p_regs = MemPRegs::new();
+        unsafe { parse_mem_map(&mut mem_p_regs, bytelen, addr) }.unwrap();
+        assert_eq!(regions(&mem_p_regs), [(0x101000, 0x80ef000), (0x1_0000_0000, 0x1_4000_0000)]);
+    }
+
+    #[test]
+    fn empty_mem_map() {
+        let (bytelen, addr) = make_mem_map(&[]);
+        let mut mem_p_regs = MemPRegs::new();
+        unsafe { parse_mem_map(&mut mem_p_regs, bytelen, addr) }.unwrap();
+        assert_eq!(mem_p_regs.len(), 0);
+    }
+
+    #[test]
+    fn add_regions() {
+        let mut mem_p_regs = MemPRegs::new();
+
+        // Empty regions and regions entirely outside the kernel window are ignored.
+        add_mem_phys_regs(&mut mem_p_regs, PhysRegion { start: 0x1000, end: 0x1000 }).unwrap();
+        add_mem_phys_regs(&mut mem_p_regs, PhysRegion { start: PADDR_TOP + 0x1000, end: PADDR_TOP + 0x2000 }).unwrap();
+        assert_eq!(mem_p_regs.len(), 0);
+
+        // Regions crossing the top of the kernel window are clamped.
+        add_mem_phys_regs(&mut mem_p_regs, PhysRegion { start: PADDR_TOP - 0x1000, end: PADDR_TOP + 0x1000 }).unwrap();
+        assert_eq!(regions(&mem_p_regs), [(PADDR_TOP - 0x1000, PADDR_TOP)]);
+    }
+
+    #[test]
+    fn too_many_regions() {
+        let mut mem_p_regs = MemPRegs::new();
+        for i in 1..MAX_NUM_FREEMEM_REG {
+            add_mem_phys_regs(&mut mem_p_regs, PhysRegion { start: i * 0x10000, end: i * 0x10000 + 0x1000 }).unwrap();
+        }
+
+        let (bytelen, addr) = make_mem_map(&[
+            (20, 0x1000_0000, 0x1000, MMapType::Usable),
+            (20, 0x2000_0000, 0x1000, MMapType::Usable),
+        ]);
+        assert!(unsafe { parse_mem_map(&mut mem_p_regs, bytelen, addr) }.is_err());
+        assert_eq!(mem_p_regs.len(), MAX_NUM_FREEMEM_REG);
+    }
+}
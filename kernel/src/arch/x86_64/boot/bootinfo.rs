@@ -1,21 +1,23 @@
 use ufmt::derive::uDebug;
 use crate::arch::x86_64::acpi::AcpiRsdp;
 use crate::basic_types::{CpuId, Paddr, PhysRegion};
-use crate::config::{CONFIG_MAX_NUM_NODES, CONFIG_MAX_NUM_IOAPIC};
+use crate::config::{CONFIG_KERNEL_STACK_BITS, CONFIG_MAX_NUM_FREEMEM_REG, CONFIG_MAX_NUM_IOAPIC, CONFIG_MAX_NUM_NODES};
+use crate::const_assert;
+use crate::utils::bit_usize;
 use crate::utils::fixedarr::FixedArr;
 use super::super::devices::MAX_NUM_DRHU;
+use super::cmdline::CmdLineOpts;
 
 /// The maximum number of reserved regions.
 ///
-/// This is simply set to 16 because thats the value in include/arch/x86/arch. The arm code has
-/// more complex logic to calculate this, but 16 is probably fine.
+/// SeL4 sets this to 16 on x86, which is our default. (See max_num_freemem_reg in kernel.toml.)
 ///
 /// Here's a comment from the riscv code (which also just arbitrarily picks 16):
 ///
 /// > The value for the max number of free memory region is basically an arbitrary
 /// > choice. We could calculate the exact number, but just picking 16 will also
 /// > do for now. Increase this value if the boot fails.
-pub(super) const MAX_NUM_FREEMEM_REG: usize = 16;
+pub(super) const MAX_NUM_FREEMEM_REG: usize = CONFIG_MAX_NUM_FREEMEM_REG;
 
 pub type MemPRegs = FixedArr<PhysRegion, MAX_NUM_FREEMEM_REG>;
 
@@ -58,8 +60,16 @@ pub(super) struct BootState {
     // seL4_X86_BootInfo_VBE vbe_info; /* Potential VBE information from multiboot */
     // seL4_X86_BootInfo_mmap_t mb_mmap_info; /* memory map information from multiboot */
     // seL4_X86_BootInfo_fb_t fb_info; /* framebuffer information as set by bootloader */
+
+    /// The options from the kernel command line
+    pub cmdline: CmdLineOpts,
 }
 
+// BootState lives on the boot stack, along with everything boot_sys calls.
+const_assert!(size_of::<BootState>() <= bit_usize(CONFIG_KERNEL_STACK_BITS) / 2,
+    "BootState doesn't fit on the kernel stack. Increase kernel_stack_bits, or reduce max_num_drhu, \
+     max_num_freemem_reg or max_num_nodes.");
+
 struct BootStateVBE {
 
 }
\ No newline at end of file
@@ -0,0 +1,69 @@
+//! The kernel command line, from the boot loader. This is based on src/arch/x86/kernel/cmdline.c.
+//!
+//! The command line is a list of `key=value` options, separated by spaces. Anything else (like the
+//! kernel's path, which GRUB and QEMU put first) is ignored, and so are keys we don't know. With
+//! `cargo xtask run`, pass it to QEMU with `-- -append "..."`.
+//!
+//! Options:
+//!
+//! - `mitigations=off`: Turn off every speculative execution mitigation not set below.
+//! - `skim`, `ibrs`, `ibpb`, `rsb` and `mds`: Each mitigation in speculation.rs. These are `auto`
+//!   (the default, which is on when the CPU looks vulnerable), `on` or `off`.
+//!
+//! DEPARTURE: SeL4's options are console_port, debug_port and disable_iommu. Our serial ports are
+//! fixed (see console.rs and CONFIG_GDB_SERIAL_PORT), and there's no IOMMU support to disable.
+
+use crate::arch::x86_64::speculation::{MitigationOpts, Setting};
+use crate::kwarnln;
+
+#[derive(Copy, Clone, Default)]
+pub(crate) struct CmdLineOpts {
+    pub mitigations: MitigationOpts,
+}
+
+#[unsafe(link_section = ".boot.text")]
+pub(super) fn parse_cmdline(cmdline: &str) -> CmdLineOpts {
+    let mut opts = CmdLineOpts::default();
+    let mitigations = &mut opts.mitigations;
+
+    for (key, value) in cmdline.split_ascii_whitespace().filter_map(|opt| opt.split_once('=')) {
+        let setting = match key {
+            "mitigations" => {
+                match value {
+                    "auto" => mitigations.all_off = false,
+                    "off" => mitigations.all_off = true,
+                    _ => kwarnln!("Ignoring mitigations={}. Expected auto or off.", value),
+                }
+                continue;
+            }
+            "skim" => &mut mitigations.skim,
+            "ibrs" => &mut mitigations.ibrs,
+            "ibpb" => &mut mitigations.ibpb,
+            "rsb" => &mut mitigations.rsb,
+            "mds" => &mut mitigations.mds,
+            _ => continue,
+        };
+        match Setting::parse(value) {
+            Some(s) => *setting = s,
+            None => kwarnln!("Ignoring {}={}. Expected auto, on or off.", key, value),
+        }
+    }
+    opts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let opts = parse_cmdline("/boot/kernel.elf skim=on  mds=off rsb=maybe console_port=0x3f8 ibpb=off ibpb=auto");
+        let m = opts.mitigations;
+        assert!(!m.all_off);
+        assert!(m.skim == Setting::On && m.mds == Setting::Off);
+        assert!(m.rsb == Setting::Auto && m.ibpb == Setting::Auto && m.ibrs == Setting::Auto);
+
+        assert!(parse_cmdline("mitigations=off").mitigations.all_off);
+        assert!(!parse_cmdline("").mitigations.all_off);
+    }
+}
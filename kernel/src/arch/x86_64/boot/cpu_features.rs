@@ -0,0 +1,53 @@
+//! The CPU features the kernel requires. See the README.
+//!
+//! These are checked before any of them are used, by _start while it's still in 32 bit mode
+//! (boot0.rs). If any are missing, we list them all over serial and halt. Otherwise the first sign
+//! of an old CPU (or an underpowered QEMU `-cpu`) is a triple fault somewhere in early boot, which
+//! is miserable to debug.
+//!
+//! The table lives in .phys.data, because the 32 bit check reads it before paging is set up.
+//!
+//! TODO: There's no AP bring-up yet. APs start in real mode and pass through 32 bit mode on the way
+//! to long mode, like the BSP, so their trampoline should call check_cpu_features too.
+
+use crate::arch::x86_64::cpu::CpuidReg;
+
+const NAME_LEN: usize = 12;
+
+/// One feature bit. This is read by 32 bit assembly, so it's all u32s and a fixed size name.
+#[repr(C)]
+pub(super) struct CpuFeature {
+    pub leaf: u32,
+    pub subleaf: u32,
+    pub reg: CpuidReg,
+    pub bit: u32,
+    pub name_len: u32,
+    pub name: [u8; NAME_LEN],
+}
+
+impl CpuFeature {
+    const fn new(leaf: u32, subleaf: u32, reg: CpuidReg, bit: u32, name: &str) -> Self {
+        assert!(name.len() <= NAME_LEN);
+        let mut buf = [0; NAME_LEN];
+        let mut i = 0;
+        while i < name.len() {
+            buf[i] = name.as_bytes()[i];
+            i += 1;
+        }
+        Self { leaf, subleaf, reg, bit, name_len: name.len() as u32, name: buf }
+    }
+}
+
+#[unsafe(link_section = ".phys.data")]
+pub(super) static REQUIRED_FEATURES: [CpuFeature; 10] = [
+    CpuFeature::new(0x8000_0001, 0, CpuidReg::Edx, 29, "long mode"),
+    CpuFeature::new(0x8000_0001, 0, CpuidReg::Edx, 26, "1GiB pages"),
+    CpuFeature::new(0x8000_0001, 0, CpuidReg::Edx, 20, "NX"),
+    CpuFeature::new(0x8000_0001, 0, CpuidReg::Edx, 11, "SYSCALL"),
+    CpuFeature::new(1, 0, CpuidReg::Ecx, 17, "PCID"),
+    CpuFeature::new(7, 0, CpuidReg::Ebx, 10, "INVPCID"),
+    CpuFeature::new(1, 0, CpuidReg::Ecx, 26, "XSAVE"),
+    CpuFeature::new(7, 0, CpuidReg::Ebx, 0, "FSGSBASE"),
+    CpuFeature::new(1, 0, CpuidReg::Ecx, 24, "TSC-deadline"),
+    CpuFeature::new(1, 0, CpuidReg::Ecx, 21, "x2APIC"),
+];
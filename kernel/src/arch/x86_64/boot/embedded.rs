@@ -0,0 +1,35 @@
+//! The root task, when it's embedded in the kernel image.
+//!
+//! DEPARTURE: SeL4 always loads the root task from the first multiboot module. When the kernel is
+//! built with ROOT_TASK_ELF set (see kernel/build.rs), the root task's ELF file is linked into the
+//! kernel's .root_task section instead. Then the kernel is a single bootable file.
+//!
+//! The image lives inside the kernel image's physical region, so its memory is never handed out as
+//! untyped. It stays reserved after boot, like the rest of the kernel's boot data.
+
+use crate::basic_types::{Paddr, PhysRegion};
+
+/// Page aligned, so the ELF file's segments keep their in-file page offsets.
+#[cfg_attr(not(embedded_root_task), allow(unused))]
+#[repr(C, align(4096))]
+struct RootTaskImage<T>(T);
+
+// ROOT_TASK_IMAGE, in the .root_task section.
+#[cfg(embedded_root_task)]
+include!(concat!(env!("OUT_DIR"), "/root_task.rs"));
+
+/// The physical region holding the embedded root task's ELF file, or None if the kernel was built
+/// without one.
+#[unsafe(link_section = ".boot.text")]
+pub(super) fn embedded_root_task() -> Option<PhysRegion> {
+    #[cfg(embedded_root_task)]
+    {
+        use crate::hardware::KERNEL_ELF_BASE_OFFSET;
+
+        let start = ROOT_TASK_IMAGE.0.as_ptr() as Paddr - KERNEL_ELF_BASE_OFFSET;
+        Some(PhysRegion { start, end: start + ROOT_TASK_IMAGE.0.len() })
+    }
+
+    #[cfg(not(embedded_root_task))]
+    None
+}
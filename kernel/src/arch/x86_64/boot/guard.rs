@@ -0,0 +1,87 @@
+//! Unmapping the kernel stacks' guard pages. See crate::stack.
+//!
+//! The boot page tables (boot0.rs) map low memory with 2MiB pages, and guard pages are 4KiB. So
+//! each 2MiB page with a guard in it is split into a page table first. The boot page directory is
+//! shared by every alias of low memory (the identity map, the physical memory window and the kernel
+//! window), so the guard pages disappear from all of them.
+
+use crate::arch::constants::{LARGE_PAGE_BITS, PAGE_BITS};
+use crate::config::CONFIG_MAX_NUM_NODES;
+use crate::racycell::RacyCell;
+use crate::stack::CoreStacks;
+use crate::utils::bit_usize;
+
+const PDE_LARGE_PAGE: u64 = 1 << 7;
+/// The flags a 2MiB page and the 4KiB pages split from it have in common. (Present, writable, user,
+/// write through, cache disable, accessed and dirty.)
+const PAGE_FLAGS: u64 = 0x7f;
+const LARGE_PAGE_ADDR_MASK: u64 = 0x000f_ffff_ffe0_0000;
+const PAGE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+const PT_ENTRIES: usize = 512;
+
+#[repr(C, align(4096))]
+struct PageTable([u64; PT_ENTRIES]);
+
+/// Enough page tables to split every 2MiB page the stacks touch.
+const NUM_PAGE_TABLES: usize = size_of::<[CoreStacks; CONFIG_MAX_NUM_NODES]>() / bit_usize(LARGE_PAGE_BITS) + 2;
+
+/// These outlive boot, so they're in the kernel's normal .bss.
+static PAGE_TABLES: RacyCell<[PageTable; NUM_PAGE_TABLES]> =
+    RacyCell::new([const { PageTable([0; PT_ENTRIES]) }; NUM_PAGE_TABLES]);
+
+/// Fill pt with 4KiB pages, mapping the same memory as the 2MiB page pde.
+fn split_large_page(pde: u64, pt: &mut PageTable) {
+    let base = pde & LARGE_PAGE_ADDR_MASK;
+    for (i, pte) in pt.0.iter_mut().enumerate() {
+        *pte = (base + ((i as u64) << PAGE_BITS)) | (pde & PAGE_FLAGS);
+    }
+}
+
+/// Unmap every core's stack guard pages.
+///
+/// SAFETY: This must be called once, at boot, while the boot page tables are in use.
+#[cfg(not(test))]
+#[unsafe(link_section = ".boot.text")]
+pub(crate) unsafe fn map_stack_guards() {
+    use super::boot0::BOOT_PML2;
+    use crate::arch::asm::{read_cr3, write_cr3};
+    use crate::hardware::KERNEL_ELF_BASE_OFFSET;
+    use crate::stack::KERNEL_STACKS;
+
+    // The boot page directory is linked at its physical address, which is identity mapped.
+    let pd = unsafe { &raw mut BOOT_PML2.0 };
+    let mut tables = unsafe { PAGE_TABLES.get_mut() }.iter_mut();
+    let stacks = unsafe { &*KERNEL_STACKS.as_ptr() };
+
+    for (_, guard) in stacks.iter().flat_map(CoreStacks::guards) {
+        let paddr = guard.start - KERNEL_ELF_BASE_OFFSET;
+        let pde = unsafe { &mut (*pd)[paddr >> LARGE_PAGE_BITS] };
+        if *pde & PDE_LARGE_PAGE != 0 {
+            let pt = tables.next().expect("Out of page tables for stack guards");
+            split_large_page(*pde, pt);
+            let pt_paddr = (pt as *mut PageTable as usize - KERNEL_ELF_BASE_OFFSET) as u64;
+            *pde = pt_paddr | (*pde & PAGE_FLAGS);
+        }
+
+        let pt = ((*pde & PAGE_ADDR_MASK) as usize + KERNEL_ELF_BASE_OFFSET) as *mut PageTable;
+        unsafe { (*pt).0[(paddr >> PAGE_BITS) % PT_ENTRIES] = 0 };
+    }
+
+    // Flush the old 2MiB mappings out of the TLB.
+    unsafe { write_cr3(read_cr3()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split() {
+        let mut pt = PageTable([0; PT_ENTRIES]);
+        split_large_page(0x0060_0000 | PDE_LARGE_PAGE | 0x3, &mut pt);
+        assert_eq!(pt.0[0], 0x0060_0003);
+        assert_eq!(pt.0[1], 0x0060_1003);
+        assert_eq!(pt.0[PT_ENTRIES - 1], 0x007f_f003);
+    }
+}
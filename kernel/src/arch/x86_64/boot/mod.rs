@@ -1,4 +1,10 @@
+// The entry point defines _start, which would clash with the C runtime's in host unit tests.
+#[cfg(not(test))]
 mod boot0;
 pub mod boot1;
 mod multiboot;
 mod bootinfo;
+mod embedded;
+mod guard;
+mod cpu_features;
+mod cmdline;
@@ -62,8 +62,8 @@ const MULTIBOOT_HEADER_MAGIC: u32 = 0x1BADB002;
 const fn gfx_flags() -> (u32, u32) {
     match CONFIG_MULTIBOOT_GRAPHICS_MODE {
         ConfigGraphicsMode::None => (0, 0),
-        ConfigGraphicsMode::Text => (MultibootFlags::VideoMode as u32, 0),
-        ConfigGraphicsMode::Linear => (MultibootFlags::VideoMode as u32, 1),
+        ConfigGraphicsMode::Linear => (MultibootFlags::VideoMode as u32, 0),
+        ConfigGraphicsMode::Text => (MultibootFlags::VideoMode as u32, 1),
     }
 }
 
@@ -338,3 +338,34 @@ pub(crate) struct MultibootModule {
     /// padding to take it to 16 bytes (must be zero)
     _pad: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::x86_64::alloc_low_memory;
+
+    #[test]
+    fn header_checksum() {
+        assert_eq!(MULTIBOOT_HEADER.magic.wrapping_add(MULTIBOOT_HEADER.flags).wrapping_add(MULTIBOOT_HEADER.checksum), 0);
+    }
+
+    #[test]
+    fn slices() {
+        let mem = alloc_low_memory(16);
+        for (i, byte) in mem.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let addr = mem.as_ptr() as u32;
+
+        let slice = MultibootSlice::<u32> { len: 3, addr: U32Ptr::new(addr) };
+        let expected = [0x03020100, 0x07060504, 0x0b0a0908];
+        assert_eq!(unsafe { slice.to_slice(&()) }, &expected);
+
+        // Byte length slices drop any partial item at the end.
+        let slice = MultibootByteLenSlice::<u32> { byte_len: 14, addr: U32Ptr::new(addr) };
+        assert_eq!(unsafe { slice.to_slice(&()) }, &expected);
+
+        let empty = MultibootSlice::<u32> { len: 0, addr: U32Ptr::new(addr) };
+        assert!(unsafe { empty.to_slice(&()) }.is_empty());
+    }
+}
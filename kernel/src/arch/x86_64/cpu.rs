@@ -1,11 +1,14 @@
-//! From SeL4 cpu_identification.c.
+//! CPU identification. From SeL4 cpu_identification.c.
+//!
+//! Each core fills in a [CpuInfo] as it boots (init_cpu_info), and the rest of the kernel asks it
+//! about the CPU, rather than running CPUID itself. This is the only place which runs CPUID, except
+//! for the 32 bit feature check in boot0, which runs before there's anywhere to keep the results.
 
-use core::arch::asm;
-use core::arch::x86_64::__cpuid;
-use core::slice;
+use core::arch::x86_64::{CpuidResult, __cpuid_count};
 use ufmt::derive::uDebug;
-use crate::{const_assert, kpanic, kprintln, kwarnln};
-use crate::config::CONFIG_KERNEL_SKIM_WINDOW;
+use crate::{kprintln, kwarnln};
+use crate::config::CONFIG_MAX_NUM_NODES;
+use crate::racycell::RacyCell;
 
 #[derive(uDebug, Copy, Clone, Eq, PartialEq)]
 pub enum CpuVendor {
@@ -14,112 +17,805 @@ pub enum CpuVendor {
     Other,
 }
 
-struct CpuInfo {
-    // vendor: CpuVendor,
+/// Run CPUID.
+pub(crate) fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    unsafe { __cpuid_count(leaf, subleaf) }
+}
+
+/// Which CPUID output register something is in.
+#[derive(Copy, Clone)]
+#[repr(u32)]
+pub(crate) enum CpuidReg {
+    Eax = 0,
+    Ebx = 1,
+    Ecx = 2,
+    Edx = 3,
+}
+
+impl CpuidReg {
+    pub fn get(self, regs: CpuidResult) -> u32 {
+        match self {
+            CpuidReg::Eax => regs.eax,
+            CpuidReg::Ebx => regs.ebx,
+            CpuidReg::Ecx => regs.ecx,
+            CpuidReg::Edx => regs.edx,
+        }
+    }
+}
+
+const EXTENDED_LEAVES: u32 = 0x8000_0000;
+
+/// The CPUID registers [CpuInfo] keeps feature bits from. Features are numbered
+/// `word * 32 + bit`, where word is the index in here. (This is how Linux numbers them.)
+const FEATURE_WORDS: [(u32, u32, CpuidReg); 9] = [
+    (1, 0, CpuidReg::Ecx),
+    (1, 0, CpuidReg::Edx),
+    (7, 0, CpuidReg::Ebx),
+    (7, 0, CpuidReg::Ecx),
+    (7, 0, CpuidReg::Edx),
+    (0x8000_0001, 0, CpuidReg::Ecx),
+    (0x8000_0001, 0, CpuidReg::Edx),
+    (0xd, 1, CpuidReg::Eax),
+    (0x8000_0008, 0, CpuidReg::Ebx),
+];
+
+/// The number of XSAVE state components [CpuInfo] keeps the layout of. Up to 19 are defined so far.
+const XSAVE_COMPONENTS: usize = 32;
+
+/// The size of the legacy (FXSAVE) area and the XSAVE header, which hold components 0 and 1 (x87
+/// and SSE). The other components follow them.
+const XSAVE_LEGACY_SIZE: u32 = 576;
+
+/// CPU feature bits. Add more as they're needed.
+#[derive(uDebug, Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub(crate) enum Feature {
+    // Leaf 1, ecx.
+    Sse3 = 0,
+    Ssse3 = 9,
+    Fma = 12,
+    Cx16 = 13,
+    Pcid = 17,
+    Sse41 = 19,
+    Sse42 = 20,
+    X2Apic = 21,
+    Movbe = 22,
+    Popcnt = 23,
+    TscDeadline = 24,
+    Xsave = 26,
+    Osxsave = 27,
+    Avx = 28,
+    F16c = 29,
+    Hypervisor = 31,
+
+    // Leaf 1, edx.
+    Fpu = 32,
+    Pse = 32 + 3,
+    Tsc = 32 + 4,
+    Msr = 32 + 5,
+    Pae = 32 + 6,
+    Apic = 32 + 9,
+    Pge = 32 + 13,
+    Pat = 32 + 16,
+    Clflush = 32 + 19,
+    Fxsr = 32 + 24,
+    Sse = 32 + 25,
+    Sse2 = 32 + 26,
+
+    // Leaf 7, ebx.
+    Fsgsbase = 64,
+    Bmi1 = 64 + 3,
+    Avx2 = 64 + 5,
+    Smep = 64 + 7,
+    Bmi2 = 64 + 8,
+    Invpcid = 64 + 10,
+    Avx512f = 64 + 16,
+    Smap = 64 + 20,
+
+    // Leaf 7, ecx.
+    Umip = 96 + 2,
+    Pku = 96 + 3,
+    La57 = 96 + 16,
+
+    // Leaf 7, edx.
+    MdClear = 128 + 10,
+    SpecCtrl = 128 + 26,
+    Stibp = 128 + 27,
+    FlushL1d = 128 + 28,
+    ArchCapabilities = 128 + 29,
+    Ssbd = 128 + 31,
 
+    // Leaf 0x8000_0001, ecx.
+    LahfLm = 160,
+    Lzcnt = 160 + 5,
+    TopologyExtensions = 160 + 22,
+
+    // Leaf 0x8000_0001, edx.
+    Syscall = 192 + 11,
+    Nx = 192 + 20,
+    Page1Gb = 192 + 26,
+    Rdtscp = 192 + 27,
+    LongMode = 192 + 29,
+
+    // Leaf 0xd subleaf 1, eax.
+    Xsaveopt = 224,
+    Xsavec = 224 + 1,
+    Xsaves = 224 + 3,
+
+    // Leaf 0x8000_0008, ebx. (AMD's speculation controls.)
+    AmdIbpb = 256 + 12,
+    AmdIbrs = 256 + 14,
+    AmdStibp = 256 + 15,
+    AmdSsbd = 256 + 24,
 }
 
-/// VendorInfo is a 12 byte long string stored in ebx, edx, ecx after performing cpuid(0).
-#[repr(C)]
-struct VendorInfo(u32, u32, u32);
-const_assert!(size_of::<VendorInfo>() == 12);
+/// The features in x86-64-v3, which the kernel is compiled for (see .cargo/config.toml).
+const X86_64_V3: &[Feature] = &[
+    // v2
+    Feature::Cx16, Feature::LahfLm, Feature::Popcnt, Feature::Sse3, Feature::Sse41, Feature::Sse42,
+    Feature::Ssse3,
+    // v3
+    Feature::Avx, Feature::Avx2, Feature::Bmi1, Feature::Bmi2, Feature::F16c, Feature::Fma,
+    Feature::Lzcnt, Feature::Movbe,
+    // The psABI lists OSXSAVE, which says the OS has turned XSAVE on. That's us (fpu.rs), and it
+    // happens after this check, so we check the CPU has XSAVE instead.
+    Feature::Xsave,
+];
 
-impl VendorInfo {
-    fn new() -> Self {
-        let vendor_info = unsafe { __cpuid(0) };
-        Self(vendor_info.ebx, vendor_info.edx, vendor_info.ecx)
+/// The microarchitectures we know about. This is only used to tell people what they're running on,
+/// and isn't exhaustive.
+#[derive(uDebug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Microarch {
+    Unknown,
+    Nehalem,
+    Westmere,
+    SandyBridge,
+    IvyBridge,
+    Haswell,
+    Broadwell,
+    Skylake,
+    IceLake,
+    TigerLake,
+    SapphireRapids,
+    AlderLake,
+    RaptorLake,
+    Zen,
+    Zen2,
+    Zen3,
+    Zen4,
+    Zen5,
+}
+
+impl Microarch {
+    fn identify(vendor: CpuVendor, family: u32, model: u32) -> Self {
+        use Microarch::*;
+        match (vendor, family) {
+            (CpuVendor::Intel, 6) => match model {
+                0x1a | 0x1e | 0x1f | 0x2e => Nehalem,
+                0x25 | 0x2c | 0x2f => Westmere,
+                0x2a | 0x2d => SandyBridge,
+                0x3a | 0x3e => IvyBridge,
+                0x3c | 0x3f | 0x45 | 0x46 => Haswell,
+                0x3d | 0x47 | 0x4f | 0x56 => Broadwell,
+                // Kaby Lake, Coffee Lake and Comet Lake are all Skylake inside.
+                0x4e | 0x5e | 0x55 | 0x8e | 0x9e | 0xa5 | 0xa6 => Skylake,
+                0x6a | 0x6c | 0x7d | 0x7e => IceLake,
+                0x8c | 0x8d => TigerLake,
+                0x8f => SapphireRapids,
+                0x97 | 0x9a => AlderLake,
+                0xb7 | 0xba | 0xbf => RaptorLake,
+                _ => Unknown,
+            },
+            (CpuVendor::Amd, 0x17) if model < 0x30 => Zen,
+            (CpuVendor::Amd, 0x17) => Zen2,
+            (CpuVendor::Amd, 0x19) => match model {
+                0x10..=0x1f | 0x60..=0x7f | 0xa0..=0xaf => Zen4,
+                _ => Zen3,
+            },
+            (CpuVendor::Amd, 0x1a) => Zen5,
+            _ => Unknown,
+        }
     }
+}
 
-    fn as_slice(&self) -> &[u8] {
-        let ptr = self as *const _ as *const u8;
-        unsafe {
-            slice::from_raw_parts(ptr, size_of::<VendorInfo>())
+#[derive(uDebug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum CacheType {
+    Data,
+    Instruction,
+    Unified,
+}
+
+impl CacheType {
+    /// The type field in leaves 4, 0x8000_001d and 0x18. 0 means there are no more.
+    fn from_cpuid(value: u32) -> Option<Self> {
+        match value {
+            1 => Some(CacheType::Data),
+            2 => Some(CacheType::Instruction),
+            3 => Some(CacheType::Unified),
+            _ => None,
         }
     }
 
-    fn as_str(&self) -> &str {
-        let Ok(s) = (unsafe { core::str::from_utf8(self.as_slice()) }) else {
-            kpanic!("CPU vendor string does not contain valid bytes");
-        };
-        s
+    fn suffix(self) -> &'static str {
+        match self {
+            CacheType::Data => "d",
+            CacheType::Instruction => "i",
+            CacheType::Unified => "",
+        }
     }
+}
 
-    // fn as_vendor(&self) -> CpuVendor {
-    //     match self.as_slice() {
-    //         b"GenuineIntel" => CpuVendor::Intel,
-    //         b"AuthenticAMD" => CpuVendor::Amd,
-    //         _ => CpuVendor::Other,
-    //     }
-    // }
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub(crate) struct CacheInfo {
+    pub level: u32,
+    pub kind: CacheType,
+    pub size: u32,
+    pub line_size: u32,
+    pub ways: u32,
+    /// The number of logical processors sharing this cache.
+    pub shared_by: u32,
 }
 
-/// Get the IA32_ARCH_CAPABILITIES MSR. In SeL4 this code is generated and typesafe, which is nice!
-#[unsafe(link_section = ".boot.text")]
-fn cpuid_007h_edx_get_ia32_arch_cap_msr(cpuid_007_edx: u32) -> bool {
-    // The CAP_MSR is bit 29.
-    let mask = 1u32 << 29;
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub(crate) struct TlbInfo {
+    pub level: u32,
+    pub kind: CacheType,
+    /// Bit n is set for each page size 4KiB << (9 * n) this TLB holds. (So 1 is 4KiB, 2 is 2MiB and
+    /// 4 is 1GiB.)
+    pub page_sizes: u32,
+    pub entries: u32,
+    /// 0 for fully associative.
+    pub ways: u32,
+}
+
+pub(crate) const PAGES_4K: u32 = 1;
+pub(crate) const PAGES_2M: u32 = 2;
+pub(crate) const PAGES_1G: u32 = 4;
 
-    (cpuid_007_edx & mask) != 0
+/// Where this core sits in the system, from leaf 0xb or 0x1f. Each level's ID is a bit field in
+/// the x2APIC ID.
+#[derive(Copy, Clone, Default, Eq, PartialEq)]
+pub(crate) struct Topology {
+    pub x2apic_id: u32,
+    /// The low bits of the x2APIC ID which select the thread in a core.
+    pub thread_bits: u32,
+    /// The low bits of the x2APIC ID which select the thread in a package.
+    pub package_bits: u32,
+    pub threads_per_core: u32,
+    pub threads_per_package: u32,
 }
 
-/// RDCL_NO: The processor is not susceptible to Rogue Data Cache Load (RDCL).
-///
-/// TODO: Make capabilities MSR into a NewType and add functions like this to read the bits out.
-#[unsafe(link_section = ".boot.text")]
-pub fn ia32_arch_caps_msr_get_rdcl_no(ia32_arch_caps_msr: u64) -> bool {
-    (ia32_arch_caps_msr & 0x1) != 0
+impl Topology {
+    pub fn thread_id(&self) -> u32 {
+        self.x2apic_id & mask(self.thread_bits)
+    }
+
+    pub fn core_id(&self) -> u32 {
+        (self.x2apic_id & mask(self.package_bits)) >> self.thread_bits
+    }
+
+    pub fn package_id(&self) -> u32 {
+        self.x2apic_id.checked_shr(self.package_bits).unwrap_or(0)
+    }
+}
+
+fn mask(bits: u32) -> u32 {
+    1u32.checked_shl(bits).map_or(u32::MAX, |b| b - 1)
 }
 
-unsafe fn rdmsr(msr: u32) -> u64 {
-    let (high, low): (u32, u32);
-    unsafe {
-        asm!("rdmsr",
-            out("eax") low,
-            out("edx") high,
-            in("ecx") msr
-        );
+const MAX_CACHES: usize = 8;
+const MAX_TLBS: usize = 16;
+
+/// Everything we know about a core.
+#[derive(Copy, Clone)]
+pub(crate) struct CpuInfo {
+    pub vendor: CpuVendor,
+    vendor_string: [u8; 12],
+    /// The display family and model. (The base values, plus the extended ones where those apply.)
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    pub microarch: Microarch,
+    brand: [u8; 48],
+    pub max_leaf: u32,
+    pub max_extended_leaf: u32,
+    pub phys_addr_bits: u32,
+    pub virt_addr_bits: u32,
+    features: [u32; FEATURE_WORDS.len()],
+    caches: [Option<CacheInfo>; MAX_CACHES],
+    tlbs: [Option<TlbInfo>; MAX_TLBS],
+    pub topology: Topology,
+    /// The TSC frequency, from leaf 0x15 (or the base frequency in leaf 0x16). 0 if the CPU
+    /// doesn't say.
+    pub tsc_hz: u64,
+    /// The XSAVE state components XCR0 can turn on, from leaf 0xd.
+    pub xsave_components: u64,
+    /// Where each XSAVE state component ends, in XSAVE's standard format. 0 if it's unsupported.
+    xsave_ends: [u32; XSAVE_COMPONENTS],
+}
+
+impl CpuInfo {
+    const EMPTY: Self = Self {
+        vendor: CpuVendor::Other,
+        vendor_string: [0; 12],
+        family: 0,
+        model: 0,
+        stepping: 0,
+        microarch: Microarch::Unknown,
+        brand: [0; 48],
+        max_leaf: 0,
+        max_extended_leaf: 0,
+        phys_addr_bits: 0,
+        virt_addr_bits: 0,
+        features: [0; FEATURE_WORDS.len()],
+        caches: [None; MAX_CACHES],
+        tlbs: [None; MAX_TLBS],
+        topology: Topology { x2apic_id: 0, thread_bits: 0, package_bits: 0, threads_per_core: 0, threads_per_package: 0 },
+        tsc_hz: 0,
+        xsave_components: 0,
+        xsave_ends: [0; XSAVE_COMPONENTS],
+    };
+
+    /// Identify a CPU. cpuid(leaf, subleaf) queries it.
+    fn from_cpuid(cpuid: impl Fn(u32, u32) -> CpuidResult) -> Self {
+        let mut info = Self::EMPTY;
+
+        let leaf0 = cpuid(0, 0);
+        info.max_leaf = leaf0.eax;
+        // The vendor string is in ebx, edx, ecx (in that order).
+        for (i, reg) in [leaf0.ebx, leaf0.edx, leaf0.ecx].into_iter().enumerate() {
+            info.vendor_string[i * 4..i * 4 + 4].copy_from_slice(&reg.to_le_bytes());
+        }
+        info.vendor = match &info.vendor_string {
+            b"GenuineIntel" => CpuVendor::Intel,
+            b"AuthenticAMD" => CpuVendor::Amd,
+            _ => CpuVendor::Other,
+        };
+        info.max_extended_leaf = cpuid(EXTENDED_LEAVES, 0).eax;
+
+        // Leaves past the maximum return junk, so don't ask.
+        let (max_leaf, max_extended_leaf) = (info.max_leaf, info.max_extended_leaf);
+        let has_leaf = |leaf: u32| leaf <= if leaf >= EXTENDED_LEAVES { max_extended_leaf } else { max_leaf };
+
+        let leaf1 = cpuid(1, 0);
+        (info.family, info.model, info.stepping) = decode_signature(leaf1.eax);
+        info.microarch = Microarch::identify(info.vendor, info.family, info.model);
+
+        for (word, &(leaf, subleaf, reg)) in info.features.iter_mut().zip(FEATURE_WORDS.iter()) {
+            if has_leaf(leaf) {
+                *word = reg.get(cpuid(leaf, subleaf));
+            }
+        }
+
+        if has_leaf(0x8000_0004) {
+            for (i, leaf) in (0x8000_0002..=0x8000_0004).enumerate() {
+                let regs = cpuid(leaf, 0);
+                for (j, reg) in [regs.eax, regs.ebx, regs.ecx, regs.edx].into_iter().enumerate() {
+                    let offset = i * 16 + j * 4;
+                    info.brand[offset..offset + 4].copy_from_slice(&reg.to_le_bytes());
+                }
+            }
+        }
+
+        if has_leaf(0x8000_0008) {
+            let eax = cpuid(0x8000_0008, 0).eax;
+            info.phys_addr_bits = eax & 0xff;
+            info.virt_addr_bits = (eax >> 8) & 0xff;
+        }
+
+        // Intel and AMD describe their caches with the same layout, in different leaves.
+        let cache_leaf = match info.vendor {
+            CpuVendor::Amd if info.has(Feature::TopologyExtensions) => Some(0x8000_001d),
+            CpuVendor::Amd => None,
+            _ => Some(4),
+        };
+        if let Some(leaf) = cache_leaf.filter(|&l| has_leaf(l)) {
+            info.read_caches(|subleaf| cpuid(leaf, subleaf));
+        }
+
+        match info.vendor {
+            CpuVendor::Amd => info.read_amd_tlbs(&cpuid, has_leaf(0x8000_0006)),
+            _ if has_leaf(0x18) => info.read_intel_tlbs(|subleaf| cpuid(0x18, subleaf)),
+            _ => {}
+        }
+
+        let topology_leaf = [0x1f, 0xb].into_iter()
+            .find(|&leaf| has_leaf(leaf) && cpuid(leaf, 0).ebx != 0);
+        info.topology = match topology_leaf {
+            Some(leaf) => read_topology(|subleaf| cpuid(leaf, subleaf)),
+            None => {
+                // Only the 8 bit initial APIC ID, and the number of IDs in the package.
+                let threads = (leaf1.ebx >> 16) & 0xff;
+                Topology {
+                    x2apic_id: leaf1.ebx >> 24,
+                    thread_bits: 0,
+                    package_bits: threads.max(1).next_power_of_two().trailing_zeros(),
+                    threads_per_core: 1,
+                    threads_per_package: threads,
+                }
+            }
+        };
+
+        // Leaf 0x15 is the TSC's ratio to the crystal clock, and the crystal's frequency. Some CPUs
+        // leave the crystal out, but the TSC runs at the base frequency anyway.
+        let tsc = Some(0x15).filter(|&l| has_leaf(l)).map(|l| cpuid(l, 0)).filter(|r| r.eax != 0 && r.ecx != 0);
+        info.tsc_hz = match tsc {
+            Some(r) => r.ecx as u64 * r.ebx as u64 / r.eax as u64,
+            None if has_leaf(0x16) => (cpuid(0x16, 0).eax & 0xffff) as u64 * 1_000_000,
+            None => 0,
+        };
+
+        if has_leaf(0xd) {
+            let leaf = cpuid(0xd, 0);
+            info.xsave_components = (leaf.edx as u64) << 32 | leaf.eax as u64;
+            // Subleaf i is component i's size (eax) and offset (ebx), for i >= 2.
+            for (i, end) in info.xsave_ends.iter_mut().enumerate().skip(2) {
+                if info.xsave_components >> i & 1 != 0 {
+                    let regs = cpuid(0xd, i as u32);
+                    *end = regs.ebx + regs.eax;
+                }
+            }
+        }
+
+        info
+    }
+
+    /// Leaf 4 (Intel) or 0x8000_001d (AMD).
+    fn read_caches(&mut self, cpuid: impl Fn(u32) -> CpuidResult) {
+        for (subleaf, slot) in (0..).zip(self.caches.iter_mut()) {
+            let regs = cpuid(subleaf);
+            let Some(kind) = CacheType::from_cpuid(regs.eax & 0x1f) else { break };
+            let line_size = (regs.ebx & 0xfff) + 1;
+            let partitions = ((regs.ebx >> 12) & 0x3ff) + 1;
+            let ways = (regs.ebx >> 22) + 1;
+            let sets = regs.ecx.wrapping_add(1);
+            *slot = Some(CacheInfo {
+                level: (regs.eax >> 5) & 0x7,
+                kind,
+                size: line_size * partitions * ways * sets,
+                line_size,
+                ways,
+                shared_by: ((regs.eax >> 14) & 0xfff) + 1,
+            });
+        }
+    }
+
+    /// Leaf 0x18, Intel's deterministic address translation parameters.
+    fn read_intel_tlbs(&mut self, cpuid: impl Fn(u32) -> CpuidResult) {
+        let max_subleaf = cpuid(0).eax;
+        let mut slots = self.tlbs.iter_mut();
+        for subleaf in 0..=max_subleaf {
+            let regs = cpuid(subleaf);
+            // Types 4 and 5 are load only and store only data TLBs.
+            let kind = match regs.edx & 0x1f {
+                4 | 5 => Some(CacheType::Data),
+                t => CacheType::from_cpuid(t),
+            };
+            // Unlike the cache leaves, invalid subleaves can come before valid ones.
+            let Some(kind) = kind else { continue };
+            let Some(slot) = slots.next() else { break };
+            let fully_associative = regs.edx & (1 << 8) != 0;
+            let ways = regs.ebx >> 16;
+            // Bits 0-3 are 4KiB, 2MiB, 4MiB and 1GiB pages. We never use 4MiB pages.
+            let page_sizes = (regs.ebx & 0b11) | ((regs.ebx >> 1) & PAGES_1G);
+            *slot = Some(TlbInfo {
+                level: (regs.edx >> 5) & 0x7,
+                kind,
+                page_sizes,
+                entries: ways * regs.ecx,
+                ways: if fully_associative { 0 } else { ways },
+            });
+        }
+    }
+
+    /// Leaves 0x8000_0005 and 0x8000_0006, AMD's L1 and L2 TLBs.
+    fn read_amd_tlbs(&mut self, cpuid: impl Fn(u32, u32) -> CpuidResult, has_l2: bool) {
+        let l1 = cpuid(0x8000_0005, 0);
+        // Each register describes a data and an instruction TLB. eax is 2MiB pages, ebx is 4KiB.
+        let mut tlbs: [(u32, CacheType, u32, u32, Option<u32>); 8] = [
+            (1, CacheType::Data, PAGES_4K, (l1.ebx >> 16) & 0xff, amd_l1_ways(l1.ebx >> 24)),
+            (1, CacheType::Instruction, PAGES_4K, l1.ebx & 0xff, amd_l1_ways((l1.ebx >> 8) & 0xff)),
+            (1, CacheType::Data, PAGES_2M, (l1.eax >> 16) & 0xff, amd_l1_ways(l1.eax >> 24)),
+            (1, CacheType::Instruction, PAGES_2M, l1.eax & 0xff, amd_l1_ways((l1.eax >> 8) & 0xff)),
+            (0, CacheType::Data, 0, 0, None),
+            (0, CacheType::Data, 0, 0, None),
+            (0, CacheType::Data, 0, 0, None),
+            (0, CacheType::Data, 0, 0, None),
+        ];
+        if has_l2 {
+            let l2 = cpuid(0x8000_0006, 0);
+            tlbs[4] = (2, CacheType::Data, PAGES_4K, (l2.ebx >> 16) & 0xfff, amd_l2_ways(l2.ebx >> 28));
+            tlbs[5] = (2, CacheType::Instruction, PAGES_4K, l2.ebx & 0xfff, amd_l2_ways((l2.ebx >> 12) & 0xf));
+            tlbs[6] = (2, CacheType::Data, PAGES_2M, (l2.eax >> 16) & 0xfff, amd_l2_ways(l2.eax >> 28));
+            tlbs[7] = (2, CacheType::Instruction, PAGES_2M, l2.eax & 0xfff, amd_l2_ways((l2.eax >> 12) & 0xf));
+        }
+
+        let valid = tlbs.into_iter()
+            .filter_map(|(level, kind, page_sizes, entries, ways)| {
+                let ways = ways?;
+                (entries != 0).then_some(TlbInfo { level, kind, page_sizes, entries, ways })
+            });
+        for (slot, tlb) in self.tlbs.iter_mut().zip(valid) {
+            *slot = Some(tlb);
+        }
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        let bit = feature as u32;
+        self.features[bit as usize / 32] & (1 << (bit % 32)) != 0
+    }
+
+    /// The size of the area XSAVE writes with components turned on in XCR0. This is what leaf 0xd's
+    /// ebx says once XCR0 is set.
+    pub fn xsave_size(&self, components: u64) -> usize {
+        self.xsave_ends.iter().enumerate()
+            .filter(|&(i, _)| components >> i & 1 != 0)
+            .fold(XSAVE_LEGACY_SIZE, |size, (_, &end)| size.max(end)) as usize
+    }
+
+    /// A CPU with only the given features, for tests elsewhere.
+    #[cfg(test)]
+    pub fn with_features(vendor: CpuVendor, features: &[Feature]) -> Self {
+        let mut info = Self { vendor, ..Self::EMPTY };
+        for &feature in features {
+            let bit = feature as u32;
+            info.features[bit as usize / 32] |= 1 << (bit % 32);
+        }
+        info
+    }
+
+    pub fn vendor_string(&self) -> &str {
+        core::str::from_utf8(&self.vendor_string).unwrap_or("(not utf8)")
+    }
+
+    /// The marketing name, like "Intel(R) Core(TM) i7-8700 CPU @ 3.20GHz".
+    pub fn brand(&self) -> &str {
+        let len = self.brand.iter().position(|&b| b == 0).unwrap_or(self.brand.len());
+        core::str::from_utf8(&self.brand[..len]).unwrap_or("(not utf8)").trim()
+    }
+
+    pub fn caches(&self) -> impl Iterator<Item = &CacheInfo> {
+        self.caches.iter().flatten()
+    }
+
+    pub fn tlbs(&self) -> impl Iterator<Item = &TlbInfo> {
+        self.tlbs.iter().flatten()
+    }
+
+    fn print(&self, core: usize) {
+        kprintln!("CPU {}: {}", core, self.brand());
+        kprintln!("    {} family 0x{:x} model 0x{:x} stepping 0x{:x} ({:?})",
+            self.vendor_string(), self.family, self.model, self.stepping, self.microarch);
+        let t = &self.topology;
+        kprintln!("    x2APIC ID {}: package {} core {} thread {}",
+            t.x2apic_id, t.package_id(), t.core_id(), t.thread_id());
+        if self.tsc_hz != 0 {
+            kprintln!("    TSC: {} MHz", self.tsc_hz / 1_000_000);
+        }
+        for c in self.caches() {
+            kprintln!("    L{}{} cache: {}KiB, {}-way, {}B lines, shared by {} threads",
+                c.level, c.kind.suffix(), c.size / 1024, c.ways, c.line_size, c.shared_by);
+        }
+        for tlb in self.tlbs() {
+            kprintln!("    L{}{} TLB: {} entries, {}-way (0 is fully associative), page sizes 0x{:x}",
+                tlb.level, tlb.kind.suffix(), tlb.entries, tlb.ways, tlb.page_sizes);
+        }
     }
-    ((high as u64) << 32) | (low as u64)
 }
 
-const IA32_ARCH_CAPABILITIES_MSR: u32 = 0x10A;
+/// Decode the family, model and stepping from leaf 1's eax. The extended family only applies to
+/// family 0xf, and the extended model to families 6 and 0xf.
+fn decode_signature(eax: u32) -> (u32, u32, u32) {
+    let stepping = eax & 0xf;
+    let base_model = (eax >> 4) & 0xf;
+    let base_family = (eax >> 8) & 0xf;
+    let family = match base_family {
+        0xf => base_family + ((eax >> 20) & 0xff),
+        f => f,
+    };
+    let model = match base_family {
+        6 | 0xf => base_model | (((eax >> 16) & 0xf) << 4),
+        _ => base_model,
+    };
+    (family, model, stepping)
+}
 
-/// Returns None if the CPU doesn't support capabilities MSR.
-///
-/// Documentation:
-/// https://www.intel.com/content/www/us/en/developer/articles/technical/software-security-guidance/technical-documentation/cpuid-enumeration-and-architectural-msrs.html
-pub fn read_ia32_arch_cap_msr() -> Option<u64> {
-    let cpuid_007_edx = unsafe { __cpuid(0x7) }.edx;
-    if cpuid_007h_edx_get_ia32_arch_cap_msr(cpuid_007_edx) {
-        let msr = unsafe { rdmsr(IA32_ARCH_CAPABILITIES_MSR) };
+/// Leaf 0xb or 0x1f. Each subleaf is a level (thread, core, and on 0x1f, module, tile and die),
+/// and gives the number of x2APIC ID bits below the next level up.
+fn read_topology(cpuid: impl Fn(u32) -> CpuidResult) -> Topology {
+    const LEVEL_THREAD: u32 = 1;
 
-        Some(msr)
-    } else {
-        None
+    let mut topology = Topology { x2apic_id: cpuid(0).edx, ..Default::default() };
+    for subleaf in 0.. {
+        let regs = cpuid(subleaf);
+        let level_type = (regs.ecx >> 8) & 0xff;
+        if level_type == 0 {
+            break;
+        }
+        let shift = regs.eax & 0x1f;
+        let threads = regs.ebx & 0xffff;
+        if level_type == LEVEL_THREAD {
+            topology.thread_bits = shift;
+            topology.threads_per_core = threads;
+        }
+        // The last level is the package.
+        topology.package_bits = shift;
+        topology.threads_per_package = threads;
     }
+    topology
 }
 
+/// AMD's L1 TLB associativity. 0xff is fully associative.
+fn amd_l1_ways(value: u32) -> Option<u32> {
+    match value {
+        0 => None,
+        0xff => Some(0),
+        ways => Some(ways),
+    }
+}
+
+/// AMD's L2 TLB associativity is encoded.
+fn amd_l2_ways(value: u32) -> Option<u32> {
+    match value {
+        0 => None,
+        1..=4 => Some(value),
+        5 => Some(6),
+        6 => Some(8),
+        8 => Some(16),
+        0xa => Some(32),
+        0xb => Some(48),
+        0xc => Some(64),
+        0xd => Some(96),
+        0xe => Some(128),
+        0xf => Some(0),
+        _ => None,
+    }
+}
+
+static CPU_INFO: RacyCell<[CpuInfo; CONFIG_MAX_NUM_NODES]> =
+    RacyCell::new([CpuInfo::EMPTY; CONFIG_MAX_NUM_NODES]);
+
+/// Identify the current CPU, and record it as core's. Each core calls this once as it boots.
+///
+/// DEPARTURE: SeL4 checks the microarchitecture against the one the kernel was configured for
+/// (CONFIG_ARCH_X86_SKYLAKE and friends). We're compiled for x86-64-v3 instead, so we check for
+/// that.
 #[unsafe(link_section = ".boot.text")]
-pub fn x86_cpuid_get_vendor() -> CpuVendor {
-    // DEPARTURE: I've dramatically simplified this code. Hopefully, keeping the same behaviour.
+pub(crate) fn init_cpu_info(core: usize) -> &'static CpuInfo {
+    let info = unsafe { &mut CPU_INFO.get_mut()[core] };
+    *info = CpuInfo::from_cpuid(cpuid);
+    info.print(core);
 
-    // The SeL4 code populates a static CPU info struct, and only uses it in one place. I'm just
-    // going to combine all that behaviour together.
-    match VendorInfo::new().as_slice() {
+    if info.vendor == CpuVendor::Other {
         // SeL4 only officially supports AMD and Intel CPUs.
-        b"GenuineIntel" => CpuVendor::Intel,
-        b"AuthenticAMD" => CpuVendor::Amd,
-        slice => {
-            let Ok(vendor_str) = (unsafe { core::str::from_utf8(slice) }) else {
-                kpanic!("CPU vendor string does not contain valid bytes");
-            };
+        kwarnln!("Warning: Your x86 CPU has an unsupported vendor, '{}' \n\
+               \tYour setup may not be able to competently run seL4 as \
+               \tintended.\
+               \tCurrently supported x86 vendors are AMD and Intel.",
+               info.vendor_string());
+    }
+    if X86_64_V3.iter().any(|&f| !info.has(f)) {
+        kwarnln!("Warning: The kernel is built for x86-64-v3, which this CPU ({:?}) doesn't fully \
+               support. Expect invalid opcode faults.", info.microarch);
+    }
+    info
+}
+
+/// What core found in init_cpu_info.
+pub(crate) fn cpu_info(core: usize) -> &'static CpuInfo {
+    unsafe { &(*CPU_INFO.as_ptr())[core] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regs(eax: u32, ebx: u32, ecx: u32, edx: u32) -> CpuidResult {
+        CpuidResult { eax, ebx, ecx, edx }
+    }
+
+    /// A CPU made of a table of (leaf, subleaf, registers). Anything else reads as zero.
+    fn fake_cpu(table: &'static [(u32, u32, CpuidResult)]) -> impl Fn(u32, u32) -> CpuidResult {
+        move |leaf, subleaf| {
+            table.iter().find(|(l, s, _)| (*l, *s) == (leaf, subleaf)).map_or(regs(0, 0, 0, 0), |t| t.2)
+        }
+    }
+
+    #[test]
+    fn signature() {
+        // Coffee Lake, and Zen 3.
+        assert_eq!(decode_signature(0x000906ea), (6, 0x9e, 0xa));
+        assert_eq!(decode_signature(0x00a20f10), (0x19, 0x21, 0));
+        assert!(Microarch::identify(CpuVendor::Intel, 6, 0x9e) == Microarch::Skylake);
+        assert!(Microarch::identify(CpuVendor::Amd, 0x19, 0x21) == Microarch::Zen3);
+        assert!(Microarch::identify(CpuVendor::Amd, 6, 0x9e) == Microarch::Unknown);
+    }
+
+    #[test]
+    fn intel() {
+        static TABLE: [(u32, u32, CpuidResult); 18] = [
+            // "GenuineIntel"
+            (0, 0, CpuidResult { eax: 0x1f, ebx: 0x756e6547, ecx: 0x6c65746e, edx: 0x49656e69 }),
+            (1, 0, CpuidResult { eax: 0x000906ea, ebx: 0x00100800, ecx: 1 << 21, edx: 0 }),
+            // L1d: 32KiB, 8 ways, 64 byte lines, shared by 2. L3: 12MiB, 16 ways.
+            (4, 0, CpuidResult { eax: 0x4121, ebx: 0x01c0003f, ecx: 63, edx: 0 }),
+            (4, 1, CpuidResult { eax: 0x3c163, ebx: 0x03c0003f, ecx: 12287, edx: 0 }),
+            (7, 0, CpuidResult { eax: 0, ebx: 1 << 10, ecx: 0, edx: 0 }),
+            // XSAVE can save x87, SSE and AVX, which is 256 bytes after the legacy area and header.
+            (0xd, 0, CpuidResult { eax: 7, ebx: 576, ecx: 832, edx: 0 }),
+            (0xd, 2, CpuidResult { eax: 256, ebx: 576, ecx: 0, edx: 0 }),
+            // No crystal frequency in leaf 0x15, so the TSC runs at the 3.2GHz base frequency.
+            (0x15, 0, CpuidResult { eax: 2, ebx: 266, ecx: 0, edx: 0 }),
+            (0x16, 0, CpuidResult { eax: 3200, ebx: 4600, ecx: 100, edx: 0 }),
+            // A 64 entry, 4 way L1 data TLB for 4KiB pages, after an invalid subleaf.
+            (0x18, 0, CpuidResult { eax: 1, ebx: 0, ecx: 0, edx: 0 }),
+            (0x18, 1, CpuidResult { eax: 0, ebx: (4 << 16) | 1, ecx: 16, edx: 0x21 }),
+            // Two threads per core, and 16 threads in the package. This is thread 1 of core 2.
+            (0x1f, 0, CpuidResult { eax: 1, ebx: 2, ecx: 0x100, edx: 5 }),
+            (0x1f, 1, CpuidResult { eax: 4, ebx: 16, ecx: 0x201, edx: 5 }),
+            (0x8000_0000, 0, CpuidResult { eax: 0x8000_0008, ebx: 0, ecx: 0, edx: 0 }),
+            (0x8000_0001, 0, CpuidResult { eax: 0, ebx: 0, ecx: 0, edx: 1 << 29 }),
+            (0x8000_0002, 0, CpuidResult { eax: 0x65746e49, ebx: 0x2952286c, ecx: 0x726f4320, edx: 0x4d542865 }),
+            (0x8000_0003, 0, CpuidResult { eax: 0x37692029, ebx: 0x3037382d, ecx: 0x00000030, edx: 0 }),
+            (0x8000_0008, 0, CpuidResult { eax: 0x3027, ebx: 0, ecx: 0, edx: 0 }),
+        ];
+        let info = CpuInfo::from_cpuid(fake_cpu(&TABLE));
+
+        assert!(info.vendor == CpuVendor::Intel);
+        assert_eq!(info.vendor_string(), "GenuineIntel");
+        assert_eq!(info.brand(), "Intel(R) Core(TM) i7-8700");
+        assert_eq!((info.family, info.model, info.stepping), (6, 0x9e, 0xa));
+        assert!(info.microarch == Microarch::Skylake);
+        assert_eq!((info.phys_addr_bits, info.virt_addr_bits), (39, 48));
+        assert_eq!(info.tsc_hz, 3_200_000_000);
+        assert_eq!(info.xsave_components, 7);
+        assert_eq!((info.xsave_size(3), info.xsave_size(7)), (576, 832));
+
+        assert!(info.has(Feature::X2Apic) && info.has(Feature::Invpcid) && info.has(Feature::LongMode));
+        assert!(!info.has(Feature::Xsave) && !info.has(Feature::Avx2));
+
+        let caches: Vec<_> = info.caches().copied().collect();
+        assert!(caches == [
+            CacheInfo { level: 1, kind: CacheType::Data, size: 32 << 10, line_size: 64, ways: 8, shared_by: 2 },
+            CacheInfo { level: 3, kind: CacheType::Unified, size: 12 << 20, line_size: 64, ways: 16, shared_by: 16 },
+        ]);
+        let tlbs: Vec<_> = info.tlbs().copied().collect();
+        assert!(tlbs == [TlbInfo { level: 1, kind: CacheType::Data, page_sizes: PAGES_4K, entries: 64, ways: 4 }]);
+
+        let t = info.topology;
+        assert_eq!((t.threads_per_core, t.threads_per_package), (2, 16));
+        assert_eq!((t.package_id(), t.core_id(), t.thread_id()), (0, 2, 1));
+    }
+
+    #[test]
+    fn amd() {
+        static TABLE: [(u32, u32, CpuidResult); 7] = [
+            // "AuthenticAMD"
+            (0, 0, CpuidResult { eax: 0x10, ebx: 0x68747541, ecx: 0x444d4163, edx: 0x69746e65 }),
+            (1, 0, CpuidResult { eax: 0x00a20f10, ebx: 0x0c100800, ecx: 0, edx: 0 }),
+            (0x8000_0000, 0, CpuidResult { eax: 0x8000_001f, ebx: 0, ecx: 0, edx: 0 }),
+            (0x8000_0001, 0, CpuidResult { eax: 0, ebx: 0, ecx: 1 << 22, edx: 0 }),
+            // 64 entry fully associative L1 TLBs for 4KiB pages, and a 2048 entry 8 way L2 dTLB.
+            (0x8000_0005, 0, CpuidResult { eax: 0, ebx: 0xff40ff40, ecx: 0, edx: 0 }),
+            (0x8000_0006, 0, CpuidResult { eax: 0, ebx: 0x68000000, ecx: 0, edx: 0 }),
+            // L1d: 32KiB, 8 ways.
+            (0x8000_001d, 0, CpuidResult { eax: 0x4121, ebx: 0x01c0003f, ecx: 63, edx: 0 }),
+        ];
+        let info = CpuInfo::from_cpuid(fake_cpu(&TABLE));
+
+        assert!(info.vendor == CpuVendor::Amd);
+        assert!(info.microarch == Microarch::Zen3);
+        assert_eq!(info.brand(), "");
+        assert_eq!(info.caches().count(), 1);
+        let tlbs: Vec<_> = info.tlbs().copied().collect();
+        assert!(tlbs == [
+            TlbInfo { level: 1, kind: CacheType::Data, page_sizes: PAGES_4K, entries: 64, ways: 0 },
+            TlbInfo { level: 1, kind: CacheType::Instruction, page_sizes: PAGES_4K, entries: 64, ways: 0 },
+            TlbInfo { level: 2, kind: CacheType::Data, page_sizes: PAGES_4K, entries: 0x800, ways: 8 },
+        ]);
 
-            kwarnln!("Warning: Your x86 CPU has an unsupported vendor, '{}' \n\
-                   \tYour setup may not be able to competently run seL4 as \
-                   \tintended.\
-                   \tCurrently supported x86 vendors are AMD and Intel.",
-                   vendor_str);
-            CpuVendor::Other
-        },
+        // No leaf 0xb, so the topology comes from leaf 1: APIC ID 12, 16 IDs in the package.
+        let t = info.topology;
+        assert_eq!((t.x2apic_id, t.package_bits, t.package_id(), t.core_id()), (12, 4, 0, 12));
     }
 }
@@ -5,7 +5,7 @@
 
 use crate::arch::constants::PAGE_BITS;
 use crate::arch::hardware::KDEV_BASE;
-use crate::config::CONFIG_MAX_NUM_IOAPIC;
+use crate::config::{CONFIG_MAX_NUM_DRHU, CONFIG_MAX_NUM_IOAPIC};
 use crate::const_assert;
 use crate::utils::bit_usize;
 
@@ -16,7 +16,8 @@ use crate::utils::bit_usize;
 
 // pub const MAX_NUM_DRHU: usize = PPTR_DRHU_START.wrapping_neg() >> PAGE_BITS;
 
-/// Most hardware has just 1-3 iommus. This should be pretty generous in practice.
+/// Most hardware has just 1-3 iommus. The default of 8 should be pretty generous in practice. (See
+/// max_num_drhu in kernel.toml.)
 // DEPARTURE: SeL4 just allows as many as would fit in memory. But because I'm putting BootState on
 // the stack, doing that blows out my stack space and I get a triple fault.
-pub const MAX_NUM_DRHU: usize = 8;
\ No newline at end of file
+pub const MAX_NUM_DRHU: usize = CONFIG_MAX_NUM_DRHU;
\ No newline at end of file
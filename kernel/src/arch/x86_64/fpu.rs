@@ -0,0 +1,242 @@
+//! User FPU, SSE and AVX state. This is based on src/arch/x86/machine/fpu.c and
+//! include/arch/x86/arch/machine/fpu.h.
+//!
+//! The kernel is built soft-float, so the only FPU state belongs to user threads. Each thread's is
+//! kept in its UserContext, saved with XSAVEOPT (or XSAVE, on CPUs without it) and restored with
+//! XRSTOR. CONFIG_XSAVE_FEATURE_SET goes in XCR0, which picks the state components those cover, and
+//! CONFIG_XSAVE_SIZE is the space each thread has for them. init_fpu checks both against what CPUID
+//! leaf 0xD says (in CpuInfo).
+//!
+//! Each core's FPU holds one thread's state at a time, its owner's, which is only saved when another
+//! thread takes the FPU. When that happens depends on CONFIG_FPU_SWITCHING:
+//!
+//! - Eager: switching to a thread makes it the owner (fpu_switch_to_thread).
+//! - Lazy: switching to a thread which isn't the owner just disables the FPU (CR0.TS). The thread's
+//!   first FPU instruction raises #NM (int_unimpl_dev), and handle_fpu_fault makes it the owner.
+//!
+//! DEPARTURE: SeL4 only has lazy switching, and only saves state on threads which have used the
+//! FPU. Lazy switching lets the next thread read the owner's registers speculatively on CPUs
+//! affected by LazyFP (CVE-2018-3665), so we default to eager.
+//!
+//! TODO: Nothing switches threads yet, so #NM is only handled for the context the exception stub
+//! saved (see idt.rs), not a thread's.
+
+use core::arch::asm;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use crate::arch::asm::{read_cr0, read_cr4, write_cr0, write_cr4};
+use crate::arch::registerset::UserContext;
+use crate::arch::x86_64::cpu::{CpuInfo, Feature};
+use crate::config::{ConfigFpuSwitching, CONFIG_FPU_MAX_RESTORES_SINCE_SWITCH, CONFIG_FPU_SWITCHING,
+                    CONFIG_MAX_NUM_NODES, CONFIG_XSAVE_FEATURE_SET, CONFIG_XSAVE_SIZE};
+use crate::kprintln;
+use crate::racycell::RacyCell;
+
+const CR0_MP: usize = 1 << 1;
+const CR0_EM: usize = 1 << 2;
+const CR0_TS: usize = 1 << 3;
+const CR0_NE: usize = 1 << 5;
+const CR4_OSFXSR: usize = 1 << 9;
+const CR4_OSXMMEXCPT: usize = 1 << 10;
+const CR4_OSXSAVE: usize = 1 << 18;
+
+/// Where MXCSR is in the legacy (FXSAVE) part of the XSAVE area.
+const MXCSR_OFFSET: usize = 24;
+/// SSE exceptions all masked, and round to nearest. This is MXCSR's value after reset.
+const MXCSR_INIT: u32 = 0x1f80;
+
+/// One thread's saved FPU state, in XSAVE's standard format. This is user_fpu_state_t in SeL4.
+#[derive(Copy, Clone)]
+#[repr(C, align(64))]
+pub(crate) struct FpuState([u8; CONFIG_XSAVE_SIZE]);
+
+impl FpuState {
+    /// A new thread's FPU state, which is the state after reset. This is x86KSnullFpuState in SeL4,
+    /// which is saved after FNINIT at boot.
+    pub const fn new() -> Self {
+        // The XSAVE header's XSTATE_BV is zero, so XRSTOR puts every component in its initial
+        // state. The exception is MXCSR, which XRSTOR always loads.
+        let mut area = [0; CONFIG_XSAVE_SIZE];
+        let (_, mxcsr) = area.split_at_mut(MXCSR_OFFSET);
+        mxcsr.split_at_mut(size_of::<u32>()).0.copy_from_slice(&MXCSR_INIT.to_le_bytes());
+        Self(area)
+    }
+}
+
+/// Whether to save with XSAVEOPT, which skips components which haven't changed since the XRSTOR
+/// which loaded them.
+static USE_XSAVEOPT: RacyCell<bool> = RacyCell::new(false);
+
+/// The state in each core's FPU. It's null when nobody owns the FPU, which is then disabled. This
+/// is ksActiveFPUState in SeL4.
+static FPU_OWNER: [AtomicPtr<FpuState>; CONFIG_MAX_NUM_NODES] =
+    [const { AtomicPtr::new(null_mut()) }; CONFIG_MAX_NUM_NODES];
+
+/// The number of thread switches on each core since its FPU last changed owner. This is
+/// ksFPURestoresSinceSwitch in SeL4.
+static RESTORES_SINCE_SWITCH: RacyCell<[u32; CONFIG_MAX_NUM_NODES]> = RacyCell::new([0; CONFIG_MAX_NUM_NODES]);
+
+#[inline(always)]
+unsafe fn xsetbv(xcr: u32, value: u64) {
+    unsafe {
+        asm!("xsetbv",
+            in("ecx") xcr,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+}
+
+/// Save the FPU into state. This is saveFpuState in SeL4.
+unsafe fn save(state: *mut FpuState) {
+    // EDX:EAX is ANDed with XCR0, so all ones saves everything XCR0 turns on.
+    unsafe {
+        if *USE_XSAVEOPT.as_ptr() {
+            asm!("xsaveopt64 [{}]", in(reg) state, in("eax") u32::MAX, in("edx") u32::MAX,
+                options(nostack, preserves_flags));
+        } else {
+            asm!("xsave64 [{}]", in(reg) state, in("eax") u32::MAX, in("edx") u32::MAX,
+                options(nostack, preserves_flags));
+        }
+    }
+}
+
+/// Load the FPU from state. This is loadFpuState in SeL4.
+unsafe fn restore(state: *const FpuState) {
+    unsafe {
+        asm!("xrstor64 [{}]", in(reg) state, in("eax") u32::MAX, in("edx") u32::MAX,
+            options(nostack, preserves_flags, readonly));
+    }
+}
+
+/// Let FPU instructions run. This is enableFpu in SeL4.
+fn enable_fpu() {
+    unsafe { asm!("clts", options(nomem, nostack, preserves_flags)) };
+}
+
+/// Make the next FPU instruction raise #NM. This is disableFpu in SeL4.
+fn disable_fpu() {
+    unsafe { write_cr0(read_cr0() | CR0_TS) };
+}
+
+/// Turn on the FPU, SSE and XSAVE for user threads on core, which is the current one, and check
+/// CONFIG_XSAVE_FEATURE_SET and CONFIG_XSAVE_SIZE suit the CPU. This is Arch_initFpu in SeL4.
+#[unsafe(link_section = ".boot.text")]
+pub(crate) fn init_fpu(core: usize, cpu: &CpuInfo) -> Result<(), ()> {
+    // boot0 checked for XSAVE.
+    if CONFIG_XSAVE_FEATURE_SET & !cpu.xsave_components != 0 {
+        kprintln!("xsave_feature_set is 0x{:x}, but this CPU only supports 0x{:x}",
+            CONFIG_XSAVE_FEATURE_SET, cpu.xsave_components);
+        return Err(());
+    }
+    let size = cpu.xsave_size(CONFIG_XSAVE_FEATURE_SET);
+    if size > CONFIG_XSAVE_SIZE {
+        kprintln!("xsave_feature_set 0x{:x} needs an xsave_size of at least {}, but it's {}",
+            CONFIG_XSAVE_FEATURE_SET, size, CONFIG_XSAVE_SIZE);
+        return Err(());
+    }
+
+    unsafe {
+        // Run FPU instructions natively (not EM), with x87 errors raising #MF (NE), and FWAIT
+        // raising #NM when the FPU is disabled (MP).
+        write_cr0(read_cr0() & !CR0_EM | CR0_MP | CR0_NE);
+        write_cr4(read_cr4() | CR4_OSFXSR | CR4_OSXMMEXCPT | CR4_OSXSAVE);
+        xsetbv(0, CONFIG_XSAVE_FEATURE_SET);
+    }
+
+    unsafe { *USE_XSAVEOPT.get_mut() = cpu.has(Feature::Xsaveopt) };
+    FPU_OWNER[core].store(null_mut(), Ordering::Relaxed);
+    disable_fpu();
+    Ok(())
+}
+
+/// Save core's FPU into its owner's state, and give it to new_owner. A null new_owner leaves the
+/// FPU disabled. This is switchLocalFpuOwner in SeL4.
+///
+/// SAFETY: new_owner must be null or valid until it's switched away from, or released.
+unsafe fn switch_local_fpu_owner(core: usize, new_owner: *mut FpuState) {
+    enable_fpu();
+    let owner = FPU_OWNER[core].load(Ordering::Relaxed);
+    unsafe {
+        if !owner.is_null() {
+            save(owner);
+        }
+        if new_owner.is_null() {
+            disable_fpu();
+        } else {
+            restore(new_owner);
+        }
+        RESTORES_SINCE_SWITCH.get_mut()[core] = 0;
+    }
+    FPU_OWNER[core].store(new_owner, Ordering::Relaxed);
+}
+
+/// Called when core switches to the thread with ctx. This is lazyFPURestore in SeL4, which
+/// Arch_switchToThread calls.
+///
+/// SAFETY: ctx mustn't move until the thread is deleted (fpu_release).
+pub(crate) unsafe fn fpu_switch_to_thread(core: usize, ctx: &mut UserContext) {
+    let state = &raw mut ctx.fpu_state;
+    let owner = FPU_OWNER[core].load(Ordering::Relaxed);
+    match CONFIG_FPU_SWITCHING {
+        ConfigFpuSwitching::Eager => {
+            if owner != state {
+                unsafe { switch_local_fpu_owner(core, state) };
+            }
+        }
+        ConfigFpuSwitching::Lazy => {
+            // With no owner, the FPU is already disabled.
+            if owner.is_null() {
+                return;
+            }
+            // Give up the FPU once it's been a while since it changed owner, so a thread which
+            // stopped using it doesn't make every other thread fault to get it back.
+            let restores = unsafe { &mut RESTORES_SINCE_SWITCH.get_mut()[core] };
+            if *restores > CONFIG_FPU_MAX_RESTORES_SINCE_SWITCH {
+                unsafe { switch_local_fpu_owner(core, null_mut()) };
+                return;
+            }
+            *restores += 1;
+            if owner == state {
+                enable_fpu();
+            } else {
+                disable_fpu();
+            }
+        }
+    }
+}
+
+/// Handle #NM (int_unimpl_dev) from the thread with ctx, which used the FPU while it was disabled.
+/// This is handleFPUFault in SeL4.
+///
+/// SAFETY: As for fpu_switch_to_thread.
+pub(crate) unsafe fn handle_fpu_fault(core: usize, ctx: &mut UserContext) {
+    unsafe { switch_local_fpu_owner(core, &raw mut ctx.fpu_state) };
+}
+
+/// Called when the thread with ctx is deleted, so core's FPU doesn't keep pointing at it. This is
+/// fpuRelease in SeL4.
+///
+/// TODO: With SMP, this has to happen on the core the thread last ran on, which SeL4 does with a
+/// remote call.
+pub(crate) fn fpu_release(core: usize, ctx: &mut UserContext) {
+    if FPU_OWNER[core].load(Ordering::Relaxed) == &raw mut ctx.fpu_state {
+        unsafe { switch_local_fpu_owner(core, null_mut()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initial_state() {
+        let state = FpuState::new();
+        assert_eq!(align_of::<FpuState>(), 64);
+        assert_eq!(state.0[MXCSR_OFFSET..MXCSR_OFFSET + 4], [0x80, 0x1f, 0, 0]);
+        // The x87 state and the XSAVE header (XSTATE_BV and XCOMP_BV) are zero.
+        assert!(state.0[..MXCSR_OFFSET].iter().all(|&b| b == 0));
+        assert!(state.0[512..528].iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,131 @@
+//! The kernel's runtime GDT and TSSes.
+//!
+//! The boot GDT (boot0.rs) only has code and data segments. In long mode the TSS isn't used for
+//! task switching any more, but it's still where the CPU finds the stacks to switch to on an
+//! interrupt: rsp0 for entries from user mode, and the IST (interrupt stack table) for vectors
+//! which must always get a fresh stack. Those are the small entry stacks in the SKIM window (see
+//! skim.rs), not the kernel stacks themselves. Each core needs its own TSS, and each TSS needs its
+//! own GDT descriptor.
+//!
+//! The code and data selectors are the same as in the boot GDT, so the segment registers stay valid
+//! across the switch.
+
+use core::arch::asm;
+use crate::config::CONFIG_MAX_NUM_NODES;
+use crate::racycell::RacyCell;
+use crate::stack::KERNEL_STACKS;
+use super::skim::ENTRY_STACKS;
+
+pub(crate) const SEL_CS_0: u16 = 0x08;
+pub(crate) const SEL_DS_0: u16 = 0x10;
+/// The first TSS descriptor. TSS descriptors are 16 bytes in long mode, so each takes two slots.
+const SEL_TSS_BASE: u16 = 0x18;
+
+/// The IST slots used by the #DF and NMI gates in the IDT. (IST entries are numbered from 1. 0 in an
+/// IDT gate means "don't switch stacks".)
+pub(crate) const IST_DOUBLE_FAULT: u8 = 1;
+pub(crate) const IST_NMI: u8 = 2;
+
+/// The 64 bit TSS. See the Intel SDM vol 3, "Task Management in 64-bit Mode".
+#[repr(C, packed)]
+struct Tss {
+    _reserved0: u32,
+    rsp: [u64; 3],
+    _reserved1: u64,
+    ist: [u64; 7],
+    _reserved2: u64,
+    _reserved3: u16,
+    iomap_base: u16,
+}
+
+impl Tss {
+    const fn new() -> Self {
+        Self {
+            _reserved0: 0, rsp: [0; 3], _reserved1: 0, ist: [0; 7], _reserved2: 0, _reserved3: 0,
+            // Past the end of the TSS, so there's no IO permission bitmap.
+            iomap_base: size_of::<Tss>() as u16,
+        }
+    }
+}
+
+const GDT_ENTRIES: usize = 3 + 2 * CONFIG_MAX_NUM_NODES;
+
+#[repr(C, align(16))]
+struct Gdt([u64; GDT_ENTRIES]);
+
+// The CPU reads the GDT, TSS and IDT on every kernel entry, so they're in the SKIM window (skim.rs).
+#[unsafe(link_section = ".skim.data")]
+static GDT: RacyCell<Gdt> = RacyCell::new(Gdt({
+    let mut gdt = [0; GDT_ENTRIES];
+    gdt[1] = 0x0020_9800_0000_0000; // Code: 64 bit, present, executable.
+    gdt[2] = 0x0000_9000_0000_0000; // Data: present.
+    gdt
+}));
+
+#[unsafe(link_section = ".skim.data")]
+static TSS: RacyCell<[Tss; CONFIG_MAX_NUM_NODES]> = RacyCell::new([const { Tss::new() }; CONFIG_MAX_NUM_NODES]);
+
+/// A 16 byte system descriptor for an available 64 bit TSS.
+const fn tss_descriptor(base: u64, limit: u32) -> [u64; 2] {
+    let low = (limit as u64 & 0xffff)
+        | (base & 0xff_ffff) << 16
+        | 0x89 << 40 // Present, 64 bit TSS (available).
+        | ((limit as u64 >> 16) & 0xf) << 48
+        | ((base >> 24) & 0xff) << 56;
+    [low, base >> 32]
+}
+
+#[repr(C, packed)]
+struct DescriptorPtr {
+    limit: u16,
+    base: u64,
+}
+
+/// Fill in core's TSS, and load the GDT and TSS on the current CPU.
+///
+/// SAFETY: This must be called once per core, on that core, before it enables interrupts.
+pub(crate) unsafe fn init_cpu_descriptors(core: usize) {
+    let stacks = unsafe { &(*KERNEL_STACKS.as_ptr())[core] };
+    let entry = unsafe { &mut ENTRY_STACKS.get_mut()[core] };
+    let tss = unsafe { &mut TSS.get_mut()[core] };
+    tss.rsp[0] = entry.kernel.init(stacks.kernel.top());
+    tss.ist[IST_DOUBLE_FAULT as usize - 1] = entry.double_fault.init(stacks.double_fault.top());
+    tss.ist[IST_NMI as usize - 1] = entry.nmi.init(stacks.nmi.top());
+
+    let gdt = unsafe { &mut GDT.get_mut().0 };
+    let slot = 3 + 2 * core;
+    let descriptor = tss_descriptor(tss as *mut Tss as u64, size_of::<Tss>() as u32 - 1);
+    gdt[slot..slot + 2].copy_from_slice(&descriptor);
+
+    let ptr = DescriptorPtr { limit: (size_of::<Gdt>() - 1) as u16, base: gdt.as_ptr() as u64 };
+    let tss_sel = SEL_TSS_BASE + 16 * core as u16;
+    unsafe {
+        asm!(
+            "lgdt [{ptr}]",
+            "mov ss, {ds:x}",
+            "mov ds, {ds:x}",
+            "mov es, {ds:x}",
+            "ltr {tss:x}",
+            ptr = in(reg) &ptr,
+            ds = in(reg) SEL_DS_0 as u64,
+            tss = in(reg) tss_sel as u64,
+            options(readonly, nostack, preserves_flags),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptors() {
+        assert_eq!(size_of::<Tss>(), 104);
+        // The same encoding the boot GDT uses.
+        assert_eq!(unsafe { GDT.get_mut().0[1] }, ((0x20u64) << 48) | (0x98u64 << 40));
+
+        let [low, high] = tss_descriptor(0xffff_ffff_8012_3456, 103);
+        assert_eq!(low, 0x8000_8912_3456_0067);
+        assert_eq!(high, 0xffff_ffff);
+    }
+}
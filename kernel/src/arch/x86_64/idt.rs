@@ -0,0 +1,492 @@
+//! The IDT, and the kernel's own fault handlers.
+//!
+//! The faults which mean the kernel itself is broken (#PF, #DF and NMI) have their own handlers
+//! here. The debug exceptions (#DB and #BP) and #NM go through exception_entry, which saves the
+//! interrupted context and calls c_handle_exception (traps.rs), so the GDB stub and lazy FPU
+//! switching can run. Anything else still triple faults. The entries for syscalls and interrupts don't exist yet.
+//!
+//! #DF and NMI run on their own IST stacks (see gdt.rs and crate::stack). When the kernel stack
+//! overflows, the CPU faults on the guard page, then faults again trying to push the #PF frame
+//! onto it. That second fault is a #DF, which gets a good stack, and CR2 still holds the address
+//! in the guard page.
+
+use core::arch::{asm, naked_asm};
+use core::mem::offset_of;
+use crate::arch::asm::read_cr2;
+use crate::arch::registerset::{Register, UserContext};
+use crate::config::CONFIG_MAX_NUM_NODES;
+use crate::racycell::RacyCell;
+use crate::stack::{current_core, find_guard};
+use crate::utils::backtrace::print_backtrace_from;
+use super::gdt::{IST_DOUBLE_FAULT, IST_NMI, SEL_CS_0};
+use super::skim::KERNEL_CR3;
+use super::traps::c_handle_exception;
+use crate::{kpanic, kprintln, kwarnln};
+
+const VECTOR_DEBUG: usize = 1;
+const VECTOR_NMI: usize = 2;
+const VECTOR_BREAKPOINT: usize = 3;
+const VECTOR_DEVICE_NOT_AVAILABLE: usize = 7;
+const VECTOR_DOUBLE_FAULT: usize = 8;
+const VECTOR_PAGE_FAULT: usize = 14;
+
+const IDT_ENTRIES: usize = 256;
+
+/// What the CPU pushes on an exception with an error code.
+#[repr(C)]
+struct ExceptionFrame {
+    error_code: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+/// The stack when exception_entry calls handle_exception: the general purpose registers it saved,
+/// the vector, and then what the CPU pushed. Exceptions without an error code push a zero in its
+/// place, so they all look the same.
+#[derive(Default)]
+#[repr(C)]
+pub(crate) struct TrapFrame {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub vector: u64,
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl TrapFrame {
+    fn general_registers(&mut self) -> [(Register, &mut u64); 15] {
+        [
+            (Register::RAX, &mut self.rax),
+            (Register::RBX, &mut self.rbx),
+            (Register::RCX, &mut self.rcx),
+            (Register::RDX, &mut self.rdx),
+            (Register::RSI, &mut self.rsi),
+            (Register::RDI, &mut self.rdi),
+            (Register::RBP, &mut self.rbp),
+            (Register::R8, &mut self.r8),
+            (Register::R9, &mut self.r9),
+            (Register::R10, &mut self.r10),
+            (Register::R11, &mut self.r11),
+            (Register::R12, &mut self.r12),
+            (Register::R13, &mut self.r13),
+            (Register::R14, &mut self.r14),
+            (Register::R15, &mut self.r15),
+        ]
+    }
+
+    /// Copy the interrupted context into ctx. It resumes from where it was interrupted, unless
+    /// NextIP is changed.
+    pub fn save(&mut self, ctx: &mut UserContext) {
+        for (reg, value) in self.general_registers() {
+            ctx.set(reg, *value as usize);
+        }
+        ctx.set(Register::FaultIP, self.rip as usize);
+        ctx.set(Register::NextIP, self.rip as usize);
+        ctx.set(Register::Error, self.error_code as usize);
+        ctx.set(Register::FLAGS, self.rflags as usize);
+        ctx.set(Register::RSP, self.rsp as usize);
+        ctx.set(Register::CS, self.cs as usize);
+        ctx.set(Register::SS, self.ss as usize);
+    }
+
+    /// Copy ctx back, to be resumed. CS and SS are left alone, so a bad value can't make iretq
+    /// fault.
+    pub fn restore(&mut self, ctx: &UserContext) {
+        for (reg, value) in self.general_registers() {
+            *value = ctx.get(reg) as u64;
+        }
+        self.rip = ctx.get(Register::NextIP) as u64;
+        self.rflags = ctx.get(Register::FLAGS) as u64;
+        self.rsp = ctx.get(Register::RSP) as u64;
+    }
+}
+
+/// Where each core saves the context exception_entry interrupted.
+///
+/// TODO: Exceptions from userland should save into the current thread's TCB, as SeL4 does, once
+/// there are threads.
+static EXCEPTION_CONTEXT: RacyCell<[UserContext; CONFIG_MAX_NUM_NODES]> =
+    RacyCell::new([const { UserContext::new() }; CONFIG_MAX_NUM_NODES]);
+
+#[repr(C, align(16))]
+struct Idt([[u64; 2]; IDT_ENTRIES]);
+
+/// In the SKIM window, like the GDT.
+#[unsafe(link_section = ".skim.data")]
+static IDT: RacyCell<Idt> = RacyCell::new(Idt([[0; 2]; IDT_ENTRIES]));
+
+/// A 16 byte interrupt gate, for a kernel handler. ist is the TSS IST slot to switch to, or 0. dpl
+/// is the least privileged ring which can raise the vector with an INT instruction.
+const fn interrupt_gate(handler: u64, ist: u8, dpl: u8) -> [u64; 2] {
+    let low = (handler & 0xffff)
+        | (SEL_CS_0 as u64) << 16
+        | (ist as u64 & 0x7) << 32
+        | (0x8e | (dpl as u64 & 0x3) << 5) << 40 // Present, 64 bit interrupt gate.
+        | ((handler >> 16) & 0xffff) << 48;
+    [low, handler >> 32]
+}
+
+/// Fill in the IDT. This only needs to happen once, on the boot core.
+pub(crate) fn init_idt() {
+    let idt = unsafe { &mut IDT.get_mut().0 };
+    idt[VECTOR_DEBUG] = interrupt_gate(debug_entry as *const () as u64, 0, 0);
+    idt[VECTOR_NMI] = interrupt_gate(nmi_entry as *const () as u64, IST_NMI, 0);
+    // With the GDB stub, user code can run int3 to stop in the debugger. Otherwise there's nothing
+    // to deliver #BP to yet, so int3 in userland is a #GP, like INT with any other kernel vector.
+    let breakpoint_dpl = if cfg!(feature = "gdb") { 3 } else { 0 };
+    idt[VECTOR_BREAKPOINT] = interrupt_gate(breakpoint_entry as *const () as u64, 0, breakpoint_dpl);
+    idt[VECTOR_DEVICE_NOT_AVAILABLE] = interrupt_gate(device_not_available_entry as *const () as u64, 0, 0);
+    idt[VECTOR_DOUBLE_FAULT] = interrupt_gate(double_fault_entry as *const () as u64, IST_DOUBLE_FAULT, 0);
+    idt[VECTOR_PAGE_FAULT] = interrupt_gate(page_fault_entry as *const () as u64, 0, 0);
+}
+
+#[repr(C, packed)]
+struct DescriptorPtr {
+    limit: u16,
+    base: u64,
+}
+
+/// Load the IDT on the current CPU. This must be called on every core, after init_idt.
+pub(crate) unsafe fn load_idt() {
+    let ptr = DescriptorPtr { limit: (size_of::<Idt>() - 1) as u16, base: IDT.as_ptr() as u64 };
+    unsafe { asm!("lidt [{}]", in(reg) &ptr, options(readonly, nostack, preserves_flags)) };
+}
+
+// #DF and #PF don't return. The stubs switch to the kernel's page tables, in case the fault came
+// from user code running on the SKIM window (see skim.rs). If the CPU pushed the frame onto an
+// entry stack, they move on to the real stack above it, and leave the frame where it is. It's still
+// mapped. They pass the CPU's frame and the frame pointer of the code which faulted (for the
+// backtrace), and realign the stack for the call.
+#[unsafe(naked)]
+extern "C" fn double_fault_entry() -> ! {
+    naked_asm!(r"
+        mov rax, [rip + {kernel_cr3}]
+        mov cr3, rax
+        mov rdi, rsp
+        mov rsi, rbp
+        mov rsp, [rsp + {kernel_rsp}]
+        call {handler}
+        ud2
+    ",
+        handler = sym handle_double_fault,
+        kernel_cr3 = sym KERNEL_CR3,
+        kernel_rsp = const size_of::<ExceptionFrame>(),
+    )
+}
+
+#[unsafe(naked)]
+extern "C" fn page_fault_entry() -> ! {
+    naked_asm!(r"
+        mov rax, [rip + {kernel_cr3}]
+        mov cr3, rax
+        mov rdi, rsp
+        mov rsi, rbp
+        // Only faults from userland switch stacks.
+        test byte ptr [rsp + {cs}], 3
+        jz 2f
+        mov rsp, [rsp + {kernel_rsp}]
+    2:
+        and rsp, -16
+        call {handler}
+        ud2
+    ",
+        handler = sym handle_page_fault,
+        kernel_cr3 = sym KERNEL_CR3,
+        cs = const offset_of!(ExceptionFrame, cs),
+        kernel_rsp = const size_of::<ExceptionFrame>(),
+    )
+}
+
+// NMIs return, so the stub saves the caller saved registers. It also switches to the kernel's page
+// tables if it interrupted user code running on the SKIM window, and back afterwards.
+//
+// rax and rcx are saved on the entry stack, with the CPU's 5 words. Then the stub moves to the
+// real NMI stack for the rest, and comes back to the entry stack to return. The NMI stack is 16
+// byte aligned, so after the entry stack pointer, the saved CR3 and 7 registers (padded to 10
+// words) it's aligned again for the call.
+#[unsafe(naked)]
+extern "C" fn nmi_entry() {
+    naked_asm!(r"
+        push rax
+        push rcx
+        mov rax, cr3
+        mov rcx, [rip + {kernel_cr3}]
+        cmp rax, rcx
+        je 2f
+        mov cr3, rcx
+    2:
+        mov rcx, rsp
+        mov rsp, [rcx + {kernel_rsp}]
+        push rcx
+        push rax
+        push rdx
+        push rsi
+        push rdi
+        push r8
+        push r9
+        push r10
+        push r11
+        sub rsp, 8
+        call {handler}
+        add rsp, 8
+        pop r11
+        pop r10
+        pop r9
+        pop r8
+        pop rdi
+        pop rsi
+        pop rdx
+        pop rax
+        pop rsp
+        mov rcx, cr3
+        cmp rax, rcx
+        je 3f
+        mov cr3, rax
+    3:
+        pop rcx
+        pop rax
+        iretq
+    ",
+        handler = sym handle_nmi,
+        kernel_cr3 = sym KERNEL_CR3,
+        // rcx, rax and the CPU's 5 words.
+        kernel_rsp = const 7 * 8,
+    )
+}
+
+/// The entry stub for an exception without an error code. It pushes a zero error code and the
+/// vector, then saves the rest of the frame in exception_common.
+macro_rules! exception_entry {
+    ($name:ident, $vector:expr) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            naked_asm!(r"
+                push 0
+                push {vector}
+                jmp {common}
+            ",
+                vector = const $vector,
+                common = sym exception_common,
+            )
+        }
+    };
+}
+
+exception_entry!(debug_entry, VECTOR_DEBUG);
+exception_entry!(breakpoint_entry, VECTOR_BREAKPOINT);
+exception_entry!(device_not_available_entry, VECTOR_DEVICE_NOT_AVAILABLE);
+
+// Save the general purpose registers to make a TrapFrame, and switch to the kernel's page tables
+// like the NMI stub. handle_exception also gets the interrupted CR3, so the GDB stub can read
+// user memory.
+//
+// Exceptions in the kernel don't switch stacks, so the TrapFrame is built around the CPU's frame.
+// The CPU aligns the stack to 16 bytes before pushing its 5 words, so after the error code, the
+// vector, 15 registers and the saved CR3 (padded to 2 words) it's aligned for the call.
+//
+// Exceptions from userland arrive on the entry stack, which is all that's mapped with the SKIM
+// window (see skim.rs). The stub saves rax and rcx there, switches page tables, and builds the
+// TrapFrame on the kernel stack from a copy of the entry stack. After the entry stack pointer and
+// the saved CR3 it's aligned for the call. On the way out it copies the frame back, and returns
+// from the entry stack after switching page tables back.
+#[unsafe(naked)]
+extern "C" fn exception_common() {
+    naked_asm!(r"
+        test byte ptr [rsp + {cs}], 3
+        jnz .Lfrom_user
+
+        push r15
+        push r14
+        push r13
+        push r12
+        push r11
+        push r10
+        push r9
+        push r8
+        push rbp
+        push rdi
+        push rsi
+        push rdx
+        push rcx
+        push rbx
+        push rax
+        cld
+        mov rax, cr3
+        push rax
+        sub rsp, 8
+        mov rcx, [rip + {kernel_cr3}]
+        cmp rax, rcx
+        je 2f
+        mov cr3, rcx
+    2:
+        lea rdi, [rsp + 16]
+        mov rsi, rax
+        call {handler}
+        add rsp, 8
+        pop rax
+        mov rcx, cr3
+        cmp rax, rcx
+        je 3f
+        mov cr3, rax
+    3:
+        pop rax
+        pop rbx
+        pop rcx
+        pop rdx
+        pop rsi
+        pop rdi
+        pop rbp
+        pop r8
+        pop r9
+        pop r10
+        pop r11
+        pop r12
+        pop r13
+        pop r14
+        pop r15
+        add rsp, 16
+        iretq
+
+    .Lfrom_user:
+        // The entry stack is rcx, rax, the vector, the error code, the CPU's 5 words, and then the
+        // kernel stack pointer.
+        push rax
+        push rcx
+        mov rax, cr3
+        mov rcx, [rip + {kernel_cr3}]
+        mov cr3, rcx
+        mov rcx, rsp
+        mov rsp, [rcx + 72]
+        push qword ptr [rcx + 64]
+        push qword ptr [rcx + 56]
+        push qword ptr [rcx + 48]
+        push qword ptr [rcx + 40]
+        push qword ptr [rcx + 32]
+        push qword ptr [rcx + 24]
+        push qword ptr [rcx + 16]
+        push r15
+        push r14
+        push r13
+        push r12
+        push r11
+        push r10
+        push r9
+        push r8
+        push rbp
+        push rdi
+        push rsi
+        push rdx
+        push qword ptr [rcx]
+        push rbx
+        push qword ptr [rcx + 8]
+        cld
+        push rax
+        push rcx
+        lea rdi, [rsp + 16]
+        mov rsi, rax
+        call {handler}
+        // The saved CR3 goes in the entry stack's vector slot, which is free now.
+        pop rcx
+        pop qword ptr [rcx + 16]
+        pop qword ptr [rcx + 8]
+        pop rbx
+        pop qword ptr [rcx]
+        pop rdx
+        pop rsi
+        pop rdi
+        pop rbp
+        pop r8
+        pop r9
+        pop r10
+        pop r11
+        pop r12
+        pop r13
+        pop r14
+        pop r15
+        add rsp, 16
+        pop qword ptr [rcx + 32]
+        pop qword ptr [rcx + 40]
+        pop qword ptr [rcx + 48]
+        pop qword ptr [rcx + 56]
+        pop qword ptr [rcx + 64]
+        mov rsp, rcx
+        mov rax, [rsp + 16]
+        mov cr3, rax
+        pop rcx
+        pop rax
+        add rsp, 16
+        iretq
+    ",
+        handler = sym handle_exception,
+        kernel_cr3 = sym KERNEL_CR3,
+        // The vector is below the error code.
+        cs = const 8 + offset_of!(ExceptionFrame, cs),
+    )
+}
+
+extern "C" fn handle_exception(frame: &mut TrapFrame, cr3: usize) {
+    let ctx = unsafe { &mut EXCEPTION_CONTEXT.get_mut()[current_core()] };
+    frame.save(ctx);
+    c_handle_exception(frame.vector as u8, ctx, cr3);
+    frame.restore(ctx);
+}
+
+/// If addr is in a guard page, report the stack overflow and stop.
+fn check_stack_overflow(addr: usize, rbp: usize) {
+    if let Some((core, stack)) = find_guard(addr) {
+        kprintln!("Fault accessing 0x{:x}, in a stack guard page", addr);
+        print_backtrace_from(None, rbp);
+        kpanic!("Kernel stack overflow on core {} ({} stack)", core, stack);
+    }
+}
+
+extern "C" fn handle_double_fault(frame: &ExceptionFrame, rbp: usize) -> ! {
+    // A #DF's saved rip is undefined, but CR2 still has the address of the #PF which caused it.
+    check_stack_overflow(read_cr2(), rbp);
+    print_backtrace_from(None, rbp);
+    kpanic!("Double fault (rsp 0x{:x})", frame.rsp);
+}
+
+extern "C" fn handle_page_fault(frame: &ExceptionFrame, rbp: usize) -> ! {
+    let addr = read_cr2();
+    check_stack_overflow(addr, rbp);
+    print_backtrace_from(Some(frame.rip as usize), rbp);
+    kpanic!("Kernel page fault at 0x{:x} accessing 0x{:x} (error 0x{:x})", frame.rip, addr, frame.error_code);
+}
+
+extern "C" fn handle_nmi() {
+    kwarnln!("Ignoring unexpected NMI");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gate() {
+        let [low, high] = interrupt_gate(0xffff_ffff_8012_3456, 2, 0);
+        assert_eq!(low, 0x8012_8e02_0008_3456);
+        assert_eq!(high, 0xffff_ffff);
+        assert_eq!(interrupt_gate(0xffff_ffff_8012_3456, 0, 3)[0], 0x8012_ee00_0008_3456);
+    }
+}
@@ -0,0 +1,157 @@
+//! IO port capabilities. This is ported from src/arch/x86/object/ioport.c.
+//!
+//! Userland drivers for legacy devices get access to IO ports through IOPort capabilities. The
+//! IOPortControl capability issues IOPort caps for a range of ports, and the kernel tracks which
+//! ports have been handed out so that no two IOPort caps ever cover the same port.
+//!
+//! TODO: There's no CSpace or syscall path yet, so nothing decodes these invocations. IoPortCap
+//! stands in for cap_io_port_cap_t until we have real capabilities.
+
+use ufmt::derive::uDebug;
+use crate::api::failures::SyscallError;
+use crate::arch::x86_64::asm::{in16, in32, in8, out16, out32, out8};
+use crate::console::DEBUG_SERIAL_PORT;
+use crate::kwarnln;
+use crate::racycell::RacyCell;
+
+const NUM_IO_PORTS: usize = 1 << 16;
+
+/// Number of consecutive ports used by a 16550 UART.
+const SERIAL_PORT_COUNT: u16 = 8;
+
+/// A bitmap with 1 bit for every IO port. Set bits have been issued to an IOPort cap (or are
+/// reserved by the kernel).
+struct IoPortBitmap([u64; NUM_IO_PORTS / 64]);
+
+impl IoPortBitmap {
+    fn is_set(&self, port: u16) -> bool {
+        let port = port as usize;
+        (self.0[port / 64] >> (port % 64)) & 1 != 0
+    }
+
+    fn set(&mut self, port: u16, allocated: bool) {
+        let port = port as usize;
+        let mask = 1u64 << (port % 64);
+        if allocated {
+            self.0[port / 64] |= mask;
+        } else {
+            self.0[port / 64] &= !mask;
+        }
+    }
+
+    /// Returns true if none of the ports in [first, last] have been allocated.
+    fn is_range_free(&self, first: u16, last: u16) -> bool {
+        // This is a bit slower than SeL4's word-at-a-time version, but we issue IOPort caps rarely
+        // enough that it doesn't matter.
+        (first..=last).all(|port| !self.is_set(port))
+    }
+
+    fn set_range(&mut self, first: u16, last: u16, allocated: bool) {
+        for port in first..=last {
+            self.set(port, allocated);
+        }
+    }
+}
+
+/// x86KSAllocatedIOPorts in SeL4.
+static ALLOCATED_IO_PORTS: RacyCell<IoPortBitmap> = RacyCell::new(IoPortBitmap([0; _]));
+
+/// An IOPort capability, allowing access to all ports in the range [first_port, last_port].
+#[derive(uDebug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct IoPortCap {
+    pub first_port: u16,
+    pub last_port: u16,
+}
+
+/// Mark the ports used by the kernel itself as allocated, so they can never be issued to userland.
+///
+/// This should be called once at boot, before any IOPort caps are issued.
+#[unsafe(link_section = ".boot.text")]
+pub(crate) fn reserve_kernel_ioports() {
+    let ports = unsafe { ALLOCATED_IO_PORTS.get_mut() };
+    ports.set_range(DEBUG_SERIAL_PORT, DEBUG_SERIAL_PORT + SERIAL_PORT_COUNT - 1, true);
+
+    #[cfg(feature = "gdb")]
+    {
+        use crate::config::CONFIG_GDB_SERIAL_PORT;
+        ports.set_range(CONFIG_GDB_SERIAL_PORT, CONFIG_GDB_SERIAL_PORT + SERIAL_PORT_COUNT - 1, true);
+    }
+}
+
+/// Issue a new IOPort cap covering [first_port, last_port]. This is the IOPortControl_Issue
+/// invocation (decodeX86PortControlInvocation in SeL4).
+///
+/// Fails if any port in the range has already been issued, or is in use by the kernel.
+pub(crate) fn ioport_control_issue(first_port: u16, last_port: u16) -> Result<IoPortCap, SyscallError> {
+    if last_port < first_port {
+        kwarnln!("IOPortControl: Last port must be > first port.");
+        return Err(SyscallError::InvalidArgument { arg: 1 });
+    }
+
+    let ports = unsafe { ALLOCATED_IO_PORTS.get_mut() };
+    if !ports.is_range_free(first_port, last_port) {
+        kwarnln!("IOPortControl: Some ports in range already in use.");
+        return Err(SyscallError::RevokeFirst);
+    }
+
+    ports.set_range(first_port, last_port, true);
+    Ok(IoPortCap { first_port, last_port })
+}
+
+/// Release the ports held by an IOPort cap. This should be called when the last copy of the cap is
+/// deleted (finaliseCap in SeL4).
+pub(crate) fn free_ioport_range(cap: IoPortCap) {
+    let ports = unsafe { ALLOCATED_IO_PORTS.get_mut() };
+    ports.set_range(cap.first_port, cap.last_port, false);
+}
+
+impl IoPortCap {
+    /// Check that a read or write of `size` bytes starting at `start_port` falls entirely inside
+    /// this cap's range.
+    fn ensure_port_operation_allowed(&self, start_port: u16, size: u16) -> Result<(), SyscallError> {
+        debug_assert!(self.first_port <= self.last_port);
+
+        // Widen to u32, so a 32 bit access at port 0xffff doesn't wrap back around to 0.
+        let end_port = start_port as u32 + size as u32 - 1;
+
+        if start_port < self.first_port || end_port > self.last_port as u32 {
+            kwarnln!("IOPort: Ports {}--{} fall outside permitted range {}--{}.",
+                start_port, end_port, self.first_port, self.last_port);
+            return Err(SyscallError::IllegalOperation);
+        }
+        Ok(())
+    }
+
+    pub fn in8(&self, port: u16) -> Result<u8, SyscallError> {
+        self.ensure_port_operation_allowed(port, 1)?;
+        Ok(unsafe { in8(port) })
+    }
+
+    pub fn in16(&self, port: u16) -> Result<u16, SyscallError> {
+        self.ensure_port_operation_allowed(port, 2)?;
+        Ok(unsafe { in16(port) })
+    }
+
+    pub fn in32(&self, port: u16) -> Result<u32, SyscallError> {
+        self.ensure_port_operation_allowed(port, 4)?;
+        Ok(unsafe { in32(port) })
+    }
+
+    pub fn out8(&self, port: u16, value: u8) -> Result<(), SyscallError> {
+        self.ensure_port_operation_allowed(port, 1)?;
+        unsafe { out8(port, value) };
+        Ok(())
+    }
+
+    pub fn out16(&self, port: u16, value: u16) -> Result<(), SyscallError> {
+        self.ensure_port_operation_allowed(port, 2)?;
+        unsafe { out16(port, value) };
+        Ok(())
+    }
+
+    pub fn out32(&self, port: u16, value: u32) -> Result<(), SyscallError> {
+        self.ensure_port_operation_allowed(port, 4)?;
+        unsafe { out32(port, value) };
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+//! The kernel's virtual memory layout. See the diagram in hardware.rs.
+//!
+//! These constants are the single source of truth for the layout. kernel/build.rs includes this
+//! file too, and generates the linker script's constants from it. So this file can't use anything
+//! from the rest of the kernel.
+
+/* Define USER_TOP to be 1 before the last address before sign extension occurs.
+ * This ensures that
+ *  1. user addresses never needed to be sign extended to be valid canonical addresses
+ *  2. the user cannot map the last page before addresses need sign extension. This prevents
+ *     the user doing a syscall as the very last instruction and the CPU calculated PC + 2
+ *     from being an invalid (non sign extended) address
+ */
+pub const USER_TOP: usize = 0x7FFF_FFFFFFFF;
+
+/* The first physical address to map into the kernel's physical memory
+ * window */
+pub const PADDR_BASE: usize = 0x00000000;
+
+/* The base address in virtual memory to use for the 1:1 physical memory
+ * mapping. Our kernel window is 2^39 bits (2^9 * 1gb) and the virtual
+ * address range is 48 bits. Therefore our base is 2^48 - 2^39 */
+pub const PPTR_BASE: usize = 0xffffff80_00000000;
+
+// /* Below the main kernel window we have any slots for the TLB bitmap */
+// #define TLBBITMAP_PML4_RESERVED (TLBBITMAP_ROOT_ENTRIES * BIT(PML4_INDEX_OFFSET))
+// #define TLBBITMAP_PPTR (PPTR_BASE - TLBBITMAP_PML4_RESERVED)
+
+/* The kernel binary itself is placed in the bottom 1gb of the top
+ * 2gb of virtual address space. This is so we can use the 'kernel'
+ * memory model of GCC, which requires all symbols to be linked
+ * within the top 2GiB of memory. This is (2^48 - 2 ^ 31) */
+pub const PPTR_TOP: usize = 0xffffffff_80000000;
+
+/* The physical memory address to use for mapping the kernel ELF */
+pub const KERNEL_ELF_PADDR_BASE: usize = 0x00100000;
+
+/* Kernel mapping starts directly after the physical memory window */
+pub const KERNEL_ELF_BASE: usize = PPTR_TOP + KERNEL_ELF_PADDR_BASE;
+
+/* Put the kernel devices at the very beginning of the top
+ * 1GB. This means they are precisely after the kernel binary
+ * region. This is 2^48 - 2^30 */
+pub const KDEV_BASE: usize = 0xffffffff_c0000000;
+
+const GIB: usize = 1 << 30;
+
+const _: () = assert!(USER_TOP < PPTR_BASE, "User memory overlaps the kernel window");
+const _: () = assert!(PPTR_BASE < PPTR_TOP && PPTR_TOP <= KERNEL_ELF_BASE && KERNEL_ELF_BASE < KDEV_BASE,
+    "The kernel's regions must be in order: physical memory window, kernel ELF, devices");
+// The physical memory window is mapped with 1GiB pages.
+const _: () = assert!(PPTR_BASE.is_multiple_of(GIB) && PPTR_TOP.is_multiple_of(GIB) && KDEV_BASE.is_multiple_of(GIB),
+    "Kernel regions must be 1GiB aligned");
+const _: () = assert!(KERNEL_ELF_PADDR_BASE.is_multiple_of(4096), "The kernel must be loaded at a page boundary");
+// The kernel is built with the kernel code model.
+const _: () = assert!(KERNEL_ELF_BASE >= 0xffffffff_80000000, "The kernel image must be in the top 2GiB");
+// Multiboot loaders only load below 4GiB.
+const _: () = assert!(KERNEL_ELF_PADDR_BASE < 1 << 32, "The kernel must be loaded below 4GiB");
@@ -8,6 +8,9 @@ pub const IOAPIC_IRQ_LINES: u32 = 240;
 pub const IRQ_INT_OFFSET: u32 = 0x20;
 pub const IRQ_CNODE_SLOT_BITS: u32 = 8;
 
+/// The device not available exception (#NM), which FPU instructions raise while CR0.TS is set.
+pub const INT_UNIMPL_DEV: u8 = 7;
+
 
 // typedef enum _interrupt_t {
 //     int_invalid                 = -1,
@@ -4,16 +4,26 @@ use ufmt::derive::uDebug;
 
 pub mod constants;
 pub mod hardware;
+mod layout;
 mod boot;
 mod acpi;
 mod machine;
 mod cpu;
+mod msr;
+mod speculation;
+mod skim;
+mod fpu;
 
 #[cfg(feature = "smp")]
 mod smp;
 mod pic;
-mod asm;
+pub mod asm;
 mod interrupt;
+mod ioport;
+pub mod registerset;
+mod traps;
+pub mod gdt;
+pub mod idt;
 pub mod devices;
 
 /// This is a wrapper for u32 values we read from system descriptor tables which are actually
@@ -85,3 +95,39 @@ impl CStr32 {
     }
 }
 
+
+/// Allocate zeroed memory below 4GiB, so host unit tests can build fake boot structures which are
+/// referred to by [U32Ptr] and [CStr32]. It's never freed.
+#[cfg(test)]
+pub(crate) fn alloc_low_memory(len: usize) -> &'static mut [u8] {
+    let ptr = unsafe {
+        libc::mmap(core::ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE,
+                   libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_32BIT, -1, 0)
+    };
+    assert_ne!(ptr, libc::MAP_FAILED, "mmap failed");
+    unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, len) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u32_ptr() {
+        let mem = alloc_low_memory(8);
+        mem[4] = 0x42;
+        let ptr = U32Ptr::<u8>::new(mem.as_ptr() as u32 + 4);
+        assert!(ptr.not_null());
+        assert_eq!(unsafe { *ptr.as_ptr() }, 0x42);
+        assert!(!U32Ptr::<u8>::new(0).not_null());
+    }
+
+    #[test]
+    fn cstr32() {
+        let mem = alloc_low_memory(16);
+        mem[..6].copy_from_slice(b"hello\0");
+        let s = CStr32(mem.as_ptr() as u32);
+        assert_eq!(unsafe { s.try_as_cstr(&()) }, Some(c"hello"));
+        assert_eq!(unsafe { CStr32(0).try_as_cstr(&()) }, None);
+    }
+}
@@ -0,0 +1,387 @@
+//! Model specific registers. This is the only place the kernel reads or writes MSRs. (The 32 bit
+//! boot code in boot0 uses the constants from here.)
+//!
+//! Each MSR the kernel uses has a newtype, with a named accessor for each bit. For flag bits, `x()`
+//! reads the bit, `with_x(on)` returns a copy with the bit changed, and `X` is its mask. See the
+//! Intel SDM vol 4, "Model-Specific Registers", and the AMD APM vol 2, appendix A.
+
+use core::arch::asm;
+use crate::arch::x86_64::cpu::{CpuInfo, Feature};
+
+pub(crate) const IA32_APIC_BASE: u32 = 0x1b;
+pub(crate) const IA32_SPEC_CTRL: u32 = 0x48;
+pub(crate) const IA32_PRED_CMD: u32 = 0x49;
+pub(crate) const IA32_ARCH_CAPABILITIES: u32 = 0x10a;
+pub(crate) const IA32_PAT: u32 = 0x277;
+pub(crate) const IA32_TSC_DEADLINE: u32 = 0x6e0;
+pub(crate) const IA32_EFER: u32 = 0xc000_0080;
+pub(crate) const IA32_STAR: u32 = 0xc000_0081;
+pub(crate) const IA32_LSTAR: u32 = 0xc000_0082;
+pub(crate) const IA32_FS_BASE: u32 = 0xc000_0100;
+pub(crate) const IA32_GS_BASE: u32 = 0xc000_0101;
+
+/// SAFETY: The MSR must exist on this CPU, or this raises #GP.
+pub(crate) unsafe fn rdmsr(msr: u32) -> u64 {
+    let (high, low): (u32, u32);
+    unsafe {
+        asm!("rdmsr",
+            out("eax") low,
+            out("edx") high,
+            in("ecx") msr,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    ((high as u64) << 32) | (low as u64)
+}
+
+/// SAFETY: The MSR must exist on this CPU and accept the value, or this raises #GP. MSRs control
+/// everything from paging to syscall entry, so the caller must know what the write does.
+pub(crate) unsafe fn wrmsr(msr: u32, value: u64) {
+    unsafe {
+        asm!("wrmsr",
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            in("ecx") msr,
+            options(nostack, preserves_flags),
+        );
+    }
+}
+
+/// An MSR with a typed value.
+pub(crate) trait Msr: Copy {
+    const ADDRESS: u32;
+
+    fn from_bits(bits: u64) -> Self;
+    fn bits(self) -> u64;
+
+    /// SAFETY: See [rdmsr].
+    unsafe fn read() -> Self {
+        Self::from_bits(unsafe { rdmsr(Self::ADDRESS) })
+    }
+
+    /// SAFETY: See [wrmsr].
+    unsafe fn write(self) {
+        unsafe { wrmsr(Self::ADDRESS, self.bits()) }
+    }
+}
+
+macro_rules! msr {
+    ($(#[$meta:meta])* $name:ident = $address:expr) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, Default, Eq, PartialEq)]
+        #[repr(transparent)]
+        pub(crate) struct $name(pub u64);
+
+        impl Msr for $name {
+            const ADDRESS: u32 = $address;
+
+            fn from_bits(bits: u64) -> Self {
+                Self(bits)
+            }
+
+            fn bits(self) -> u64 {
+                self.0
+            }
+        }
+    };
+}
+
+macro_rules! msr_flags {
+    ($name:ident { $($(#[$meta:meta])* $mask:ident = $bit:literal => $get:ident, $with:ident;)* }) => {
+        impl $name {
+            $(
+                pub const $mask: u64 = 1 << $bit;
+
+                $(#[$meta])*
+                pub const fn $get(self) -> bool {
+                    self.0 & Self::$mask != 0
+                }
+
+                pub const fn $with(self, on: bool) -> Self {
+                    if on { Self(self.0 | Self::$mask) } else { Self(self.0 & !Self::$mask) }
+                }
+            )*
+        }
+    };
+}
+
+msr!(
+    /// IA32_EFER, the extended feature enables.
+    Efer = IA32_EFER
+);
+
+msr_flags!(Efer {
+    /// SYSCALL and SYSRET are enabled.
+    SCE = 0 => sce, with_sce;
+    /// Long mode is enabled. It's active once paging is turned on too (see lma).
+    LME = 8 => lme, with_lme;
+    /// Long mode is active. This is read only.
+    LMA = 10 => lma, with_lma;
+    /// The NX (execute disable) bit in page table entries is enabled.
+    NXE = 11 => nxe, with_nxe;
+    /// SVM is enabled. (AMD only.)
+    SVME = 12 => svme, with_svme;
+    /// Long mode segment limits are enabled. (AMD only.)
+    LMSLE = 13 => lmsle, with_lmsle;
+    /// FXSAVE and FXRSTOR skip the SSE registers in ring 0. (AMD only.)
+    FFXSR = 14 => ffxsr, with_ffxsr;
+    /// INVLPG only flushes the page it's given, not upper level entries. (AMD only.)
+    TCE = 15 => tce, with_tce;
+});
+
+msr!(
+    /// IA32_STAR, the segments for SYSCALL and SYSRET.
+    Star = IA32_STAR
+);
+
+impl Star {
+    /// SYSCALL loads CS with this, and SS with this + 8.
+    pub const fn syscall_cs(self) -> u16 {
+        (self.0 >> 32) as u16
+    }
+
+    pub const fn with_syscall_cs(self, selector: u16) -> Self {
+        Self((self.0 & !(0xffff << 32)) | (selector as u64) << 32)
+    }
+
+    /// SYSRET to 64 bit mode loads CS with this + 16, and SS with this + 8.
+    pub const fn sysret_cs(self) -> u16 {
+        (self.0 >> 48) as u16
+    }
+
+    pub const fn with_sysret_cs(self, selector: u16) -> Self {
+        Self((self.0 & !(0xffff << 48)) | (selector as u64) << 48)
+    }
+}
+
+msr!(
+    /// IA32_LSTAR, the 64 bit SYSCALL entry point.
+    Lstar = IA32_LSTAR
+);
+
+msr!(
+    /// IA32_FS_BASE, the FS segment base. Also writable with WRFSBASE.
+    FsBase = IA32_FS_BASE
+);
+
+msr!(
+    /// IA32_GS_BASE, the GS segment base. Also writable with WRGSBASE.
+    GsBase = IA32_GS_BASE
+);
+
+msr!(
+    /// IA32_TSC_DEADLINE. The local APIC timer fires when the TSC reaches this, in TSC deadline
+    /// mode. Writing 0 disarms it.
+    TscDeadline = IA32_TSC_DEADLINE
+);
+
+msr!(
+    /// IA32_APIC_BASE, the local APIC's base address and mode.
+    ApicBase = IA32_APIC_BASE
+);
+
+msr_flags!(ApicBase {
+    /// This is the bootstrap processor. This is read only.
+    BSP = 8 => bsp, with_bsp;
+    /// x2APIC mode is enabled. (EN must be set too.)
+    EXTD = 10 => extd, with_extd;
+    /// The local APIC is enabled.
+    EN = 11 => en, with_en;
+});
+
+impl ApicBase {
+    const BASE_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+    /// The xAPIC MMIO registers' physical address.
+    pub const fn base(self) -> u64 {
+        self.0 & Self::BASE_MASK
+    }
+
+    pub const fn with_base(self, paddr: u64) -> Self {
+        Self((self.0 & !Self::BASE_MASK) | (paddr & Self::BASE_MASK))
+    }
+}
+
+/// A memory type in the PAT.
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub(crate) enum PatType {
+    Uncacheable = 0,
+    WriteCombining = 1,
+    WriteThrough = 4,
+    WriteProtected = 5,
+    WriteBack = 6,
+    /// UC-. Uncacheable, but MTRRs can override it with write combining.
+    Uncached = 7,
+}
+
+msr!(
+    /// IA32_PAT, the page attribute table. Page table entries pick one of its 8 entries with their
+    /// PAT, PCD and PWT bits.
+    Pat = IA32_PAT
+);
+
+impl Pat {
+    /// Entry i's memory type, or None if it's a reserved encoding.
+    pub const fn entry(self, i: usize) -> Option<PatType> {
+        match (self.0 >> (i * 8)) & 0x7 {
+            0 => Some(PatType::Uncacheable),
+            1 => Some(PatType::WriteCombining),
+            4 => Some(PatType::WriteThrough),
+            5 => Some(PatType::WriteProtected),
+            6 => Some(PatType::WriteBack),
+            7 => Some(PatType::Uncached),
+            _ => None,
+        }
+    }
+
+    pub const fn with_entry(self, i: usize, kind: PatType) -> Self {
+        Self((self.0 & !(0xff << (i * 8))) | (kind as u64) << (i * 8))
+    }
+}
+
+msr!(
+    /// IA32_SPEC_CTRL, the speculative execution controls. Which bits exist depends on the CPU (see
+    /// the Feature bits in cpu.rs).
+    SpecCtrl = IA32_SPEC_CTRL
+);
+
+msr_flags!(SpecCtrl {
+    /// Indirect branch restricted speculation.
+    IBRS = 0 => ibrs, with_ibrs;
+    /// Single thread indirect branch predictors.
+    STIBP = 1 => stibp, with_stibp;
+    /// Speculative store bypass disable.
+    SSBD = 2 => ssbd, with_ssbd;
+    /// Indirect predictors aren't used in user mode until a branch of the same kind executes.
+    IPRED_DIS_U = 3 => ipred_dis_u, with_ipred_dis_u;
+    /// Indirect predictors aren't used in kernel mode until a branch of the same kind executes.
+    IPRED_DIS_S = 4 => ipred_dis_s, with_ipred_dis_s;
+    /// Alternate RSB predictions are disabled in user mode.
+    RRSBA_DIS_U = 5 => rrsba_dis_u, with_rrsba_dis_u;
+    /// Alternate RSB predictions are disabled in kernel mode.
+    RRSBA_DIS_S = 6 => rrsba_dis_s, with_rrsba_dis_s;
+    /// Fast store forwarding predictor disable.
+    PSFD = 7 => psfd, with_psfd;
+    /// Data dependent prefetcher disable in user mode.
+    DDPD_U = 8 => ddpd_u, with_ddpd_u;
+    /// Branch history isn't used for indirect branch prediction in kernel mode.
+    BHI_DIS_S = 10 => bhi_dis_s, with_bhi_dis_s;
+});
+
+msr!(
+    /// IA32_PRED_CMD, prediction barrier commands. This is write only: each set bit runs the
+    /// command once.
+    PredCmd = IA32_PRED_CMD
+);
+
+msr_flags!(PredCmd {
+    /// Indirect branch prediction barrier. Branches after it aren't predicted from ones before it.
+    IBPB = 0 => ibpb, with_ibpb;
+});
+
+msr!(
+    /// IA32_ARCH_CAPABILITIES, which vulnerabilities the CPU isn't affected by, and which
+    /// mitigation controls it has. This is read only. See
+    /// https://www.intel.com/content/www/us/en/developer/articles/technical/software-security-guidance/technical-documentation/cpuid-enumeration-and-architectural-msrs.html
+    ArchCapabilities = IA32_ARCH_CAPABILITIES
+);
+
+msr_flags!(ArchCapabilities {
+    /// Not vulnerable to Rogue Data Cache Load (Meltdown).
+    RDCL_NO = 0 => rdcl_no, with_rdcl_no;
+    /// Enhanced IBRS: setting IBRS once protects from then on.
+    IBRS_ALL = 1 => ibrs_all, with_ibrs_all;
+    /// RET may predict from the alternate predictor when the RSB is empty.
+    RSBA = 2 => rsba, with_rsba;
+    /// A hypervisor doesn't need to flush the L1D on VM entry.
+    SKIP_L1DFL_VMENTRY = 3 => skip_l1dfl_vmentry, with_skip_l1dfl_vmentry;
+    /// Not vulnerable to Speculative Store Bypass.
+    SSB_NO = 4 => ssb_no, with_ssb_no;
+    /// Not vulnerable to Microarchitectural Data Sampling.
+    MDS_NO = 5 => mds_no, with_mds_no;
+    /// Changing a page size doesn't cause a machine check.
+    IF_PSCHANGE_MC_NO = 6 => if_pschange_mc_no, with_if_pschange_mc_no;
+    /// IA32_TSX_CTRL exists.
+    TSX_CTRL = 7 => tsx_ctrl, with_tsx_ctrl;
+    /// Not vulnerable to TSX Asynchronous Abort.
+    TAA_NO = 8 => taa_no, with_taa_no;
+    /// IA32_MCU_OPT_CTRL exists.
+    MCU_CONTROL = 9 => mcu_control, with_mcu_control;
+    /// IA32_MISC_PACKAGE_CTLS exists.
+    MISC_PACKAGE_CTLS = 10 => misc_package_ctls, with_misc_package_ctls;
+    /// Energy filtering can be enabled in IA32_MISC_PACKAGE_CTLS.
+    ENERGY_FILTERING_CTL = 11 => energy_filtering_ctl, with_energy_filtering_ctl;
+    /// Data operand independent timing mode is supported.
+    DOITM = 12 => doitm, with_doitm;
+    /// Not vulnerable to Shared Buffers Data Read or Sideband Stale Data Propagator.
+    SBDR_SSDP_NO = 13 => sbdr_ssdp_no, with_sbdr_ssdp_no;
+    /// Not vulnerable to Fill Buffer Stale Data Propagator.
+    FBSDP_NO = 14 => fbsdp_no, with_fbsdp_no;
+    /// Not vulnerable to Primary Stale Data Propagator.
+    PSDP_NO = 15 => psdp_no, with_psdp_no;
+    /// VERW clears the fill buffers.
+    FB_CLEAR = 17 => fb_clear, with_fb_clear;
+    /// IA32_MCU_OPT_CTRL can turn off the fill buffer clearing.
+    FB_CLEAR_CTRL = 18 => fb_clear_ctrl, with_fb_clear_ctrl;
+    /// RET and indirect branches may use the alternate predictor.
+    RRSBA = 19 => rrsba, with_rrsba;
+    /// Not vulnerable to Branch History Injection.
+    BHI_NO = 20 => bhi_no, with_bhi_no;
+    /// The xAPIC is disabled and locked, and only x2APIC mode works.
+    XAPIC_DISABLE_STATUS = 21 => xapic_disable_status, with_xapic_disable_status;
+    /// IA32_OVERCLOCKING_STATUS exists.
+    OVERCLOCKING_STATUS = 23 => overclocking_status, with_overclocking_status;
+    /// Not vulnerable to Post-barrier RSB predictions.
+    PBRSB_NO = 24 => pbrsb_no, with_pbrsb_no;
+    /// The Gather Data Sampling mitigation can be controlled and locked.
+    GDS_CTRL = 25 => gds_ctrl, with_gds_ctrl;
+    /// Not vulnerable to Gather Data Sampling.
+    GDS_NO = 26 => gds_no, with_gds_no;
+    /// Not vulnerable to Register File Data Sampling.
+    RFDS_NO = 27 => rfds_no, with_rfds_no;
+    /// VERW clears the register file.
+    RFDS_CLEAR = 28 => rfds_clear, with_rfds_clear;
+});
+
+impl ArchCapabilities {
+    /// Read the MSR, if cpu has it.
+    pub fn read_if_present(cpu: &CpuInfo) -> Option<Self> {
+        cpu.has(Feature::ArchCapabilities).then(|| unsafe { Self::read() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags() {
+        let efer = Efer(0).with_lme(true).with_nxe(true);
+        assert_eq!(efer.0, 0x900);
+        assert!(efer.lme() && !efer.lma());
+        assert_eq!(efer.with_lme(false).0, Efer::NXE);
+
+        let caps = ArchCapabilities(0x0c00_00eb);
+        assert!(caps.rdcl_no() && caps.ibrs_all() && caps.mds_no() && caps.gds_no() && caps.rfds_no());
+        assert!(!caps.rsba() && !caps.taa_no());
+    }
+
+    #[test]
+    fn fields() {
+        let star = Star(0).with_syscall_cs(0x08).with_sysret_cs(0x1b);
+        assert_eq!(star.0, 0x001b_0008_0000_0000);
+        assert_eq!((star.syscall_cs(), star.sysret_cs()), (0x08, 0x1b));
+
+        let apic = ApicBase(0xfee0_0900);
+        assert!(apic.bsp() && apic.en() && !apic.extd());
+        assert_eq!(apic.base(), 0xfee0_0000);
+        assert_eq!(apic.with_base(0x1234_5000).0, 0x1234_5900);
+
+        // The power on default: WB, WT, UC-, UC, repeated.
+        let pat = Pat(0x0007_0406_0007_0406);
+        assert!(pat.entry(0) == Some(PatType::WriteBack) && pat.entry(2) == Some(PatType::Uncached));
+        assert_eq!(pat.with_entry(1, PatType::WriteCombining).0, 0x0007_0406_0007_0106);
+        assert!(Pat(2).entry(0).is_none());
+    }
+}
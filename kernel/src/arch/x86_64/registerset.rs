@@ -0,0 +1,80 @@
+//! Based on include/arch/x86/arch/64/mode/machine/registerset.h.
+//!
+//! This describes the user register state we save on kernel entry, and which registers are used to
+//! pass syscall arguments.
+
+use crate::arch::constants::FAST_MESSAGE_REGISTERS;
+use crate::arch::x86_64::fpu::FpuState;
+
+/// Indexes into [UserContext::registers].
+///
+/// The order here matters. It matches the order registers are pushed to the kernel stack on entry.
+/// The cap and badge registers are deliberately placed early, so the fastpath can avoid popping
+/// them.
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[repr(usize)]
+#[allow(unused, clippy::upper_case_acronyms)]
+pub(crate) enum Register {
+    RDI = 0,
+    RSI = 1,
+    RAX = 2,
+    RBX = 3,
+    RBP = 4,
+    R12 = 5,
+    R13 = 6,
+    R14 = 7,
+    RDX = 8,
+    // The message registers are grouped so they can be efficiently copied.
+    R10 = 9,
+    R8 = 10,
+    R9 = 11,
+    R15 = 12,
+    FLAGS = 13,
+    /// NextIP is a virtual register. Its here because we need to set it in the syscall path.
+    NextIP = 14,
+    Error = 15,
+    /// The kernel stack points here on kernel entry.
+    RSP = 16,
+    FaultIP = 17,
+    // R11 and RCX are clobbered by SYSCALL/SYSRET.
+    R11 = 18,
+    RCX = 19,
+    CS = 20,
+    SS = 21,
+    // n_immContextRegisters = 22. The registers below aren't pushed on kernel entry.
+    FsBase = 22,
+    GsBase = 23,
+}
+
+pub(crate) const N_CONTEXT_REGISTERS: usize = 24;
+
+pub(crate) const CAP_REGISTER: Register = Register::RDI;
+pub(crate) const BADGE_REGISTER: Register = Register::RDI;
+pub(crate) const MSG_INFO_REGISTER: Register = Register::RSI;
+pub(crate) const TLS_BASE: Register = Register::FsBase;
+
+/// The registers used to pass the first few message words, without touching the IPC buffer.
+pub(crate) const MSG_REGISTERS: [Register; FAST_MESSAGE_REGISTERS] = [Register::R10, Register::R8, Register::R9, Register::R15];
+
+/// The saved register state of a user thread. This is user_context_t in SeL4.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub(crate) struct UserContext {
+    /// This is first, like in SeL4, since it's 64 byte aligned.
+    pub fpu_state: FpuState,
+    pub registers: [usize; N_CONTEXT_REGISTERS],
+}
+
+impl UserContext {
+    pub const fn new() -> Self {
+        Self { fpu_state: FpuState::new(), registers: [0; _] }
+    }
+
+    pub fn get(&self, reg: Register) -> usize {
+        self.registers[reg as usize]
+    }
+
+    pub fn set(&mut self, reg: Register, value: usize) {
+        self.registers[reg as usize] = value;
+    }
+}
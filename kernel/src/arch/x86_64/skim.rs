@@ -0,0 +1,239 @@
+//! The Static Kernel Image and Micro-state (SKIM) window, which mitigates Meltdown. This is based
+//! on SeL4's KERNEL_SKIM_WINDOW support.
+//!
+//! Meltdown lets user code read anything mapped in its page tables, supervisor only or not. So on a
+//! vulnerable CPU, user page tables can't map the kernel window. The CPU still needs some of the
+//! kernel mapped to enter it though: the entry code, the IDT, GDT and TSS, and the stack it pushes
+//! the interrupt frame onto. The SKIM window is just that. It's everything the linker script puts
+//! between ki_skim_start and ki_skim_end: the kernel's code, read only data and .skim.data.
+//!
+//! The kernel stacks aren't in the window. Instead the TSS points the CPU at small per core entry
+//! stacks in .skim.data ([EntryStack]), and the entry stubs (idt.rs) move to the real stacks once
+//! they've switched page tables. Returning to userland goes back through the entry stack the same
+//! way.
+//!
+//! User address spaces get the SKIM window's kernel half instead of the kernel's
+//! (copy_global_mappings). Kernel entry switches to the kernel's page tables (KERNEL_CR3) before
+//! touching anything outside the window, and the exit path switches back to the thread's
+//! (set_current_user_cr3) as its last step.
+//!
+//! TODO: The syscall and interrupt stubs don't exist yet (see traps.rs), and neither do user address
+//! spaces, so nothing calls copy_global_mappings or set_current_user_cr3 yet. The exception stubs
+//! (idt.rs) already switch page tables.
+
+use core::ops::Range;
+use crate::arch::asm::write_cr3;
+use crate::arch::constants::{LARGE_PAGE_BITS, PAGE_BITS};
+use crate::arch::hardware::KERNEL_ELF_BASE;
+use crate::config::CONFIG_MAX_NUM_NODES;
+use crate::hardware::PPTR_BASE_OFFSET;
+use crate::const_assert;
+use crate::racycell::RacyCell;
+use crate::utils::bit_usize;
+use super::speculation::{address_space_switch, mitigations};
+
+const PT_ENTRIES: usize = 512;
+const PML4_SHIFT: usize = 39;
+const PDPT_SHIFT: usize = 30;
+
+const PRESENT: u64 = 1 << 0;
+const WRITABLE: u64 = 1 << 1;
+const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+#[repr(C, align(4096))]
+struct PageTable([u64; PT_ENTRIES]);
+
+/// Enough page tables for 6MiB of kernel image, wherever it starts.
+const NUM_PAGE_TABLES: usize = 4;
+
+static SKIM_PDPT: RacyCell<PageTable> = RacyCell::new(PageTable([0; PT_ENTRIES]));
+static SKIM_PD: RacyCell<PageTable> = RacyCell::new(PageTable([0; PT_ENTRIES]));
+static SKIM_PAGE_TABLES: RacyCell<[PageTable; NUM_PAGE_TABLES]> =
+    RacyCell::new([const { PageTable([0; PT_ENTRIES]) }; NUM_PAGE_TABLES]);
+
+/// The SKIM window's top level. Only its kernel half is used, and it's empty unless the SKIM window
+/// is on. This is x64KSSKIMPML4 in SeL4.
+static SKIM_PML4: RacyCell<PageTable> = RacyCell::new(PageTable([0; PT_ENTRIES]));
+
+/// The kernel's page tables. Every core shares them. The entry code loads this, so it's in the
+/// SKIM window.
+#[unsafe(link_section = ".skim.data")]
+pub(super) static KERNEL_CR3: RacyCell<usize> = RacyCell::new(0);
+
+/// Each core's current user page tables. This is x64KSCurrentUserCR3 in SeL4.
+#[unsafe(link_section = ".skim.data")]
+static USER_CR3: RacyCell<[usize; CONFIG_MAX_NUM_NODES]> = RacyCell::new([0; CONFIG_MAX_NUM_NODES]);
+
+/// The most an entry stub keeps on an entry stack: the CPU's frame, the vector and error code, and
+/// two scratch registers. With room to spare.
+const ENTRY_STACK_WORDS: usize = 16;
+
+/// A stack the CPU switches to on kernel entry, through the TSS. The CPU pushes the interrupt frame
+/// here, before the entry stub can switch to the kernel's page tables. Then the stub moves on to
+/// the stack at kernel_rsp. This is x64KSIRQStack in SeL4.
+#[repr(C, align(16))]
+pub(super) struct EntryStack {
+    stack: [u64; ENTRY_STACK_WORDS],
+    /// The top of the real stack. It's right above the entry stack, so the stubs find it at a fixed
+    /// offset from the frame.
+    kernel_rsp: u64,
+}
+
+const_assert!(core::mem::offset_of!(EntryStack, kernel_rsp).is_multiple_of(16),
+    "The CPU aligns the stack to 16 bytes on entry, so the top of an entry stack must be aligned.");
+
+impl EntryStack {
+    const fn new() -> Self {
+        Self { stack: [0; ENTRY_STACK_WORDS], kernel_rsp: 0 }
+    }
+
+    /// Move on to the stack with top kernel_rsp after entry. Returns the entry stack's top, for the
+    /// TSS.
+    pub fn init(&mut self, kernel_rsp: usize) -> u64 {
+        self.kernel_rsp = kernel_rsp as u64;
+        &raw const self.kernel_rsp as u64
+    }
+}
+
+/// One core's entry stacks. The CPU switches to `kernel` (rsp0 in the TSS) on entries from
+/// userland, and to the others (through the IST) for every #DF and NMI.
+#[repr(C)]
+pub(super) struct EntryStacks {
+    pub kernel: EntryStack,
+    pub double_fault: EntryStack,
+    pub nmi: EntryStack,
+}
+
+#[unsafe(link_section = ".skim.data")]
+pub(super) static ENTRY_STACKS: RacyCell<[EntryStacks; CONFIG_MAX_NUM_NODES]> = RacyCell::new(
+    [const { EntryStacks { kernel: EntryStack::new(), double_fault: EntryStack::new(), nmi: EntryStack::new() } };
+        CONFIG_MAX_NUM_NODES]);
+
+/// Map the pages covering vaddrs into pd, with 4KiB pages. Page tables are taken from pts as they're
+/// needed. Physical addresses are virtual addresses - offset.
+fn map_pages<'a>(pd: &mut PageTable, pts: &mut impl Iterator<Item = &'a mut PageTable>, vaddrs: Range<usize>,
+                 offset: usize) {
+    let page = bit_usize(PAGE_BITS);
+    for vaddr in (vaddrs.start & !(page - 1)..vaddrs.end).step_by(page) {
+        let pde = &mut pd.0[(vaddr >> LARGE_PAGE_BITS) % PT_ENTRIES];
+        if *pde == 0 {
+            let pt = pts.next().expect("Out of page tables for the SKIM window");
+            *pde = (pt as *mut PageTable as usize - offset) as u64 | PRESENT | WRITABLE;
+        }
+        let pt = ((*pde & ADDR_MASK) as usize + offset) as *mut PageTable;
+        // Supervisor only. Like the kernel window, everything is writable and executable.
+        unsafe { (*pt).0[(vaddr >> PAGE_BITS) % PT_ENTRIES] = (vaddr - offset) as u64 | PRESENT | WRITABLE };
+    }
+}
+
+/// Record the kernel's page tables, for the entry code. This must happen before the IDT is loaded.
+///
+/// SAFETY: This must be called once, at boot, while the boot page tables are in use.
+pub(crate) unsafe fn init_kernel_cr3() {
+    unsafe { *KERNEL_CR3.get_mut() = crate::arch::asm::read_cr3() };
+}
+
+/// Build the SKIM window's page tables.
+///
+/// SAFETY: This must be called once, at boot, before any user address spaces exist.
+#[cfg(not(test))]
+#[unsafe(link_section = ".boot.text")]
+pub(crate) unsafe fn init_skim_window() {
+    use crate::hardware::KERNEL_ELF_BASE_OFFSET;
+    use crate::kprintln;
+
+    unsafe extern "C" {
+        static ki_skim_start: u8;
+        static ki_skim_end: u8;
+    }
+
+    let window = &raw const ki_skim_start as usize..&raw const ki_skim_end as usize;
+    let pd = unsafe { SKIM_PD.get_mut() };
+    let pdpt = unsafe { SKIM_PDPT.get_mut() };
+    let mut pts = unsafe { SKIM_PAGE_TABLES.get_mut() }.iter_mut();
+
+    map_pages(pd, &mut pts, window.clone(), KERNEL_ELF_BASE_OFFSET);
+
+    let paddr = |table: &PageTable| (table as *const PageTable as usize - KERNEL_ELF_BASE_OFFSET) as u64;
+    pdpt.0[(KERNEL_ELF_BASE >> PDPT_SHIFT) % PT_ENTRIES] = paddr(pd) | PRESENT | WRITABLE;
+    let pml4 = unsafe { SKIM_PML4.get_mut() };
+    pml4.0[(KERNEL_ELF_BASE >> PML4_SHIFT) % PT_ENTRIES] = paddr(pdpt) | PRESENT | WRITABLE;
+
+    kprintln!("SKIM window: 0x{:x}-0x{:x}, using {} of {} page tables", window.start, window.end,
+        NUM_PAGE_TABLES - pts.len(), NUM_PAGE_TABLES);
+}
+
+/// The first PML4 entry in the kernel half of the address space (from 0xffff800000000000).
+const KERNEL_HALF: usize = PT_ENTRIES / 2;
+
+/// Copy the kernel half of src's entries into dst.
+fn copy_kernel_half(dst: &mut [u64; PT_ENTRIES], src: &PageTable) {
+    dst[KERNEL_HALF..].copy_from_slice(&src.0[KERNEL_HALF..]);
+}
+
+/// Fill in the kernel half of a user address space's top level. That's the SKIM window if it's on,
+/// and the whole kernel window otherwise. This is copyGlobalMappings in SeL4.
+pub(crate) fn copy_global_mappings(pml4: &mut [u64; PT_ENTRIES]) {
+    let src = if mitigations().skim {
+        SKIM_PML4.as_ptr() as *const PageTable
+    } else {
+        (unsafe { *KERNEL_CR3.as_ptr() } + PPTR_BASE_OFFSET) as *const PageTable
+    };
+    copy_kernel_half(pml4, unsafe { &*src });
+}
+
+/// Switch core to a thread's address space. This is setCurrentUserCR3 in SeL4.
+///
+/// With the SKIM window on, the kernel can't run on user page tables, so this just records them
+/// for the exit path to load.
+///
+/// SAFETY: cr3 must be a user address space set up with copy_global_mappings.
+pub(crate) unsafe fn set_current_user_cr3(core: usize, cr3: usize) {
+    let current = unsafe { &mut USER_CR3.get_mut()[core] };
+    if *current == cr3 {
+        return;
+    }
+    *current = cr3;
+    address_space_switch();
+    if !mitigations().skim {
+        unsafe { write_cr3(cr3) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map() {
+        let mut pd = PageTable([0; PT_ENTRIES]);
+        let mut tables = [const { PageTable([0; PT_ENTRIES]) }; 2];
+        let table_addrs = tables.each_ref().map(|t| t as *const PageTable as u64);
+        let mut pts = tables.iter_mut();
+
+        // Three pages, straddling a 2MiB boundary, then one page which needs no new page table.
+        let base = 0x40_0000;
+        map_pages(&mut pd, &mut pts, base - 0x2000..base + 0x800, 0);
+        map_pages(&mut pd, &mut pts, base + 0x5000..base + 0x6000, 0);
+        assert_eq!(pts.len(), 0);
+
+        assert_eq!(pd.0[1], table_addrs[0] | 3);
+        assert_eq!(pd.0[2], table_addrs[1] | 3);
+        assert_eq!(pd.0.iter().filter(|&&e| e != 0).count(), 2);
+
+        let [low, high] = &tables;
+        assert_eq!(&low.0[510..], [(base - 0x2000) as u64 | 3, (base - 0x1000) as u64 | 3]);
+        assert_eq!(high.0[..6], [base as u64 | 3, 0, 0, 0, 0, (base + 0x5000) as u64 | 3]);
+        assert_eq!(low.0.iter().chain(&high.0).filter(|&&e| e != 0).count(), 4);
+    }
+
+    #[test]
+    fn global_mappings() {
+        let src = PageTable(core::array::from_fn(|i| i as u64 + 1));
+        let mut dst = [0; PT_ENTRIES];
+        copy_kernel_half(&mut dst, &src);
+
+        assert!(dst[..256].iter().all(|&e| e == 0));
+        assert_eq!(&dst[256..], &src.0[256..]);
+    }
+}
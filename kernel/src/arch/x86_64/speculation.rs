@@ -0,0 +1,270 @@
+//! Mitigations for speculative execution attacks (Meltdown, Spectre and friends).
+//!
+//! Each mitigation is chosen once, at boot. By default it's on when the CPU looks vulnerable:
+//! IA32_ARCH_CAPABILITIES says which attacks a CPU isn't affected by, and without it we assume the
+//! worst. The command line can force each one on or off (see boot/cmdline.rs).
+//!
+//! - The SKIM window (skim.rs) keeps the kernel out of user page tables, for Meltdown.
+//! - Enhanced IBRS is set once per core, so user code can't train the kernel's indirect branches.
+//! - IBPB flushes the indirect branch predictors on each address space switch, so one address space
+//!   can't train another's.
+//! - RSB filling overwrites the return stack buffer on each address space switch, so returns don't
+//!   consume predictions from another address space (or fall back to the BTB when it runs dry).
+//! - VERW clears the CPU's internal buffers on each return to user mode, for MDS.
+//!
+//! DEPARTURE: SeL4 only has the SKIM window, and selects it at build time.
+//!
+//! NOTE: Legacy IBRS (on CPUs without IBRS_ALL) isn't supported, on purpose. It only protects the
+//! kernel while it's set, so it has to be set on every kernel entry and cleared on every exit, and
+//! each SPEC_CTRL write costs hundreds of cycles. Those CPUs only get IBPB and RSB filling, like
+//! Linux without retpolines.
+//!
+//! TODO: The SKIM window, IBPB and RSB filling only matter once there are user address spaces to
+//! switch between (skim.rs). Until then they're chosen, but nothing uses them.
+
+use core::arch::asm;
+use crate::arch::x86_64::cpu::{CpuInfo, CpuVendor, Feature};
+use crate::arch::x86_64::gdt::SEL_DS_0;
+use crate::arch::x86_64::msr::{ArchCapabilities, Msr, PredCmd, SpecCtrl};
+use crate::racycell::RacyCell;
+use crate::{kprintln, kwarnln};
+
+/// How one mitigation was set on the command line.
+#[derive(Copy, Clone, Default, Eq, PartialEq)]
+pub(crate) enum Setting {
+    /// On if the CPU looks vulnerable.
+    #[default]
+    Auto,
+    On,
+    Off,
+}
+
+impl Setting {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "auto" => Some(Self::Auto),
+            "on" => Some(Self::On),
+            "off" => Some(Self::Off),
+            _ => None,
+        }
+    }
+}
+
+/// The mitigation settings from the command line.
+#[derive(Copy, Clone, Default)]
+pub(crate) struct MitigationOpts {
+    /// `mitigations=off`. Mitigations left on auto are turned off too.
+    pub all_off: bool,
+    pub skim: Setting,
+    pub ibrs: Setting,
+    pub ibpb: Setting,
+    pub rsb: Setting,
+    pub mds: Setting,
+}
+
+/// The mitigations in use.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub(crate) struct Mitigations {
+    pub skim: bool,
+    pub ibrs: bool,
+    pub ibpb: bool,
+    pub rsb: bool,
+    pub mds: bool,
+}
+
+impl Mitigations {
+    const NONE: Self = Self { skim: false, ibrs: false, ibpb: false, rsb: false, mds: false };
+
+    /// Choose the mitigations for cpu, which has IA32_ARCH_CAPABILITIES caps if it has the MSR.
+    fn choose(cpu: &CpuInfo, caps: Option<ArchCapabilities>, opts: &MitigationOpts) -> Self {
+        let intel = cpu.vendor == CpuVendor::Intel;
+        let caps_say = |f: fn(ArchCapabilities) -> bool| caps.is_some_and(f);
+        let enhanced_ibrs = caps_say(ArchCapabilities::ibrs_all);
+        let pick = |name, attack, setting, wanted, available| {
+            decide(name, attack, setting, opts.all_off, wanted, available)
+        };
+
+        Self {
+            // Only Intel CPUs are affected by Meltdown and MDS.
+            skim: pick("The SKIM window", "Meltdown", opts.skim,
+                       intel && !caps_say(ArchCapabilities::rdcl_no), true),
+            ibrs: pick("Enhanced IBRS", "Spectre v2", opts.ibrs, enhanced_ibrs, enhanced_ibrs),
+            ibpb: pick("IBPB", "Spectre v2", opts.ibpb, true,
+                       cpu.has(Feature::SpecCtrl) || cpu.has(Feature::AmdIbpb)),
+            // With enhanced IBRS, RSB underflow is safe, and only post-barrier RSB predictions
+            // (PBRSB) are left.
+            rsb: pick("RSB filling", "Spectre-RSB", opts.rsb,
+                      !(enhanced_ibrs && caps_say(ArchCapabilities::pbrsb_no)), true),
+            // RFDS is the same attack on the register file, which VERW also clears.
+            mds: pick("VERW buffer clearing", "MDS", opts.mds,
+                      intel && !(caps_say(ArchCapabilities::mds_no) && caps_say(ArchCapabilities::rfds_no)),
+                      cpu.has(Feature::MdClear)),
+        }
+    }
+
+    pub fn print(&self) {
+        let on_off = |on| if on { "on" } else { "off" };
+        // The others aren't reported until there are user address spaces for them to protect.
+        kprintln!("Speculation mitigations: enhanced IBRS {}, VERW {}", on_off(self.ibrs), on_off(self.mds));
+    }
+}
+
+/// Decide whether to use one mitigation. It's wanted if the CPU looks vulnerable to attack, and
+/// only available if the CPU supports it.
+fn decide(name: &str, attack: &str, setting: Setting, all_off: bool, wanted: bool, available: bool) -> bool {
+    let on = match setting {
+        Setting::On => true,
+        Setting::Off => false,
+        Setting::Auto => wanted && !all_off,
+    };
+
+    if on && !available {
+        kwarnln!("Warning: {} isn't supported by this CPU. A microcode update might add it.", name);
+        return false;
+    }
+    if wanted && !on {
+        kwarnln!("Warning: {} is disabled, and this CPU is probably vulnerable to {}.", name, attack);
+    } else if on && !wanted {
+        kwarnln!("{} is enabled, but this CPU isn't vulnerable to {}. Performance is being needlessly \
+            impacted.", name, attack);
+    }
+    on
+}
+
+static MITIGATIONS: RacyCell<Mitigations> = RacyCell::new(Mitigations::NONE);
+
+/// Choose the mitigations for the boot core's CPU, and turn on the ones which are set once per core.
+#[unsafe(link_section = ".boot.text")]
+pub(crate) fn init_mitigations(cpu: &CpuInfo, opts: &MitigationOpts) -> Mitigations {
+    let chosen = Mitigations::choose(cpu, ArchCapabilities::read_if_present(cpu), opts);
+    unsafe { *MITIGATIONS.get_mut() = chosen };
+    chosen.print();
+    init_core_mitigations();
+    chosen
+}
+
+/// Turn on the mitigations which are set once per core. Each AP calls this as it comes up.
+pub(crate) fn init_core_mitigations() {
+    if mitigations().ibrs {
+        unsafe {
+            let spec_ctrl = SpecCtrl::read();
+            spec_ctrl.with_ibrs(true).write();
+        }
+    }
+}
+
+pub(crate) fn mitigations() -> Mitigations {
+    unsafe { *MITIGATIONS.as_ptr() }
+}
+
+/// Indirect branch prediction barrier. Indirect branches after this can't be steered by anything
+/// before it.
+pub(crate) fn ibpb() {
+    unsafe { PredCmd(0).with_ibpb(true).write() };
+}
+
+/// The number of entries in the return stack buffer. It's 16 or 32 on every CPU so far, so we fill
+/// 32.
+const RSB_ENTRIES: usize = 32;
+
+/// Fill the return stack buffer with calls which land in a speculation trap, which pushes out
+/// anything which was in it before.
+#[inline(always)]
+pub(crate) fn fill_rsb() {
+    // This is Linux's __FILL_RETURN_BUFFER. Each call pushes an RSB entry pointing at the
+    // pause/lfence loop just after it, which a mispredicted ret would spin in harmlessly.
+    unsafe {
+        asm!(
+            "mov {n:e}, {loops}",
+            "2:",
+            "call 4f",
+            "3:",
+            "pause",
+            "lfence",
+            "jmp 3b",
+            "4:",
+            "call 6f",
+            "5:",
+            "pause",
+            "lfence",
+            "jmp 5b",
+            "6:",
+            "dec {n:e}",
+            "jnz 2b",
+            // Drop the return addresses the calls pushed.
+            "add rsp, {bytes}",
+            "lfence",
+            n = out(reg) _,
+            loops = const RSB_ENTRIES / 2,
+            bytes = const RSB_ENTRIES * 8,
+        );
+    }
+}
+
+/// VERW's operand. The microcode only clears the buffers when it's a valid, writable data segment.
+static VERW_SELECTOR: u16 = SEL_DS_0;
+
+/// Clear the CPU's internal buffers (store buffer, fill buffers and load ports), for MDS. This only
+/// clears them on CPUs with Feature::MdClear. Otherwise VERW just checks the segment.
+#[inline(always)]
+pub(crate) fn clear_cpu_buffers() {
+    unsafe { asm!("verw word ptr [{}]", in(reg) &VERW_SELECTOR, options(nostack, readonly)) };
+}
+
+/// Called on every return to user mode, as late as possible.
+#[inline(always)]
+pub(crate) fn kernel_exit() {
+    if mitigations().mds {
+        clear_cpu_buffers();
+    }
+}
+
+/// Called when a core switches to a different address space.
+pub(crate) fn address_space_switch() {
+    let mitigations = mitigations();
+    if mitigations.ibpb {
+        ibpb();
+    }
+    if mitigations.rsb {
+        fill_rsb();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn choose(cpu: &CpuInfo, caps: Option<u64>, opts: MitigationOpts) -> Mitigations {
+        Mitigations::choose(cpu, caps.map(ArchCapabilities), &opts)
+    }
+
+    fn on(skim: bool, ibrs: bool, ibpb: bool, rsb: bool, mds: bool) -> Mitigations {
+        Mitigations { skim, ibrs, ibpb, rsb, mds }
+    }
+
+    #[test]
+    fn auto() {
+        let skylake = CpuInfo::with_features(CpuVendor::Intel, &[Feature::SpecCtrl, Feature::MdClear]);
+        assert!(choose(&skylake, None, MitigationOpts::default()) == on(true, false, true, true, true));
+
+        // An Intel CPU which isn't affected by any of it.
+        let caps = ArchCapabilities::RDCL_NO | ArchCapabilities::IBRS_ALL | ArchCapabilities::MDS_NO
+            | ArchCapabilities::RFDS_NO | ArchCapabilities::PBRSB_NO;
+        assert!(choose(&skylake, Some(caps), MitigationOpts::default()) == on(false, true, true, false, false));
+
+        let zen = CpuInfo::with_features(CpuVendor::Amd, &[Feature::AmdIbpb]);
+        assert!(choose(&zen, None, MitigationOpts::default()) == on(false, false, true, true, false));
+    }
+
+    #[test]
+    fn overrides() {
+        let skylake = CpuInfo::with_features(CpuVendor::Intel, &[Feature::SpecCtrl, Feature::MdClear]);
+        let off = MitigationOpts { all_off: true, ibpb: Setting::On, ..Default::default() };
+        assert!(choose(&skylake, None, off) == on(false, false, true, false, false));
+
+        // The SKIM window works anywhere, but VERW and IBPB need microcode support.
+        let zen = CpuInfo::with_features(CpuVendor::Amd, &[]);
+        let forced = MitigationOpts { skim: Setting::On, mds: Setting::On, rsb: Setting::Off, ..Default::default() };
+        assert!(choose(&zen, None, forced) == on(true, false, false, false, false));
+    }
+}
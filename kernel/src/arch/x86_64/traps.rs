@@ -0,0 +1,77 @@
+//! The C parts of the kernel entry paths. This is based on src/arch/x86/c_traps.c.
+//!
+//! TODO: Only the #DB, #BP and #NM stubs (exception_entry in idt.rs) call into here so far.
+//! SYSCALL, interrupts and the other exceptions don't have entry stubs yet.
+
+use crate::api::failures::Exception;
+use crate::api::syscall::slowpath;
+use crate::arch::registerset::{Register, UserContext};
+use crate::arch::x86_64::fpu::handle_fpu_fault;
+use crate::arch::x86_64::machine::INT_UNIMPL_DEV;
+use crate::basic_types::Cptr;
+use crate::stack::current_core;
+use crate::kpanic;
+use crate::utils::backtrace::print_backtrace_from;
+
+/// Called on every kernel entry. This is c_entry_hook in SeL4.
+#[inline(always)]
+fn c_entry_hook() {
+    #[cfg(feature = "benchmark")]
+    crate::benchmark::utilisation::track_kernel_entry();
+}
+
+/// Called on every kernel exit. This is c_exit_hook in SeL4.
+#[inline(always)]
+fn c_exit_hook() {
+    #[cfg(feature = "benchmark")]
+    crate::benchmark::utilisation::track_kernel_exit();
+
+    // Last, so nothing the kernel touches after it is left in the CPU's buffers.
+    super::speculation::kernel_exit();
+}
+
+/// Entry point for the SYSCALL instruction, once the user context has been saved.
+///
+/// The syscall number is passed in RDX. The cap and message info are passed in RDI and RSI, which
+/// are also saved in ctx.
+///
+/// TODO: SeL4 takes seL4_Call and seL4_ReplyRecv down the fastpath (fastpath.c) here, which
+/// switches straight to the receiver. That needs endpoints, reply caps and threads to switch
+/// between, so for now every syscall goes through the slowpath.
+pub(crate) fn c_handle_syscall(_cptr: Cptr, _msg_info: usize, syscall: isize, ctx: &mut UserContext) -> Result<(), Exception> {
+    c_entry_hook();
+
+    let result = slowpath(syscall, ctx);
+
+    // TODO: This should restore the user context and return to userland (restore_user_context).
+    c_exit_hook();
+    result
+}
+
+/// Entry point for CPU exceptions (vectors below IRQ_INT_OFFSET), once the interrupted context has
+/// been saved. This is the exception half of c_handle_interrupt in SeL4.
+///
+/// cr3 is the page tables the context was running on.
+pub(crate) fn c_handle_exception(vector: u8, ctx: &mut UserContext, cr3: usize) {
+    c_entry_hook();
+
+    #[cfg(feature = "gdb")]
+    if crate::gdb::handle_exception(vector, ctx, cr3) {
+        c_exit_hook();
+        return;
+    }
+
+    // The thread used the FPU while it was disabled, to switch its state lazily (see fpu.rs).
+    if vector == INT_UNIMPL_DEV && ctx.get(Register::CS) & 3 != 0 {
+        unsafe { handle_fpu_fault(current_core(), ctx) };
+        c_exit_hook();
+        return;
+    }
+
+    // TODO: Deliver user faults to the thread's fault handler (handleUserLevelFault and
+    // handleVMFaultEvent). Exceptions in the kernel itself are fatal.
+    if ctx.get(Register::CS) & 3 == 0 {
+        print_backtrace_from(Some(ctx.get(Register::FaultIP)), ctx.get(Register::RBP));
+    }
+    kpanic!("Unhandled exception {} at 0x{:x}", vector, ctx.get(Register::FaultIP));
+}
@@ -0,0 +1,75 @@
+//! Benchmarking support. This is only compiled in with the `benchmark` feature
+//! (CONFIG_ENABLE_BENCHMARKS, CONFIG_BENCHMARK_TRACK_UTILISATION and CONFIG_KERNEL_LOG_BUFFER in
+//! SeL4).
+//!
+//! Based on src/benchmark/benchmark.c and the benchmark parts of handleUnknownSyscall in
+//! src/api/syscall.c. This lets us run sel4bench-style microbenchmarks and compare the numbers
+//! against upstream SeL4.
+
+pub mod utilisation;
+
+use crate::api::failures::{Exception, SyscallError};
+use crate::arch::registerset::{UserContext, CAP_REGISTER};
+use crate::basic_types::Paddr;
+use crate::cspace::lookup_cap_type;
+use crate::racycell::RacyCell;
+use crate::kwarnln;
+
+/// State for the kernel log buffer.
+struct LogBufferState {
+    /// Physical address of the user frame mapped at KS_LOG_PPTR. This is ksUserLogBuffer in SeL4.
+    /// 0 if no log buffer has been set.
+    user_log_buffer: Paddr,
+    /// Index of the next free entry in the log buffer.
+    log_index: usize,
+    /// The value of log_index when the log was last finalized.
+    log_index_finalized: usize,
+}
+
+static LOG_BUFFER: RacyCell<LogBufferState> = RacyCell::new(LogBufferState {
+    user_log_buffer: 0,
+    log_index: 0,
+    log_index_finalized: 0,
+});
+
+/// Write the error code into the caller's cap register, the way SeL4's benchmark syscalls report
+/// errors.
+fn syscall_error(ctx: &mut UserContext, err: SyscallError) -> Result<(), Exception> {
+    ctx.set(CAP_REGISTER, err.code());
+    Err(Exception::SyscallError(err))
+}
+
+/// seL4_BenchmarkResetLog. Clears the log buffer and starts tracking utilisation.
+///
+/// The log buffer is optional. Utilisation tracking doesn't use it, so this works without one, like
+/// SeL4 built with CONFIG_BENCHMARK_TRACK_UTILISATION but not CONFIG_KERNEL_LOG_BUFFER.
+pub(crate) fn handle_reset_log(ctx: &mut UserContext) -> Result<(), Exception> {
+    let log = unsafe { LOG_BUFFER.get_mut() };
+    log.log_index = 0;
+
+    utilisation::reset();
+
+    // seL4_NoError.
+    ctx.set(CAP_REGISTER, 0);
+    Ok(())
+}
+
+/// seL4_BenchmarkFinalizeLog. Stops tracking utilisation and returns the number of log entries.
+pub(crate) fn handle_finalize_log(ctx: &mut UserContext) {
+    let log = unsafe { LOG_BUFFER.get_mut() };
+    log.log_index_finalized = log.log_index;
+    ctx.set(CAP_REGISTER, log.log_index_finalized);
+
+    utilisation::finalise();
+}
+
+/// seL4_BenchmarkSetLogBuffer. Map the large frame in the cap register into the kernel at
+/// KS_LOG_PPTR, to use as the kernel log buffer.
+pub(crate) fn handle_set_log_buffer(ctx: &mut UserContext) -> Result<(), Exception> {
+    // TODO: This should look up a 2MiB frame cap, and map it into the kernel's device page
+    // directory at KS_LOG_PPTR (benchmark_arch_map_logBuffer). Neither frame caps nor the kernel
+    // page tables exist yet, so the lookup always fails.
+    let cap_type = lookup_cap_type(ctx.get(CAP_REGISTER));
+    kwarnln!("Invalid cap type for log buffer: {:?}", cap_type);
+    syscall_error(ctx, SyscallError::IllegalOperation)
+}
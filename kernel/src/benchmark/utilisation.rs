@@ -0,0 +1,216 @@
+//! Per-thread CPU utilisation tracking, measured with the TSC.
+//!
+//! Based on src/benchmark/benchmark_utilisation.c and include/benchmark/benchmark_utilisation.h.
+//!
+//! Time is accounted to a thread from when it is scheduled until it is switched away from. Time
+//! spent inside the kernel is also tracked separately, both per thread and in total.
+
+use crate::api::failures::{Exception, SyscallError};
+use crate::arch::asm::rdtsc;
+use crate::arch::registerset::{UserContext, CAP_REGISTER};
+use crate::basic_types::Timestamp;
+use crate::cspace::{lookup_cap_type, CapTag};
+use crate::racycell::RacyCell;
+use crate::kwarnln;
+
+/// Indexes into the IPC buffer's message registers for the result of
+/// seL4_BenchmarkGetThreadUtilisation. This is benchmark_track_util_ipc_index in SeL4.
+#[derive(Copy, Clone)]
+#[repr(usize)]
+#[allow(unused)]
+pub(crate) enum UtilisationIndex {
+    TcbUtilisation,
+    TcbNumberSchedules,
+    TcbKernelUtilisation,
+    TcbNumberKernelEntries,
+    IdleLocalCpuUtilisation,
+    IdleTcbCpuUtilisation,
+    IdleNumberSchedules,
+    IdleKernelUtilisation,
+    IdleNumberKernelEntries,
+    TotalUtilisation,
+    TotalNumberSchedules,
+    TotalKernelUtilisation,
+    TotalNumberKernelEntries,
+}
+
+pub(crate) const UTILISATION_LENGTH: usize = UtilisationIndex::TotalNumberKernelEntries as usize + 1;
+
+/// Utilisation counters for a single thread. This is benchmark_util_t in SeL4, and lives in the
+/// TCB.
+#[derive(Copy, Clone, Default)]
+pub(crate) struct BenchmarkUtil {
+    pub schedule_start_time: Timestamp,
+    pub utilisation: u64,
+    pub number_schedules: u64,
+    pub kernel_utilisation: u64,
+    pub number_kernel_entries: u64,
+}
+
+impl BenchmarkUtil {
+    pub const fn new() -> Self {
+        Self {
+            schedule_start_time: 0,
+            utilisation: 0,
+            number_schedules: 0,
+            kernel_utilisation: 0,
+            number_kernel_entries: 0,
+        }
+    }
+
+    /// benchmark_track_reset_utilisation in SeL4.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// The per-node benchmark state. These are all NODE_STATE variables in SeL4.
+// TODO: With SMP, there should be one of these per core.
+struct NodeBenchmarkState {
+    /// Timestamp of the current kernel entry. This is ksEnter in SeL4.
+    enter: Timestamp,
+    log_utilisation_enabled: bool,
+    start_time: Timestamp,
+    end_time: Timestamp,
+    kernel_time: u64,
+    kernel_number_entries: u64,
+    kernel_number_schedules: u64,
+
+    // TODO: These belong in the TCBs of the current and idle threads. We don't have TCBs yet, so
+    // for now they're tracked here.
+    cur_thread: BenchmarkUtil,
+    idle_thread: BenchmarkUtil,
+}
+
+static NODE_STATE: RacyCell<NodeBenchmarkState> = RacyCell::new(NodeBenchmarkState {
+    enter: 0,
+    log_utilisation_enabled: false,
+    start_time: 0,
+    end_time: 0,
+    kernel_time: 0,
+    kernel_number_entries: 0,
+    kernel_number_schedules: 0,
+    cur_thread: BenchmarkUtil::new(),
+    idle_thread: BenchmarkUtil::new(),
+});
+
+/// Time elapsed from start to end, allowing for the TSC wrapping around.
+fn elapsed(start: Timestamp, end: Timestamp) -> u64 {
+    end.wrapping_sub(start)
+}
+
+/// Called on every kernel entry, before anything else. This is c_entry_hook in SeL4.
+pub(crate) fn track_kernel_entry() {
+    unsafe { NODE_STATE.get_mut() }.enter = rdtsc();
+}
+
+/// Called on every kernel exit. This is benchmark_track_exit in SeL4.
+pub(crate) fn track_kernel_exit() {
+    let state = unsafe { NODE_STATE.get_mut() };
+    if state.log_utilisation_enabled {
+        let duration = elapsed(state.enter, rdtsc());
+
+        state.cur_thread.number_kernel_entries += 1;
+        state.cur_thread.kernel_utilisation += duration;
+        state.kernel_number_entries += 1;
+        state.kernel_time += duration;
+    }
+}
+
+/// Account for a context switch from heir to next. This is benchmark_utilisation_switch in SeL4.
+pub(crate) fn utilisation_switch(heir: &mut BenchmarkUtil, next: &mut BenchmarkUtil) {
+    let state = unsafe { NODE_STATE.get_mut() };
+    if state.log_utilisation_enabled {
+        heir.utilisation += elapsed(heir.schedule_start_time, state.enter);
+
+        next.schedule_start_time = state.enter;
+        next.number_schedules += 1;
+        state.kernel_number_schedules += 1;
+    }
+}
+
+/// Start tracking utilisation. Called by seL4_BenchmarkResetLog.
+pub(super) fn reset() {
+    let state = unsafe { NODE_STATE.get_mut() };
+    state.log_utilisation_enabled = true;
+    state.idle_thread.reset();
+    state.cur_thread.schedule_start_time = state.enter;
+    state.cur_thread.number_schedules += 1;
+    state.start_time = state.enter;
+    state.kernel_time = 0;
+    state.kernel_number_entries = 0;
+    state.kernel_number_schedules = 1;
+}
+
+/// Stop tracking utilisation. Called by seL4_BenchmarkFinalizeLog. This is
+/// benchmark_utilisation_finalise in SeL4.
+pub(super) fn finalise() {
+    let state = unsafe { NODE_STATE.get_mut() };
+    // Add the time between the current thread being scheduled and now.
+    let (mut cur, mut idle) = (state.cur_thread, state.idle_thread);
+    utilisation_switch(&mut cur, &mut idle);
+    (state.cur_thread, state.idle_thread) = (cur, idle);
+
+    state.end_time = state.enter;
+    state.log_utilisation_enabled = false;
+}
+
+/// Fill in the counters for seL4_BenchmarkGetThreadUtilisation, for the given thread.
+pub(crate) fn utilisation_dump(tcb: &BenchmarkUtil, buffer: &mut [u64; UTILISATION_LENGTH]) {
+    use UtilisationIndex::*;
+    let state = unsafe { NODE_STATE.get_mut() };
+
+    buffer[TcbUtilisation as usize] = tcb.utilisation;
+    buffer[TcbNumberSchedules as usize] = tcb.number_schedules;
+    buffer[TcbKernelUtilisation as usize] = tcb.kernel_utilisation;
+    buffer[TcbNumberKernelEntries as usize] = tcb.number_kernel_entries;
+
+    buffer[IdleLocalCpuUtilisation as usize] = state.idle_thread.utilisation;
+    // TODO: With SMP, this should be the idle thread on the core the TCB has affinity with.
+    buffer[IdleTcbCpuUtilisation as usize] = state.idle_thread.utilisation;
+    buffer[IdleNumberSchedules as usize] = state.idle_thread.number_schedules;
+    buffer[IdleKernelUtilisation as usize] = state.idle_thread.kernel_utilisation;
+    buffer[IdleNumberKernelEntries as usize] = state.idle_thread.number_kernel_entries;
+
+    buffer[TotalUtilisation as usize] = elapsed(state.start_time, state.end_time);
+    buffer[TotalNumberSchedules as usize] = state.kernel_number_schedules;
+    buffer[TotalKernelUtilisation as usize] = state.kernel_time;
+    buffer[TotalNumberKernelEntries as usize] = state.kernel_number_entries;
+}
+
+/// seL4_BenchmarkGetThreadUtilisation. Writes the utilisation of the TCB in the cap register into
+/// the caller's IPC buffer.
+pub(crate) fn handle_get_thread_utilisation(ctx: &mut UserContext) -> Result<(), Exception> {
+    if lookup_cap_type(ctx.get(CAP_REGISTER)) != CapTag::Thread {
+        kwarnln!("SysBenchmarkGetThreadUtilisation: cap is not a TCB");
+        return Ok(());
+    }
+
+    // TODO: Look up the TCB and the caller's IPC buffer, and call utilisation_dump.
+    kwarnln!("Warning: per-thread utilisation is not supported yet");
+    super::syscall_error(ctx, SyscallError::IllegalOperation)
+}
+
+/// seL4_BenchmarkResetThreadUtilisation. Resets the counters of the TCB in the cap register.
+pub(crate) fn handle_reset_thread_utilisation(ctx: &mut UserContext) -> Result<(), Exception> {
+    if lookup_cap_type(ctx.get(CAP_REGISTER)) != CapTag::Thread {
+        kwarnln!("SysBenchmarkResetThreadUtilisation: cap is not a TCB");
+        return Ok(());
+    }
+
+    // TODO: Look up the TCB and reset its BenchmarkUtil.
+    kwarnln!("Warning: per-thread utilisation is not supported yet");
+    super::syscall_error(ctx, SyscallError::IllegalOperation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elapsed_wraps() {
+        assert_eq!(elapsed(10, 25), 15);
+        assert_eq!(elapsed(7, 7), 0);
+        assert_eq!(elapsed(u64::MAX - 1, 3), 5);
+    }
+}
@@ -1,30 +1,42 @@
-//! This file contains SeL4's configuration time parameters
-//! Right now these values are all hard coded, but we could totally take them at compile time with
-//! some work.
+//! This file contains SeL4's configuration time parameters.
+//!
+//! The options with a key in kernel/kernel.toml are set at compile time by build.rs, from that file
+//! or the environment. Everything else is hard coded. Bad combinations are rejected at the bottom
+//! of this file.
 //!
 //! Note I don't intend to ever implement the full set of features here that sel4 supports. Mostly,
 //! I'm not interested in any features that are only needed or used on legacy chipsets. For example,
 //! you can't configure this port to use PIC (it must use APIC). And you can't disable IOMMU.
 
-use crate::const_assert;
+use crate::{const_assert, kprintln};
+
+/// The values from kernel.toml, generated by build.rs.
+mod generated {
+    use super::{ConfigFpuSwitching, ConfigGraphicsMode};
+    include!(concat!(env!("OUT_DIR"), "/config.rs"));
+}
 
-/// Max number of CPU cores to boot.
+/// Max number of CPU cores to boot. (`max_num_nodes`)
 ///
-/// TODO: In SeL4 this defaults to 1, which seems insufficient.
-#[cfg(feature = "smp")]
-pub const CONFIG_MAX_NUM_NODES: usize = 32;
-#[cfg(not(feature = "smp"))]
-pub const CONFIG_MAX_NUM_NODES: usize = 1;
+/// TODO: In SeL4 this defaults to 1, which seems insufficient. We default to 32 with the smp
+/// feature.
+pub const CONFIG_MAX_NUM_NODES: usize = generated::MAX_NUM_NODES;
 
 /// Configure the maximum number of IOAPIC controllers that can be supported. SeL4
 /// will detect IOAPICs regardless of whether the IOAPIC will actually be used as
-/// the final IRQ controller.
-pub const CONFIG_MAX_NUM_IOAPIC: usize = 1;
+/// the final IRQ controller. (`max_num_ioapic`)
+pub const CONFIG_MAX_NUM_IOAPIC: usize = generated::MAX_NUM_IOAPIC;
+
+/// The max number of IOMMUs (DMA remapping hardware units) we track. (`max_num_drhu`)
+pub const CONFIG_MAX_NUM_DRHU: usize = generated::MAX_NUM_DRHU;
 
-/// This describes the log2 size of the kernel stack. Great care should be taken as
-/// there is no guard below the stack so setting this too small will cause random
-/// memory corruption
-pub(crate) const CONFIG_KERNEL_STACK_BITS: u32 = 12;
+/// The max number of free physical memory regions we track during boot. Increase this if the
+/// boot drops memory regions. (`max_num_freemem_reg`)
+pub const CONFIG_MAX_NUM_FREEMEM_REG: usize = generated::MAX_NUM_FREEMEM_REG;
+
+/// This describes the log2 size of each core's kernel stack. There's an unmapped guard page below
+/// each stack, so setting this too small gives a "kernel stack overflow" panic. (`kernel_stack_bits`)
+pub(crate) const CONFIG_KERNEL_STACK_BITS: u32 = generated::KERNEL_STACK_BITS;
 
 pub(crate) enum ConfigGraphicsMode {
     None,
@@ -34,18 +46,94 @@ pub(crate) enum ConfigGraphicsMode {
 
 /// The type of graphics mode to request from the boot loader. This is encoded into the
 /// multiboot header and is merely a hint, the boot loader is free to ignore or set some
-/// other mode.
-pub(crate) const CONFIG_MULTIBOOT_GRAPHICS_MODE: ConfigGraphicsMode = ConfigGraphicsMode::None;
+/// other mode. (`multiboot_graphics_mode`)
+pub(crate) const CONFIG_MULTIBOOT_GRAPHICS_MODE: ConfigGraphicsMode = generated::MULTIBOOT_GRAPHICS_MODE;
+
+/// The number of milliseconds between timer ticks. (`timer_tick_ms`)
+pub(crate) const CONFIG_TIMER_TICK_MS: u64 = generated::TIMER_TICK_MS;
+
+/// The number of scheduling domains. (`num_domains`)
+pub(crate) const CONFIG_NUM_DOMAINS: usize = generated::NUM_DOMAINS;
+
+/// The XCR0 mask, which says what parts of each user thread's FPU state (x87, SSE, AVX and so on)
+/// are saved and restored. (`xsave_feature_set`)
+pub(crate) const CONFIG_XSAVE_FEATURE_SET: u64 = generated::XSAVE_FEATURE_SET;
 
-/// Prevent against the Meltdown vulnerability by using a reduced Static Kernel
-/// Image and Micro-state window instead of having all kernel state in the kernel window.
-/// This only needs to be enabled if deploying to a vulnerable processor.
-pub(crate) const CONFIG_KERNEL_SKIM_WINDOW: bool = false;
+/// The size of each thread's XSAVE area, which must be enough for CONFIG_XSAVE_FEATURE_SET. The
+/// CPU's actual size is checked at boot. (`xsave_size`)
+pub(crate) const CONFIG_XSAVE_SIZE: usize = generated::XSAVE_SIZE;
+
+pub(crate) enum ConfigFpuSwitching {
+    /// Switch FPU state on every switch to a thread which doesn't own the FPU.
+    Eager,
+    /// Disable the FPU on a switch instead, and switch its state on the #NM fault from the thread's
+    /// first FPU instruction.
+    Lazy,
+}
+
+/// How FPU state is switched between threads. Lazy switching is SeL4's only option, but it leaks
+/// the previous owner's registers to speculative execution on some CPUs (LazyFP). (`fpu_switching`)
+pub(crate) const CONFIG_FPU_SWITCHING: ConfigFpuSwitching = generated::FPU_SWITCHING;
+
+/// With lazy FPU switching, how many thread switches a core can make after its FPU changes owner
+/// before the owner's state is saved and the FPU given up. This keeps a thread which stopped using
+/// the FPU from holding onto it. (`fpu_max_restores_since_switch`)
+pub(crate) const CONFIG_FPU_MAX_RESTORES_SINCE_SWITCH: u32 = generated::FPU_MAX_RESTORES_SINCE_SWITCH;
+
+// SeL4's CONFIG_KERNEL_SKIM_WINDOW is chosen at boot instead, along with the other speculative
+// execution mitigations. See arch/x86_64/speculation.rs.
 
 /// IOMMU support for VT-d enabled chipsets. This is an intel-only feature. AMD chipsets also
 /// support IOMMU but use a different IOMMU technology.
 /// TODO: This is currently unsupported, since I only have an AMD chipset to test with.
 pub(crate) const CONFIG_IOMMU: bool = false;
 
+/// The base IO port of the UART the GDB stub talks on (COM2). This is only used when the kernel is
+/// built with the `gdb` feature. It must not be the debug console's port.
+pub(crate) const CONFIG_GDB_SERIAL_PORT: u16 = 0x2F8;
+
 
-const_assert!(CONFIG_KERNEL_SKIM_WINDOW == false, "SKIM window not implemented.");
+const_assert!(CONFIG_GDB_SERIAL_PORT.abs_diff(crate::console::DEBUG_SERIAL_PORT) >= 8,
+    "The GDB stub and debug console can't share a UART.");
+const_assert!(CONFIG_MAX_NUM_NODES >= 1);
+const_assert!(cfg!(feature = "smp") || CONFIG_MAX_NUM_NODES == 1,
+    "max_num_nodes > 1 needs the smp feature.");
+// Below a page, the boot code runs out of stack.
+const_assert!(CONFIG_KERNEL_STACK_BITS >= 12 && CONFIG_KERNEL_STACK_BITS <= 20,
+    "kernel_stack_bits must be between 12 and 20.");
+const_assert!(CONFIG_MAX_NUM_IOAPIC >= 1, "max_num_ioapic must be at least 1.");
+const_assert!(CONFIG_MAX_NUM_FREEMEM_REG >= 1, "max_num_freemem_reg must be at least 1.");
+const_assert!(CONFIG_TIMER_TICK_MS >= 1, "timer_tick_ms must be at least 1.");
+// SeL4 stores domains in a byte.
+const_assert!(CONFIG_NUM_DOMAINS >= 1 && CONFIG_NUM_DOMAINS <= 256,
+    "num_domains must be between 1 and 256.");
+// XCR0 rules (Intel SDM vol 1, 13.3): x87 is always required, AVX needs SSE, and the AVX-512
+// components go together, with AVX. We also need SSE, and supervisor state (like bit 8, PT) isn't
+// in XCR0.
+const_assert!(CONFIG_XSAVE_FEATURE_SET & 0b11 == 0b11, "xsave_feature_set must include x87 and SSE (bits 0 and 1).");
+const_assert!(CONFIG_XSAVE_FEATURE_SET & 0xe0 == 0 || CONFIG_XSAVE_FEATURE_SET & 0xe4 == 0xe4,
+    "The AVX-512 bits (5-7) in xsave_feature_set must be set together, along with AVX (bit 2).");
+const_assert!(CONFIG_XSAVE_FEATURE_SET & 0x1_fd00 == 0,
+    "xsave_feature_set can't include supervisor state components (bits 8 and 10-16).");
+// The legacy area and XSAVE header come first, so anything smaller can't even hold x87 and SSE.
+const_assert!(CONFIG_XSAVE_SIZE >= 576 && CONFIG_XSAVE_SIZE.is_multiple_of(64),
+    "xsave_size must be at least 576, and a multiple of 64.");
+
+/// Print the resolved configuration. Called once at boot.
+pub(crate) fn print_config() {
+    let graphics_mode = match CONFIG_MULTIBOOT_GRAPHICS_MODE {
+        ConfigGraphicsMode::None => "none",
+        ConfigGraphicsMode::Text => "text",
+        ConfigGraphicsMode::Linear => "linear",
+    };
+    let fpu_switching = match CONFIG_FPU_SWITCHING {
+        ConfigFpuSwitching::Eager => "eager",
+        ConfigFpuSwitching::Lazy => "lazy",
+    };
+    kprintln!("Kernel config: max_num_nodes={} kernel_stack_bits={} max_num_ioapic={} max_num_drhu={}",
+        CONFIG_MAX_NUM_NODES, CONFIG_KERNEL_STACK_BITS, CONFIG_MAX_NUM_IOAPIC, CONFIG_MAX_NUM_DRHU);
+    kprintln!("    max_num_freemem_reg={} multiboot_graphics_mode={} timer_tick_ms={} num_domains={}",
+        CONFIG_MAX_NUM_FREEMEM_REG, graphics_mode, CONFIG_TIMER_TICK_MS, CONFIG_NUM_DOMAINS);
+    kprintln!("    xsave_feature_set=0x{:x} xsave_size={} fpu_switching={} fpu_max_restores_since_switch={}",
+        CONFIG_XSAVE_FEATURE_SET, CONFIG_XSAVE_SIZE, fpu_switching, CONFIG_FPU_MAX_RESTORES_SINCE_SWITCH);
+}
@@ -0,0 +1,33 @@
+//! Based on src/kernel/cspace.c and the cap definitions in include/object/structures.h.
+//!
+//! TODO: CNodes don't exist yet. Until they do, every lookup finds an empty slot - which is also
+//! what SeL4 returns when a lookup fails.
+
+use ufmt::derive::uDebug;
+use crate::basic_types::Cptr;
+
+/// The type of a capability. This is cap_tag_t in SeL4.
+///
+/// The generic caps all have even numbers. Architecture specific caps use the odd numbers, and get
+/// added here as they're implemented.
+#[derive(uDebug, Copy, Clone, Eq, PartialEq)]
+#[repr(usize)]
+#[allow(unused)]
+pub(crate) enum CapTag {
+    Null = 0,
+    Untyped = 2,
+    Endpoint = 4,
+    Notification = 6,
+    Reply = 8,
+    CNode = 10,
+    Thread = 12,
+    IrqControl = 14,
+    IrqHandler = 16,
+    Zombie = 18,
+    Domain = 20,
+}
+
+/// Look up the type of the cap at cptr in the current thread's CSpace.
+pub(crate) fn lookup_cap_type(_cptr: Cptr) -> CapTag {
+    CapTag::Null
+}
@@ -0,0 +1,665 @@
+//! An in-kernel GDB remote stub. This is only built with the `gdb` feature.
+//!
+//! QEMU has its own gdbstub (see `cargo xtask gdb`), but that doesn't help on real hardware. This
+//! stub talks the GDB remote serial protocol over a second UART ([CONFIG_GDB_SERIAL_PORT], COM2 by
+//! default), so the kernel and root task can be debugged with nothing more than a serial cable:
+//!
+//!     gdb target/x86_64-unknown-none/debug/kernel
+//!     (gdb) set architecture i386:x86-64
+//!     (gdb) target remote /dev/ttyUSB0
+//!
+//! Under QEMU, pass `-serial mon:stdio -serial tcp::1235,server,nowait` and connect to
+//! `localhost:1235` instead.
+//!
+//! The stub runs when the CPU takes a debug exception (#DB or #BP), or any other exception while
+//! GDB is attached. It supports:
+//!
+//!  - Reading and writing the registers of the interrupted context. That's the current user thread
+//!    for exceptions from userland, or the kernel's own registers for exceptions in the kernel.
+//!  - Reading and writing memory: the kernel window, the kernel image, and the user mappings of
+//!    the interrupted context (by walking the page tables it was running on). It's all accessed
+//!    through the kernel window.
+//!  - Software breakpoints, by writing int3 into memory.
+//!  - Single stepping, by setting the trap flag.
+//!
+//! The stub only ever runs with interrupts off, so there's no way to interrupt a running kernel
+//! with Ctrl+C. Use [breakpoint] to drop into the debugger from kernel code.
+
+mod packet;
+
+use core::arch::asm;
+use uart_16550::SerialPort;
+use crate::arch::registerset::{Register, UserContext};
+use crate::config::CONFIG_GDB_SERIAL_PORT;
+use crate::hardware::{KERNEL_ELF_BASE_OFFSET, KERNEL_ELF_TOP, PPTR_BASE_OFFSET};
+use crate::arch::constants::{PAGE_BITS, PAGE_TABLE_ENTRY_BITS, PAGE_TABLE_INDEX_BITS};
+use crate::arch::hardware::{KERNEL_ELF_BASE, PPTR_BASE, PPTR_TOP, USER_TOP};
+use crate::kprintln;
+use crate::racycell::RacyCell;
+use crate::utils::bit_usize;
+use packet::{decode_hex, hex_value, parse_hex, read_packet, send_packet, Connection, Reply, MAX_PACKET_SIZE};
+
+/// Exception vectors the stub cares about.
+const VECTOR_DIVIDE_ERROR: u8 = 0;
+const VECTOR_DEBUG: u8 = 1;
+const VECTOR_BREAKPOINT: u8 = 3;
+const VECTOR_INVALID_OPCODE: u8 = 6;
+const VECTOR_STACK_FAULT: u8 = 12;
+const VECTOR_GP_FAULT: u8 = 13;
+const VECTOR_PAGE_FAULT: u8 = 14;
+
+/// The trap flag in RFLAGS. When set, the CPU raises #DB after every instruction.
+const FLAGS_TF: usize = 1 << 8;
+
+const INT3: u8 = 0xcc;
+
+/// The most software breakpoints GDB can set at once (with Z0 packets). GDB falls back to writing
+/// int3 itself if we run out.
+const MAX_BREAKPOINTS: usize = 32;
+
+/// errno values for error replies.
+const EINVAL: u8 = 22;
+const EFAULT: u8 = 14;
+const ENOSPC: u8 = 28;
+
+/// Page table entry bits, for walking user page tables.
+const PTE_PRESENT: u64 = 1 << 0;
+const PTE_USER: u64 = 1 << 2;
+/// Set in a PDPT or PD entry which maps a 1GiB or 2MiB page.
+const PTE_PAGE_SIZE: u64 = 1 << 7;
+const PTE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// GDB's amd64 register numbers, for `p` and `P` packets. `g` and `G` use the same order.
+///
+/// The general purpose registers, rip and eflags come straight from the saved context. The
+/// segment registers are only partly saved. Everything past gs (FPU, SSE, fs_base...) is reported
+/// as unavailable.
+const GDB_REGISTERS: [(Option<Register>, usize); 24] = [
+    (Some(Register::RAX), 8),
+    (Some(Register::RBX), 8),
+    (Some(Register::RCX), 8),
+    (Some(Register::RDX), 8),
+    (Some(Register::RSI), 8),
+    (Some(Register::RDI), 8),
+    (Some(Register::RBP), 8),
+    (Some(Register::RSP), 8),
+    (Some(Register::R8), 8),
+    (Some(Register::R9), 8),
+    (Some(Register::R10), 8),
+    (Some(Register::R11), 8),
+    (Some(Register::R12), 8),
+    (Some(Register::R13), 8),
+    (Some(Register::R14), 8),
+    (Some(Register::R15), 8),
+    (Some(Register::FaultIP), 8),
+    (Some(Register::FLAGS), 4),
+    (Some(Register::CS), 4),
+    (Some(Register::SS), 4),
+    // ds, es, fs and gs.
+    (None, 4),
+    (None, 4),
+    (None, 4),
+    (None, 4),
+];
+
+/// A software breakpoint set with a Z0 packet.
+#[derive(Copy, Clone)]
+struct Breakpoint {
+    addr: usize,
+    /// Where addr was in the kernel window. The breakpoint is removed through this, in case it's
+    /// in a different address space to the one we've stopped in.
+    alias: usize,
+    /// The byte int3 replaced.
+    saved: u8,
+}
+
+/// What to do after handling a packet.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Action {
+    /// Send the reply, and wait for the next packet.
+    Reply,
+    /// Return from the exception. GDB is waiting for us to stop again.
+    Resume,
+    /// Return from the exception. GDB has gone away.
+    Detach,
+}
+
+pub(crate) struct GdbStub<C> {
+    conn: C,
+    /// Set by QStartNoAckMode.
+    no_ack: bool,
+    /// True when GDB is connected, and expecting us to report the next stop.
+    attached: bool,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    /// The page tables of the interrupted context, for user addresses. Only set while the stub is
+    /// running.
+    cr3: Option<usize>,
+}
+
+static GDB_STUB: RacyCell<GdbStub<SerialPort>> = RacyCell::new(GdbStub::new(unsafe {
+    SerialPort::new(CONFIG_GDB_SERIAL_PORT)
+}));
+
+/// Set up the GDB UART.
+///
+/// SAFETY: This should only be called once at startup.
+pub(crate) unsafe fn init_gdb() {
+    unsafe { GDB_STUB.get_mut() }.conn.init();
+    kprintln!("GDB stub listening on serial port 0x{:x}", CONFIG_GDB_SERIAL_PORT);
+}
+
+/// Called from the exception entry path, with the context which was interrupted and the page
+/// tables it was running on. Returns true if the stub handled the exception, and the context
+/// should be resumed.
+///
+/// Debug exceptions always stop in the debugger. Other exceptions only stop if GDB is attached,
+/// so faults still take their normal path when nobody is debugging.
+pub(crate) fn handle_exception(vector: u8, ctx: &mut UserContext, cr3: usize) -> bool {
+    let stub = unsafe { GDB_STUB.get_mut() };
+    if !stub.attached && vector != VECTOR_DEBUG && vector != VECTOR_BREAKPOINT {
+        return false;
+    }
+    stub.cr3 = Some(cr3);
+    stub.run(vector, ctx);
+    stub.cr3 = None;
+    true
+}
+
+/// Stop in the debugger, as if a breakpoint was hit here.
+#[inline(always)]
+pub(crate) fn breakpoint() {
+    unsafe { asm!("int3", options(nomem, nostack)) };
+}
+
+/// The signal GDB shows for an exception. The numbers are GDB's, not the host's.
+fn signal_for_vector(vector: u8) -> u8 {
+    const SIGILL: u8 = 4;
+    const SIGTRAP: u8 = 5;
+    const SIGFPE: u8 = 8;
+    const SIGSEGV: u8 = 11;
+
+    match vector {
+        VECTOR_DIVIDE_ERROR => SIGFPE,
+        VECTOR_INVALID_OPCODE => SIGILL,
+        VECTOR_STACK_FAULT | VECTOR_GP_FAULT | VECTOR_PAGE_FAULT => SIGSEGV,
+        _ => SIGTRAP,
+    }
+}
+
+/// The alias of a physical address in the kernel window.
+fn paddr_to_window(paddr: usize) -> Option<usize> {
+    let vaddr = paddr.checked_add(PPTR_BASE_OFFSET)?;
+    (vaddr < PPTR_TOP).then_some(vaddr)
+}
+
+/// Find the physical address which a user virtual address maps to, by walking the page tables at
+/// cr3. read_entry reads the page table entry at a physical address.
+///
+/// Returns None unless addr is mapped, and user accessible at every level.
+fn user_paddr(cr3: usize, addr: usize, read_entry: impl Fn(usize) -> Option<u64>) -> Option<usize> {
+    if addr > USER_TOP {
+        return None;
+    }
+    let mut table = cr3 & PTE_ADDR_MASK as usize;
+    // The PML4, PDPT, PD and then PT.
+    for level in (0..4).rev() {
+        let shift = PAGE_BITS + PAGE_TABLE_INDEX_BITS * level;
+        let index = (addr >> shift) % bit_usize(PAGE_TABLE_INDEX_BITS);
+        let entry = read_entry(table + (index << PAGE_TABLE_ENTRY_BITS))?;
+        if entry & (PTE_PRESENT | PTE_USER) != PTE_PRESENT | PTE_USER {
+            return None;
+        }
+        // Large pages keep PAT in bit 12, so the offset is masked off by the page size.
+        let base = entry as usize & PTE_ADDR_MASK as usize;
+        if level == 0 || (level < 3 && entry & PTE_PAGE_SIZE != 0) {
+            let offset_mask = bit_usize(shift) - 1;
+            return Some((base & !offset_mask) | (addr & offset_mask));
+        }
+        table = base;
+    }
+    None
+}
+
+/// Split a packet's arguments on a separator. `m1000,4` becomes `1000` and `4`.
+fn split(s: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let i = s.iter().position(|c| *c == sep)?;
+    Some((&s[..i], &s[i + 1..]))
+}
+
+impl<C: Connection> GdbStub<C> {
+    pub const fn new(conn: C) -> Self {
+        Self { conn, no_ack: false, attached: false, breakpoints: [None; MAX_BREAKPOINTS], cr3: None }
+    }
+
+    /// Translate a virtual address to its alias in the kernel window. We always access memory
+    /// through the window, so writing breakpoints into read only text works.
+    ///
+    /// Returns None if addr isn't in the window, the kernel image or the interrupted context's
+    /// user mappings.
+    fn window_addr(&self, addr: usize) -> Option<*mut u8> {
+        let alias = if addr <= USER_TOP {
+            let read_entry = |paddr| Some(unsafe { (paddr_to_window(paddr)? as *const u64).read_volatile() });
+            paddr_to_window(user_paddr(self.cr3?, addr, read_entry)?)?
+        } else if (PPTR_BASE..PPTR_TOP).contains(&addr) {
+            addr
+        } else if (KERNEL_ELF_BASE..KERNEL_ELF_TOP as usize).contains(&addr) {
+            addr - KERNEL_ELF_BASE_OFFSET + PPTR_BASE_OFFSET
+        } else {
+            return None;
+        };
+        Some(alias as *mut u8)
+    }
+
+    /// The alias of the byte at addr + i. Ranges can cross into unmapped pages, so each byte is
+    /// translated separately.
+    fn byte_addr(&self, addr: usize, i: usize) -> Option<*mut u8> {
+        self.window_addr(addr.checked_add(i)?)
+    }
+
+    /// Talk to GDB until it tells us to resume.
+    fn run(&mut self, vector: u8, ctx: &mut UserContext) {
+        // The kernel stack is only 4KiB. These would take up most of it.
+        static PACKET: RacyCell<[u8; MAX_PACKET_SIZE]> = RacyCell::new([0; _]);
+        static REPLY: RacyCell<Reply> = RacyCell::new(Reply::new());
+        let buf = unsafe { PACKET.get_mut() };
+        let reply = unsafe { REPLY.get_mut() };
+        reply.clear();
+
+        // GDB is waiting to hear why we stopped. If it isn't attached yet, it'll ask with `?`.
+        if self.attached {
+            self.stop_reply(vector, reply);
+            send_packet(&mut self.conn, reply.as_bytes(), self.no_ack);
+        }
+
+        loop {
+            let packet = read_packet(&mut self.conn, buf, self.no_ack);
+            reply.clear();
+            let action = self.handle_packet(packet, vector, ctx, reply);
+            if action == Action::Detach {
+                self.remove_all_breakpoints();
+                self.attached = false;
+                ctx.set(Register::FLAGS, ctx.get(Register::FLAGS) & !FLAGS_TF);
+            }
+            if action != Action::Resume {
+                send_packet(&mut self.conn, reply.as_bytes(), self.no_ack);
+            }
+            if action != Action::Reply {
+                return;
+            }
+        }
+    }
+
+    fn stop_reply(&self, vector: u8, reply: &mut Reply) {
+        reply.push(b'S');
+        reply.push_hex_byte(signal_for_vector(vector));
+    }
+
+    /// Handle one packet, filling in the reply.
+    fn handle_packet(&mut self, packet: &[u8], vector: u8, ctx: &mut UserContext, reply: &mut Reply) -> Action {
+        let Some((&command, args)) = packet.split_first() else {
+            return Action::Reply;
+        };
+        self.attached = true;
+
+        match command {
+            b'?' => self.stop_reply(vector, reply),
+            b'g' => self.read_registers(ctx, reply),
+            b'G' => self.write_registers(args, ctx, reply),
+            b'p' => self.read_register(args, ctx, reply),
+            b'P' => self.write_register(args, ctx, reply),
+            b'm' => self.read_memory(args, reply),
+            b'M' => self.write_memory(args, reply),
+            b'c' | b's' => {
+                if !args.is_empty() {
+                    let Some(addr) = parse_hex(args) else {
+                        reply.error(EINVAL);
+                        return Action::Reply;
+                    };
+                    set_ip(ctx, addr as usize);
+                }
+                let flags = ctx.get(Register::FLAGS) & !FLAGS_TF;
+                ctx.set(Register::FLAGS, if command == b's' { flags | FLAGS_TF } else { flags });
+                return Action::Resume;
+            }
+            b'Z' | b'z' => self.breakpoint_packet(command == b'Z', args, reply),
+            b'D' => {
+                reply.push_str("OK");
+                return Action::Detach;
+            }
+            // We can't kill the kernel. Carry on as if GDB detached.
+            b'k' => return Action::Detach,
+            // There's only one thread as far as GDB is concerned.
+            b'H' => reply.push_str("OK"),
+            b'q' if args.starts_with(b"Supported") => {
+                reply.push_str("PacketSize=");
+                reply.push_hex(MAX_PACKET_SIZE as u64);
+                reply.push_str(";QStartNoAckMode+");
+            }
+            b'q' if args == b"Attached" => reply.push(b'1'),
+            b'Q' if args == b"StartNoAckMode" => {
+                // The ack for this packet has already gone out, so we switch over for the next one.
+                self.no_ack = true;
+                reply.push_str("OK");
+            }
+            // An empty reply tells GDB we don't support the packet.
+            _ => {}
+        }
+        Action::Reply
+    }
+
+    fn read_registers(&self, ctx: &UserContext, reply: &mut Reply) {
+        for (reg, size) in GDB_REGISTERS {
+            push_register(ctx, reg, size, reply);
+        }
+    }
+
+    fn write_registers(&self, args: &[u8], ctx: &mut UserContext, reply: &mut Reply) {
+        let mut bytes = [0u8; 8];
+        let mut rest = args;
+        for (reg, size) in GDB_REGISTERS {
+            // GDB may send fewer registers than we report.
+            if rest.len() < size * 2 {
+                break;
+            }
+            let (value, tail) = rest.split_at(size * 2);
+            rest = tail;
+            // Unavailable registers come back as x's. Skip them.
+            if let (Some(reg), Some(_)) = (reg, decode_hex(value, &mut bytes[..size])) {
+                set_register(ctx, reg, size, &bytes);
+            }
+        }
+        reply.push_str("OK");
+    }
+
+    fn read_register(&self, args: &[u8], ctx: &UserContext, reply: &mut Reply) {
+        match parse_hex(args).and_then(|n| GDB_REGISTERS.get(n as usize)) {
+            Some(&(reg, size)) => push_register(ctx, reg, size, reply),
+            None => reply.error(EINVAL),
+        }
+    }
+
+    fn write_register(&self, args: &[u8], ctx: &mut UserContext, reply: &mut Reply) {
+        let mut bytes = [0u8; 8];
+        let parsed = split(args, b'=').and_then(|(n, value)| {
+            let &(reg, size) = GDB_REGISTERS.get(parse_hex(n)? as usize)?;
+            (decode_hex(value, &mut bytes[..size])? == size).then_some((reg, size))
+        });
+        let Some((reg, size)) = parsed else {
+            reply.error(EINVAL);
+            return;
+        };
+        // Writes to registers we don't save are dropped.
+        if let Some(reg) = reg {
+            set_register(ctx, reg, size, &bytes);
+        }
+        reply.push_str("OK");
+    }
+
+    /// `m addr,len`. We may return fewer bytes than asked for, if the reply would be too big.
+    fn read_memory(&self, args: &[u8], reply: &mut Reply) {
+        let Some((addr, len)) = split(args, b',').and_then(|(a, l)| Some((parse_hex(a)?, parse_hex(l)?))) else {
+            reply.error(EINVAL);
+            return;
+        };
+        let (addr, len) = (addr as usize, (len as usize).min(reply.remaining() / 2));
+        if self.window_addr(addr).is_none() {
+            reply.error(EFAULT);
+            return;
+        }
+        // If the range runs into an unmapped page, GDB gets the bytes up to it.
+        for ptr in (0..len).map_while(|i| self.byte_addr(addr, i)) {
+            reply.push_hex_byte(unsafe { ptr.read_volatile() });
+        }
+    }
+
+    /// `M addr,len:bytes`.
+    fn write_memory(&self, args: &[u8], reply: &mut Reply) {
+        let parsed = split(args, b':').and_then(|(range, data)| {
+            let (addr, len) = split(range, b',')?;
+            let (addr, len) = (parse_hex(addr)? as usize, parse_hex(len)? as usize);
+            let valid = data.len() == len * 2 && data.iter().all(|c| hex_value(*c).is_some());
+            valid.then_some((addr, data))
+        });
+        let Some((addr, data)) = parsed else {
+            reply.error(EINVAL);
+            return;
+        };
+        if !(0..data.len() / 2).all(|i| self.byte_addr(addr, i).is_some()) {
+            reply.error(EFAULT);
+            return;
+        }
+        // Decode straight into memory. The packet and the range have already been checked.
+        for (i, pair) in data.chunks_exact(2).enumerate() {
+            let mut byte = [0];
+            decode_hex(pair, &mut byte);
+            unsafe { self.byte_addr(addr, i).unwrap().write_volatile(byte[0]) };
+        }
+        reply.push_str("OK");
+    }
+
+    /// `Z0,addr,kind` and `z0,addr,kind`. Only software breakpoints are supported.
+    fn breakpoint_packet(&mut self, insert: bool, args: &[u8], reply: &mut Reply) {
+        let Some((kind, rest)) = split(args, b',') else {
+            reply.error(EINVAL);
+            return;
+        };
+        if kind != b"0" {
+            // Hardware breakpoints and watchpoints aren't supported.
+            return;
+        }
+        let Some(addr) = split(rest, b',').and_then(|(addr, _)| parse_hex(addr)) else {
+            reply.error(EINVAL);
+            return;
+        };
+        let addr = addr as usize;
+
+        let existing = self.breakpoints.iter().position(|b| b.is_some_and(|b| b.addr == addr));
+        match (insert, existing) {
+            (true, Some(_)) => {}
+            (true, None) => {
+                let Some(ptr) = self.window_addr(addr) else {
+                    reply.error(EFAULT);
+                    return;
+                };
+                let Some(slot) = self.breakpoints.iter_mut().find(|b| b.is_none()) else {
+                    reply.error(ENOSPC);
+                    return;
+                };
+                let saved = unsafe { ptr.read_volatile() };
+                unsafe { ptr.write_volatile(INT3) };
+                *slot = Some(Breakpoint { addr, alias: ptr as usize, saved });
+            }
+            (false, Some(i)) => {
+                let bp = self.breakpoints[i].take().unwrap();
+                unsafe { (bp.alias as *mut u8).write_volatile(bp.saved) };
+            }
+            (false, None) => {}
+        }
+        reply.push_str("OK");
+    }
+
+    fn remove_all_breakpoints(&mut self) {
+        for bp in self.breakpoints.iter_mut().filter_map(Option::take) {
+            unsafe { (bp.alias as *mut u8).write_volatile(bp.saved) };
+        }
+    }
+}
+
+fn push_register(ctx: &UserContext, reg: Option<Register>, size: usize, reply: &mut Reply) {
+    match reg {
+        Some(reg) => reply.push_hex_le(ctx.get(reg) as u64, size),
+        None => (0..size * 2).for_each(|_| reply.push(b'x')),
+    }
+}
+
+fn set_register(ctx: &mut UserContext, reg: Register, size: usize, bytes: &[u8; 8]) {
+    let mut value = [0u8; 8];
+    value[..size].copy_from_slice(&bytes[..size]);
+    let value = u64::from_le_bytes(value) as usize;
+    match reg {
+        Register::FaultIP => set_ip(ctx, value),
+        _ => ctx.set(reg, value),
+    }
+}
+
+/// The exception entry path saves rip in FaultIP, but we resume from NextIP. Keep them in sync.
+fn set_ip(ctx: &mut UserContext, ip: usize) {
+    ctx.set(Register::FaultIP, ip);
+    ctx.set(Register::NextIP, ip);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::packet::tests::FakeConnection;
+    use crate::arch::idt::TrapFrame;
+
+    struct NoConnection;
+
+    /// Frame a packet, as GDB would send it.
+    fn packet(body: &str) -> std::string::String {
+        std::format!("${body}#{:02x}", packet::checksum(body.as_bytes()))
+    }
+
+    impl Connection for NoConnection {
+        fn read_byte(&mut self) -> u8 {
+            unreachable!()
+        }
+
+        fn write_byte(&mut self, _byte: u8) {
+            unreachable!()
+        }
+    }
+
+    fn handle(stub: &mut GdbStub<NoConnection>, packet: &[u8], ctx: &mut UserContext) -> (Action, std::vec::Vec<u8>) {
+        let mut reply = Reply::new();
+        let action = stub.handle_packet(packet, VECTOR_BREAKPOINT, ctx, &mut reply);
+        (action, reply.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn registers() {
+        let mut stub = GdbStub::new(NoConnection);
+        let mut ctx = UserContext::new();
+        ctx.set(Register::RAX, 0x1122334455667788);
+        ctx.set(Register::FaultIP, 0xffffffff80100000);
+
+        let (_, reply) = handle(&mut stub, b"g", &mut ctx);
+        assert_eq!(reply.len(), (17 * 8 + 7 * 4) * 2);
+        assert!(reply.starts_with(b"8877665544332211"));
+        assert_eq!(&reply[16 * 16..17 * 16], b"00001080ffffffff");
+        assert!(reply.ends_with(b"xxxxxxxx"));
+
+        let (_, reply) = handle(&mut stub, b"p10", &mut ctx);
+        assert_eq!(reply, b"00001080ffffffff");
+        let (_, reply) = handle(&mut stub, b"p40", &mut ctx);
+        assert_eq!(reply, b"E16");
+
+        // Setting rip also sets where we resume from.
+        let (_, reply) = handle(&mut stub, b"P10=0010000000000000", &mut ctx);
+        assert_eq!(reply, b"OK");
+        assert_eq!(ctx.get(Register::FaultIP), 0x1000);
+        assert_eq!(ctx.get(Register::NextIP), 0x1000);
+
+        // G round trips g.
+        let (_, regs) = handle(&mut stub, b"g", &mut ctx);
+        let mut other = UserContext::new();
+        let mut packet = std::vec![b'G'];
+        packet.extend_from_slice(&regs);
+        assert_eq!(handle(&mut stub, &packet, &mut other).1, b"OK");
+        assert_eq!(other.registers, ctx.registers);
+    }
+
+    #[test]
+    fn step_and_continue() {
+        let mut stub = GdbStub::new(NoConnection);
+        let mut ctx = UserContext::new();
+
+        assert_eq!(handle(&mut stub, b"s", &mut ctx).0, Action::Resume);
+        assert_ne!(ctx.get(Register::FLAGS) & FLAGS_TF, 0);
+        assert_eq!(handle(&mut stub, b"c2000", &mut ctx).0, Action::Resume);
+        assert_eq!(ctx.get(Register::FLAGS) & FLAGS_TF, 0);
+        assert_eq!(ctx.get(Register::NextIP), 0x2000);
+    }
+
+    #[test]
+    fn queries() {
+        let mut stub = GdbStub::new(NoConnection);
+        let mut ctx = UserContext::new();
+
+        assert_eq!(handle(&mut stub, b"?", &mut ctx).1, b"S05");
+        assert_eq!(handle(&mut stub, b"qSupported:multiprocess+", &mut ctx).1, b"PacketSize=400;QStartNoAckMode+");
+        assert_eq!(handle(&mut stub, b"vMustReplyEmpty", &mut ctx).1, b"");
+        assert_eq!(handle(&mut stub, b"m0,4", &mut ctx).1, b"E0e");
+        assert_eq!(handle(&mut stub, b"Z1,1000,1", &mut ctx).1, b"");
+        assert_eq!(handle(&mut stub, b"D", &mut ctx).0, Action::Detach);
+    }
+
+    #[test]
+    fn user_page_walk() {
+        // Physical address and entry. Everything else is zero.
+        let tables = [
+            (0x1000, 0x2007),                // PML4[0]
+            (0x2000, 0x3007),                // PDPT[0]
+            (0x2008, 0x8000_0087),           // PDPT[1]: a 1GiB page
+            (0x3010, 0x4007),                // PD[2]
+            (0x3018, 0x20_1087),             // PD[3]: a 2MiB page, with PAT set
+            (0x3020, 0x5003),                // PD[4]: supervisor only
+            (0x4008, 0x8000_0000_0000_9007), // PT[1]: no execute
+        ];
+        let read_entry = |paddr| Some(tables.iter().find(|(a, _)| *a == paddr).map_or(0, |(_, e)| *e));
+        let walk = |addr| user_paddr(0x1000, addr, read_entry);
+
+        assert_eq!(walk(0x40_1234), Some(0x9234));
+        assert_eq!(walk(0x60_0042), Some(0x20_0042));
+        assert_eq!(walk(0x4000_5678), Some(0x8000_5678));
+        assert_eq!(walk(0x80_0000), None);
+        assert_eq!(walk(0xa0_0000), None);
+        assert_eq!(walk(USER_TOP + 1), None);
+    }
+
+    #[test]
+    fn breakpoint_round_trip() {
+        // What exception_entry saves for an int3 in the kernel. rip is just past the int3.
+        let mut frame = TrapFrame {
+            rax: 1,
+            r15: 15,
+            vector: VECTOR_BREAKPOINT as u64,
+            rip: 0xffffffff80100001,
+            cs: 0x8,
+            rflags: 0x46,
+            rsp: 0xffffffff80200000,
+            ss: 0x10,
+            ..Default::default()
+        };
+        let mut ctx = UserContext::new();
+        frame.save(&mut ctx);
+
+        // GDB attaches, asks why we stopped, sets rax, and steps. Every packet and reply is acked.
+        let input = [packet("?"), "+".into(), packet("P0=2a00000000000000"), "+".into(), packet("s")].concat();
+        let mut stub = GdbStub::new(FakeConnection::new(input.as_bytes()));
+        stub.run(VECTOR_BREAKPOINT, &mut ctx);
+        frame.restore(&ctx);
+
+        assert_eq!(stub.conn.output, [b"+", packet("S05").as_bytes(), b"+", packet("OK").as_bytes(), b"+"].concat());
+        assert_eq!(frame.rax, 42);
+        assert_eq!(frame.r15, 15);
+        assert_eq!(frame.rip, 0xffffffff80100001);
+        assert_eq!(frame.rflags, 0x46 | FLAGS_TF as u64);
+        assert!(stub.conn.input.is_empty());
+
+        // The step stops with #DB. Now GDB is attached, so it's told straight away. It continues
+        // from somewhere else.
+        stub.conn.input.extend([b"+", packet("c1000").as_bytes()].concat());
+        stub.conn.output.clear();
+        frame.vector = VECTOR_DEBUG as u64;
+        frame.save(&mut ctx);
+        stub.run(VECTOR_DEBUG, &mut ctx);
+        frame.restore(&ctx);
+
+        assert_eq!(stub.conn.output, [packet("S05").as_bytes(), b"+"].concat());
+        assert_eq!(frame.rip, 0x1000);
+        assert_eq!(frame.rflags, 0x46);
+        assert_eq!(frame.cs, 0x8);
+    }
+}
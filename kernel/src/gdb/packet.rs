@@ -0,0 +1,288 @@
+//! The GDB remote serial protocol's packet layer.
+//!
+//! Packets look like `$<body>#<checksum>`, where the checksum is the sum of the body bytes mod 256
+//! as two hex digits. Each packet is acknowledged with `+`, or `-` to ask for a retransmit - unless
+//! GDB has switched acks off with QStartNoAckMode.
+
+/// A byte stream to GDB. This is a UART in the kernel.
+pub(crate) trait Connection {
+    /// Block until a byte arrives.
+    fn read_byte(&mut self) -> u8;
+    fn write_byte(&mut self, byte: u8);
+}
+
+impl Connection for uart_16550::SerialPort {
+    fn read_byte(&mut self) -> u8 {
+        self.receive()
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        // send() mangles backspace characters, which are perfectly valid in binary data.
+        self.send_raw(byte)
+    }
+}
+
+/// The largest packet we accept, and the largest reply we send. This is reported to GDB in
+/// qSupported, so GDB won't send anything bigger.
+pub(crate) const MAX_PACKET_SIZE: usize = 1024;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+pub(crate) fn checksum(body: &[u8]) -> u8 {
+    body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+pub(crate) fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parse a big endian hex number, like the addresses and lengths in `m addr,len`.
+pub(crate) fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter().try_fold(0u64, |n, c| Some((n << 4) | hex_value(*c)? as u64))
+}
+
+/// Decode pairs of hex digits into dst. Returns the number of bytes decoded.
+pub(crate) fn decode_hex(s: &[u8], dst: &mut [u8]) -> Option<usize> {
+    if !s.len().is_multiple_of(2) || s.len() / 2 > dst.len() {
+        return None;
+    }
+    for (i, pair) in s.chunks_exact(2).enumerate() {
+        dst[i] = (hex_value(pair[0])? << 4) | hex_value(pair[1])?;
+    }
+    Some(s.len() / 2)
+}
+
+/// Read the next packet into buf and acknowledge it. Returns the packet body.
+///
+/// Anything outside a packet (stray acks, or Ctrl+C while we're already stopped) is ignored.
+/// Packets with a bad checksum, or which don't fit in buf, are dropped and GDB is asked to send
+/// them again.
+pub(crate) fn read_packet<'a>(conn: &mut impl Connection, buf: &'a mut [u8], no_ack: bool) -> &'a [u8] {
+    loop {
+        while conn.read_byte() != b'$' {}
+
+        let mut len = 0;
+        let mut overflow = false;
+        loop {
+            match conn.read_byte() {
+                b'#' => break,
+                // GDB gave up on this packet and started a new one.
+                b'$' => {
+                    len = 0;
+                    overflow = false;
+                }
+                c if len < buf.len() => {
+                    buf[len] = c;
+                    len += 1;
+                }
+                _ => overflow = true,
+            }
+        }
+        let sum = hex_value(conn.read_byte()).zip(hex_value(conn.read_byte()))
+            .map(|(high, low)| (high << 4) | low);
+
+        if no_ack {
+            // Nobody will retransmit. Take what we got.
+            return &buf[..len];
+        }
+        if !overflow && sum == Some(checksum(&buf[..len])) {
+            conn.write_byte(b'+');
+            return &buf[..len];
+        }
+        conn.write_byte(b'-');
+    }
+}
+
+/// Send a packet, and wait for GDB to acknowledge it.
+pub(crate) fn send_packet(conn: &mut impl Connection, body: &[u8], no_ack: bool) {
+    let sum = checksum(body);
+    loop {
+        conn.write_byte(b'$');
+        for b in body {
+            conn.write_byte(*b);
+        }
+        conn.write_byte(b'#');
+        conn.write_byte(HEX_DIGITS[(sum >> 4) as usize]);
+        conn.write_byte(HEX_DIGITS[(sum & 0xf) as usize]);
+
+        if no_ack {
+            return;
+        }
+        // Anything other than an ack or a nack is the start of GDB's next packet, which it won't
+        // send until it has our reply. So we'll only ever see + or - here in practice.
+        if conn.read_byte() != b'-' {
+            return;
+        }
+    }
+}
+
+/// A reply being built up. Writes past the end of the buffer are dropped. Callers keep replies
+/// short enough that this never happens.
+pub(crate) struct Reply {
+    buf: [u8; MAX_PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    pub const fn new() -> Self {
+        Self { buf: [0; MAX_PACKET_SIZE], len: 0 }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.len
+    }
+
+    pub fn push(&mut self, b: u8) {
+        if self.len < self.buf.len() {
+            self.buf[self.len] = b;
+            self.len += 1;
+        }
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        for b in s.bytes() {
+            self.push(b);
+        }
+    }
+
+    pub fn push_hex_byte(&mut self, b: u8) {
+        self.push(HEX_DIGITS[(b >> 4) as usize]);
+        self.push(HEX_DIGITS[(b & 0xf) as usize]);
+    }
+
+    /// Append a number in hex, without leading zeros. This is how GDB writes numbers in packets.
+    pub fn push_hex(&mut self, value: u64) {
+        let digits = (64 - value.leading_zeros()).div_ceil(4).max(1);
+        for i in (0..digits).rev() {
+            self.push(HEX_DIGITS[((value >> (i * 4)) & 0xf) as usize]);
+        }
+    }
+
+    /// Append the first `size` bytes of value, in target (little endian) byte order. This is how
+    /// GDB expects register values.
+    pub fn push_hex_le(&mut self, value: u64, size: usize) {
+        for b in &value.to_le_bytes()[..size] {
+            self.push_hex_byte(*b);
+        }
+    }
+
+    /// An error reply. GDB doesn't care what the number is.
+    pub fn error(&mut self, errno: u8) {
+        self.clear();
+        self.push(b'E');
+        self.push_hex_byte(errno);
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    /// A fake GDB. Bytes in `input` are what GDB sends us. The stub's tests use this too.
+    pub(crate) struct FakeConnection {
+        pub input: VecDeque<u8>,
+        pub output: Vec<u8>,
+    }
+
+    impl FakeConnection {
+        pub fn new(input: &[u8]) -> Self {
+            Self { input: input.iter().copied().collect(), output: Vec::new() }
+        }
+    }
+
+    impl Connection for FakeConnection {
+        fn read_byte(&mut self) -> u8 {
+            self.input.pop_front().expect("Read past the end of the input")
+        }
+
+        fn write_byte(&mut self, byte: u8) {
+            self.output.push(byte);
+        }
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(parse_hex(b"ffffffff80100000"), Some(0xffffffff80100000));
+        assert_eq!(parse_hex(b"1A"), Some(0x1a));
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"12g"), None);
+        assert_eq!(parse_hex(b"10000000000000000"), None);
+
+        let mut buf = [0; 4];
+        assert_eq!(decode_hex(b"cc90", &mut buf), Some(2));
+        assert_eq!(&buf[..2], &[0xcc, 0x90]);
+        assert_eq!(decode_hex(b"ccc", &mut buf), None);
+        assert_eq!(decode_hex(b"0102030405", &mut buf), None);
+    }
+
+    #[test]
+    fn read() {
+        let mut conn = FakeConnection::new(b"+$m1000,4#8e");
+        let mut buf = [0; 64];
+        assert_eq!(read_packet(&mut conn, &mut buf, false), b"m1000,4");
+        assert_eq!(conn.output, b"+");
+    }
+
+    #[test]
+    fn read_bad_checksum() {
+        let mut conn = FakeConnection::new(b"$g#00$g#67");
+        let mut buf = [0; 64];
+        assert_eq!(read_packet(&mut conn, &mut buf, false), b"g");
+        assert_eq!(conn.output, b"-+");
+    }
+
+    #[test]
+    fn read_overflow() {
+        let mut conn = FakeConnection::new(b"$ggggg#37$g#67");
+        let mut buf = [0; 4];
+        assert_eq!(read_packet(&mut conn, &mut buf, false), b"g");
+        assert_eq!(conn.output, b"-+");
+    }
+
+    #[test]
+    fn read_no_ack() {
+        let mut conn = FakeConnection::new(b"$g#00");
+        let mut buf = [0; 64];
+        assert_eq!(read_packet(&mut conn, &mut buf, true), b"g");
+        assert!(conn.output.is_empty());
+    }
+
+    #[test]
+    fn send() {
+        let mut conn = FakeConnection::new(b"-+");
+        send_packet(&mut conn, b"OK", false);
+        assert_eq!(conn.output, b"$OK#9a$OK#9a");
+    }
+
+    #[test]
+    fn reply() {
+        let mut reply = Reply::new();
+        reply.push_hex_le(0x1234, 4);
+        assert_eq!(reply.as_bytes(), b"34120000");
+        reply.push(b',');
+        reply.push_hex(0x400);
+        reply.push(b',');
+        reply.push_hex(0);
+        assert_eq!(reply.as_bytes(), b"34120000,400,0");
+        reply.error(14);
+        assert_eq!(reply.as_bytes(), b"E0e");
+    }
+}
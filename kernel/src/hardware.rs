@@ -45,6 +45,13 @@ unsafe extern "C" {
     static ki_end: [u8; 1];
 }
 
+/// There's no linker script in host unit tests, so we define the symbol ourselves.
+#[cfg(test)]
+mod test_symbols {
+    #[unsafe(no_mangle)]
+    static ki_end: [u8; 1] = [0];
+}
+
 /// This value marks the last valid address in the kernel's virtual region.
 /// Note this is a pointer because the value unfortunately isn't known at compile time.
 pub const KERNEL_ELF_TOP: *const u8 = unsafe { ki_end.as_ptr() };
@@ -0,0 +1,21 @@
+#![cfg_attr(not(test), no_std)]
+#![allow(unused)]
+
+pub(crate) mod racycell;
+pub(crate) mod console;
+pub(crate) mod utils;
+pub(crate) mod basic_types;
+pub(crate) mod config;
+pub(crate) mod arch;
+pub(crate) mod hardware;
+pub(crate) mod stack;
+pub(crate) mod api;
+pub(crate) mod cspace;
+#[cfg(feature = "benchmark")]
+pub(crate) mod benchmark;
+#[cfg(feature = "test")]
+pub(crate) mod test;
+#[cfg(feature = "gdb")]
+pub(crate) mod gdb;
+mod machine;
+mod boot;
@@ -1,14 +1,6 @@
 #![no_std]
 #![no_main]
-#![allow(unused)]
 
-pub(crate) mod racycell;
-pub(crate) mod console;
-pub(crate) mod utils;
-pub(crate) mod basic_types;
-pub(crate) mod config;
-pub(crate) mod arch;
-pub(crate) mod hardware;
-pub(crate) mod stack;
-mod machine;
-mod boot;
+// Everything lives in the library crate, so the kernel's logic can also be built and unit tested
+// on the host. Linking it in is all the binary needs to do.
+extern crate kernel;
@@ -18,6 +18,12 @@ impl<T> RacyCell<T> {
     pub unsafe fn get_mut(&self) -> &mut T {
         unsafe { &mut *self.0.get() }
     }
+
+    /// Gets a raw pointer to the wrapped value. This is useful for data which is shared with
+    /// something outside the kernel's control, where we can't ever safely hold a reference.
+    pub const fn as_ptr(&self) -> *mut T {
+        self.0.get()
+    }
 }
 
 unsafe impl<T> Send for RacyCell<T> where T: Send {}
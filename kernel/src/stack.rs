@@ -1,9 +1,135 @@
+//! Kernel stacks.
+//!
+//! Each core has a kernel stack, and two small stacks for the #DF and NMI handlers. Those run on
+//! the IST (see arch::x86_64::idt), so they get a good stack even when the kernel stack is broken.
+//!
+//! Every stack has a guard page below it, which is unmapped at boot (map_stack_guards). Stacks grow
+//! down, so overflowing one faults on its guard page, instead of silently corrupting whatever is
+//! below it. The fault handlers use find_guard to report that as a stack overflow.
+
+use core::arch::asm;
+use core::mem::offset_of;
+use core::ops::Range;
+use crate::arch::constants::PAGE_BITS;
 use crate::config::{CONFIG_KERNEL_STACK_BITS, CONFIG_MAX_NUM_NODES};
+use crate::const_assert;
 use crate::racycell::RacyCell;
 use crate::utils::bit_usize;
 
-#[repr(align(16))]
-#[allow(unused)]
-pub(crate) struct KernelStack([[u8; bit_usize(CONFIG_KERNEL_STACK_BITS)]; CONFIG_MAX_NUM_NODES]);
+pub(crate) const GUARD_SIZE: usize = bit_usize(PAGE_BITS);
+pub(crate) const KERNEL_STACK_SIZE: usize = bit_usize(CONFIG_KERNEL_STACK_BITS);
+
+/// The #DF and NMI stacks. The #DF handler prints a backtrace and panics, which needs about as much
+/// stack as anything else in the kernel.
+pub(crate) const IST_STACK_SIZE: usize = KERNEL_STACK_SIZE;
+
+const_assert!(KERNEL_STACK_SIZE.is_multiple_of(GUARD_SIZE) && IST_STACK_SIZE.is_multiple_of(GUARD_SIZE),
+    "Stacks must be a whole number of pages, so the guard pages don't share a page with anything.");
+
+/// A stack, with its guard page below it.
+#[repr(C, align(4096))]
+pub(crate) struct GuardedStack<const SIZE: usize> {
+    guard: [u8; GUARD_SIZE],
+    stack: [u8; SIZE],
+}
+
+impl<const SIZE: usize> GuardedStack<SIZE> {
+    const fn new() -> Self {
+        Self { guard: [0; GUARD_SIZE], stack: [0; SIZE] }
+    }
+
+    /// The initial stack pointer. This is the end of the stack, since stacks grow down.
+    pub fn top(&self) -> usize {
+        self.stack.as_ptr_range().end as usize
+    }
+
+    pub fn stack(&self) -> Range<usize> {
+        let stack = self.stack.as_ptr_range();
+        stack.start as usize..stack.end as usize
+    }
+
+    pub fn guard(&self) -> Range<usize> {
+        let guard = self.guard.as_ptr_range();
+        guard.start as usize..guard.end as usize
+    }
+}
+
+/// All of one core's stacks.
+#[repr(C)]
+pub(crate) struct CoreStacks {
+    pub kernel: GuardedStack<KERNEL_STACK_SIZE>,
+    pub double_fault: GuardedStack<IST_STACK_SIZE>,
+    pub nmi: GuardedStack<IST_STACK_SIZE>,
+}
+
+impl CoreStacks {
+    const fn new() -> Self {
+        Self { kernel: GuardedStack::new(), double_fault: GuardedStack::new(), nmi: GuardedStack::new() }
+    }
+
+    /// Each stack, without its guard page.
+    pub fn stacks(&self) -> [Range<usize>; 3] {
+        [self.kernel.stack(), self.double_fault.stack(), self.nmi.stack()]
+    }
+
+    /// Each stack's name and guard page.
+    pub fn guards(&self) -> [(&'static str, Range<usize>); 3] {
+        [("kernel", self.kernel.guard()), ("#DF", self.double_fault.guard()), ("NMI", self.nmi.guard())]
+    }
+}
+
+pub(crate) static KERNEL_STACKS: RacyCell<[CoreStacks; CONFIG_MAX_NUM_NODES]> =
+    RacyCell::new([const { CoreStacks::new() }; CONFIG_MAX_NUM_NODES]);
+
+/// The offset of the boot core's initial stack pointer in KERNEL_STACKS. The boot code switches to
+/// this stack before there's anywhere to compute it.
+pub(crate) const BOOT_STACK_TOP_OFFSET: usize =
+    offset_of!(CoreStacks, kernel) + offset_of!(GuardedStack<KERNEL_STACK_SIZE>, stack) + KERNEL_STACK_SIZE;
+
+/// Find the guard page containing addr. Returns the core, and which of its stacks overflowed.
+pub(crate) fn find_guard(addr: usize) -> Option<(usize, &'static str)> {
+    // The guard pages are never written, so reading their addresses is fine while the stacks are
+    // in use.
+    let stacks = unsafe { &*KERNEL_STACKS.as_ptr() };
+    stacks.iter().enumerate().find_map(|(core, stacks)| {
+        stacks.guards().into_iter().find(|(_, guard)| guard.contains(&addr)).map(|(name, _)| (core, name))
+    })
+}
+
+/// Find the core whose stacks contain addr.
+pub(crate) fn core_for_stack(addr: usize) -> Option<usize> {
+    let stacks = unsafe { &*KERNEL_STACKS.as_ptr() };
+    stacks.iter().position(|stacks| stacks.stacks().iter().any(|stack| stack.contains(&addr)))
+}
+
+/// The core we're running on, found from the stack we're running on. SeL4 keeps the core's index
+/// in per core data (through GS), which we don't have yet.
+pub(crate) fn current_core() -> usize {
+    let rsp: usize;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags)) };
+    core_for_stack(rsp).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guards() {
+        let stacks = unsafe { &*KERNEL_STACKS.as_ptr() };
+        let core0 = &stacks[0];
+        assert_eq!(core0.kernel.guard().len(), GUARD_SIZE);
+        assert_eq!(core0.kernel.guard().start % GUARD_SIZE, 0);
+        assert_eq!(core0.kernel.guard().end + KERNEL_STACK_SIZE, core0.kernel.top());
+        assert_eq!(KERNEL_STACKS.as_ptr() as usize + BOOT_STACK_TOP_OFFSET, core0.kernel.top());
+
+        assert_eq!(find_guard(core0.kernel.guard().start), Some((0, "kernel")));
+        assert_eq!(find_guard(core0.nmi.guard().end - 1), Some((0, "NMI")));
+        assert_eq!(find_guard(core0.kernel.top() - 8), None);
+        assert_eq!(find_guard(core0.double_fault.guard().end), None);
 
-pub(crate) static KERNEL_STACK: RacyCell<KernelStack> = RacyCell::new(KernelStack([[0; _]; _]));
\ No newline at end of file
+        assert_eq!(core_for_stack(core0.kernel.top() - 8), Some(0));
+        assert_eq!(core_for_stack(core0.nmi.stack().start), Some(0));
+        assert_eq!(core_for_stack(core0.kernel.guard().start), None);
+    }
+}
@@ -0,0 +1,28 @@
+//! Support for running the kernel under the QEMU integration test harness (`cargo xtask test`).
+//!
+//! With the `test` feature, the kernel reports what it found during boot as `KTEST key=value`
+//! lines on the serial console. Then instead of halting, it exits QEMU through the isa-debug-exit
+//! device, so the harness gets a pass / fail exit code. Panics exit with [TestExit::Failure].
+//!
+//! QEMU must be started with `-device isa-debug-exit,iobase=0xf4,iosize=0x04`. QEMU exits with
+//! status `(code << 1) | 1`, so success is 33 and failure is 35. (It can't exit with 0.)
+
+use crate::arch::asm::out32;
+use crate::utils::halt;
+
+/// The IO port the isa-debug-exit device is mapped at.
+const DEBUG_EXIT_PORT: u16 = 0xf4;
+
+#[derive(Copy, Clone)]
+#[repr(u32)]
+pub(crate) enum TestExit {
+    Success = 0x10,
+    Failure = 0x11,
+}
+
+/// Exit QEMU. If the debug exit device is missing (for example, if this kernel was booted some
+/// other way) this just halts.
+pub(crate) fn test_exit(code: TestExit) -> ! {
+    unsafe { out32(DEBUG_EXIT_PORT, code as u32) };
+    halt();
+}
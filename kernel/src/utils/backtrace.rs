@@ -0,0 +1,108 @@
+//! Kernel backtraces, for the panic and exception paths.
+//!
+//! The kernel is built with frame pointers (see .cargo/config.toml), so unwinding is just following
+//! the chain of saved rbp values. Each frame is `[saved rbp, return address]`.
+//!
+//! Return addresses are resolved against the symbol table in the .ksyms section (common::ksyms).
+//! That's only there when the kernel is built with `cargo xtask build`. Without it, we print raw
+//! addresses, which `addr2line -e target/.../kernel` can resolve.
+
+use core::arch::asm;
+use common::ksyms::Ksyms;
+use crate::arch::hardware::{KERNEL_ELF_BASE, KERNEL_ELF_PADDR_BASE};
+use crate::hardware::{KERNEL_ELF_BASE_OFFSET, KERNEL_ELF_TOP};
+use crate::kprintln;
+use crate::stack::find_guard;
+
+/// Stop after this many frames, in case the chain loops or runs off into junk.
+const MAX_FRAMES: usize = 32;
+
+// Defined by the linker script, around the .ksyms section.
+unsafe extern "C" {
+    static __ksyms_start: u8;
+    static __ksyms_end: u8;
+}
+
+// KSYMS_TABLE, in the .ksyms section.
+#[cfg(embedded_ksyms)]
+include!(concat!(env!("OUT_DIR"), "/ksyms.rs"));
+
+/// There's no linker script in host unit tests, so we define the symbols ourselves.
+#[cfg(test)]
+mod test_symbols {
+    #[unsafe(no_mangle)]
+    static __ksyms_start: u8 = 0;
+    #[unsafe(no_mangle)]
+    static __ksyms_end: u8 = 0;
+}
+
+/// The embedded symbol table, if there is one.
+fn ksyms() -> Option<Ksyms<'static>> {
+    let start = &raw const __ksyms_start;
+    let end = &raw const __ksyms_end;
+    let table = unsafe { core::slice::from_raw_parts(start, end as usize - start as usize) };
+    Ksyms::from_bytes(table)
+}
+
+/// Is there a whole frame record at rbp? Kernel stacks are always inside the kernel image - either
+/// the boot stack (at its physical address) or KERNEL_STACKS (virtual). Anything else means the
+/// chain is broken. The stacks' guard pages are in the image too, but they're unmapped, so a frame
+/// which touches one is broken as well.
+fn valid_frame(rbp: usize) -> bool {
+    let top = KERNEL_ELF_TOP as usize;
+    let in_image = |start: usize, end: usize| rbp >= start && rbp.saturating_add(16) <= end;
+
+    rbp.is_multiple_of(8)
+        && (in_image(KERNEL_ELF_BASE, top) || in_image(KERNEL_ELF_PADDR_BASE, top - KERNEL_ELF_BASE_OFFSET))
+        && find_guard(rbp).is_none() && find_guard(rbp + 8).is_none()
+}
+
+fn print_frame(ksyms: &Option<Ksyms>, n: usize, addr: usize, lookup_addr: usize) {
+    match ksyms.as_ref().and_then(|k| k.lookup(lookup_addr as u64)) {
+        Some((name, offset)) => kprintln!("  #{} 0x{:x} {}+0x{:x}", n, addr, name, offset + (addr - lookup_addr) as u64),
+        None => kprintln!("  #{} 0x{:x}", n, addr),
+    }
+}
+
+/// Print a backtrace of the caller's stack.
+#[inline(never)]
+pub(crate) fn print_backtrace() {
+    let rbp: usize;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    print_backtrace_from(None, rbp);
+}
+
+/// Print a backtrace from a saved context. ip is the instruction which was interrupted, if known,
+/// and rbp is the frame pointer at that point.
+pub(crate) fn print_backtrace_from(ip: Option<usize>, mut rbp: usize) {
+    let ksyms = ksyms();
+    kprintln!("Backtrace:");
+
+    let mut n = 0;
+    if let Some(ip) = ip {
+        print_frame(&ksyms, n, ip, ip);
+        n += 1;
+    }
+
+    while n < MAX_FRAMES && valid_frame(rbp) {
+        let frame = rbp as *const usize;
+        let (next, ret) = unsafe { (frame.read(), frame.add(1).read()) };
+        if ret == 0 {
+            break;
+        }
+        // The return address is just past the call. Look up the call itself, in case it was the
+        // last instruction in its function.
+        print_frame(&ksyms, n, ret, ret - 1);
+        n += 1;
+
+        // Stacks grow down, so callers' frames are always above ours.
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
+
+    if ksyms.is_none() {
+        kprintln!("(No kernel symbols. Build with `cargo xtask build` to embed them.)");
+    }
+}
@@ -138,4 +138,67 @@ impl<T, const N: usize> IndexMut<usize> for FixedArr<T, N> {
 
         &mut self.items[index]
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FixedArr;
+
+    #[test]
+    fn starts_empty() {
+        let arr: FixedArr<u32, 4> = FixedArr::new();
+        assert_eq!(arr.len(), 0);
+        assert!(arr.as_slice().is_empty());
+        assert_eq!(arr.iter().count(), 0);
+    }
+
+    #[test]
+    fn push_until_full() {
+        let mut arr: FixedArr<u32, 3> = FixedArr::new();
+        assert_eq!(arr.try_push(1), Ok(()));
+        arr.push(2);
+        assert_eq!(arr.try_push(3), Ok(()));
+        assert_eq!(arr.try_push(4), Err(4));
+        assert_eq!(arr.len(), 3);
+        assert_eq!(arr.as_slice(), &[1, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Fixed size array exhausted")]
+    fn push_past_capacity_panics() {
+        let mut arr: FixedArr<u32, 1> = FixedArr::new();
+        arr.push(1);
+        arr.push(2);
+    }
+
+    #[test]
+    fn index() {
+        let mut arr: FixedArr<u32, 4> = FixedArr::new();
+        arr.push(10);
+        arr.push(20);
+        arr[1] += 5;
+        assert_eq!(arr[0], 10);
+        assert_eq!(arr[1], 25);
+        assert_eq!(arr.as_mut_slice(), &mut [10, 25]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Index out of bounds")]
+    fn index_past_len_panics() {
+        // The slot exists in the backing array, but hasn't been pushed.
+        let arr: FixedArr<u32, 4> = FixedArr::new();
+        let _ = arr[0];
+    }
+
+    #[test]
+    fn other_constructors() {
+        let mut arr: FixedArr<String, 2> = FixedArr::new_from_example(String::new());
+        arr.push("a".into());
+        assert_eq!(arr.as_slice(), &["a"]);
+
+        let mut arr: FixedArr<usize, 2> = FixedArr::new_from_fn(|i| i * 100);
+        assert_eq!(arr.len(), 0);
+        arr.push(7);
+        assert_eq!(arr.iter().copied().collect::<Vec<_>>(), vec![7]);
+    }
+}
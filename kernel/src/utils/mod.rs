@@ -1,4 +1,7 @@
 pub mod fixedarr;
+pub(crate) mod backtrace;
+// Host unit tests use std's panic handler.
+#[cfg(not(test))]
 mod panic;
 
 use core::arch::asm;
@@ -20,6 +23,7 @@ pub trait NumUtils {
 
     /// Round self down to the nearest boundary of N bits. Essentially zeros b bits in the int.
     fn round_down(self, b: u32) -> Self;
+    /// Round self up to the nearest boundary of N bits. 0 rounds to 0.
     fn round_up(self, b: u32) -> Self;
 }
 
@@ -32,7 +36,7 @@ impl NumUtils for usize {
     }
 
     fn round_up(self, b: u32) -> Self {
-        (((self - 1) >> b) + 1) << b
+        self.next_multiple_of(Self::bit(b))
     }
 }
 
@@ -44,7 +48,7 @@ impl NumUtils for u64 {
     }
 
     fn round_up(self, b: u32) -> Self {
-        (((self - 1) >> b) + 1) << b
+        self.next_multiple_of(Self::bit(b))
     }
 }
 
@@ -56,7 +60,7 @@ impl NumUtils for u32 {
     }
 
     fn round_up(self, b: u32) -> Self {
-        (((self - 1) >> b) + 1) << b
+        self.next_multiple_of(Self::bit(b))
     }
 }
 
@@ -71,3 +75,36 @@ macro_rules! const_assert {
         const _: () = assert!($condition, $($msg)+);
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_down() {
+        assert_eq!(0usize.round_down(12), 0);
+        assert_eq!(0xfffusize.round_down(12), 0);
+        assert_eq!(0x1000usize.round_down(12), 0x1000);
+        assert_eq!(0x1fffu64.round_down(12), 0x1000);
+        assert_eq!(0x7u32.round_down(0), 0x7);
+    }
+
+    #[test]
+    fn round_up() {
+        assert_eq!(0usize.round_up(12), 0);
+        assert_eq!(0u64.round_up(12), 0);
+        assert_eq!(0u32.round_up(12), 0);
+        assert_eq!(1usize.round_up(12), 0x1000);
+        assert_eq!(0x1000usize.round_up(12), 0x1000);
+        assert_eq!(0x1001u64.round_up(12), 0x2000);
+        assert_eq!(0x7u32.round_up(0), 0x7);
+        assert_eq!(0x10_0001u64.round_up(21), 0x20_0000);
+    }
+
+    #[test]
+    fn bit() {
+        assert_eq!(usize::bit(0), 1);
+        assert_eq!(u64::bit(63), 1 << 63);
+        assert_eq!(bit_u32(31), u32::bit(31));
+    }
+}
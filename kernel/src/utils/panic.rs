@@ -1,11 +1,12 @@
+use crate::utils::backtrace::print_backtrace;
 use crate::utils::halt;
-use crate::{kprintln, kprintln_big};
+use crate::kprintln;
 use core::panic::PanicInfo;
 
-/// NOTE: Its only possible to print out the panic using core::fmt, which adds 60kb or so to the
-/// kernel size. I'm going to keep this giant size for now through the panic handler, but in the
-/// future I might make the kernel panic free. (Or at least, have a compilation option to avoid
-/// pulling in all this crap).
+/// NOTE: Its only possible to print out most panic messages using core::fmt. The `tiny` feature
+/// avoids formatting them: we print the message only if it's a plain string with no arguments
+/// (like `panic!("oh no")`), and the location either way. Core still uses core::fmt itself, so
+/// this doesn't leave it out of the kernel.
 ///
 /// The nice thing about the rust panic handler is it'll call this path for all out of bounds errors
 /// and things like that, which is very useful during development.
@@ -13,18 +14,22 @@ use core::panic::PanicInfo;
 fn panic(info: &PanicInfo) -> ! {
     kprintln!("\n\nKERNEL PANIC! Aaaah!");
 
-    // For small builds, this removes about 20k of formatting infrastructure.
-    // let msg = info.message();
-    // if let Some(s) = msg.as_str() {
-    //     kprintln!("{}", s);
-    // }
-    kprintln_big!("{}", info.message());
+    #[cfg(not(feature = "tiny"))]
+    crate::kprintln_big!("{}", info.message());
+    #[cfg(feature = "tiny")]
+    if let Some(msg) = info.message().as_str() {
+        kprintln!("{}", msg);
+    }
 
     if let Some(location) = info.location() {
-        kprintln_big!("at {:?}", location);
+        kprintln!("at {}:{}:{}", location.file(), location.line(), location.column());
     } else {
-        kprintln_big!("Location unknown");
+        kprintln!("Location unknown");
     }
 
+    print_backtrace();
+
+    #[cfg(feature = "test")]
+    crate::test::test_exit(crate::test::TestExit::Failure);
     halt();
 }
\ No newline at end of file
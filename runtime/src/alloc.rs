@@ -0,0 +1,132 @@
+//! A bump allocator for kernel objects.
+//!
+//! Objects are created by retyping the untyped memory listed in the boot info, and caps to them
+//! are placed in the empty slots of the root task's CNode. Nothing is ever freed. This is plenty
+//! for setting up a system at boot, but anything more dynamic will want a real allocator.
+
+use common::bootinfo::{CAP_INIT_THREAD_CNODE, MAX_BOOTINFO_UNTYPED_CAPS};
+use common::invocation::ObjectType;
+use common::objects::{CNode, Untyped};
+use common::{BootInfo, CPtr, Error};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AllocError {
+    /// There are no empty slots left in the root CNode.
+    OutOfSlots,
+    /// No untyped has enough space left for the object.
+    OutOfMemory,
+    /// A device frame's address isn't inside a device untyped, is misaligned, or is already in
+    /// use.
+    BadAddress,
+    /// The kernel rejected the retype.
+    Kernel(Error),
+}
+
+pub struct ObjectAllocator {
+    bootinfo: &'static BootInfo,
+    next_slot: CPtr,
+    /// How many bytes of each untyped we've used.
+    used: [usize; MAX_BOOTINFO_UNTYPED_CAPS],
+}
+
+impl ObjectAllocator {
+    /// Create an allocator using all the untyped memory and empty slots in the boot info. Nothing
+    /// else should retype the untypeds or use the empty slots after this.
+    pub fn new(bootinfo: &'static BootInfo) -> Self {
+        Self {
+            bootinfo,
+            next_slot: bootinfo.empty.start,
+            used: [0; MAX_BOOTINFO_UNTYPED_CAPS],
+        }
+    }
+
+    /// Take an empty slot in the root CNode.
+    pub fn alloc_slot(&mut self) -> Result<CPtr, AllocError> {
+        self.alloc_slots(1)
+    }
+
+    /// Take `count` consecutive empty slots in the root CNode. Returns the first one.
+    pub fn alloc_slots(&mut self, count: usize) -> Result<CPtr, AllocError> {
+        if self.next_slot + count > self.bootinfo.empty.end {
+            return Err(AllocError::OutOfSlots);
+        }
+        let first = self.next_slot;
+        self.next_slot += count;
+        Ok(first)
+    }
+
+    /// Create a new object, returning the slot of the cap to it. `size_bits` is only used for
+    /// untypeds and CNodes. See [ObjectType::size_bits].
+    pub fn alloc(&mut self, object_type: ObjectType, size_bits: u32) -> Result<CPtr, AllocError> {
+        let slot = self.alloc_slot()?;
+        if let Err(err) = self.alloc_at(object_type, size_bits, slot) {
+            // Give the slot back. Nothing else can have taken it in the meantime.
+            self.next_slot -= 1;
+            return Err(err);
+        }
+        Ok(slot)
+    }
+
+    /// Create a new object, with the cap to it in `slot` of the root CNode.
+    pub fn alloc_at(&mut self, object_type: ObjectType, size_bits: u32, slot: CPtr) -> Result<(), AllocError> {
+        let object_size = 1usize << object_type.size_bits(size_bits);
+
+        // Find the first untyped with room. The kernel aligns objects to their size within the
+        // untyped, so we need to do the same when counting the space used.
+        let (index, untyped, offset) = self.bootinfo.untypeds()
+            .enumerate()
+            .filter(|(_, (_, desc))| desc.is_device == 0)
+            .find_map(|(i, (cap, desc))| {
+                let offset = self.used[i].next_multiple_of(object_size);
+                (offset + object_size <= 1 << desc.size_bits).then_some((i, cap, offset))
+            })
+            .ok_or(AllocError::OutOfMemory)?;
+
+        retype(untyped, object_type, size_bits, slot)?;
+        self.used[index] = offset + object_size;
+        Ok(())
+    }
+
+    /// Create a frame at a specific physical address, which must be inside a device untyped. This
+    /// is used for memory mapped devices.
+    ///
+    /// Device untypeds are used from the bottom up, so frames in the same untyped must be created
+    /// in address order. Any gap before the frame is filled with untyped objects, which use up
+    /// slots.
+    pub fn alloc_device_frame(&mut self, paddr: usize, object_type: ObjectType, slot: CPtr) -> Result<(), AllocError> {
+        let frame_size = 1usize << object_type.size_bits(0);
+
+        let (index, untyped, base) = self.bootinfo.untypeds()
+            .enumerate()
+            .find(|(_, (_, desc))| {
+                desc.is_device != 0 && desc.paddr <= paddr && paddr + frame_size <= desc.paddr + (1 << desc.size_bits)
+            })
+            .map(|(i, (cap, desc))| (i, cap, desc.paddr))
+            .ok_or(AllocError::BadAddress)?;
+
+        let offset = paddr - base;
+        if offset < self.used[index] || !offset.is_multiple_of(frame_size) {
+            return Err(AllocError::BadAddress);
+        }
+
+        // Skip up to the frame by creating the largest aligned untypeds that fit in the gap.
+        while self.used[index] < offset {
+            let gap = offset - self.used[index];
+            let align_bits = (base + self.used[index]).trailing_zeros();
+            let bits = align_bits.min(gap.ilog2());
+            let filler = self.alloc_slot()?;
+            retype(untyped, ObjectType::Untyped, bits, filler)?;
+            self.used[index] += 1 << bits;
+        }
+
+        retype(untyped, object_type, 0, slot)?;
+        self.used[index] = offset + frame_size;
+        Ok(())
+    }
+}
+
+fn retype(untyped: CPtr, object_type: ObjectType, size_bits: u32, slot: CPtr) -> Result<(), AllocError> {
+    let root = CNode(CAP_INIT_THREAD_CNODE);
+    Untyped(untyped).retype(object_type, size_bits as usize, root, 0, 0, slot, 1)
+        .map_err(AllocError::Kernel)
+}
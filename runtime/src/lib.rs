@@ -0,0 +1,44 @@
+//! The runtime for root tasks written in Rust. This plays the part of sel4runtime.
+//!
+//! It provides the `_start` entry point, sets up a stack, TLS and the IPC buffer, then calls the
+//! function named with [entry!]. It also provides a panic handler, debug printing through the
+//! seL4_DebugPutChar syscall, and a simple allocator for creating kernel objects from the untyped
+//! memory listed in the boot info.
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! runtime::entry!(main);
+//!
+//! fn main(bootinfo: &'static runtime::BootInfo) {
+//!     runtime::println!("Hello!");
+//! }
+//! ```
+//!
+//! The debug printing and panic handler need a kernel built with the `debug` feature. Otherwise
+//! the syscalls fault, and the root task stops.
+
+#![no_std]
+
+mod start;
+mod tls;
+mod panic;
+pub mod debug;
+pub mod alloc;
+
+pub use common;
+pub use common::BootInfo;
+
+/// Declare the root task's main function. It's called with the boot info once the runtime is set
+/// up. When it returns, the root task suspends itself.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[unsafe(no_mangle)]
+        fn __runtime_main(bootinfo: &'static $crate::BootInfo) {
+            let main: fn(&'static $crate::BootInfo) = $main;
+            main(bootinfo)
+        }
+    };
+}
@@ -0,0 +1,9 @@
+use core::panic::PanicInfo;
+use common::syscalls::debug_halt;
+use crate::println;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    println!("Root task panicked: {}", info);
+    debug_halt()
+}
@@ -0,0 +1,52 @@
+//! The entry point. The kernel starts the root task at `_start`, with the boot info address in
+//! RDI and no stack.
+
+use common::bootinfo::CAP_INIT_THREAD_TCB;
+use common::ipc_buffer::set_ipc_buffer;
+use common::objects::Tcb;
+use common::BootInfo;
+
+const STACK_SIZE: usize = 64 * 1024;
+
+#[repr(C, align(16))]
+struct Stack([u8; STACK_SIZE]);
+
+/// The root task's stack. This is only referenced from assembly.
+static mut STACK: Stack = Stack([0; STACK_SIZE]);
+
+unsafe extern "Rust" {
+    /// Defined by the entry! macro.
+    fn __runtime_main(bootinfo: &'static BootInfo);
+}
+
+#[unsafe(naked)]
+#[unsafe(no_mangle)]
+unsafe extern "C" fn _start() -> ! {
+    core::arch::naked_asm!(
+        "lea rsp, [rip + {stack} + {stack_size}]",
+        "xor ebp, ebp",
+        // RDI (the boot info) is passed straight through.
+        "call {start_rust}",
+        "ud2",
+        stack = sym STACK,
+        stack_size = const STACK_SIZE,
+        start_rust = sym start_rust,
+    )
+}
+
+extern "C" fn start_rust(bootinfo: *const BootInfo) -> ! {
+    // SAFETY: The kernel maps the boot info frame read only, for the life of the root task.
+    let bootinfo: &'static BootInfo = unsafe { &*bootinfo };
+
+    // The IPC buffer has to be set before making any invocations.
+    unsafe { set_ipc_buffer(bootinfo.ipc_buffer) };
+    crate::tls::init();
+
+    unsafe { __runtime_main(bootinfo) };
+
+    let _ = Tcb(CAP_INIT_THREAD_TCB).suspend();
+    // Suspending ourselves can't fail. But just in case.
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,68 @@
+//! Thread local storage for the root task's initial thread.
+//!
+//! We use the x86_64 ELF TLS layout ("variant II"). The thread pointer (FS base) points just past
+//! the end of the TLS block, and the word it points to holds the thread pointer itself. Statically
+//! linked code reads thread locals at negative offsets from FS.
+//!
+//! The TLS template (.tdata and .tbss) is found through the PT_TLS program header. The ELF headers
+//! are loaded as part of the first segment, and the linker tells us where with __ehdr_start.
+
+use common::bootinfo::CAP_INIT_THREAD_TCB;
+use common::elf::{self, Elf, EHDR_SIZE, PT_TLS};
+use common::objects::Tcb;
+
+/// Space for the initial thread's TLS block and thread pointer.
+const TLS_AREA_SIZE: usize = 4096;
+
+#[repr(C, align(64))]
+struct TlsArea([u8; TLS_AREA_SIZE]);
+
+static mut TLS_AREA: TlsArea = TlsArea([0; TLS_AREA_SIZE]);
+
+unsafe extern "C" {
+    static __ehdr_start: [u8; EHDR_SIZE];
+}
+
+/// The fields we need from a PT_TLS program header.
+struct TlsTemplate {
+    vaddr: usize,
+    file_size: usize,
+    mem_size: usize,
+    align: usize,
+}
+
+fn find_tls_template() -> Option<TlsTemplate> {
+    // SAFETY: __ehdr_start is provided by the linker, and the headers are mapped.
+    let headers = unsafe {
+        let ehdr = &__ehdr_start;
+        core::slice::from_raw_parts(ehdr.as_ptr(), elf::headers_len(ehdr))
+    };
+
+    Elf::parse(headers)?.program_headers().find(|phdr| phdr.kind == PT_TLS).map(|phdr| TlsTemplate {
+        vaddr: phdr.vaddr,
+        file_size: phdr.file_size,
+        mem_size: phdr.mem_size,
+        align: phdr.align.max(size_of::<usize>()),
+    })
+}
+
+/// Set up TLS for the initial thread. The IPC buffer must already be set, because this sets FS
+/// using TCB_SetTLSBase.
+pub(crate) fn init() {
+    let template = find_tls_template().unwrap_or(TlsTemplate { vaddr: 0, file_size: 0, mem_size: 0, align: 8 });
+
+    let area = (&raw mut TLS_AREA) as usize;
+    let block_size = template.mem_size.next_multiple_of(template.align);
+    let tp = (area + block_size).next_multiple_of(template.align);
+    assert!(tp + size_of::<usize>() <= area + TLS_AREA_SIZE, "TLS block too big");
+
+    let block = (tp - block_size) as *mut u8;
+    // SAFETY: The block is inside TLS_AREA, and nothing else references it yet. The rest of the
+    // area is already zeroed, which takes care of .tbss.
+    unsafe {
+        core::ptr::copy_nonoverlapping(template.vaddr as *const u8, block, template.file_size);
+        (tp as *mut usize).write(tp);
+    }
+
+    Tcb(CAP_INIT_THREAD_TCB).set_tls_base(tp).expect("Failed to set TLS base");
+}
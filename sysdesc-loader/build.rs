@@ -0,0 +1,34 @@
+//! Embed the compiled system description.
+//!
+//! The spec is named by the SYSDESC_SPEC environment variable. It's the output of tools/sysdesc:
+//!
+//! ```sh
+//! cargo build -p crasher
+//! (cd tools/sysdesc && cargo run -- ../../sysdesc-loader/specs/crasher.toml -o ../../target/crasher.sysdesc)
+//! SYSDESC_SPEC=target/crasher.sysdesc cargo build -p sysdesc-loader
+//! ```
+//!
+//! Relative paths are relative to the workspace root.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=SYSDESC_SPEC");
+
+    let workspace_root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("..");
+    let out = match env::var("SYSDESC_SPEC") {
+        Ok(path) if !path.is_empty() => {
+            let path = workspace_root.join(path);
+            let path = path.canonicalize()
+                .unwrap_or_else(|e| panic!("System description {}: {e}", path.display()));
+            println!("cargo:rerun-if-changed={}", path.display());
+            format!("pub(crate) static SPEC: &[u8] = include_bytes!({:?});\n", path.display().to_string())
+        }
+        _ => "pub(crate) static SPEC: &[u8] = &[];\n".into(),
+    };
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("spec.rs");
+    fs::write(out_path, out).unwrap();
+}
@@ -0,0 +1,201 @@
+//! Create the objects and caps described by a spec. Based on capdl-loader-app's main.c, minus
+//! the parts for features we don't have (MCS, SMP affinity and IOMMUs).
+
+use runtime::alloc::{AllocError, ObjectAllocator};
+use runtime::common::bootinfo::{CAP_DOMAIN, CAP_INIT_THREAD_ASID_POOL, CAP_INIT_THREAD_CNODE, CAP_INIT_THREAD_TCB,
+                                CAP_INIT_THREAD_VSPACE, CAP_IRQ_CONTROL};
+use runtime::common::sysdesc::{ObjectRecord, Spec, KIND_IRQ, NONE};
+use runtime::common::constants::{PAGE_BITS, WORD_BITS};
+use runtime::common::invocation::{ObjectType, VmAttributes};
+use runtime::common::objects::{AsidPool, CNode, DomainSet, IrqControl, IrqHandler, Notification, Page,
+                               PageDirectory, PageTable, Pdpt, Pml4, Tcb, UserContext};
+use runtime::common::{CPtr, CapRights, Error};
+
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+
+/// Where the loader maps frames while it fills them in.
+const SCRATCH_VADDR: usize = 0x80_0000_0000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum LoadError {
+    /// A record has a value the kernel can't use, like a bad object type for its table.
+    BadRecord,
+    Alloc(AllocError),
+    Kernel(Error),
+}
+
+impl From<AllocError> for LoadError {
+    fn from(err: AllocError) -> Self {
+        LoadError::Alloc(err)
+    }
+}
+
+impl From<Error> for LoadError {
+    fn from(err: Error) -> Self {
+        LoadError::Kernel(err)
+    }
+}
+
+/// Our own CNode, which holds the original cap to every object.
+const ROOT: CNode = CNode(CAP_INIT_THREAD_CNODE);
+
+/// Load the system. When this returns, every thread marked to resume is running.
+pub(crate) fn load(spec: &Spec, allocator: &mut ObjectAllocator) -> Result<(), LoadError> {
+    // Object i's cap lives in slot first_slot + i.
+    let first_slot = allocator.alloc_slots(spec.objects.len())?;
+    let slot = |object: u64| first_slot + object as CPtr;
+
+    init_scratch(allocator)?;
+    create_objects(spec, allocator, first_slot)?;
+
+    // Each VSpace needs an ASID before anything can be mapped into it.
+    for (i, object) in spec.objects.iter().enumerate() {
+        if object.kind == ObjectType::X64PML4 as u64 {
+            AsidPool(CAP_INIT_THREAD_ASID_POOL).assign(Pml4(slot(i as u64)))?;
+        }
+    }
+
+    fill_frames(spec, first_slot)?;
+    map(spec, allocator, first_slot)?;
+    init_irqs(spec, allocator, first_slot)?;
+    init_tcbs(spec, first_slot)?;
+
+    // CSpaces are filled last, so they can hold caps to anything the loader has set up. This
+    // includes copies of the caps the loader itself needs, which is why they aren't moved.
+    for cap in spec.caps.iter() {
+        let cnode = spec.objects.get(cap.cnode as usize);
+        if cnode.kind != ObjectType::CapTable as u64 {
+            return Err(LoadError::BadRecord);
+        }
+        CNode(slot(cap.cnode)).mint(cap.slot as usize, cnode.size_bits as u8, ROOT, slot(cap.object),
+                                    WORD_BITS as u8, CapRights(cap.rights as usize), cap.badge as usize)?;
+    }
+
+    for tcb in spec.tcbs.iter().filter(|t| t.resume != 0) {
+        Tcb(slot(tcb.tcb)).resume()?;
+    }
+    Ok(())
+}
+
+/// Map paging structures in our own VSpace, so frames can be mapped at SCRATCH_VADDR.
+fn init_scratch(allocator: &mut ObjectAllocator) -> Result<(), LoadError> {
+    let vspace = Pml4(CAP_INIT_THREAD_VSPACE);
+    let attr = VmAttributes::DEFAULT;
+
+    // SCRATCH_VADDR is in the second 512 GiB (PML4 index 1), away from our own image. As in the
+    // supervisor example, we map the PDPT anyway in case something else already put one there.
+    let pdpt = Pdpt(allocator.alloc(ObjectType::X86PDPT, 0)?);
+    match pdpt.map(vspace, SCRATCH_VADDR, attr) {
+        Ok(()) | Err(Error::DeleteFirst) => {}
+        Err(err) => return Err(err.into()),
+    }
+    PageDirectory(allocator.alloc(ObjectType::X86PageDirectory, 0)?).map(vspace, SCRATCH_VADDR, attr)?;
+    PageTable(allocator.alloc(ObjectType::X86PageTable, 0)?).map(vspace, SCRATCH_VADDR, attr)?;
+    Ok(())
+}
+
+fn create_objects(spec: &Spec, allocator: &mut ObjectAllocator, first_slot: CPtr) -> Result<(), LoadError> {
+    for (i, object) in spec.objects.iter().enumerate() {
+        let slot = first_slot + i;
+
+        if object.kind == KIND_IRQ {
+            IrqControl(CAP_IRQ_CONTROL).get(object.size_bits as usize, ROOT, slot, WORD_BITS as u8)?;
+            continue;
+        }
+
+        // The spec has already been validated, so this can't fail.
+        let object_type = ObjectType::from_word(object.kind as usize).ok_or(LoadError::BadRecord)?;
+        if object.paddr != NONE {
+            allocator.alloc_device_frame(object.paddr as usize, object_type, slot)?;
+        } else {
+            allocator.alloc_at(object_type, object.size_bits as u32, slot)?;
+        }
+    }
+    Ok(())
+}
+
+fn fill_frames(spec: &Spec, first_slot: CPtr) -> Result<(), LoadError> {
+    for (i, object) in spec.objects.iter().enumerate().filter(|(_, o)| o.fill_len != 0) {
+        let ObjectRecord { kind, fill_start, fill_len, .. } = object;
+        if kind != ObjectType::X86SmallPage as u64 || fill_len as usize > PAGE_SIZE {
+            return Err(LoadError::BadRecord);
+        }
+
+        let frame = Page(first_slot + i);
+        frame.map(Pml4(CAP_INIT_THREAD_VSPACE), SCRATCH_VADDR, CapRights::READ_WRITE, VmAttributes::DEFAULT)?;
+        let data = &spec.data[fill_start as usize..(fill_start + fill_len) as usize];
+        // SAFETY: The frame is mapped at SCRATCH_VADDR, and nothing else uses that address. New
+        // frames are already zeroed, so only the data needs copying.
+        let page = unsafe { &mut *(SCRATCH_VADDR as *mut [u8; PAGE_SIZE]) };
+        page[..data.len()].copy_from_slice(data);
+        frame.unmap()?;
+    }
+    Ok(())
+}
+
+fn map(spec: &Spec, allocator: &mut ObjectAllocator, first_slot: CPtr) -> Result<(), LoadError> {
+    let slot = |object: u64| first_slot + object as CPtr;
+
+    for mapping in spec.mappings.iter() {
+        let vspace = Pml4(slot(mapping.vspace));
+        let vaddr = mapping.vaddr as usize;
+        let attr = VmAttributes::from_word(mapping.attr as usize).ok_or(LoadError::BadRecord)?;
+
+        match ObjectType::from_word(spec.objects.get(mapping.object as usize).kind as usize) {
+            Some(ObjectType::X86PDPT) => Pdpt(slot(mapping.object)).map(vspace, vaddr, attr)?,
+            Some(ObjectType::X86PageDirectory) => PageDirectory(slot(mapping.object)).map(vspace, vaddr, attr)?,
+            Some(ObjectType::X86PageTable) => PageTable(slot(mapping.object)).map(vspace, vaddr, attr)?,
+            Some(ObjectType::X86SmallPage | ObjectType::X86LargePage | ObjectType::X64HugePage) => {
+                // A frame cap can only be mapped once, and frames can be shared. So every mapping
+                // uses its own copy, and the original is left for CSpaces and IPC buffers.
+                let copy = allocator.alloc_slot()?;
+                ROOT.copy(copy, WORD_BITS as u8, ROOT, slot(mapping.object), WORD_BITS as u8, CapRights::ALL)?;
+                Page(copy).map(vspace, vaddr, CapRights(mapping.rights as usize), attr)?;
+            }
+            _ => return Err(LoadError::BadRecord),
+        }
+    }
+    Ok(())
+}
+
+fn init_irqs(spec: &Spec, allocator: &mut ObjectAllocator, first_slot: CPtr) -> Result<(), LoadError> {
+    for irq in spec.irqs.iter() {
+        let mut notification = first_slot + irq.notification as CPtr;
+        if irq.badge != 0 {
+            let badged = allocator.alloc_slot()?;
+            ROOT.mint(badged, WORD_BITS as u8, ROOT, notification, WORD_BITS as u8, CapRights::ALL,
+                      irq.badge as usize)?;
+            notification = badged;
+        }
+        IrqHandler(first_slot + irq.irq as CPtr).set_notification(Notification(notification))?;
+    }
+    Ok(())
+}
+
+fn init_tcbs(spec: &Spec, first_slot: CPtr) -> Result<(), LoadError> {
+    let slot = |object: u64| first_slot + object as CPtr;
+
+    for t in spec.tcbs.iter() {
+        let tcb = Tcb(slot(t.tcb));
+        let buffer_frame = if t.ipc_buffer_frame == NONE { Page(0) } else { Page(slot(t.ipc_buffer_frame)) };
+
+        tcb.configure(t.fault_ep as CPtr, CNode(slot(t.cspace)), t.cspace_data as usize, Pml4(slot(t.vspace)), 0,
+                      t.ipc_buffer_addr as usize, buffer_frame)?;
+        tcb.set_sched_params(Tcb(CAP_INIT_THREAD_TCB), t.max_priority as usize, t.priority as usize)?;
+        if t.domain != 0 {
+            DomainSet(CAP_DOMAIN).set(t.domain as u8, tcb)?;
+        }
+
+        let regs = UserContext {
+            rip: t.ip as usize,
+            rsp: t.sp as usize,
+            rdi: t.arg0 as usize,
+            rsi: t.arg1 as usize,
+            rdx: t.arg2 as usize,
+            rcx: t.arg3 as usize,
+            ..Default::default()
+        };
+        tcb.write_registers(false, 0, UserContext::COUNT, &regs)?;
+    }
+    Ok(())
+}
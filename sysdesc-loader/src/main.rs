@@ -0,0 +1,37 @@
+//! A root task which creates a system from a static description, like SeL4's capDL loader does
+//! from a capDL spec.
+//!
+//! The description is compiled by tools/sysdesc and embedded at build time. See build.rs. The loader
+//! creates every object, fills and maps frames, fills CNodes, binds IRQs and configures TCBs. Then
+//! it starts the threads and gets out of the way. See common::sysdesc for the spec format.
+
+#![no_std]
+#![no_main]
+
+mod loader;
+
+use runtime::alloc::ObjectAllocator;
+use runtime::common::sysdesc::Spec;
+use runtime::{println, BootInfo};
+
+// SPEC.
+include!(concat!(env!("OUT_DIR"), "/spec.rs"));
+
+runtime::entry!(main);
+
+fn main(bootinfo: &'static BootInfo) {
+    if SPEC.is_empty() {
+        println!("sysdesc-loader: No spec. Set SYSDESC_SPEC when building.");
+        return;
+    }
+
+    let spec = Spec::from_bytes(SPEC).expect("Invalid system description");
+    println!("sysdesc-loader: {} objects, {} caps, {} mappings, {} IRQs, {} TCBs",
+             spec.objects.len(), spec.caps.len(), spec.mappings.len(), spec.irqs.len(), spec.tcbs.len());
+
+    let mut allocator = ObjectAllocator::new(bootinfo);
+    match loader::load(&spec, &mut allocator) {
+        Ok(()) => println!("sysdesc-loader: System started"),
+        Err(err) => panic!("sysdesc-loader: Failed to load the system: {err:?}"),
+    }
+}
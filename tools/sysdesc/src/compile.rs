@@ -0,0 +1,483 @@
+//! Lower the TOML description into the records of a binary spec.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use common::sysdesc::{CapRecord, IrqRecord, MappingRecord, ObjectRecord, Record, SpecHeader, TcbRecord, KIND_IRQ, NONE};
+use common::constants::{HUGE_PAGE_BITS, LARGE_PAGE_BITS, PAGE_BITS, USER_TOP, WORD_BITS};
+use common::invocation::{ObjectType, VmAttributes};
+use common::types::{cnode_cap_data, CapRights};
+use crate::elf::load_segments;
+use crate::spec::{ObjectSpec, SpecFile};
+
+type Result<T> = std::result::Result<T, String>;
+
+/// An object, before it's been given an index.
+struct Object {
+    name: String,
+    kind: u64,
+    size_bits: u32,
+    paddr: Option<u64>,
+    fill: Vec<u8>,
+}
+
+impl Object {
+    fn new(name: impl Into<String>, object_type: ObjectType, size_bits: u32) -> Self {
+        Self { name: name.into(), kind: object_type as u64, size_bits, paddr: None, fill: Vec::new() }
+    }
+
+    /// The order objects are created in. Regular objects are created largest first, to avoid
+    /// wasting memory on alignment. Then device frames in address order, because device untypeds
+    /// are used from the bottom up. Then IRQ handlers.
+    fn sort_key(&self) -> (u8, i64, u64) {
+        match (self.kind, self.paddr) {
+            (KIND_IRQ, _) => (2, 0, self.size_bits as u64),
+            (_, Some(paddr)) => (1, 0, paddr),
+            (kind, None) => {
+                let object_type = ObjectType::from_word(kind as usize).unwrap();
+                (0, -(object_type.size_bits(self.size_bits) as i64), 0)
+            }
+        }
+    }
+}
+
+/// A frame mapping, by name.
+struct Mapping {
+    vspace: String,
+    vaddr: u64,
+    frame: String,
+    frame_bits: u32,
+    rights: CapRights,
+    attr: VmAttributes,
+}
+
+/// The compiled spec, ready to be written out.
+#[derive(Default)]
+pub(crate) struct Compiled {
+    pub objects: Vec<ObjectRecord>,
+    pub caps: Vec<CapRecord>,
+    pub mappings: Vec<MappingRecord>,
+    pub irqs: Vec<IrqRecord>,
+    pub tcbs: Vec<TcbRecord>,
+    pub data: Vec<u8>,
+}
+
+impl Compiled {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = SpecHeader {
+            num_objects: self.objects.len(),
+            num_caps: self.caps.len(),
+            num_mappings: self.mappings.len(),
+            num_irqs: self.irqs.len(),
+            num_tcbs: self.tcbs.len(),
+            data_len: self.data.len(),
+        };
+
+        let mut words = header.to_words().to_vec();
+        push_records(&mut words, &self.objects);
+        push_records(&mut words, &self.caps);
+        push_records(&mut words, &self.mappings);
+        push_records(&mut words, &self.irqs);
+        push_records(&mut words, &self.tcbs);
+
+        let mut bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+fn push_records<R: Record>(words: &mut Vec<u64>, records: &[R]) {
+    for record in records {
+        let start = words.len();
+        words.resize(start + R::WORDS, 0);
+        record.to_words(&mut words[start..]);
+    }
+}
+
+fn parse_rights(rights: &str) -> Result<CapRights> {
+    let mut r = (false, false, false, false);
+    for c in rights.chars() {
+        match c.to_ascii_lowercase() {
+            'p' => r.0 = true,
+            'g' => r.1 = true,
+            'r' => r.2 = true,
+            'w' => r.3 = true,
+            _ => return Err(format!("Invalid rights {rights:?}. Expected some of r, w, g and p")),
+        }
+    }
+    Ok(CapRights::new(r.0, r.1, r.2, r.3))
+}
+
+fn frame_type(size_bits: u32) -> Result<ObjectType> {
+    match size_bits {
+        PAGE_BITS => Ok(ObjectType::X86SmallPage),
+        LARGE_PAGE_BITS => Ok(ObjectType::X86LargePage),
+        HUGE_PAGE_BITS => Ok(ObjectType::X64HugePage),
+        _ => Err(format!("Frames must have size_bits of {PAGE_BITS}, {LARGE_PAGE_BITS} or {HUGE_PAGE_BITS}")),
+    }
+}
+
+pub(crate) fn compile(spec: &SpecFile, base_dir: &Path) -> Result<Compiled> {
+    let mut objects = Vec::new();
+    let mut mappings = Vec::new();
+    // The entry points of VSpaces with ELF files. Used as the default TCB ip.
+    let mut entry_points = HashMap::new();
+
+    for (name, object) in &spec.objects {
+        if name.contains('.') {
+            // Generated objects have dots in their names, so they can't clash.
+            return Err(format!("{name}: Object names can't contain '.'"));
+        }
+        let context = |e: String| format!("{name}: {e}");
+
+        match object {
+            ObjectSpec::Untyped { size_bits } => objects.push(Object::new(name, ObjectType::Untyped, *size_bits)),
+            ObjectSpec::Endpoint => objects.push(Object::new(name, ObjectType::Endpoint, 0)),
+            ObjectSpec::Notification => objects.push(Object::new(name, ObjectType::Notification, 0)),
+            ObjectSpec::Cnode { size_bits, .. } => objects.push(Object::new(name, ObjectType::CapTable, *size_bits)),
+            ObjectSpec::Tcb(_) => objects.push(Object::new(name, ObjectType::TCB, 0)),
+            ObjectSpec::Frame { size_bits, paddr, fill, fill_offset } => {
+                let mut frame = Object::new(name, frame_type(*size_bits).map_err(context)?, 0);
+                frame.paddr = *paddr;
+                if let Some(path) = fill {
+                    let path = base_dir.join(path);
+                    let bytes = std::fs::read(&path).map_err(|e| context(format!("{}: {e}", path.display())))?;
+                    let start = (*fill_offset as usize).min(bytes.len());
+                    let end = (start + (1 << size_bits)).min(bytes.len());
+                    frame.fill = bytes[start..end].to_vec();
+                }
+                if !frame.fill.is_empty() && *size_bits != PAGE_BITS {
+                    return Err(context("Only 4 KiB frames can be filled".into()));
+                }
+                objects.push(frame);
+            }
+            ObjectSpec::Vspace { elf, mappings: explicit, zero } => {
+                objects.push(Object::new(name, ObjectType::X64PML4, 0));
+
+                if let Some(path) = elf {
+                    let path = base_dir.join(path);
+                    let bytes = std::fs::read(&path).map_err(|e| context(format!("{}: {e}", path.display())))?;
+                    let (entry, pages) = load_segments(&bytes).map_err(context)?;
+                    entry_points.insert(name.clone(), entry);
+                    for page in pages {
+                        let frame_name = format!("{name}.elf.{:x}", page.vaddr);
+                        let mut frame = Object::new(&frame_name, ObjectType::X86SmallPage, 0);
+                        frame.fill = page.data;
+                        objects.push(frame);
+                        mappings.push(Mapping {
+                            vspace: name.clone(),
+                            vaddr: page.vaddr,
+                            frame: frame_name,
+                            frame_bits: PAGE_BITS,
+                            rights: if page.writable { CapRights::READ_WRITE } else { CapRights::READ },
+                            attr: VmAttributes::DEFAULT,
+                        });
+                    }
+                }
+
+                for region in zero {
+                    let rights = parse_rights(&region.rights).map_err(context)?;
+                    for vaddr in (region.vaddr..region.vaddr + region.size).step_by(1 << PAGE_BITS) {
+                        let frame_name = format!("{name}.zero.{vaddr:x}");
+                        objects.push(Object::new(&frame_name, ObjectType::X86SmallPage, 0));
+                        mappings.push(Mapping {
+                            vspace: name.clone(),
+                            vaddr,
+                            frame: frame_name,
+                            frame_bits: PAGE_BITS,
+                            rights,
+                            attr: VmAttributes::DEFAULT,
+                        });
+                    }
+                }
+
+                for m in explicit {
+                    let frame_bits = match spec.objects.get(&m.frame) {
+                        Some(ObjectSpec::Frame { size_bits, .. }) => *size_bits,
+                        _ => return Err(context(format!("Mapping of {:?}, which isn't a frame", m.frame))),
+                    };
+                    mappings.push(Mapping {
+                        vspace: name.clone(),
+                        vaddr: m.vaddr,
+                        frame: m.frame.clone(),
+                        frame_bits,
+                        rights: parse_rights(&m.rights).map_err(context)?,
+                        attr: if m.cached { VmAttributes::DEFAULT } else { VmAttributes::CacheDisabled },
+                    });
+                }
+            }
+            ObjectSpec::Irq { irq, .. } => {
+                let mut object = Object::new(name, ObjectType::Untyped, *irq as u32);
+                object.kind = KIND_IRQ;
+                objects.push(object);
+            }
+        }
+    }
+
+    // Work out the paging structures the mappings need. The map orders them by level, so they're
+    // mapped top down.
+    let mut tables = BTreeMap::new();
+    let mut mapped = HashMap::new();
+    for m in &mappings {
+        let size = 1u64 << m.frame_bits;
+        if m.vaddr % size != 0 || m.vaddr + size > USER_TOP as u64 {
+            return Err(format!("{}: Can't map {} at {:#x}", m.vspace, m.frame, m.vaddr));
+        }
+        if let Some(other) = mapped.insert((m.vspace.clone(), m.vaddr), &m.frame) {
+            return Err(format!("{}: {} and {} are both mapped at {:#x}", m.vspace, other, m.frame, m.vaddr));
+        }
+
+        let levels = [
+            (0, ObjectType::X86PDPT, 39),
+            (1, ObjectType::X86PageDirectory, 30),
+            (2, ObjectType::X86PageTable, 21),
+        ];
+        for (level, object_type, shift) in levels {
+            if m.frame_bits >= shift {
+                break;
+            }
+            let base = m.vaddr >> shift << shift;
+            tables.insert((level, m.vspace.clone(), base), object_type);
+        }
+    }
+
+    let mut table_names = Vec::new();
+    for ((_, vspace, base), object_type) in &tables {
+        let name = format!("{vspace}.table.{:?}.{base:x}", object_type);
+        objects.push(Object::new(&name, *object_type, 0));
+        table_names.push((vspace.clone(), *base, name));
+    }
+
+    objects.sort_by_key(|o| o.sort_key());
+
+    let mut compiled = Compiled::default();
+    let mut index = HashMap::new();
+    for (i, object) in objects.iter().enumerate() {
+        index.insert(object.name.clone(), i as u64);
+
+        let (fill_start, fill_len) = (compiled.data.len() as u64, object.fill.len() as u64);
+        compiled.data.extend_from_slice(&object.fill);
+        compiled.objects.push(ObjectRecord {
+            kind: object.kind,
+            size_bits: object.size_bits as u64,
+            paddr: object.paddr.unwrap_or(NONE),
+            fill_start,
+            fill_len,
+        });
+    }
+    let lookup = |name: &str| index.get(name).copied().ok_or_else(|| format!("No object named {name:?}"));
+
+    for (vspace, base, name) in &table_names {
+        compiled.mappings.push(MappingRecord {
+            vspace: lookup(vspace)?,
+            vaddr: *base,
+            object: lookup(name)?,
+            rights: 0,
+            attr: VmAttributes::DEFAULT as u64,
+        });
+    }
+    for m in &mappings {
+        compiled.mappings.push(MappingRecord {
+            vspace: lookup(&m.vspace)?,
+            vaddr: m.vaddr,
+            object: lookup(&m.frame)?,
+            rights: m.rights.0 as u64,
+            attr: m.attr as u64,
+        });
+    }
+
+    for (name, object) in &spec.objects {
+        let context = |e: String| format!("{name}: {e}");
+        match object {
+            ObjectSpec::Cnode { size_bits, caps } => {
+                for cap in caps {
+                    if cap.slot >= 1 << size_bits {
+                        return Err(context(format!("Slot {} is outside the CNode", cap.slot)));
+                    }
+                    let badge = match spec.objects.get(&cap.object) {
+                        Some(ObjectSpec::Cnode { .. }) => cnode_cap_data(cap.guard as usize, cap.guard_size) as u64,
+                        _ => cap.badge,
+                    };
+                    compiled.caps.push(CapRecord {
+                        cnode: lookup(name)?,
+                        slot: cap.slot,
+                        object: lookup(&cap.object).map_err(context)?,
+                        rights: parse_rights(&cap.rights).map_err(context)?.0 as u64,
+                        badge,
+                    });
+                }
+            }
+            ObjectSpec::Irq { notification, badge, .. } => {
+                if !matches!(spec.objects.get(notification), Some(ObjectSpec::Notification)) {
+                    return Err(context(format!("{notification:?} isn't a notification")));
+                }
+                compiled.irqs.push(IrqRecord {
+                    irq: lookup(name)?,
+                    notification: lookup(notification)?,
+                    badge: *badge,
+                });
+            }
+            ObjectSpec::Tcb(tcb) => {
+                let Some(ObjectSpec::Cnode { size_bits: cspace_bits, .. }) = spec.objects.get(&tcb.cspace) else {
+                    return Err(context(format!("cspace {:?} isn't a CNode", tcb.cspace)));
+                };
+                if !matches!(spec.objects.get(&tcb.vspace), Some(ObjectSpec::Vspace { .. })) {
+                    return Err(context(format!("vspace {:?} isn't a VSpace", tcb.vspace)));
+                }
+                if tcb.args.len() > 4 {
+                    return Err(context("At most 4 arguments can be passed".into()));
+                }
+
+                let (ipc_buffer_frame, ipc_buffer_addr) = match &tcb.ipc_buffer {
+                    Some(buffer) => {
+                        if !matches!(spec.objects.get(&buffer.frame), Some(ObjectSpec::Frame { size_bits: PAGE_BITS, .. })) {
+                            return Err(context(format!("IPC buffer {:?} isn't a 4 KiB frame", buffer.frame)));
+                        }
+                        (lookup(&buffer.frame)?, buffer.vaddr)
+                    }
+                    None => (NONE, 0),
+                };
+                let ip = tcb.ip.or_else(|| entry_points.get(&tcb.vspace).copied())
+                    .ok_or_else(|| context("No ip, and the VSpace has no ELF file".into()))?;
+                let guard_size = tcb.cspace_guard_size.unwrap_or(WORD_BITS - cspace_bits);
+                let arg = |i: usize| tcb.args.get(i).copied().unwrap_or(0);
+
+                compiled.tcbs.push(TcbRecord {
+                    tcb: lookup(name)?,
+                    cspace: lookup(&tcb.cspace)?,
+                    cspace_data: cnode_cap_data(tcb.cspace_guard as usize, guard_size) as u64,
+                    vspace: lookup(&tcb.vspace)?,
+                    ipc_buffer_frame,
+                    ipc_buffer_addr,
+                    fault_ep: tcb.fault_ep,
+                    priority: tcb.priority as u64,
+                    max_priority: tcb.max_priority as u64,
+                    domain: tcb.domain as u64,
+                    ip,
+                    sp: tcb.sp,
+                    arg0: arg(0),
+                    arg1: arg(1),
+                    arg2: arg(2),
+                    arg3: arg(3),
+                    resume: tcb.resume as u64,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(compiled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::sysdesc::{Spec, SpecError};
+
+    /// One thread with a cap to an endpoint, a frame filled from a file, and an IRQ.
+    const SYSTEM: &str = r#"
+        [objects.ep]
+        type = "endpoint"
+
+        [objects.irq_ntfn]
+        type = "notification"
+
+        [objects.serial]
+        type = "irq"
+        irq = 4
+        notification = "irq_ntfn"
+        badge = 1
+
+        [objects.cnode]
+        type = "cnode"
+        size_bits = 4
+        caps = [{ slot = 1, object = "ep", rights = "wg", badge = 7 }]
+
+        [objects.data]
+        type = "frame"
+        fill = "Cargo.toml"
+
+        [objects.vspace]
+        type = "vspace"
+        zero = [{ vaddr = 0x0fffc000, size = 0x4000 }]
+        mappings = [{ vaddr = 0x10001000, frame = "ipc" }, { vaddr = 0x20000000, frame = "data", rights = "r" }]
+
+        [objects.ipc]
+        type = "frame"
+
+        [objects.tcb]
+        type = "tcb"
+        cspace = "cnode"
+        vspace = "vspace"
+        ipc_buffer = { frame = "ipc", vaddr = 0x10001000 }
+        priority = 100
+        ip = 0x400000
+        sp = 0x0ffffff8
+    "#;
+
+    fn compile_system() -> Compiled {
+        let spec: SpecFile = toml::from_str(SYSTEM).unwrap();
+        compile(&spec, Path::new(env!("CARGO_MANIFEST_DIR"))).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let compiled = compile_system();
+        let bytes = compiled.to_bytes();
+        let spec = Spec::from_bytes(&bytes).unwrap();
+
+        assert_eq!(spec.objects.iter().collect::<Vec<_>>(), compiled.objects);
+        assert_eq!(spec.caps.iter().collect::<Vec<_>>(), compiled.caps);
+        assert_eq!(spec.mappings.iter().collect::<Vec<_>>(), compiled.mappings);
+        assert_eq!(spec.irqs.iter().collect::<Vec<_>>(), compiled.irqs);
+        assert_eq!(spec.tcbs.iter().collect::<Vec<_>>(), compiled.tcbs);
+        assert_eq!(spec.data, compiled.data);
+        assert!(!spec.data.is_empty());
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = compile_system().to_bytes();
+        for len in 0..bytes.len() {
+            assert!(matches!(Spec::from_bytes(&bytes[..len]), Err(SpecError::Truncated)), "length {len}");
+        }
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] ^= 1;
+        assert!(matches!(Spec::from_bytes(&bad_magic), Err(SpecError::BadMagic)));
+    }
+
+    #[test]
+    fn bad_object_index() {
+        let n = compile_system().objects.len() as u64;
+        let cases: [fn(&mut Compiled, u64); 6] = [
+            |c, n| c.caps[0].object = n,
+            |c, n| c.caps[0].cnode = n,
+            |c, n| c.mappings[0].vspace = n,
+            |c, n| c.irqs[0].notification = n,
+            |c, n| c.tcbs[0].ipc_buffer_frame = n,
+            |c, n| c.objects[0].fill_start = n * 0x10000,
+        ];
+        for (i, corrupt) in cases.iter().enumerate() {
+            let mut compiled = compile_system();
+            corrupt(&mut compiled, n);
+            assert!(matches!(Spec::from_bytes(&compiled.to_bytes()), Err(SpecError::BadRecord)), "case {i}");
+        }
+
+        // NONE is only allowed where the reference is optional.
+        let mut compiled = compile_system();
+        compiled.tcbs[0].ipc_buffer_frame = NONE;
+        assert!(Spec::from_bytes(&compiled.to_bytes()).is_ok());
+        compiled.tcbs[0].vspace = NONE;
+        assert!(matches!(Spec::from_bytes(&compiled.to_bytes()), Err(SpecError::BadRecord)));
+    }
+
+    #[test]
+    fn rights() {
+        assert_eq!(parse_rights("rw"), Ok(CapRights::READ_WRITE));
+        assert_eq!(parse_rights("R"), Ok(CapRights::READ));
+        assert_eq!(parse_rights("wgpr"), Ok(CapRights::ALL));
+        assert_eq!(parse_rights("g"), Ok(CapRights::new(false, true, false, false)));
+        assert_eq!(parse_rights(""), Ok(CapRights::NONE));
+        assert!(parse_rights("rx").is_err());
+    }
+}
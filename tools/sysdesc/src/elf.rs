@@ -0,0 +1,54 @@
+//! Split an ELF file's loadable segments into pages.
+
+use common::constants::PAGE_BITS;
+use common::elf::Elf;
+
+const PAGE_SIZE: u64 = 1 << PAGE_BITS;
+
+pub(crate) struct Page {
+    pub vaddr: u64,
+    pub data: Vec<u8>,
+    pub writable: bool,
+}
+
+/// Returns the entry point, and the contents of every page covered by a PT_LOAD segment.
+pub(crate) fn load_segments(bytes: &[u8]) -> Result<(u64, Vec<Page>), String> {
+    let elf = Elf::parse(bytes).ok_or("Not a 64 bit little endian x86_64 ELF file")?;
+
+    let mut pages: Vec<Page> = Vec::new();
+    for segment in elf.segments() {
+        let segment = segment.ok_or("Segment outside the ELF file")?;
+        let (vaddr, mem_size, data, writable) =
+            (segment.vaddr as u64, segment.mem_size as u64, segment.data, segment.writable);
+        let file_size = data.len();
+
+        let start = vaddr & !(PAGE_SIZE - 1);
+        for page_vaddr in (start..vaddr + mem_size).step_by(PAGE_SIZE as usize) {
+            // Segments can share a page, if they have the same permissions.
+            let page = match pages.iter_mut().find(|p| p.vaddr == page_vaddr) {
+                Some(page) => {
+                    page.writable |= writable;
+                    page
+                }
+                None => {
+                    pages.push(Page { vaddr: page_vaddr, data: Vec::new(), writable });
+                    pages.last_mut().unwrap()
+                }
+            };
+
+            // Copy the part of the segment's data which lands in this page.
+            let data_start = vaddr.max(page_vaddr);
+            let data_end = (vaddr + file_size as u64).min(page_vaddr + PAGE_SIZE);
+            if data_start < data_end {
+                let page_offset = (data_end - page_vaddr) as usize;
+                if page.data.len() < page_offset {
+                    page.data.resize(page_offset, 0);
+                }
+                page.data[(data_start - page_vaddr) as usize..page_offset]
+                    .copy_from_slice(&data[(data_start - vaddr) as usize..(data_end - vaddr) as usize]);
+            }
+        }
+    }
+
+    Ok((elf.entry as u64, pages))
+}
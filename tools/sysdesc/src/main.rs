@@ -0,0 +1,68 @@
+//! Compile a TOML system description into the binary spec read by sysdesc-loader. See spec.rs for
+//! the description format, and common::sysdesc for the binary format.
+//!
+//! Usage:
+//!
+//!     sysdesc <system.toml> [-o spec.bin]
+//!
+//! Paths in the description (ELF files and frame fills) are relative to the description file.
+
+mod compile;
+mod elf;
+mod spec;
+
+use std::path::Path;
+use std::process::exit;
+use common::sysdesc::Spec;
+
+fn usage() -> ! {
+    eprintln!("Usage: sysdesc <system.toml> [-o spec.bin]");
+    exit(1);
+}
+
+fn main() {
+    let mut input = None;
+    let mut output = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            _ if input.is_none() => input = Some(arg),
+            _ => usage(),
+        }
+    }
+    let input = input.unwrap_or_else(|| usage());
+    let output = output.unwrap_or_else(|| Path::new(&input).with_extension("bin").display().to_string());
+
+    let text = std::fs::read_to_string(&input).unwrap_or_else(|e| {
+        eprintln!("{input}: {e}");
+        exit(1);
+    });
+    let spec = toml::from_str(&text).unwrap_or_else(|e| {
+        eprintln!("{input}: {e}");
+        exit(1);
+    });
+
+    let base_dir = Path::new(&input).parent().unwrap_or(Path::new("."));
+    let compiled = compile::compile(&spec, base_dir).unwrap_or_else(|e| {
+        eprintln!("{input}: {e}");
+        exit(1);
+    });
+
+    let bytes = compiled.to_bytes();
+    // Make sure the loader will accept it.
+    if let Err(e) = Spec::from_bytes(&bytes) {
+        eprintln!("Internal error: Generated an invalid spec ({e:?})");
+        exit(1);
+    }
+
+    std::fs::write(&output, &bytes).unwrap_or_else(|e| {
+        eprintln!("{output}: {e}");
+        exit(1);
+    });
+    println!("Wrote {output}: {} objects, {} caps, {} mappings, {} IRQs, {} TCBs, {} bytes of data",
+             compiled.objects.len(), compiled.caps.len(), compiled.mappings.len(), compiled.irqs.len(),
+             compiled.tcbs.len(), compiled.data.len());
+}
@@ -0,0 +1,180 @@
+//! The TOML system description.
+//!
+//! A description is a table of named objects. Each object has a `type`, and type specific fields:
+//!
+//! ```toml
+//! [objects.server_ep]
+//! type = "endpoint"
+//!
+//! [objects.client_cnode]
+//! type = "cnode"
+//! size_bits = 4
+//! caps = [{ slot = 1, object = "server_ep", rights = "wg", badge = 7 }]
+//!
+//! [objects.client_vspace]
+//! type = "vspace"
+//! elf = "client.elf"
+//! zero = [{ vaddr = 0x0fffc000, size = 0x4000 }]
+//! mappings = [{ vaddr = 0x10001000, frame = "client_ipc" }]
+//!
+//! [objects.client_ipc]
+//! type = "frame"
+//!
+//! [objects.client_tcb]
+//! type = "tcb"
+//! cspace = "client_cnode"
+//! vspace = "client_vspace"
+//! ipc_buffer = { frame = "client_ipc", vaddr = 0x10001000 }
+//! priority = 100
+//! sp = 0x0ffffff8
+//! ```
+//!
+//! The objects and caps work like capDL's, but this isn't capDL's spec language. It adds two
+//! conveniences. Paging structures are never written out: they're created for whatever the
+//! mappings need. And a VSpace can load an ELF file, which creates and maps the frames holding its
+//! image.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SpecFile {
+    #[serde(default)]
+    pub objects: BTreeMap<String, ObjectSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) enum ObjectSpec {
+    Untyped {
+        size_bits: u32,
+    },
+    Endpoint,
+    Notification,
+    Cnode {
+        size_bits: u32,
+        #[serde(default)]
+        caps: Vec<CapSpec>,
+    },
+    Tcb(TcbSpec),
+    Frame {
+        /// 12, 21 or 30.
+        #[serde(default = "default_frame_bits")]
+        size_bits: u32,
+        /// Create the frame at this physical address. This is for device memory.
+        paddr: Option<u64>,
+        /// Fill the frame from this file.
+        fill: Option<PathBuf>,
+        /// Where in the file to start reading.
+        #[serde(default)]
+        fill_offset: u64,
+    },
+    Vspace {
+        /// Load this ELF file.
+        elf: Option<PathBuf>,
+        #[serde(default)]
+        mappings: Vec<MappingSpec>,
+        /// Regions to fill with new zeroed frames, like stacks.
+        #[serde(default)]
+        zero: Vec<ZeroSpec>,
+    },
+    Irq {
+        irq: u64,
+        notification: String,
+        #[serde(default)]
+        badge: u64,
+    },
+}
+
+fn default_frame_bits() -> u32 {
+    12
+}
+
+fn default_all_rights() -> String {
+    "rwgp".into()
+}
+
+fn default_rw() -> String {
+    "rw".into()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CapSpec {
+    pub slot: u64,
+    pub object: String,
+    /// Some of r (read), w (write), g (grant) and p (grant reply).
+    #[serde(default = "default_all_rights")]
+    pub rights: String,
+    /// For endpoints and notifications.
+    #[serde(default)]
+    pub badge: u64,
+    /// For CNodes.
+    #[serde(default)]
+    pub guard: u64,
+    #[serde(default)]
+    pub guard_size: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MappingSpec {
+    pub vaddr: u64,
+    pub frame: String,
+    #[serde(default = "default_rw")]
+    pub rights: String,
+    #[serde(default = "default_true")]
+    pub cached: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ZeroSpec {
+    pub vaddr: u64,
+    pub size: u64,
+    #[serde(default = "default_rw")]
+    pub rights: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct IpcBufferSpec {
+    pub frame: String,
+    pub vaddr: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TcbSpec {
+    pub cspace: String,
+    #[serde(default)]
+    pub cspace_guard: u64,
+    /// Defaults to 64 - the CNode's size_bits, so CPtrs resolve in a single level.
+    pub cspace_guard_size: Option<u32>,
+    pub vspace: String,
+    pub ipc_buffer: Option<IpcBufferSpec>,
+    /// A CPtr in the thread's CSpace, or 0 for none.
+    #[serde(default)]
+    pub fault_ep: u64,
+    #[serde(default)]
+    pub priority: u8,
+    #[serde(default)]
+    pub max_priority: u8,
+    #[serde(default)]
+    pub domain: u8,
+    /// Defaults to the entry point of the VSpace's ELF file.
+    pub ip: Option<u64>,
+    #[serde(default)]
+    pub sp: u64,
+    /// Up to 4 arguments, passed in RDI, RSI, RDX and RCX.
+    #[serde(default)]
+    pub args: Vec<u64>,
+    #[serde(default = "default_true")]
+    pub resume: bool,
+}
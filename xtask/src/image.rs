@@ -0,0 +1,126 @@
+//! Building the kernel and root task, and packaging them into bootable images.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+use crate::{ksyms, workspace_root, Options};
+
+/// The files needed to boot.
+pub(crate) struct Image {
+    /// The kernel, as built. This is the file to load symbols from.
+    pub kernel: PathBuf,
+    /// The kernel converted to elf32, for multiboot loaders.
+    pub kernel_elf32: PathBuf,
+    /// The root task, to pass as a multiboot module. None if it's embedded in the kernel.
+    pub root_task: Option<PathBuf>,
+}
+
+/// Run a command, and turn a failure into an error message.
+pub(crate) fn run_command(command: &mut Command) -> Result<(), String> {
+    let status = command.status()
+        .map_err(|e| format!("Could not run {:?}: {e}", command.get_program()))?;
+    if !status.success() {
+        return Err(format!("{:?} failed ({status})", command.get_program()));
+    }
+    Ok(())
+}
+
+pub(crate) fn cargo_build(options: &Options, package: &str) -> Command {
+    let mut cargo = Command::new(env::var("CARGO").unwrap_or("cargo".into()));
+    cargo.current_dir(workspace_root()).args(["build", "-p", package]);
+    if options.release {
+        cargo.arg("--release");
+    }
+    cargo
+}
+
+pub(crate) fn build(options: &Options) -> Result<Image, String> {
+    let target_dir = options.target_dir();
+
+    let root_task = match &options.root_task_elf {
+        Some(elf) => elf.canonicalize().map_err(|e| format!("{}: {e}", elf.display()))?,
+        None => {
+            run_command(&mut cargo_build(options, &options.root_task))?;
+            target_dir.join(&options.root_task)
+        }
+    };
+
+    let mut cargo = cargo_build(options, "kernel");
+    if let Some(features) = &options.features {
+        cargo.args(["--features", features]);
+    }
+    // Always set or clear this, so a value left in the environment can't change the build.
+    if options.embed {
+        cargo.env("ROOT_TASK_ELF", &root_task);
+    } else {
+        cargo.env_remove("ROOT_TASK_ELF");
+    }
+    // Build against the symbol table from last time, and rebuild if it's out of date. Function
+    // addresses don't depend on the table, so the second build's table matches.
+    let kernel = target_dir.join("kernel");
+    let ksyms_path = target_dir.join("kernel.ksyms");
+    if !ksyms_path.exists() {
+        fs::create_dir_all(&target_dir).map_err(|e| format!("{}: {e}", target_dir.display()))?;
+        write(&ksyms_path, &[])?;
+    }
+    cargo.env("KERNEL_SYMBOLS", &ksyms_path);
+    run_command(&mut cargo)?;
+    let ksyms = ksyms::generate(&read(&kernel)?)?;
+    if read(&ksyms_path)? != ksyms {
+        write(&ksyms_path, &ksyms)?;
+        run_command(&mut cargo)?;
+        if ksyms::generate(&read(&kernel)?)? != ksyms {
+            return Err("Embedding the kernel symbol table moved kernel functions".into());
+        }
+    }
+
+    let kernel_elf32 = target_dir.join("kernel.elf32");
+    run_command(Command::new(env::var("OBJCOPY").unwrap_or("objcopy".into()))
+        .args(["-O", "elf32-i386"]).arg(&kernel).arg(&kernel_elf32))?;
+
+    println!("Kernel: {}", kernel_elf32.display());
+    match options.embed {
+        true => println!("Root task: {} (embedded)", root_task.display()),
+        false => println!("Root task: {}", root_task.display()),
+    }
+
+    Ok(Image {
+        kernel,
+        kernel_elf32,
+        root_task: (!options.embed).then_some(root_task),
+    })
+}
+
+/// Build a bootable ISO with GRUB, using grub-mkrescue.
+pub(crate) fn iso(options: &Options, image: &Image) -> Result<(), String> {
+    let iso_root = options.target_dir().join("iso");
+    let boot = iso_root.join("boot");
+    fs::create_dir_all(boot.join("grub")).map_err(|e| format!("{}: {e}", boot.display()))?;
+
+    copy(&image.kernel_elf32, &boot.join("kernel.elf"))?;
+    let mut grub_cfg = String::from("set timeout=0\nset default=0\n\nmenuentry \"kernel\" {\n    multiboot /boot/kernel.elf\n");
+    if let Some(root_task) = &image.root_task {
+        copy(root_task, &boot.join("roottask"))?;
+        grub_cfg.push_str("    module /boot/roottask\n");
+    }
+    grub_cfg.push_str("    boot\n}\n");
+    let cfg_path = boot.join("grub/grub.cfg");
+    fs::write(&cfg_path, grub_cfg).map_err(|e| format!("{}: {e}", cfg_path.display()))?;
+
+    let iso = options.target_dir().join("kernel.iso");
+    run_command(Command::new("grub-mkrescue").arg("-o").arg(&iso).arg(&iso_root))?;
+    println!("ISO: {}", iso.display());
+    Ok(())
+}
+
+fn copy(from: &Path, to: &Path) -> Result<(), String> {
+    fs::copy(from, to).map(|_| ()).map_err(|e| format!("Could not copy {} to {}: {e}", from.display(), to.display()))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<(), String> {
+    fs::write(path, contents).map_err(|e| format!("{}: {e}", path.display()))
+}
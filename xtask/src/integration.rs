@@ -0,0 +1,157 @@
+//! QEMU integration tests. The kernel is built with its `test` feature, booted under TCG with a
+//! range of machine configurations, and its serial output is checked.
+//!
+//! The kernel reports its boot state as `KTEST key=value` lines, then exits through the
+//! isa-debug-exit device. See kernel/src/test.rs.
+
+use std::collections::HashMap;
+use std::fs;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use crate::image::Image;
+use crate::qemu::qemu_command;
+use crate::Options;
+
+const MIB: u64 = 1 << 20;
+
+/// QEMU's exit status when the kernel writes TestExit::Success to the debug exit device.
+const EXIT_SUCCESS: i32 = (0x10 << 1) | 1;
+
+/// How long a single boot is allowed to take. TCG is slow, but not this slow.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+struct TestCase {
+    name: &'static str,
+    smp: u32,
+    mem_mib: u64,
+}
+
+const TESTS: &[TestCase] = &[
+    TestCase { name: "boot_128m", smp: 1, mem_mib: 128 },
+    TestCase { name: "boot_512m", smp: 1, mem_mib: 512 },
+    // Above 3 GiB, QEMU splits RAM around the PCI hole, so the memory map has a region above 4 GiB.
+    TestCase { name: "boot_4g", smp: 1, mem_mib: 4096 },
+    TestCase { name: "madt_smp2", smp: 2, mem_mib: 512 },
+    TestCase { name: "madt_smp4", smp: 4, mem_mib: 512 },
+    TestCase { name: "madt_smp8", smp: 8, mem_mib: 512 },
+];
+
+/// Build the test kernel and run every test case.
+pub(crate) fn test(options: &Options) -> Result<(), String> {
+    let mut options = options.clone();
+    let features = match &options.features {
+        Some(features) => format!("{features},test"),
+        None => "test".into(),
+    };
+    options.features = Some(features);
+    // KVM would pass the host's CPU through, which makes results machine dependent.
+    options.no_kvm = true;
+
+    let image = crate::image::build(&options)?;
+    if Command::new("qemu-system-x86_64").arg("--version").stdout(Stdio::null()).status().is_err() {
+        return Err("qemu-system-x86_64 not found".into());
+    }
+
+    let log_dir = options.target_dir().join("tests");
+    fs::create_dir_all(&log_dir).map_err(|e| format!("{}: {e}", log_dir.display()))?;
+
+    let mut failed = 0;
+    for case in TESTS {
+        let log = log_dir.join(format!("{}.log", case.name));
+        let result = run_case(&options, &image, case, &log.display().to_string());
+        match &result {
+            Ok(()) => println!("test {} ... ok", case.name),
+            Err(err) => {
+                println!("test {} ... FAILED\n    {err}\n    serial log: {}", case.name, log.display());
+                failed += 1;
+            }
+        }
+    }
+
+    println!("\n{} passed, {failed} failed", TESTS.len() - failed);
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{failed} integration test(s) failed")),
+    }
+}
+
+fn run_case(options: &Options, image: &Image, case: &TestCase, log: &str) -> Result<(), String> {
+    let mut options = options.clone();
+    options.smp = case.smp;
+    options.mem = format!("{}M", case.mem_mib);
+    options.serial = format!("file:{log}");
+
+    let mut qemu = qemu_command(&options, image);
+    qemu.args(["-monitor", "none", "-device", "isa-debug-exit,iobase=0xf4,iosize=0x04"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null());
+    let mut child = qemu.spawn().map_err(|e| format!("Could not run QEMU: {e}"))?;
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("Timed out after {}s", TIMEOUT.as_secs()));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+
+    let output = fs::read_to_string(log).map_err(|e| format!("{log}: {e}"))?;
+    if status.code() != Some(EXIT_SUCCESS) {
+        return Err(format!("Kernel did not exit successfully ({status})"));
+    }
+    check_output(case, &output)
+}
+
+/// Parse the `KTEST key=value` lines.
+fn parse_report(output: &str) -> HashMap<&str, u64> {
+    output.lines()
+        .filter_map(|line| line.trim().strip_prefix("KTEST "))
+        .filter_map(|kv| kv.split_once('='))
+        .filter_map(|(key, value)| Some((key, value.trim().parse().ok()?)))
+        .collect()
+}
+
+fn check_output(case: &TestCase, output: &str) -> Result<(), String> {
+    let report = parse_report(output);
+    let get = |key: &str| report.get(key).copied().ok_or_else(|| format!("Missing KTEST {key}"));
+
+    if !output.contains("Booting via multiboot v1") {
+        return Err("Kernel did not boot via multiboot".into());
+    }
+
+    // Memory map. Everything below 1 MiB is ignored, and QEMU reserves a little at the top of low
+    // memory for ACPI tables and the BIOS.
+    let mem = case.mem_mib * MIB;
+    let mem_bytes = get("mem_bytes")?;
+    if mem_bytes > mem || mem_bytes < mem - 4 * MIB {
+        return Err(format!("Expected about {mem} bytes of usable memory, got {mem_bytes}"));
+    }
+    let expected_regions = if case.mem_mib > 3072 { 2 } else { 1 };
+    if get("mem_regions")? != expected_regions {
+        return Err(format!("Expected {expected_regions} memory region(s), got {}", get("mem_regions")?));
+    }
+
+    // ACPI discovery and madt_scan. Every CPU shows up in the MADT, even the ones we don't record.
+    if !output.contains("ACPI: MADT paddr=") {
+        return Err("MADT not found".into());
+    }
+    let apics = output.matches("ACPI: MADT_APIC apic_id=").count() as u64;
+    if apics != case.smp as u64 {
+        return Err(format!("Expected {} CPUs in the MADT, found {apics}", case.smp));
+    }
+    // CPUs past the kernel's CONFIG_MAX_NUM_NODES are found in the MADT but not recorded. The kernel
+    // reports the value it was built with, so this follows kernel.toml, KERNEL_CONFIG and `smp`.
+    let expected_cpus = (case.smp as u64).min(get("max_num_nodes")?);
+    if get("cpus")? != expected_cpus {
+        return Err(format!("Expected {expected_cpus} recorded CPU(s), got {}", get("cpus")?));
+    }
+    if get("ioapics")? != 1 {
+        return Err(format!("Expected 1 IOAPIC, got {}", get("ioapics")?));
+    }
+    Ok(())
+}
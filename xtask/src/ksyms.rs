@@ -0,0 +1,235 @@
+//! Generate the kernel's embedded symbol table (common::ksyms) from the kernel ELF.
+
+use common::ksyms::{KsymsHeader, KSYMS_ENTRY_SIZE, KSYMS_HEADER_SIZE};
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+const SYM_SIZE: usize = 24;
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N], String> {
+    bytes.get(offset..offset + N)
+        .map(|b| b.try_into().unwrap())
+        .ok_or_else(|| "Truncated ELF file".to_string())
+}
+
+struct Symbol {
+    paddr: u32,
+    size: u32,
+    /// The end of the symbol's section, which bounds symbols without a size.
+    section_end: u32,
+    name: String,
+}
+
+/// Build the symbol table for a kernel ELF file. Every function symbol is included, with its name
+/// demangled. Symbols without a size are given one, running up to the next symbol or the end of
+/// their section.
+pub(crate) fn generate(elf: &[u8]) -> Result<Vec<u8>, String> {
+    if elf.get(0..4) != Some(b"\x7fELF") || elf.get(4) != Some(&2) || elf.get(5) != Some(&1) {
+        return Err("Not a 64 bit little endian ELF file".into());
+    }
+
+    let u16_at = |o| read::<2>(elf, o).map(u16::from_le_bytes);
+    let u32_at = |o| read::<4>(elf, o).map(u32::from_le_bytes);
+    let u64_at = |o| read::<8>(elf, o).map(u64::from_le_bytes);
+
+    // The kernel's virtual segments are loaded at vaddr - offset. Segments linked at physical
+    // addresses have vaddr == paddr.
+    let phoff = u64_at(32)? as usize;
+    let phentsize = u16_at(54)? as usize;
+    let phnum = u16_at(56)? as usize;
+    let mut virt_offset = None;
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        let (vaddr, paddr) = (u64_at(ph + 16)?, u64_at(ph + 24)?);
+        if u32_at(ph)? == PT_LOAD && vaddr != paddr {
+            virt_offset = Some(vaddr - paddr);
+        }
+    }
+    let virt_offset = virt_offset.ok_or("The kernel has no virtually addressed segment")?;
+
+    let shoff = u64_at(40)? as usize;
+    let shentsize = u16_at(58)? as usize;
+    let shnum = u16_at(60)? as usize;
+    let section = |i: usize| shoff + i * shentsize;
+    let symtab = (0..shnum).map(section).find(|&sh| u32_at(sh + 4) == Ok(SHT_SYMTAB))
+        .ok_or("The kernel has no symbol table. Is it stripped?")?;
+    let strtab = section(u32_at(symtab + 40)? as usize);
+
+    let sym_offset = u64_at(symtab + 24)? as usize;
+    let sym_count = u64_at(symtab + 32)? as usize / SYM_SIZE;
+    let str_offset = u64_at(strtab + 24)? as usize;
+    let str_size = u64_at(strtab + 32)? as usize;
+    let strings = elf.get(str_offset..str_offset + str_size).ok_or("String table outside the ELF file")?;
+
+    let mut symbols = Vec::new();
+    for i in 0..sym_count {
+        let sym = sym_offset + i * SYM_SIZE;
+        let info = read::<1>(elf, sym + 4)?[0];
+        let shndx = u16_at(sym + 6)?;
+        let value = u64_at(sym + 8)?;
+        if info & 0xf != STT_FUNC || shndx == 0 || value == 0 {
+            continue;
+        }
+
+        let name_offset = u32_at(sym)? as usize;
+        let name = strings.get(name_offset..)
+            .and_then(|s| std::ffi::CStr::from_bytes_until_nul(s).ok())
+            .ok_or("Bad symbol name")?
+            .to_string_lossy();
+        let to_paddr = |vaddr: u64| if vaddr >= virt_offset { vaddr - virt_offset } else { vaddr };
+        let paddr = to_paddr(value);
+        // Special section indexes (like SHN_ABS) don't have an end.
+        let section_end = match shndx as usize {
+            i if i < shnum => to_paddr(u64_at(section(i) + 16)? + u64_at(section(i) + 32)?),
+            _ => paddr,
+        };
+        symbols.push(Symbol {
+            paddr: paddr.try_into().map_err(|_| format!("Symbol {name} is above 4GiB"))?,
+            size: u64_at(sym + 16)?.try_into().unwrap_or(0),
+            section_end: section_end.try_into().unwrap_or(u32::MAX),
+            // The alternate format leaves off the hash.
+            name: format!("{:#}", rustc_demangle::demangle(&name)),
+        });
+    }
+
+    symbols.sort_by(|a, b| a.paddr.cmp(&b.paddr).then_with(|| a.name.cmp(&b.name)));
+    symbols.dedup_by_key(|s| s.paddr);
+    for i in 0..symbols.len() {
+        if symbols[i].size == 0 {
+            let next = symbols.get(i + 1).map_or(u32::MAX, |s| s.paddr);
+            symbols[i].size = next.min(symbols[i].section_end).saturating_sub(symbols[i].paddr);
+        }
+    }
+    Ok(encode(&symbols, virt_offset))
+}
+
+fn encode(symbols: &[Symbol], virt_offset: u64) -> Vec<u8> {
+    let mut names = Vec::new();
+    let mut entries = Vec::with_capacity(symbols.len() * KSYMS_ENTRY_SIZE);
+    for s in symbols {
+        entries.extend_from_slice(&s.paddr.to_le_bytes());
+        entries.extend_from_slice(&s.size.to_le_bytes());
+        entries.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        names.extend_from_slice(s.name.as_bytes());
+        names.push(0);
+    }
+
+    let header = KsymsHeader { count: symbols.len() as u32, names_len: names.len() as u32, virt_offset };
+    let mut out = Vec::with_capacity(KSYMS_HEADER_SIZE + entries.len() + names.len());
+    out.extend_from_slice(&header.to_bytes());
+    out.extend_from_slice(&entries);
+    out.extend_from_slice(&names);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::ksyms::Ksyms;
+
+    const VIRT_OFFSET: u64 = 0xffff_ffff_8000_0000;
+    const TEXT: u64 = VIRT_OFFSET + 0x20_0000;
+    const TEXT_SIZE: u64 = 0x100;
+
+    /// A kernel-like ELF file with a physically linked segment, a virtual one, and a symbol table.
+    /// Symbols are (name, type, value, size).
+    fn elf(symbols: &[(&str, u8, u64, u64)]) -> Vec<u8> {
+        let mut strtab = vec![0];
+        let mut symtab = vec![0; SYM_SIZE];
+        for &(name, kind, value, size) in symbols {
+            symtab.extend((strtab.len() as u32).to_le_bytes());
+            symtab.extend([kind, 0]);
+            symtab.extend(1u16.to_le_bytes());
+            symtab.extend(value.to_le_bytes());
+            symtab.extend(size.to_le_bytes());
+            strtab.extend(name.as_bytes());
+            strtab.push(0);
+        }
+
+        let (phoff, symtab_off) = (64, 64 + 2 * 56);
+        let strtab_off = symtab_off + symtab.len();
+        let shoff = strtab_off + strtab.len();
+        let mut out = vec![0; shoff + 4 * 64];
+        let mut put = |offset: usize, bytes: &[u8]| out[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        put(0, b"\x7fELF\x02\x01\x01");
+        put(32, &(phoff as u64).to_le_bytes());
+        put(40, &(shoff as u64).to_le_bytes());
+        put(54, &56u16.to_le_bytes());
+        put(56, &2u16.to_le_bytes());
+        put(58, &64u16.to_le_bytes());
+        put(60, &4u16.to_le_bytes());
+
+        for (i, (vaddr, paddr)) in [(0x10_0000, 0x10_0000), (TEXT, TEXT - VIRT_OFFSET)].into_iter().enumerate() {
+            let ph = phoff + i * 56;
+            put(ph, &PT_LOAD.to_le_bytes());
+            put(ph + 16, &vaddr.to_le_bytes());
+            put(ph + 24, &paddr.to_le_bytes());
+        }
+
+        // Sections: null, .text, .symtab and .strtab.
+        put(shoff + 64 + 16, &TEXT.to_le_bytes());
+        put(shoff + 64 + 32, &TEXT_SIZE.to_le_bytes());
+        put(shoff + 2 * 64 + 4, &SHT_SYMTAB.to_le_bytes());
+        put(shoff + 2 * 64 + 24, &(symtab_off as u64).to_le_bytes());
+        put(shoff + 2 * 64 + 32, &(symtab.len() as u64).to_le_bytes());
+        put(shoff + 2 * 64 + 40, &3u32.to_le_bytes());
+        put(shoff + 3 * 64 + 24, &(strtab_off as u64).to_le_bytes());
+        put(shoff + 3 * 64 + 32, &(strtab.len() as u64).to_le_bytes());
+
+        put(symtab_off, &symtab);
+        put(strtab_off, &strtab);
+        out
+    }
+
+    #[test]
+    fn generate_and_lookup() {
+        const STT_OBJECT: u8 = 1;
+        let table = generate(&elf(&[
+            ("_ZN6kernel4main17h0123456789abcdefE", STT_FUNC, TEXT, 0x10),
+            ("last", STT_FUNC, TEXT + 0xc0, 0),
+            ("no_size", STT_FUNC, TEXT + 0x40, 0),
+            ("phys_entry", STT_FUNC, 0x10_0000, 0x20),
+            ("data", STT_OBJECT, TEXT + 0x20, 0x8),
+        ])).unwrap();
+        let ksyms = Ksyms::from_bytes(&table).unwrap();
+        assert_eq!(ksyms.len(), 4);
+
+        // Virtual and physical addresses find the same symbol.
+        assert_eq!(ksyms.lookup(TEXT + 4), Some(("kernel::main", 4)));
+        assert_eq!(ksyms.lookup(TEXT - VIRT_OFFSET + 4), Some(("kernel::main", 4)));
+        assert_eq!(ksyms.lookup(0x10_0008), Some(("phys_entry", 8)));
+        assert_eq!(ksyms.lookup(0x1000), None);
+
+        // Past the end of a symbol with a size, and not a function.
+        assert_eq!(ksyms.lookup(TEXT + 0x10), None);
+        assert_eq!(ksyms.lookup(TEXT + 0x20), None);
+
+        // Symbols without a size run to the next symbol, or the end of the section.
+        assert_eq!(ksyms.lookup(TEXT + 0xbf), Some(("no_size", 0x7f)));
+        assert_eq!(ksyms.lookup(TEXT + TEXT_SIZE - 1), Some(("last", 0x3f)));
+        assert_eq!(ksyms.lookup(TEXT + TEXT_SIZE), None);
+        assert_eq!(ksyms.lookup(u64::MAX), None);
+    }
+
+    #[test]
+    fn unbounded_last_symbol() {
+        let symbol = |paddr, name: &str| Symbol { paddr, size: 0, section_end: 0, name: name.into() };
+        let table = encode(&[symbol(0x1000, "first"), symbol(0x2000, "second")], VIRT_OFFSET);
+        let ksyms = Ksyms::from_bytes(&table).unwrap();
+
+        assert_eq!(ksyms.lookup(0x1fff), Some(("first", 0xfff)));
+        assert_eq!(ksyms.lookup(0x2000), None);
+        assert_eq!(ksyms.lookup(VIRT_OFFSET + 0x2000), None);
+    }
+
+    #[test]
+    fn bad_elf() {
+        assert!(generate(b"not an ELF file").is_err());
+        let mut stripped = elf(&[]);
+        stripped[60..62].copy_from_slice(&0u16.to_le_bytes());
+        assert!(generate(&stripped).is_err());
+        assert!(Ksyms::from_bytes(&[]).is_none());
+    }
+}
@@ -0,0 +1,140 @@
+//! Build runner for the kernel. This replaces run_debug.sh.
+//!
+//! Usage:
+//!
+//!     cargo xtask build [options]    Build the kernel and root task, and convert the kernel to elf32
+//!     cargo xtask run [options]      Build, then boot in QEMU
+//!     cargo xtask iso [options]      Build a GRUB multiboot ISO
+//!     cargo xtask gdb [options]      Boot in QEMU paused, waiting for GDB
+//!     cargo xtask test [options]     Run the QEMU integration tests (see integration.rs)
+//!     cargo xtask sel4test [options] Run the upstream sel4test suite (see sel4test.rs)
+//!     cargo xtask size [options]     Report the release kernel's size, with and without `tiny` (see size.rs)
+//!
+//! Options:
+//!
+//!     --release                 Build in release mode
+//!     --features <list>         Kernel features, comma separated
+//!     --root-task <package>     The root task to build (default: hello)
+//!     --root-task-elf <path>    Boot a prebuilt root task ELF instead of building one
+//!     --embed                   Embed the root task in the kernel, instead of passing it as a module
+//!     --mem <size>              QEMU memory size (default: 512M)
+//!     --smp <n>                 QEMU CPU count (default: 1)
+//!     --serial <chardev>        Where COM1 goes. Any QEMU -serial value (default: mon:stdio)
+//!     --no-kvm                  Use TCG, even if KVM is available
+//!     --update-expected         sel4test, size: rewrite the expected results from this run
+//!     -- <args>                 Extra arguments passed to QEMU
+//!
+//! The kernel is linked with a symbol table for backtraces, generated from its own ELF file. See
+//! kernel/build.rs.
+//!
+//! QEMU multiboot only loads 32 bit ELF files, so the kernel is converted with objcopy. Set
+//! OBJCOPY to use something other than `objcopy` from the path.
+
+mod image;
+mod integration;
+mod ksyms;
+mod qemu;
+mod sel4test;
+mod size;
+
+use std::path::PathBuf;
+use std::process::exit;
+
+#[derive(Clone)]
+pub(crate) struct Options {
+    pub release: bool,
+    pub features: Option<String>,
+    pub root_task: String,
+    pub root_task_elf: Option<PathBuf>,
+    pub embed: bool,
+    pub mem: String,
+    pub smp: u32,
+    pub serial: String,
+    pub no_kvm: bool,
+    pub update_expected: bool,
+    pub qemu_args: Vec<String>,
+}
+
+impl Options {
+    fn profile(&self) -> &'static str {
+        if self.release { "release" } else { "debug" }
+    }
+
+    /// Where cargo puts the kernel and root task binaries.
+    pub fn target_dir(&self) -> PathBuf {
+        workspace_root().join("target/x86_64-unknown-none").join(self.profile())
+    }
+}
+
+pub(crate) fn workspace_root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..").canonicalize().unwrap()
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: cargo xtask <build|run|iso|gdb|test|sel4test|size> [--release] [--features <list>]");
+    eprintln!("                        [--root-task <package>] [--root-task-elf <path>] [--embed] [--mem <size>]");
+    eprintln!("                        [--smp <n>] [--serial <chardev>] [--no-kvm] [--update-expected]");
+    eprintln!("                        [-- <qemu args>]");
+    exit(1);
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Options {
+    let mut options = Options {
+        release: false,
+        features: None,
+        root_task: "hello".into(),
+        root_task_elf: None,
+        embed: false,
+        mem: "512M".into(),
+        smp: 1,
+        serial: "mon:stdio".into(),
+        no_kvm: false,
+        update_expected: false,
+        qemu_args: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--release" => options.release = true,
+            "--features" => options.features = Some(value()),
+            "--root-task" => options.root_task = value(),
+            "--root-task-elf" => options.root_task_elf = Some(value().into()),
+            "--embed" => options.embed = true,
+            "--mem" => options.mem = value(),
+            "--smp" => options.smp = value().parse().unwrap_or_else(|_| usage()),
+            "--serial" => options.serial = value(),
+            "--no-kvm" => options.no_kvm = true,
+            "--update-expected" => options.update_expected = true,
+            "--" => {
+                options.qemu_args.extend(args);
+                break;
+            }
+            _ => usage(),
+        }
+    }
+    options
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let command = args.next().unwrap_or_else(|| usage());
+    let options = parse_options(args);
+
+    let result = match command.as_str() {
+        "build" => image::build(&options).map(|_| ()),
+        "run" => image::build(&options).and_then(|image| qemu::run(&options, &image)),
+        "iso" => image::build(&options).and_then(|image| image::iso(&options, &image)),
+        "gdb" => image::build(&options).and_then(|image| qemu::gdb(&options, &image)),
+        "test" => integration::test(&options),
+        "sel4test" => sel4test::run(&options),
+        "size" => size::run(&options),
+        "-h" | "--help" => usage(),
+        _ => usage(),
+    };
+
+    if let Err(err) = result {
+        eprintln!("xtask: {err}");
+        exit(1);
+    }
+}
@@ -0,0 +1,72 @@
+//! Booting images in QEMU.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use crate::image::{run_command, Image};
+use crate::Options;
+
+/// Can we use KVM? If not, QEMU falls back to TCG. This is slower, but works on CI machines and
+/// inside containers.
+fn kvm_available() -> bool {
+    fs::OpenOptions::new().read(true).write(true).open("/dev/kvm").is_ok()
+}
+
+/// The QEMU command to boot an image.
+pub(crate) fn qemu_command(options: &Options, image: &Image) -> Command {
+    let mut qemu = Command::new("qemu-system-x86_64");
+
+    if !options.no_kvm && kvm_available() {
+        qemu.args(["-enable-kvm", "-cpu", "host"]);
+    } else {
+        if !options.no_kvm {
+            println!("KVM not available. Using TCG");
+        }
+        // The kernel is built for x86-64-v3, and refuses to boot without the CPU features in
+        // cpu_features.rs. TCG's "max" model is the most likely to have all of it.
+        qemu.args(["-accel", "tcg", "-cpu", "max"]);
+    }
+
+    qemu.args(["-m", &format!("size={}", options.mem)])
+        .args(["-smp", &options.smp.to_string()])
+        .args(["-serial", &options.serial])
+        .args(["-display", "none"])
+        .arg("-kernel").arg(&image.kernel_elf32)
+        .args(["-no-reboot", "-d", "cpu_reset"]);
+    if let Some(root_task) = &image.root_task {
+        qemu.arg("-initrd").arg(root_task);
+    }
+    qemu.args(&options.qemu_args);
+    qemu
+}
+
+pub(crate) fn run(options: &Options, image: &Image) -> Result<(), String> {
+    if options.serial.starts_with("mon:stdio") {
+        println!("Ctrl+A, X to terminate QEMU");
+    }
+    run_command(&mut qemu_command(options, image))
+}
+
+/// Boot with QEMU's GDB server on port 1234, paused before the first instruction.
+pub(crate) fn gdb(options: &Options, image: &Image) -> Result<(), String> {
+    let gdbinit = options.target_dir().join("gdbinit");
+    fs::write(&gdbinit, gdbinit_contents(&image.kernel))
+        .map_err(|e| format!("{}: {e}", gdbinit.display()))?;
+
+    println!("QEMU is waiting for GDB. In another terminal, run:");
+    println!();
+    println!("    gdb -x {}", gdbinit.display());
+    println!();
+    run_command(qemu_command(options, image).args(["-s", "-S"]))
+}
+
+fn gdbinit_contents(kernel: &Path) -> String {
+    // Multiboot starts us in 32 bit protected mode, and we switch to long mode in boot0. GDB gets
+    // confused by the switch, so we set the architecture up front. Breakpoints before the switch
+    // need `set architecture i386`.
+    format!("set architecture i386:x86-64\n\
+             file {}\n\
+             target remote localhost:1234\n\
+             break boot_sys\n",
+            kernel.display())
+}
@@ -0,0 +1,382 @@
+//! Run the upstream sel4test suite, and compare the results against a checked-in manifest.
+//!
+//! sel4test isn't built here. Build the `sel4test-driver` image with the upstream seL4 tooling
+//! (for x86_64 / pc99), and pass it in as the root task:
+//!
+//!     cargo xtask sel4test --root-task-elf path/to/sel4test-driver-image-x86_64-pc99
+//!
+//! The driver is booted unmodified as the multiboot module, and its serial output is parsed into
+//! a pass / fail / skip result for each test case. We recognise these lines:
+//!
+//!  - `Starting test <n>: <NAME>` starts a test.
+//!  - `Test <NAME> passed` / `Test <NAME> failed` are explicit results.
+//!  - `Skipping test <NAME>` / `Test <NAME> skipped` mark a test as skipped.
+//!  - Within a test, any line containing `FAILURE` or starting with `Error:` fails it.
+//!  - `Test suite ...` and `All is well in the universe` end the suite.
+//!
+//! A test without an explicit result passes if the next test (or the end of the suite) is reached
+//! normally. If the output stops partway through a test, the kernel crashed or hung, and that test
+//! fails.
+//!
+//! The expected results live in tests/sel4test-expected.txt. A test which is expected to pass but
+//! doesn't is a regression, and fails the run. Tests which pass unexpectedly are reported, so the
+//! manifest can be updated with `--update-expected`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::time::{Duration, Instant};
+use crate::qemu::qemu_command;
+use crate::{workspace_root, Options};
+
+/// How long the whole suite is allowed to take. The full suite is slow under TCG.
+const TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// If the serial output stops for this long, the kernel has hung (or halted).
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Outcome {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl Outcome {
+    fn name(self) -> &'static str {
+        match self {
+            Outcome::Pass => "pass",
+            Outcome::Fail => "fail",
+            Outcome::Skip => "skip",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "pass" => Outcome::Pass,
+            "fail" => Outcome::Fail,
+            "skip" => Outcome::Skip,
+            _ => return None,
+        })
+    }
+}
+
+struct TestResult {
+    outcome: Outcome,
+    /// Why the test failed, if we know.
+    detail: Option<String>,
+}
+
+fn manifest_path() -> PathBuf {
+    workspace_root().join("tests/sel4test-expected.txt")
+}
+
+pub(crate) fn run(options: &Options) -> Result<(), String> {
+    if options.root_task_elf.is_none() {
+        return Err("sel4test needs the sel4test-driver image. Pass it with --root-task-elf <path>".into());
+    }
+
+    let image = crate::image::build(options)?;
+    let log_dir = options.target_dir().join("sel4test");
+    fs::create_dir_all(&log_dir).map_err(|e| format!("{}: {e}", log_dir.display()))?;
+    let log = log_dir.join("serial.log");
+    // QEMU appends to an existing file.
+    let _ = fs::remove_file(&log);
+
+    let mut options = options.clone();
+    options.serial = format!("file:{}", log.display());
+    let mut qemu = qemu_command(&options, &image);
+    qemu.args(["-monitor", "none"]).stdin(Stdio::null()).stdout(Stdio::null());
+    let child = qemu.spawn().map_err(|e| format!("Could not run QEMU: {e}"))?;
+
+    println!("Running sel4test. Serial log: {}", log.display());
+    let (output, stop_reason) = wait_for_suite(child, &log)?;
+    if let Some(reason) = &stop_reason {
+        println!("{reason}");
+    }
+
+    let results = parse_results(&output);
+    let expected = read_manifest()?;
+    let regressions = report(&results, &expected);
+
+    if options.update_expected {
+        write_manifest(&results, &expected)?;
+        println!("Updated {}", manifest_path().display());
+        return Ok(());
+    }
+    match regressions {
+        0 => Ok(()),
+        _ => Err(format!("{regressions} sel4test regression(s)")),
+    }
+}
+
+/// Wait for the suite to finish, or for the kernel to stop making progress. Returns the serial
+/// output, and why we stopped early (if we did).
+fn wait_for_suite(mut child: Child, log: &Path) -> Result<(String, Option<String>), String> {
+    let start = Instant::now();
+    let mut last_len = 0;
+    let mut last_progress = Instant::now();
+
+    let stop_reason = loop {
+        let output = fs::read_to_string(log).unwrap_or_default();
+        if output.lines().any(is_suite_end) {
+            break None;
+        }
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break Some(format!("QEMU exited before the suite finished ({status})"));
+        }
+
+        if output.len() != last_len {
+            last_len = output.len();
+            last_progress = Instant::now();
+        }
+        if last_progress.elapsed() > IDLE_TIMEOUT {
+            break Some(format!("No serial output for {}s. Stopping", IDLE_TIMEOUT.as_secs()));
+        }
+        if start.elapsed() > TIMEOUT {
+            break Some(format!("Timed out after {}s", TIMEOUT.as_secs()));
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    };
+
+    // sel4test doesn't power the machine off when it's done.
+    let _ = child.kill();
+    let _ = child.wait();
+    let output = fs::read_to_string(log).map_err(|e| format!("{}: {e}", log.display()))?;
+    Ok((output, stop_reason))
+}
+
+fn is_suite_end(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with("Test suite ") || line.contains("All is well in the universe")
+}
+
+/// Parse sel4test's serial output into a result per test. See the module documentation for the
+/// lines we understand.
+fn parse_results(output: &str) -> BTreeMap<String, TestResult> {
+    let mut results = BTreeMap::new();
+    // The test currently running, and its explicit result if we've seen one.
+    let mut current: Option<(String, Option<TestResult>)> = None;
+
+    // Called when a test ends without crashing.
+    let finish = |results: &mut BTreeMap<String, TestResult>, (name, result): (String, Option<TestResult>)| {
+        results.insert(name, result.unwrap_or(TestResult { outcome: Outcome::Pass, detail: None }));
+    };
+
+    for line in output.lines().map(str::trim) {
+        if is_suite_end(line) {
+            if let Some(test) = current.take() {
+                finish(&mut results, test);
+            }
+            return results;
+        }
+
+        if let Some(rest) = line.strip_prefix("Starting test ") {
+            if let Some(test) = current.take() {
+                finish(&mut results, test);
+            }
+            if let Some((_, name)) = rest.split_once(": ") {
+                current = Some((name.trim().to_string(), None));
+            }
+            continue;
+        }
+
+        let skipped = line.strip_prefix("Skipping test ")
+            .or_else(|| line.strip_prefix("Test ").and_then(|l| l.strip_suffix(" skipped")));
+        if let Some(name) = skipped {
+            let name = name.split_whitespace().next().unwrap_or(name).trim_end_matches(',');
+            results.insert(name.to_string(), TestResult { outcome: Outcome::Skip, detail: None });
+            continue;
+        }
+
+        let Some((_, result)) = &mut current else { continue };
+        if line.starts_with("Test ") && line.ends_with(" passed") {
+            result.get_or_insert(TestResult { outcome: Outcome::Pass, detail: None });
+        } else if (line.starts_with("Test ") && line.ends_with(" failed"))
+            || line.starts_with("Error:")
+            || line.contains("FAILURE")
+        {
+            // Keep the first failure. It's usually the interesting one.
+            if result.as_ref().is_none_or(|r| r.outcome != Outcome::Fail) {
+                *result = Some(TestResult { outcome: Outcome::Fail, detail: Some(line.to_string()) });
+            }
+        }
+    }
+
+    // The output stopped partway through a test.
+    if let Some((name, _)) = current {
+        results.insert(name, TestResult { outcome: Outcome::Fail, detail: Some("Did not finish".into()) });
+    }
+    results
+}
+
+/// Read the expected results. Each line is `<test name> <pass|fail|skip>`. `#` starts a comment.
+fn read_manifest() -> Result<BTreeMap<String, Outcome>, String> {
+    let path = manifest_path();
+    let contents = fs::read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+    parse_manifest(&contents).map_err(|e| format!("{}:{e}", path.display()))
+}
+
+/// Parse the manifest's contents. Errors start with the line number.
+fn parse_manifest(contents: &str) -> Result<BTreeMap<String, Outcome>, String> {
+    let mut expected = BTreeMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        // Test names can contain spaces, so the outcome is whatever follows the last one.
+        let parsed = line.rsplit_once(char::is_whitespace)
+            .and_then(|(name, outcome)| Some((name.trim_end(), Outcome::parse(outcome)?)));
+        let Some((name, outcome)) = parsed else {
+            return Err(format!("{}: Expected `<test name> <pass|fail|skip>`", i + 1));
+        };
+        expected.insert(name.to_string(), outcome);
+    }
+    Ok(expected)
+}
+
+/// Rewrite the manifest from this run. Tests we knew about which didn't run this time are kept.
+fn write_manifest(results: &BTreeMap<String, TestResult>, expected: &BTreeMap<String, Outcome>) -> Result<(), String> {
+    let mut merged = expected.clone();
+    merged.extend(results.iter().map(|(name, result)| (name.clone(), result.outcome)));
+
+    let mut contents = String::from("# Expected sel4test results, used by `cargo xtask sel4test`. Each line is\n\
+        # `<test name> <pass|fail|skip>`. Regenerate with `cargo xtask sel4test --update-expected`.\n");
+    for (name, outcome) in &merged {
+        contents.push_str(&format!("{name} {}\n", outcome.name()));
+    }
+
+    let path = manifest_path();
+    fs::write(&path, contents).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Print the results, and how they compare to what we expected. Returns the number of regressions.
+fn report(results: &BTreeMap<String, TestResult>, expected: &BTreeMap<String, Outcome>) -> usize {
+    let mut regressions = 0;
+    let mut fixed = 0;
+
+    for (name, result) in results {
+        let expected = expected.get(name).copied();
+        let note = match (expected, result.outcome) {
+            (Some(Outcome::Pass), Outcome::Pass) => "",
+            (Some(Outcome::Pass), _) => {
+                regressions += 1;
+                " (REGRESSION)"
+            }
+            (_, Outcome::Pass) => {
+                fixed += 1;
+                " (newly passing)"
+            }
+            _ => "",
+        };
+        println!("test {name} ... {}{note}", result.outcome.name());
+        if let Some(detail) = &result.detail {
+            println!("    {detail}");
+        }
+    }
+
+    // Tests we expected to pass, which never ran at all.
+    for (name, _) in expected.iter().filter(|(name, outcome)| **outcome == Outcome::Pass && !results.contains_key(*name)) {
+        println!("test {name} ... did not run (REGRESSION)");
+        regressions += 1;
+    }
+
+    if results.is_empty() {
+        println!("sel4test did not start any tests");
+    }
+
+    let count = |outcome| results.values().filter(|r| r.outcome == outcome).count();
+    let passed = count(Outcome::Pass);
+    // Count tests from the manifest too, so a crash partway through doesn't make the number look
+    // better than it is.
+    let known = expected.keys().chain(results.keys()).collect::<BTreeSet<_>>().len();
+    println!("\n{passed} passed, {} failed, {} skipped", count(Outcome::Fail), count(Outcome::Skip));
+    match known {
+        0 => println!("Compatibility: no known tests"),
+        _ => println!("Compatibility: {passed}/{known} tests pass ({:.1}%)", passed as f64 * 100.0 / known as f64),
+    }
+    if fixed > 0 {
+        println!("{fixed} test(s) newly passing. Run with --update-expected to record them");
+    }
+    regressions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcomes(results: &BTreeMap<String, TestResult>) -> Vec<(&str, Outcome)> {
+        results.iter().map(|(name, result)| (name.as_str(), result.outcome)).collect()
+    }
+
+    #[test]
+    fn parse_output() {
+        let output = "\
+            Starting test 0: TEST_A\n\
+            Test TEST_A passed\n\
+            Starting test 1: TEST_B\n\
+            some output\n\
+            Error: result was 3\n\
+            Test TEST_B failed\n\
+            Skipping test TEST_C, it needs SMP\n\
+            Starting test 2: TEST_D\n\
+            Starting test 3: TEST_E\n\
+            Test suite passed. 3 tests passed. 1 tests failed.\n\
+            Starting test 4: TEST_F\n";
+        let results = parse_results(output);
+        assert_eq!(outcomes(&results), [
+            ("TEST_A", Outcome::Pass),
+            ("TEST_B", Outcome::Fail),
+            ("TEST_C", Outcome::Skip),
+            ("TEST_D", Outcome::Pass),
+            ("TEST_E", Outcome::Pass),
+        ]);
+        // The first failure is kept.
+        assert_eq!(results["TEST_B"].detail.as_deref(), Some("Error: result was 3"));
+    }
+
+    #[test]
+    fn parse_crash() {
+        let results = parse_results("Starting test 0: TEST_A\nStarting test 1: TEST_B\nKernel panic\n");
+        assert_eq!(outcomes(&results), [("TEST_A", Outcome::Pass), ("TEST_B", Outcome::Fail)]);
+        assert_eq!(results["TEST_B"].detail.as_deref(), Some("Did not finish"));
+    }
+
+    #[test]
+    fn manifest() {
+        let expected = parse_manifest("# A comment\n\nTEST_A pass\nTEST B with spaces  fail # Flaky\nTEST_C\tskip\n");
+        assert_eq!(expected.unwrap().into_iter().collect::<Vec<_>>(), [
+            ("TEST B with spaces".to_string(), Outcome::Fail),
+            ("TEST_A".to_string(), Outcome::Pass),
+            ("TEST_C".to_string(), Outcome::Skip),
+        ]);
+
+        assert!(parse_manifest("TEST_A\n").unwrap_err().starts_with("1:"));
+        assert!(parse_manifest("TEST_A pass\nTEST_B maybe\n").unwrap_err().starts_with("2:"));
+    }
+
+    #[test]
+    fn regressions() {
+        let result = |outcome| TestResult { outcome, detail: None };
+        let results = BTreeMap::from([
+            ("STILL_PASSING".to_string(), result(Outcome::Pass)),
+            ("NOW_FAILING".to_string(), result(Outcome::Fail)),
+            ("NOW_SKIPPED".to_string(), result(Outcome::Skip)),
+            ("NEWLY_PASSING".to_string(), result(Outcome::Pass)),
+            ("STILL_FAILING".to_string(), result(Outcome::Fail)),
+        ]);
+        let expected = BTreeMap::from([
+            ("STILL_PASSING".to_string(), Outcome::Pass),
+            ("NOW_FAILING".to_string(), Outcome::Pass),
+            ("NOW_SKIPPED".to_string(), Outcome::Pass),
+            ("NEWLY_PASSING".to_string(), Outcome::Fail),
+            ("STILL_FAILING".to_string(), Outcome::Fail),
+            ("DID_NOT_RUN".to_string(), Outcome::Pass),
+            ("SKIPPED_BEFORE".to_string(), Outcome::Skip),
+        ]);
+        assert_eq!(report(&results, &expected), 3);
+        assert_eq!(report(&results, &BTreeMap::new()), 0);
+    }
+}
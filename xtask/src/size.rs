@@ -0,0 +1,196 @@
+//! Report the size of the kernel, so size regressions are visible.
+//!
+//! The kernel is built in release mode twice: as configured, and with the `tiny` feature (no
+//! core::fmt). For each, we add up its sections:
+//!
+//!  - text: executable code.
+//!  - rodata: read only data.
+//!  - data: initialised writable data.
+//!  - bss: zeroed data. This costs memory, but not space in the image.
+//!
+//! The embedded root task and symbol table (.root_task and .ksyms) aren't counted, since they
+//! aren't kernel code.
+//!
+//! The sizes are compared against tests/kernel-size.txt, and the run fails if any image grew. Record
+//! new sizes with `--update-expected`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use crate::image::{cargo_build, run_command};
+use crate::{workspace_root, Options};
+
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+
+/// Sections which are part of the image, but aren't the kernel itself.
+const IGNORED_SECTIONS: &[&str] = &[".root_task", ".ksyms"];
+
+#[derive(Copy, Clone, Default, Eq, PartialEq)]
+struct Sizes {
+    text: u64,
+    rodata: u64,
+    data: u64,
+    bss: u64,
+}
+
+impl Sizes {
+    /// The bytes loaded from the image.
+    fn image(&self) -> u64 {
+        self.text + self.rodata + self.data
+    }
+
+    fn fields(&self) -> [u64; 4] {
+        [self.text, self.rodata, self.data, self.bss]
+    }
+}
+
+fn manifest_path() -> PathBuf {
+    workspace_root().join("tests/kernel-size.txt")
+}
+
+pub(crate) fn run(options: &Options) -> Result<(), String> {
+    let mut options = options.clone();
+    options.release = true;
+
+    let features = options.features.clone().unwrap_or_default();
+    let tiny = match features.as_str() {
+        "" => "tiny".to_string(),
+        f => format!("{f},tiny"),
+    };
+
+    let mut results = BTreeMap::new();
+    for features in [features, tiny] {
+        let mut cargo = cargo_build(&options, "kernel");
+        cargo.args(["--features", &features]).env_remove("ROOT_TASK_ELF").env_remove("KERNEL_SYMBOLS");
+        run_command(&mut cargo)?;
+
+        let kernel = options.target_dir().join("kernel");
+        let elf = fs::read(&kernel).map_err(|e| format!("{}: {e}", kernel.display()))?;
+        let name = if features.is_empty() { "default".to_string() } else { features };
+        results.insert(name, section_sizes(&elf)?);
+    }
+
+    let expected = read_manifest()?;
+    let grew = report(&results, &expected);
+
+    if options.update_expected {
+        let mut merged = expected;
+        merged.extend(results);
+        write_manifest(&merged)?;
+        println!("Updated {}", manifest_path().display());
+        return Ok(());
+    }
+    match grew {
+        false => Ok(()),
+        true => Err("The kernel image grew. If that's expected, record it with --update-expected".into()),
+    }
+}
+
+/// Add up the sizes of the kernel's allocated sections, by kind.
+fn section_sizes(elf: &[u8]) -> Result<Sizes, String> {
+    if elf.get(0..4) != Some(b"\x7fELF") || elf.get(4) != Some(&2) || elf.get(5) != Some(&1) {
+        return Err("Not a 64 bit little endian ELF file".into());
+    }
+
+    let bytes = |o: usize, n: usize| elf.get(o..o + n).ok_or_else(|| "Truncated ELF file".to_string());
+    let u16_at = |o| bytes(o, 2).map(|b| u16::from_le_bytes(b.try_into().unwrap()));
+    let u32_at = |o| bytes(o, 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+    let u64_at = |o| bytes(o, 8).map(|b| u64::from_le_bytes(b.try_into().unwrap()));
+
+    let shoff = u64_at(40)? as usize;
+    let shentsize = u16_at(58)? as usize;
+    let shnum = u16_at(60)? as usize;
+    let shstrndx = u16_at(62)? as usize;
+    let section = |i: usize| shoff + i * shentsize;
+    let names = u64_at(section(shstrndx) + 24)? as usize;
+
+    let mut sizes = Sizes::default();
+    for sh in (0..shnum).map(section) {
+        let name_offset = names + u32_at(sh)? as usize;
+        let name = elf.get(name_offset..)
+            .and_then(|s| std::ffi::CStr::from_bytes_until_nul(s).ok())
+            .ok_or("Bad section name")?
+            .to_string_lossy();
+        let flags = u64_at(sh + 8)?;
+        if flags & SHF_ALLOC == 0 || IGNORED_SECTIONS.contains(&name.as_ref()) {
+            continue;
+        }
+
+        let size = u64_at(sh + 32)?;
+        let kind = if u32_at(sh + 4)? == SHT_NOBITS {
+            &mut sizes.bss
+        } else if flags & SHF_EXECINSTR != 0 {
+            &mut sizes.text
+        } else if flags & SHF_WRITE != 0 {
+            &mut sizes.data
+        } else {
+            &mut sizes.rodata
+        };
+        *kind += size;
+    }
+    Ok(sizes)
+}
+
+/// Read the recorded sizes. Each line is `<features> <text> <rodata> <data> <bss>`. `#` starts a
+/// comment.
+fn read_manifest() -> Result<BTreeMap<String, Sizes>, String> {
+    let path = manifest_path();
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(format!("{}: {e}", path.display())),
+    };
+
+    let mut expected = BTreeMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap();
+        let numbers = words.map(|w| w.parse::<u64>().ok()).collect::<Option<Vec<_>>>();
+        let Some(&[text, rodata, data, bss]) = numbers.as_deref() else {
+            return Err(format!("{}:{}: Expected `<features> <text> <rodata> <data> <bss>`", path.display(), i + 1));
+        };
+        expected.insert(name.to_string(), Sizes { text, rodata, data, bss });
+    }
+    Ok(expected)
+}
+
+fn write_manifest(sizes: &BTreeMap<String, Sizes>) -> Result<(), String> {
+    let mut contents = String::from("# Release kernel sizes in bytes, used by `cargo xtask size`. Each line is\n\
+        # `<features> <text> <rodata> <data> <bss>`. Regenerate with `cargo xtask size --update-expected`.\n");
+    for (name, s) in sizes {
+        contents.push_str(&format!("{name} {} {} {} {}\n", s.text, s.rodata, s.data, s.bss));
+    }
+
+    let path = manifest_path();
+    fs::write(&path, contents).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Print the sizes, with the change from the recorded sizes in brackets. Returns true if any image
+/// grew.
+fn report(results: &BTreeMap<String, Sizes>, expected: &BTreeMap<String, Sizes>) -> bool {
+    println!("{:<24} {:>16} {:>16} {:>16} {:>16} {:>16}", "features", "text", "rodata", "data", "bss", "image");
+    let mut grew = false;
+    for (name, sizes) in results {
+        let old = expected.get(name);
+        let mut line = format!("{name:<24}");
+        let columns = sizes.fields().into_iter().chain([sizes.image()]);
+        let old_columns = old.map(|o| o.fields().into_iter().chain([o.image()]).collect::<Vec<_>>());
+        for (i, size) in columns.enumerate() {
+            let delta = match &old_columns {
+                Some(old) if old[i] != size => format!("({:+})", size as i64 - old[i] as i64),
+                _ => String::new(),
+            };
+            line.push_str(&format!(" {:>16}", format!("{size} {delta}").trim_end()));
+        }
+        println!("{line}");
+        grew |= old.is_some_and(|o| sizes.image() > o.image());
+    }
+    grew
+}